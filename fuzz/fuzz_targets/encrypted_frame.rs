@@ -0,0 +1,28 @@
+// 加密端口的报文 base64(aes-256-cbc) 以 SPLIT 分隔。解出的明文再按矿机报文解析
+#![no_main]
+use hex::FromHex;
+use libfuzzer_sys::fuzz_target;
+use mining_proxy::client::{
+    decrypt_frame,
+    encryption::{DEFAULT_IV, DEFAULT_KEY},
+    parse,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let key = Vec::from_hex(DEFAULT_KEY).unwrap();
+    let iv = Vec::from_hex(DEFAULT_IV).unwrap();
+
+    for frame in data.split(|c| *c == mining_proxy::SPLIT) {
+        if frame.is_empty() {
+            continue;
+        }
+        if let Ok(buf) = decrypt_frame(frame, &key, &iv) {
+            for line in buf.split(|c| *c == b'\n') {
+                if let Some(mut rpc) = parse(line) {
+                    rpc.get_submit_hashrate();
+                    rpc.set_worker_name("fuzz");
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,58 @@
+// 矿机发来的报文。按换行拆分后依次走各币种的解析与改写
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mining_proxy::{
+    client::{parse, parse_workername},
+    protocol::{
+        bitcoin::V1Request, rpc::eth::ClientRpc, stratum::StraumRoot,
+        xmr::XmrRequest,
+    },
+};
+
+fuzz_target!(|data: &[u8]| {
+    for line in data.split(|c| *c == b'\n') {
+        if let Some(mut rpc) = parse(line) {
+            rpc.get_id();
+            rpc.get_job_id();
+            rpc.get_eth_wallet();
+            rpc.get_worker_name();
+            rpc.get_submit_hashrate();
+            rpc.get_method();
+            rpc.is_protocol_eth_statum();
+            rpc.set_id(1);
+            rpc.set_worker_name("fuzz");
+            rpc.set_wallet("0x0000000000000000000000000000000000000000");
+            rpc.set_submit_hashrate("0x1".into());
+            let _ = rpc.to_vec();
+        }
+
+        if let Some(mut rpc) = parse_workername(line) {
+            rpc.get_eth_wallet();
+            rpc.get_worker_name();
+            rpc.get_submit_hashrate();
+            rpc.if_parse_protocol_eth_statum();
+            rpc.set_worker_name("fuzz");
+        }
+
+        if let Ok(rpc) = serde_json::from_slice::<StraumRoot>(line) {
+            // 与 stratum 登录一致 钱包.矿工名
+            if let Some(wallet) = rpc.params.get(0) {
+                let _ = wallet.split('.').collect::<Vec<&str>>();
+            }
+        }
+
+        if let Ok(mut req) = serde_json::from_slice::<V1Request>(line) {
+            req.get_wallet();
+            req.param_str(1);
+            req.set_wallet("fuzz.rig");
+        }
+
+        if let Ok(mut req) = serde_json::from_slice::<XmrRequest>(line) {
+            if let Some(login) = req.get_login() {
+                login.wallet_and_worker();
+            }
+            req.set_session_id("fuzz");
+            req.set_login("fuzz.rig");
+        }
+    }
+});
@@ -0,0 +1,83 @@
+// 矿池发来的报文。各中转逻辑对同一行依次尝试的类型都在这里解析一遍
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use mining_proxy::protocol::{
+    bitcoin::{self, BtcServerMessage},
+    cfx::{self, CfxJob, CfxServerMessage},
+    eth_stratum::EthSubscriptionNotify,
+    ethjson::{EthServer, EthServerRootObject},
+    kawpow,
+    rpc::eth::{Server, ServerJobsWithHeight, ServerRpc, ServerSideJob},
+    stratum::{
+        StraumMiningNotify, StraumMiningSet, StraumResult, StraumResultBool,
+    },
+    xmr::{self, XmrServerMessage},
+};
+
+fn server_rpc<T: ServerRpc>(mut rpc: T) {
+    rpc.get_id();
+    rpc.get_diff();
+    rpc.get_job_id();
+    rpc.set_diff("0x1".into());
+    rpc.set_id(1);
+}
+
+fuzz_target!(|data: &[u8]| {
+    for line in data.split(|c| *c == b'\n') {
+        if let Ok(rpc) = serde_json::from_slice::<ServerSideJob>(line) {
+            server_rpc(rpc);
+        }
+        if let Ok(rpc) = serde_json::from_slice::<Server>(line) {
+            server_rpc(rpc);
+        }
+        if let Ok(rpc) = serde_json::from_slice::<ServerJobsWithHeight>(line) {
+            server_rpc(rpc);
+        }
+        let _ = serde_json::from_slice::<EthServer>(line);
+        let _ = serde_json::from_slice::<EthServerRootObject>(line);
+        let _ = serde_json::from_slice::<EthSubscriptionNotify>(line);
+        let _ = serde_json::from_slice::<StraumResult>(line);
+        let _ = serde_json::from_slice::<StraumResultBool>(line);
+        let _ = serde_json::from_slice::<StraumMiningSet>(line);
+        let _ = serde_json::from_slice::<StraumMiningNotify>(line);
+
+        match BtcServerMessage::parse(line) {
+            Some(BtcServerMessage::Response(res)) => {
+                res.is_ok();
+                bitcoin::parse_subscribe_result(&res.result);
+            }
+            Some(BtcServerMessage::Notify(_)) | None => {}
+        }
+
+        match CfxServerMessage::parse(line) {
+            Some(CfxServerMessage::Notify(n)) => {
+                if let Some(job) = CfxJob::from_params(&n.params) {
+                    job.difficulty();
+                }
+                // RVN 与 CFX 同为 stratum V1 通知
+                kawpow::notify_hashes(&n);
+            }
+            Some(CfxServerMessage::Response(res)) => {
+                cfx::is_result_ok(&res);
+                kawpow::parse_subscribe_result(&res.result);
+            }
+            None => {}
+        }
+
+        match XmrServerMessage::parse(line) {
+            Some(XmrServerMessage::Job(mut job)) => {
+                xmr::job_difficulty(&job);
+                xmr::set_job_session_id(&mut job, "fuzz");
+                xmr::job_notify(&job);
+            }
+            Some(XmrServerMessage::Response(mut res)) => {
+                res.is_ok();
+                res.session_id();
+                if let Some(job) = res.job_mut() {
+                    xmr::job_difficulty(job);
+                }
+            }
+            Some(XmrServerMessage::Other(_)) | None => {}
+        }
+    }
+});
@@ -1,115 +1,15 @@
 mod version {
     include!(concat!(env!("OUT_DIR"), "/version.rs"));
 }
-use std::vec;
+use std::{sync::Arc, time::Duration};
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use clap::{
     crate_description, crate_name, crate_version, App, Arg, ArgMatches,
 };
-use futures::future;
+use mining_proxy::simulator::{run, MinerProtocol, SimConfig, SimStats};
 use serde::{Deserialize, Serialize};
-use tokio::io::{
-    split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf,
-    WriteHalf,
-};
-
-use tokio::{net::TcpSocket, time::sleep};
-
-async fn client_to_server<W>(mut w: WriteHalf<W>, i: i32) -> Result<()>
-where W: AsyncWrite {
-    let mut is_login = false;
-    loop {
-        if !is_login {
-            let login = Client {
-                id: 1,
-                method: "eth_submitLogin".into(),
-                params: vec![
-                    "0xb0B91c95D2D0ebD0C85bA14B0547668a198b9dbD".into(),
-                    "x".into(),
-                ],
-                worker: "test_".to_string() + &i.to_string(),
-            };
-
-            let mut login_msg = serde_json::to_vec(&login).unwrap();
-
-            login_msg.push(b'\n');
-            w.write(&login_msg).await.unwrap();
-            is_login = true;
-            sleep(std::time::Duration::new(10, 0)).await;
-            let eth_get_work = ClientGetWork {
-                id: 5,
-                method: "eth_getWork".into(),
-                params: vec![],
-            };
-
-            let mut eth_get_work_msg =
-                serde_json::to_vec(&eth_get_work).unwrap();
-            sleep(std::time::Duration::new(5, 0)).await;
-            eth_get_work_msg.push(b'\n');
-            w.write(&eth_get_work_msg).await?;
-        } else {
-            //计算速率
-            let submit_hashrate = Client {
-                id: 6,
-                method: "eth_submitHashrate".into(),
-                params: ["0x1111111".into(), "x".into()].to_vec(),
-                worker: "test_".to_string() + &i.to_string(),
-            };
-
-            let mut submit_hashrate_msg =
-                serde_json::to_vec(&submit_hashrate).unwrap();
-
-            submit_hashrate_msg.push(b'\n');
-            w.write(&submit_hashrate_msg).await?;
-
-            sleep(std::time::Duration::new(20, 0)).await;
-        }
-    }
-}
-
-async fn server_to_client<R>(mut r: ReadHalf<R>) -> Result<()>
-where R: AsyncRead {
-    let mut job_queue = vec![];
-    loop {
-        let mut buf = vec![0; 1024];
-        let len: usize = match tokio::time::timeout(
-            std::time::Duration::new(60, 0),
-            r.read(&mut buf),
-        )
-        .await
-        {
-            Ok(got) => match got {
-                Ok(len) => len,
-                Err(e) => {
-                    println!("读取错误 {}", e);
-                    bail!("{}", e);
-                }
-            },
-            Err(e) => {
-                panic!("读取超时 {}", e);
-            }
-        };
-        //let len = r.read(&mut buf).await?;
-        if len == 0 {
-            println!("客户端下线");
-            return Ok(());
-            //panic!("客户端下线");
-        }
-        let jobs = String::from_utf8(buf[0..len].to_vec()).unwrap();
-
-        if let Ok(job) = serde_json::from_str::<Server>(&jobs) {
-            println!("Got Message {:?}", job);
-            if let Some(id) = job.result.get(0) {
-                if job_queue.contains(id) {
-                    println!("任务重复 : {}", id);
-                } else {
-                    job_queue.push(id.to_string());
-                }
-            }
-        }
-    }
-}
+use tokio::time::Instant;
 
 pub async fn command_matches() -> Result<ArgMatches<'static>> {
     let matches = App::new(format!(
@@ -136,41 +36,116 @@ pub async fn command_matches() -> Result<ArgMatches<'static>> {
             .help("指定服务器TCP端口")
             .takes_value(true),
     )
+    .arg(
+        Arg::with_name("protocol")
+            .long("protocol")
+            .help("矿机协议 eth ethstratum stratum mixed 默认 eth")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("hashrate")
+            .long("hashrate")
+            .help("每台矿机的算力 MH/s 默认 100")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("difficulty")
+            .long("difficulty")
+            .help("份额难度 GH 默认 4")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("duration")
+            .short("d")
+            .long("duration")
+            .help("运行时间 秒 默认 60 0 为直到 Ctrl-C")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("wallet")
+            .short("w")
+            .long("wallet")
+            .help("矿机使用的钱包 可重复填写 不填随机生成")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+    )
+    .arg(
+        Arg::with_name("seed")
+            .long("seed")
+            .help("随机种子 固定后每次负载一致")
+            .takes_value(true),
+    )
     .get_matches();
     Ok(matches)
 }
+
+fn parse_arg<T: std::str::FromStr>(
+    matches: &ArgMatches, name: &str, default: T,
+) -> T {
+    match matches.value_of(name) {
+        Some(v) => v.parse().unwrap_or_else(|_| {
+            println!("参数 {} 格式错误: {}", name, v);
+            std::process::exit(1);
+        }),
+        None => default,
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let matches = command_matches().await?;
-    let phread = matches.value_of("phread").unwrap_or_else(|| {
-        println!("请正确填写本地监听端口 例如: -p 8888");
-        std::process::exit(1);
-    });
-
-    let phread_int: i32 = phread.parse()?;
+    let phread_int: u32 = parse_arg(&matches, "phread", 1);
 
     let server = matches.value_of("server").unwrap_or_else(|| {
         println!("请正确填写服务器地址 例如: -s 8.0.0.0:8888");
         std::process::exit(1);
     });
 
-    let mut i = 0;
-
-    let mut v = vec![];
-    while i <= phread_int {
-        let client = TcpSocket::new_v4()?;
-        let stream = client.connect(server.parse()?).await?;
-        let (r, w) = split(stream);
-        let a = tokio::spawn(async move {
-            tokio::try_join!(client_to_server(w, i), server_to_client(r))
-        });
-        v.push(a);
-        i += 1;
-    }
+    let protocols = MinerProtocol::parse_list(
+        matches.value_of("protocol").unwrap_or("eth"),
+    )
+    .unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(1);
+    });
 
-    let outputs = future::join_all(v.into_iter().map(tokio::spawn)).await;
+    let config = SimConfig {
+        server: server.to_string(),
+        miners: phread_int,
+        protocols,
+        hashrate: parse_arg(&matches, "hashrate", 100.0) * 1_000_000.0,
+        difficulty: parse_arg(&matches, "difficulty", 4.0) * 1_000_000_000.0,
+        wallets: matches
+            .values_of("wallet")
+            .map(|v| v.map(|s| s.to_string()).collect())
+            .unwrap_or_default(),
+        seed: matches
+            .value_of("seed")
+            .map(|_| parse_arg(&matches, "seed", 0)),
+        ..Default::default()
+    };
+
+    println!(
+        "启动 {} 台矿机 平均 {:.2} 秒一个份额",
+        config.miners,
+        config.share_interval().as_secs_f64()
+    );
+
+    let duration: u64 = parse_arg(&matches, "duration", 60);
+    let stats = Arc::new(SimStats::default());
+    if duration == 0 {
+        // 一直运行到 Ctrl-C
+        let deadline = Instant::now() + Duration::from_secs(u32::MAX as u64);
+        let run = tokio::spawn(run(config, stats.clone(), deadline));
+        tokio::signal::ctrl_c().await?;
+        run.abort();
+    } else {
+        let deadline = Instant::now() + Duration::from_secs(duration);
+        run(config, stats.clone(), deadline).await;
+    }
 
-    println!("{:?}", outputs);
+    println!("{}", stats);
 
     Ok(())
 }
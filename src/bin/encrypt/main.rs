@@ -2,13 +2,13 @@ mod version {
     include!(concat!(env!("OUT_DIR"), "/version.rs"));
 }
 
-use std::net::ToSocketAddrs;
+use std::sync::Arc;
 
 use anyhow::Result;
-use clap::{crate_name, crate_version};
-use hex::FromHex;
-
-use openssl::aes::AesKey;
+use clap::{crate_name, crate_version, ArgMatches};
+use mining_proxy::client::encryption::{
+    EncryptConfig, EncryptListener, DEFAULT_IV, DEFAULT_KEY,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -31,51 +31,67 @@ async fn main() -> Result<()> {
         version::short_sha()
     );
 
-    let key = matches.value_of("key").unwrap_or(
-        "523B607044E6BF7E46AF75233FDC1278B7AA0FC42D085DEA64AE484AD7FB3664",
-    );
-    let iv = matches
-        .value_of("iv")
-        .unwrap_or("275E2015B9E5CA4DDB87B90EBC897F8C");
-    let key = Vec::from_hex(key).unwrap();
-    let _ = AesKey::new_encrypt(&key).unwrap_or_else(|e| {
-        println!("请填写正确的 key {:?}", e);
-        std::process::exit(1);
-    });
+    // 指定配置文件时按配置文件启动多个端口 否则使用命令行参数
+    let listeners = match matches.value_of("config") {
+        Some(path) => match EncryptConfig::from_file(path) {
+            Ok(config) => config.listeners,
+            Err(e) => {
+                println!("读取配置文件 {} 失败: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => vec![command_listener(&matches)],
+    };
+
+    let mut proxies = vec![];
+    for listener in listeners {
+        match listener.build() {
+            Ok(proxy) => proxies.push(Arc::new(proxy)),
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let iv = Vec::from_hex(iv).unwrap();
+    let res =
+        mining_proxy::client::encryption::run_encrypt_proxies(proxies).await;
 
+    if let Err(err) = res {
+        log::warn!("加密服务断开: {}", err);
+    }
+
+    Ok(())
+}
+
+fn command_listener(matches: &ArgMatches) -> EncryptListener {
     let port = matches.value_of("port").unwrap_or_else(|| {
         println!("请正确填写本地监听端口 例如: -p 8888");
         std::process::exit(1);
     });
 
-    let server = matches.value_of("server").unwrap_or_else(|| {
-        println!("请正确填写服务器地址 例如: -s 8.0.0.0:8888");
-        std::process::exit(1);
-    });
-
-    let addr = match server.to_socket_addrs().unwrap().next() {
-        Some(address) => address,
-        None => {
-            println!("请正确填写服务器地址 例如: -s 8.0.0.0:8888");
-            std::process::exit(1);
-        }
-    };
-
     let port: i32 = port.parse().unwrap_or_else(|_| {
         println!("请正确填写本地监听端口 例如: -p 8888");
         std::process::exit(1);
     });
 
-    let res =
-        tokio::try_join!(mining_proxy::client::encryption::accept_encrypt_tcp(
-            port, addr, key, iv
-        ));
+    let servers = matches
+        .values_of("server")
+        .map(|v| {
+            v.flat_map(|s| s.split(','))
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
 
-    if let Err(err) = res {
-        log::warn!("加密服务断开: {}", err);
+    EncryptListener {
+        name: String::new(),
+        port,
+        servers,
+        strategy: matches.value_of("strategy").unwrap_or("").to_string(),
+        key: matches.value_of("key").unwrap_or(DEFAULT_KEY).to_string(),
+        iv: matches.value_of("iv").unwrap_or(DEFAULT_IV).to_string(),
+        tls: matches.is_present("tls"),
     }
-
-    Ok(())
 }
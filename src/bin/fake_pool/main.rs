@@ -0,0 +1,61 @@
+use anyhow::Result;
+use log::info;
+use mining_proxy::fake_pool::{
+    FakePool, FakePoolConfig, PoolProtocol, ShareRule,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = mining_proxy::util::get_fake_pool_command_matches()?;
+    mining_proxy::util::logger::init_client(1)?;
+
+    let mut config = match matches.value_of("config") {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)?;
+            serde_yaml::from_str::<FakePoolConfig>(&content)?
+        }
+        None => FakePoolConfig::default(),
+    };
+
+    if let Some(interval) = matches.value_of("interval") {
+        config.job_interval_ms = interval.parse().unwrap_or_else(|_| {
+            println!("请正确填写任务间隔 例如: -i 10000");
+            std::process::exit(1);
+        });
+    }
+
+    if let Some(rule) = matches.value_of("rule") {
+        config.share_rule = ShareRule::parse(rule).unwrap_or_else(|e| {
+            println!("{}", e);
+            std::process::exit(1);
+        });
+    }
+
+    if let Some(protocol) = matches.value_of("protocol") {
+        config.protocol =
+            Some(PoolProtocol::parse(protocol).unwrap_or_else(|e| {
+                println!("{}", e);
+                std::process::exit(1);
+            }));
+    }
+
+    if let Some(path) = matches.value_of("record") {
+        config.record_path = Some(path.to_string());
+    }
+
+    let bind = matches.value_of("bind").unwrap_or("127.0.0.1:8888");
+    let config_protocol = config.protocol;
+    let pool = FakePool::start(bind, config).await?;
+    info!("假矿池已启动 {}", pool.addr());
+    if config_protocol == Some(PoolProtocol::Sv2) {
+        info!("SV2 矿池授权公钥 {}", pool.sv2_authority_key());
+    }
+
+    tokio::signal::ctrl_c().await?;
+    info!(
+        "收到请求 {} 条 份额 {} 个",
+        pool.received().len(),
+        pool.shares().len()
+    );
+    Ok(())
+}
@@ -11,9 +11,12 @@ extern crate openssl_probe;
 use actix_web::{dev::ServiceRequest, web, App, Error, HttpServer};
 
 use mining_proxy::{
-    client::{encry::accept_en_tcp, tcp::accept_tcp, tls::accept_tcp_with_tls},
-    state::Worker,
-    util::{config::Settings, logger},
+    client::{
+        encry::accept_en_tcp, http::accept_http, tcp::accept_tcp,
+        tls::accept_tcp_with_tls,
+    },
+    state::{LoginRejects, Worker},
+    util::{capture::CaptureSetting, config::Settings, logger},
     web::{handles::auth::Claims, AppState, OnlineWorker},
 };
 
@@ -95,6 +98,8 @@ async fn async_main(_matches: ArgMatches<'_>) -> Result<()> {
                                     config: config.clone(),
                                     workers: vec![],
                                     online: 0,
+                                    control: None,
+                                    login_rejects: LoginRejects::default(),
                                 };
 
                                 data.lock()
@@ -118,6 +123,11 @@ async fn async_main(_matches: ArgMatches<'_>) -> Result<()> {
         Ok(p) => p.parse().unwrap(),
         Err(_) => 8888,
     };
+    // 界面监听地址 如 :: 或 127.0.0.1
+    let address = mining_proxy::util::bind_address(
+        &std::env::var("MINING_PROXY_WEB_BIND").unwrap_or_default(),
+        port as u32,
+    );
 
     let http_data = data.clone();
     let web_sever = if let Ok(http) = HttpServer::new(move || {
@@ -136,6 +146,10 @@ async fn async_main(_matches: ArgMatches<'_>) -> Result<()> {
                     .service(mining_proxy::web::handles::server::crate_app)
                     .service(mining_proxy::web::handles::server::server_list)
                     .service(mining_proxy::web::handles::server::server)
+                    .service(mining_proxy::web::handles::server::fee_report)
+                    .service(mining_proxy::web::handles::server::capture)
+                    .service(mining_proxy::web::handles::server::server_logs)
+                    .service(mining_proxy::web::handles::server::server_log)
                     .service(mining_proxy::web::handles::server::dashboard),
             )
             .service(actix_web_static_files::ResourceFiles::new(
@@ -144,7 +158,7 @@ async fn async_main(_matches: ArgMatches<'_>) -> Result<()> {
             .service(actix_web_static_files::ResourceFiles::new("", generated))
     })
     .workers(1)
-    .bind(format!("0.0.0.0:{}", port))
+    .bind(address.clone())
     {
         http.run()
     } else {
@@ -157,7 +171,7 @@ async fn async_main(_matches: ArgMatches<'_>) -> Result<()> {
         bail!("web端口 {} 被占用了", port);
     };
 
-    log::info!("界面启动成功地址为: {}", format!("0.0.0.0:{}", port));
+    log::info!("界面启动成功地址为: {}", address);
     web_sever.await;
     Ok(())
 }
@@ -175,13 +189,16 @@ async fn tokio_run(matches: &ArgMatches<'_>) -> Result<()> {
     let config_file_name = matches.value_of("config").unwrap_or("default.yaml");
     let config = Settings::new(config_file_name, true)?;
 
-    logger::init(
+    logger::init_with(
         config.name.as_str(),
         config.log_path.clone(),
         config.log_level,
+        &config.log_format,
+        &config.log_levels,
+        config.log_rotate(),
     )?;
 
-    match config.check() {
+    match config.check().await {
         Ok(_) => {}
         Err(err) => {
             log::error!("config配置错误 {}", err);
@@ -220,17 +237,25 @@ async fn tokio_run(matches: &ArgMatches<'_>) -> Result<()> {
 
     let state =
         std::sync::Arc::new(mining_proxy::state::GlobalState::default());
+    if config.share == 1 || config.listeners.iter().any(|l| l.share == Some(1))
+    {
+        let path = config.fee_audit_path();
+        if let Err(e) = state.audit.open(&path) {
+            log::error!("抽水审计日志 {} 打开失败 {}", path, e);
+        }
+    }
 
     let res = tokio::try_join!(
         accept_tcp(worker_tx.clone(), config.clone(), state.clone()),
         accept_en_tcp(worker_tx.clone(), config.clone(), state.clone()),
+        accept_http(worker_tx.clone(), config.clone(), state.clone()),
         accept_tcp_with_tls(
             worker_tx.clone(),
             config.clone(),
             cert,
             state.clone()
         ),
-        send_to_parent(worker_rx, &config),
+        send_to_parent(worker_rx, &config, state.clone()),
     );
 
     if let Err(err) = res {
@@ -245,31 +270,81 @@ async fn tokio_run(matches: &ArgMatches<'_>) -> Result<()> {
 pub struct SendToParentStruct {
     name: String,
     worker: Worker,
+    #[serde(default)]
+    login_rejects: LoginRejects,
 }
 
 async fn send_to_parent(
     mut worker_rx: UnboundedReceiver<Worker>, config: &Settings,
+    state: mining_proxy::state::State,
 ) -> Result<()> {
     let _runtime = std::time::Instant::now();
 
     loop {
-        if let Ok(mut stream) =
+        if let Ok(stream) =
             tokio::net::TcpStream::connect("127.0.0.1:65500").await
         {
-            //let name = config.name.clone();
+            let (r, mut stream_w) = stream.into_split();
+            let mut r_lines = BufReader::new(r).lines();
+
+            // 先报一次名称 主控端据此登记下发通道
+            let mut login_rejects = state.login_rejects();
+            let hello = SendToParentStruct {
+                name: config.name.clone(),
+                worker: Worker::default(),
+                login_rejects: login_rejects.clone(),
+            };
+            let mut rpc = serde_json::to_vec(&hello)?;
+            rpc.push(b'\n');
+            stream_w.write_all(&rpc).await?;
+
+            // 被拒绝的登录没有矿工状态 定时随空矿工上报
+            let mut report = tokio::time::interval(std::time::Duration::from_secs(10));
+
             loop {
                 select! {
                     Some(w) = worker_rx.recv() => {
                         let send = SendToParentStruct{
                             name:config.name.clone(),
                             worker:w,
+                            login_rejects: state.login_rejects(),
+                        };
+                        let mut rpc = serde_json::to_vec(&send)?;
+                        rpc.push(b'\n');
+                        stream_w.write(&rpc).await.unwrap();
+                    },
+                    _ = report.tick() => {
+                        if state.login_rejects() == login_rejects {
+                            continue;
+                        }
+                        login_rejects = state.login_rejects();
+                        let send = SendToParentStruct{
+                            name:config.name.clone(),
+                            worker:Worker::default(),
+                            login_rejects: login_rejects.clone(),
                         };
                         let mut rpc = serde_json::to_vec(&send)?;
                         rpc.push(b'\n');
-                        stream.write(&rpc).await.unwrap();
+                        if stream_w.write_all(&rpc).await.is_err() {
+                            break;
+                        }
+                    },
+                    res = r_lines.next_line() => {
+                        let line = match res {
+                            Ok(Some(line)) => line,
+                            _ => break,
+                        };
+                        if let Ok(setting) = serde_json::from_str::<CaptureSetting>(&line) {
+                            let path = config.capture_path();
+                            match state.capture.apply(&path, &setting) {
+                                Ok(_) => log::info!("抓包设置 {:?} 文件 {}", setting, path),
+                                Err(e) => log::error!("抓包文件 {} 打开失败 {}", path, e),
+                            }
+                        }
                     },
                 }
             }
+            log::error!("与主控web端的链接断开");
         } else {
             log::error!("无法链接到主控web端");
             tokio::time::sleep(tokio::time::Duration::from_secs(60 * 2)).await;
@@ -293,10 +368,21 @@ async fn recv_from_child(app: AppState) -> Result<()> {
         let inner_app = app.clone();
 
         tokio::spawn(async move {
-            let (r, _) = stream.split();
+            let (r, mut w) = stream.into_split();
             let r_buf = BufReader::new(r);
             let mut r_lines = r_buf.lines();
 
+            // 下发给子进程的设置
+            let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+            tokio::spawn(async move {
+                while let Some(mut line) = rx.recv().await {
+                    line.push('\n');
+                    if w.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
             loop {
                 if let Ok(Some(buf_str)) = r_lines.next_line().await {
                     if let Ok(online_work) =
@@ -308,6 +394,13 @@ async fn recv_from_child(app: AppState) -> Result<()> {
                         if let Some(temp_app) =
                             inner_app.lock().unwrap().get_mut(&online_work.name)
                         {
+                            temp_app.login_rejects = online_work.login_rejects;
+                            // 只是报名称。子进程重启后换成新的链接
+                            if online_work.worker.worker.is_empty() {
+                                temp_app.control = Some(tx.clone());
+                                continue;
+                            }
+
                             let mut is_update = false;
                             for worker in &mut temp_app.workers {
                                 if worker.worker == online_work.worker.worker {
@@ -323,6 +416,8 @@ async fn recv_from_child(app: AppState) -> Result<()> {
                             log::error!("未找到此端口");
                         }
                     }
+                } else {
+                    break;
                 };
             }
         });
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use log::info;
+use mining_proxy::{
+    replay::{export_corpus, group, replay, scrub, ReplayConfig},
+    util::capture::read_records,
+};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = mining_proxy::util::get_replay_command_matches()?;
+    mining_proxy::util::logger::init_client(1)?;
+
+    let mut config = ReplayConfig::default();
+    if let Some(server) = matches.value_of("server") {
+        config.server = server.to_string();
+    }
+
+    if let Some(conn) = matches.value_of("conn") {
+        config.conn = Some(conn.parse().unwrap_or_else(|_| {
+            println!("请正确填写链接序号 例如: --conn 3");
+            std::process::exit(1);
+        }));
+    }
+
+    if let Some(speed) = matches.value_of("speed") {
+        config.speed = speed.parse().unwrap_or_else(|_| {
+            println!("请正确填写倍速 例如: --speed 1");
+            std::process::exit(1);
+        });
+    }
+
+    if let Some(wait) = matches.value_of("wait") {
+        config.wait_ms = wait.parse().unwrap_or_else(|_| {
+            println!("请正确填写等待时间 例如: -w 1000");
+            std::process::exit(1);
+        });
+    }
+
+    let path = matches.value_of("file").unwrap();
+    let records = read_records(path)?;
+    info!(
+        "读取抓包 {} 条 链接 {} 个",
+        records.len(),
+        group(records.clone()).len()
+    );
+
+    if let Some(dir) = matches.value_of("corpus") {
+        // capture_eth.jsonl 的种子为 seed_eth_*
+        let tag = std::path::Path::new(path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("capture")
+            .trim_start_matches("capture_")
+            .to_string();
+        let mut records = records;
+        scrub(&mut records);
+        let count = export_corpus(&records, dir, &tag)?;
+        info!("导出种子 {} 个到 {}", count, dir);
+        return Ok(());
+    }
+
+    let diffs = replay(records, &config).await?;
+    for diff in &diffs {
+        println!("{}", diff);
+    }
+
+    if diffs.is_empty() {
+        info!("回放完成 应答一致");
+    } else {
+        info!("回放完成 不一致 {} 处", diffs.len());
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -8,18 +8,31 @@ use tokio::{
 
 use crate::{
     state::{State, Worker},
-    util::config::Settings,
+    util::{
+        capture::CaptureStream,
+        config::{Settings, LISTENER_ENCRYPT},
+        logger,
+    },
 };
 
 use super::*;
 pub async fn accept_en_tcp(
     worker_sender: UnboundedSender<Worker>, config: Settings, state: State,
 ) -> Result<()> {
-    if config.encrypt_port == 0 {
-        return Ok(());
-    }
+    let listeners = config.listener_settings(LISTENER_ENCRYPT);
+    futures::future::try_join_all(listeners.into_iter().map(
+        |(address, config)| {
+            listen_en_tcp(worker_sender.clone(), address, config, state.clone())
+        },
+    ))
+    .await?;
+    Ok(())
+}
 
-    let address = format!("0.0.0.0:{}", config.encrypt_port);
+async fn listen_en_tcp(
+    worker_sender: UnboundedSender<Worker>, address: String, config: Settings,
+    state: State,
+) -> Result<()> {
     let listener = match TcpListener::bind(address.clone()).await {
         Ok(listener) => listener,
         Err(_) => {
@@ -30,7 +43,7 @@ pub async fn accept_en_tcp(
 
     log::info!("本地TCP加密协议端口{}启动成功!!!", &address);
     loop {
-        let (stream, addr) = listener.accept().await?;
+        let (mut stream, addr) = listener.accept().await?;
 
         let config = config.clone();
         let workers = worker_sender.clone();
@@ -40,15 +53,31 @@ pub async fn accept_en_tcp(
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         // 在这里初始化矿工信息。传入spawn. 然后退出的时候再进行矿工下线通知。
 
-        tokio::spawn(async move {
+        let conn = state.next_conn();
+        tokio::spawn(logger::scope(conn, addr.to_string(), async move {
+            let addr = match client_addr(&mut stream, addr, &config).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    info!("IP: {} 恶意链接: {}", addr, e);
+                    state
+                        .online
+                        .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                    return;
+                }
+            };
+
             // 矿工状态管理
             let mut worker: Worker = Worker::default();
+            worker.ip = Some(addr.ip());
+            // 经过 PROXY 协议头后是矿机的真实地址
+            logger::update(|ctx| ctx.peer = addr.to_string());
             match transfer(
                 &mut worker,
                 workers.clone(),
                 stream,
                 &config,
                 state.clone(),
+                conn,
             )
             .await
             {
@@ -77,72 +106,31 @@ pub async fn accept_en_tcp(
                         .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
                 }
             }
-        });
+        }));
     }
 }
 
 async fn transfer(
     worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
-    tcp_stream: TcpStream, config: &Settings, state: State,
+    tcp_stream: TcpStream, config: &Settings, state: State, conn: u64,
 ) -> Result<()> {
+    let cipher = match (Vec::from_hex(&config.key), Vec::from_hex(&config.iv)) {
+        (Ok(key), Ok(iv)) => Some((key, iv)),
+        _ => None,
+    };
+    let tcp_stream =
+        CaptureStream::new(tcp_stream, state.clone(), worker.ip, cipher);
     let (worker_r, worker_w) = split(tcp_stream);
     let worker_r = BufReader::new(worker_r);
-    let (stream_type, pools) =
-        match crate::client::get_pool_ip_and_type(&config) {
-            Ok(pool) => pool,
-            Err(e) => {
-                bail!("未匹配到矿池 或 均不可链接。请修改后重试");
-            }
-        };
-
-    if config.share == 0 {
-        handle_tcp_pool(
-            worker,
-            worker_queue,
-            worker_r,
-            worker_w,
-            &pools,
-            &config,
-            state,
-            true,
-        )
-        .await
-    } else if config.share == 1 {
-        if config.share_alg == 99 {
-            handle_tcp_random(
-                worker,
-                worker_queue,
-                worker_r,
-                worker_w,
-                &pools,
-                &config,
-                state,
-                true,
-            )
-            .await
-        } else {
-            handle_tcp_pool_timer(
-                worker,
-                worker_queue,
-                worker_r,
-                worker_w,
-                &pools,
-                &config,
-                state,
-                true,
-            )
-            .await
-        }
-    } else {
-        handle_tcp_pool_all(
-            worker,
-            worker_queue,
-            worker_r,
-            worker_w,
-            &config,
-            state,
-            true,
-        )
-        .await
-    }
+    transfer_stream(
+        worker,
+        worker_queue,
+        worker_r,
+        worker_w,
+        config,
+        state,
+        conn,
+        true,
+    )
+    .await
 }
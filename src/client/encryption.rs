@@ -1,25 +1,351 @@
 #![allow(dead_code)]
 #![allow(unused)]
 
-use std::{net::SocketAddr, time::Duration};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::{bail, Result};
+use hex::FromHex;
 use log::{debug, info};
 
-use openssl::symm::{decrypt, encrypt, Cipher};
+use openssl::{
+    aes::AesKey,
+    symm::{encrypt, Cipher},
+};
+use serde::{Deserialize, Serialize};
 
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt},
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader,
+        Lines, ReadHalf, WriteHalf,
+    },
     net::{TcpListener, TcpStream},
     select,
 };
+use tokio_native_tls::TlsStream;
 
 use crate::client::{self_write_socket_byte, write_to_socket_byte};
 
-pub async fn accept_encrypt_tcp(
-    port: i32, server: SocketAddr, key: Vec<u8>, iv: Vec<u8>,
+pub const DEFAULT_KEY: &str =
+    "523B607044E6BF7E46AF75233FDC1278B7AA0FC42D085DEA64AE484AD7FB3664";
+pub const DEFAULT_IV: &str = "275E2015B9E5CA4DDB87B90EBC897F8C";
+
+// 上游断开后 同一个矿机连续重连的最大次数
+const MAX_RECONNECT: u32 = 3;
+// 打印监听与上游状态的间隔
+const HEALTH_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+// 切换上游后需要重放的矿机请求
+const HANDSHAKE_METHODS: [&str; 6] = [
+    "eth_submitLogin",
+    "eth_login",
+    "login",
+    "mining.subscribe",
+    "mining.authorize",
+    "mining.extranonce.subscribe",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    // 按填写顺序使用第一个可用的上游
+    Failover,
+    // 新矿机轮流分配到各个上游
+    RoundRobin,
+}
+
+impl Strategy {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "" | "failover" => Ok(Strategy::Failover),
+            "round-robin" | "roundrobin" => Ok(Strategy::RoundRobin),
+            _ => bail!("上游选择策略错误 {} 可选 failover 或 round-robin", s),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Health {
+    pub connected: AtomicU64,
+    pub failed: AtomicU64,
+    pub fails_in_row: AtomicU32,
+    // 当前通过该上游的矿机数
+    pub online: AtomicI32,
+}
+
+#[derive(Debug)]
+pub struct Upstream {
+    // host:port 每次链接时重新解析
+    pub address: String,
+    pub health: Health,
+}
+
+#[derive(Debug)]
+pub struct Upstreams {
+    pub list: Vec<Upstream>,
+    pub strategy: Strategy,
+    next: AtomicUsize,
+}
+
+impl Upstreams {
+    pub fn new(addresses: Vec<String>, strategy: Strategy) -> Result<Self> {
+        if addresses.is_empty() {
+            bail!("请正确填写服务器地址 例如: -s 8.0.0.0:8888");
+        }
+
+        let mut list = vec![];
+        for address in addresses {
+            if crate::client::outbound::split_host_port(&address).is_none() {
+                bail!("服务器地址格式错误 {}", address);
+            }
+            list.push(Upstream {
+                address,
+                health: Health::default(),
+            });
+        }
+
+        Ok(Self {
+            list,
+            strategy,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    // 本次链接尝试上游的顺序。健康的上游优先
+    fn order(&self) -> Vec<usize> {
+        let len = self.list.len();
+        let start = match self.strategy {
+            Strategy::Failover => 0,
+            Strategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+        };
+
+        let (mut order, down): (Vec<usize>, Vec<usize>) =
+            (0..len).map(|i| (start + i) % len).partition(|i| {
+                self.list[*i].health.fails_in_row.load(Ordering::Relaxed) == 0
+            });
+        order.extend(down);
+        order
+    }
+
+    /// 按策略链接上游。每次都重新解析域名 全部失败时返回错误
+    pub async fn connect(&self) -> Result<(usize, TcpStream)> {
+        for idx in self.order() {
+            let upstream = &self.list[idx];
+            match connect_upstream(&upstream.address).await {
+                Ok(stream) => {
+                    let health = &upstream.health;
+                    health.connected.fetch_add(1, Ordering::Relaxed);
+                    if health.fails_in_row.swap(0, Ordering::Relaxed) > 0 {
+                        info!("上游 {} 已恢复", upstream.address);
+                    }
+                    return Ok((idx, stream));
+                }
+                Err(e) => self.mark_failed(idx, &e.to_string()),
+            }
+        }
+
+        bail!("所有上游均不可链接")
+    }
+
+    pub fn mark_failed(&self, idx: usize, reason: &str) {
+        let upstream = &self.list[idx];
+        upstream.health.failed.fetch_add(1, Ordering::Relaxed);
+        let fails =
+            upstream.health.fails_in_row.fetch_add(1, Ordering::Relaxed) + 1;
+        log::warn!(
+            "上游 {} 不可用 {} 连续失败 {} 次",
+            upstream.address,
+            reason,
+            fails
+        );
+    }
+
+    pub fn report_health(&self) {
+        for upstream in &self.list {
+            let health = &upstream.health;
+            let fails = health.fails_in_row.load(Ordering::Relaxed);
+            info!(
+                "上游 {} 状态: {} 当前矿机: {} 成功链接: {} 失败: {}",
+                upstream.address,
+                if fails == 0 { "正常" } else { "异常" },
+                health.online.load(Ordering::Relaxed),
+                health.connected.load(Ordering::Relaxed),
+                health.failed.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+fn default_key() -> String { DEFAULT_KEY.into() }
+
+fn default_iv() -> String { DEFAULT_IV.into() }
+
+// 加密客户端配置文件 每个监听端口对应一组上游与秘钥
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EncryptConfig {
+    pub listeners: Vec<EncryptListener>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncryptListener {
+    #[serde(default)]
+    pub name: String,
+    pub port: i32,
+    pub servers: Vec<String>,
+    #[serde(default)]
+    pub strategy: String,
+    #[serde(default = "default_key")]
+    pub key: String,
+    #[serde(default = "default_iv")]
+    pub iv: String,
+    // 与上游之间再套一层 TLS
+    #[serde(default)]
+    pub tls: bool,
+}
+
+impl EncryptConfig {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let config: EncryptConfig = serde_yaml::from_str(&content)?;
+        if config.listeners.is_empty() {
+            bail!("配置文件 {} 中没有监听端口", path);
+        }
+
+        let mut ports = HashSet::new();
+        for listener in &config.listeners {
+            if !ports.insert(listener.port) {
+                bail!("监听端口 {} 重复", listener.port);
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl EncryptListener {
+    pub fn build(&self) -> Result<EncryptProxy> {
+        if self.port <= 0 || self.port > 65535 {
+            bail!("请正确填写本地监听端口 {}", self.port);
+        }
+
+        let key = match Vec::from_hex(&self.key) {
+            Ok(key) => key,
+            Err(_) => bail!("端口 {} 的 key 不是正确的十六进制", self.port),
+        };
+        if AesKey::new_encrypt(&key).is_err() || key.len() != 32 {
+            bail!("请填写正确的 key 端口 {}", self.port);
+        }
+        let iv = match Vec::from_hex(&self.iv) {
+            Ok(iv) if iv.len() == 16 => iv,
+            _ => bail!("请填写正确的 iv 端口 {}", self.port),
+        };
+
+        let name = if self.name.is_empty() {
+            self.port.to_string()
+        } else {
+            self.name.clone()
+        };
+
+        Ok(EncryptProxy {
+            name,
+            port: self.port,
+            upstreams: Upstreams::new(
+                self.servers.clone(),
+                Strategy::parse(&self.strategy)?,
+            )?,
+            key,
+            iv,
+            tls: self.tls,
+            online: AtomicI32::new(0),
+            accepted: AtomicU64::new(0),
+        })
+    }
+}
+
+// 一个本地监听端口的运行状态
+#[derive(Debug)]
+pub struct EncryptProxy {
+    pub name: String,
+    pub port: i32,
+    pub upstreams: Upstreams,
+    key: Vec<u8>,
+    iv: Vec<u8>,
+    tls: bool,
+    pub online: AtomicI32,
+    pub accepted: AtomicU64,
+}
+
+impl EncryptProxy {
+    pub fn report(&self) {
+        info!(
+            "监听 {} 端口 {} 当前矿机: {} 累计链接: {}",
+            self.name,
+            self.port,
+            self.online.load(Ordering::Relaxed),
+            self.accepted.load(Ordering::Relaxed)
+        );
+        self.upstreams.report_health();
+    }
+}
+
+/// 在一个进程内运行全部监听端口 并定时打印各端口的链接数
+pub async fn run_encrypt_proxies(
+    proxies: Vec<Arc<EncryptProxy>>,
 ) -> Result<()> {
-    let address = format!("0.0.0.0:{}", port);
+    let report = proxies.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEALTH_REPORT_INTERVAL);
+        loop {
+            interval.tick().await;
+            for proxy in &report {
+                proxy.report();
+            }
+        }
+    });
+
+    futures::future::try_join_all(
+        proxies.into_iter().map(|proxy| accept_encrypt_tcp(proxy)),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn connect_upstream(address: &str) -> Result<TcpStream> {
+    let addrs = tokio::net::lookup_host(address).await?.collect::<Vec<_>>();
+    if addrs.is_empty() {
+        bail!("{} 域名解析失败", address);
+    }
+
+    match crate::client::connect_happy_eyeballs(addrs, Duration::new(5, 0))
+        .await
+    {
+        Some((stream, _)) => Ok(stream),
+        None => bail!("{} 远程地址不通！", address),
+    }
+}
+
+async fn connect_tls(
+    address: &str, stream: TcpStream,
+) -> Result<TlsStream<TcpStream>> {
+    let cx = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .min_protocol_version(Some(native_tls::Protocol::Tlsv11))
+        .build()?;
+    let cx = tokio_native_tls::TlsConnector::from(cx);
+    let domain = crate::client::outbound::split_host_port(address)
+        .map(|(host, _)| host)
+        .unwrap_or_default();
+    Ok(cx.connect(&domain, stream).await?)
+}
+
+pub async fn accept_encrypt_tcp(proxy: Arc<EncryptProxy>) -> Result<()> {
+    let address = format!("0.0.0.0:{}", proxy.port);
     let listener = TcpListener::bind(address.clone()).await?;
     //info!("😄 Accepting EncryptData Tcp On: {}", &address);
 
@@ -28,39 +354,141 @@ pub async fn accept_encrypt_tcp(
     loop {
         let (stream, addr) = listener.accept().await?;
         info!("😄 Accepting EncryptData Tcp connection from {}", addr);
-        let iv = iv.clone();
-        let key = key.clone();
+        let proxy = proxy.clone();
+        proxy.accepted.fetch_add(1, Ordering::Relaxed);
+        proxy.online.fetch_add(1, Ordering::Relaxed);
 
-        tokio::spawn(async move { transfer(stream, server, key, iv).await });
+        tokio::spawn(async move {
+            let res = transfer(stream, &proxy).await;
+            proxy.online.fetch_sub(1, Ordering::Relaxed);
+            res
+        });
     }
 }
 
-async fn transfer(
-    stream: TcpStream, addr: SocketAddr, key: Vec<u8>, iv: Vec<u8>,
-) -> Result<()> {
+// 矿机的登录类请求 返回请求 id
+fn handshake_id(line: &str) -> Option<String> {
+    let rpc = serde_json::from_str::<serde_json::Value>(line).ok()?;
+    let method = rpc.get("method")?.as_str()?;
+    if HANDSHAKE_METHODS.contains(&method) {
+        Some(rpc.get("id").cloned().unwrap_or_default().to_string())
+    } else {
+        None
+    }
+}
+
+// 矿池对请求的回复 返回请求 id
+fn response_id(buf: &[u8]) -> Option<String> {
+    let rpc = serde_json::from_slice::<serde_json::Value>(buf).ok()?;
+    if rpc.get("method").is_some() {
+        return None;
+    }
+    Some(rpc.get("id")?.to_string())
+}
+
+async fn transfer(stream: TcpStream, proxy: &EncryptProxy) -> Result<()> {
+    let upstreams = &proxy.upstreams;
     let (worker_r, mut worker_w) = tokio::io::split(stream);
     let worker_r = tokio::io::BufReader::new(worker_r);
     let mut worker_r = worker_r.lines();
 
-    let std_stream = match std::net::TcpStream::connect_timeout(
-        &addr,
-        Duration::new(5, 0),
-    ) {
-        Ok(stream) => stream,
-        Err(_) => {
-            bail!("{} 远程地址不通！", addr);
+    // 矿机的登录请求。切换上游后重放 并丢弃上游对重放请求的回复
+    let mut handshake: Vec<String> = vec![];
+    let mut reconnect = 0;
+
+    loop {
+        let (idx, pool_stream) = upstreams.connect().await?;
+        let upstream = &upstreams.list[idx];
+        upstream.health.online.fetch_add(1, Ordering::Relaxed);
+
+        let mut replayed = HashSet::new();
+        if !handshake.is_empty() {
+            info!("矿机重新链接到上游 {}", upstream.address);
+            for line in &handshake {
+                replayed.extend(handshake_id(line));
+            }
+        }
+
+        let res = if proxy.tls {
+            match connect_tls(&upstream.address, pool_stream).await {
+                Ok(stream) => {
+                    relay(
+                        &mut worker_r,
+                        &mut worker_w,
+                        stream,
+                        &mut handshake,
+                        replayed,
+                        &proxy.key,
+                        &proxy.iv,
+                    )
+                    .await
+                }
+                Err(e) => Ok(Some((e.to_string(), false))),
+            }
+        } else {
+            relay(
+                &mut worker_r,
+                &mut worker_w,
+                pool_stream,
+                &mut handshake,
+                replayed,
+                &proxy.key,
+                &proxy.iv,
+            )
+            .await
+        };
+        upstream.health.online.fetch_sub(1, Ordering::Relaxed);
+
+        match res? {
+            // 上游已转发过数据 说明链接正常 重置重连次数
+            Some((reason, true)) => {
+                upstreams.mark_failed(idx, &reason);
+                reconnect = 1;
+            }
+            Some((reason, false)) => {
+                upstreams.mark_failed(idx, &reason);
+                reconnect += 1;
+            }
+            None => return Ok(()),
+        }
+
+        if reconnect > MAX_RECONNECT {
+            worker_w.shutdown().await;
+            bail!("上游多次断开 断开矿机");
         }
-    };
+    }
+}
 
-    std_stream.set_nonblocking(true).unwrap();
-    let pool_stream = TcpStream::from_std(std_stream)?;
+fn encrypt_line(buf: &str, key: &[u8], iv: &[u8]) -> Vec<u8> {
+    let cipher = Cipher::aes_256_cbc();
+    let ciphertext = encrypt(cipher, key, Some(iv), buf.as_bytes()).unwrap();
+    base64::encode(&ciphertext[..]).into_bytes()
+}
+
+// 在矿机与一个上游之间转发。矿机断开返回错误 矿机主动结束返回 None。
+// 上游断开返回 Some((原因, 是否转发过上游数据)) 由调用方重连
+async fn relay<S>(
+    worker_r: &mut Lines<BufReader<ReadHalf<TcpStream>>>,
+    worker_w: &mut WriteHalf<TcpStream>, pool_stream: S,
+    handshake: &mut Vec<String>, mut replayed: HashSet<String>, key: &[u8],
+    iv: &[u8],
+) -> Result<Option<(String, bool)>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     let (pool_r, mut pool_w) = tokio::io::split(pool_stream);
     let pool_r = tokio::io::BufReader::new(pool_r);
     let mut pool_r = pool_r.split(crate::SPLIT);
-    let mut client_timeout_sec = 1;
+    let mut forwarded = false;
 
-    let key = key.clone();
-    let iv = iv.clone();
+    for line in handshake.iter() {
+        let data = encrypt_line(line, key, iv);
+        if let Err(e) =
+            self_write_socket_byte(&mut pool_w, data, &"加密".to_string()).await
+        {
+            return Ok(Some((e.to_string(), false)));
+        }
+    }
 
     loop {
         select! {
@@ -83,19 +511,14 @@ async fn transfer(
                     if buf.is_empty() {
                         continue;
                     }
-                    let cipher = Cipher::aes_256_cbc();
-                    //let data = b"Some Crypto String";
-                    let ciphertext = encrypt(
-                        cipher,
-                        &key,
-                        Some(&iv),
-                        buf.as_bytes()).unwrap();
-
-                    let base64 = base64::encode(&ciphertext[..]);
+                    if handshake_id(buf).is_some() {
+                        handshake.push(buf.to_string());
+                    }
 
-                    match self_write_socket_byte(&mut pool_w,base64.as_bytes().to_vec(),&"加密".to_string()).await{
+                    let data = encrypt_line(buf, key, iv);
+                    match self_write_socket_byte(&mut pool_w,data,&"加密".to_string()).await{
                         Ok(_) => {},
-                        Err(e) => {info!("{}",e);bail!("矿机下线了 {}",e)}
+                        Err(e) => {info!("{}",e);return Ok(Some((e.to_string(),forwarded)))}
                     }
                 }
             },
@@ -105,14 +528,10 @@ async fn transfer(
                     Ok(res) => {
                         match res {
                             Some(buf) => buf,
-                            None => {
-                                worker_w.shutdown().await;
-
-                                bail!("矿机下线了")
-                            }
+                            None => return Ok(Some(("上游断开了".to_string(),forwarded))),
                         }
                     },
-                    Err(e) => {bail!("矿机下线了: {}",e)},
+                    Err(e) => return Ok(Some((e.to_string(),forwarded))),
                 };
 
 
@@ -125,31 +544,27 @@ async fn transfer(
                         continue;
                     }
 
-                    let buf = match base64::decode(&buf[..]) {
-                        Ok(buf) => buf,
+                    let buffer = match crate::client::decrypt_frame(buf, key, iv) {
+                        Ok(s) => s,
                         Err(e) => {
-                            log::error!("{}",e);
+                            info!("解密失败 {}",e);
                             pool_w.shutdown().await;
-                            return Ok(());
+                            worker_w.shutdown().await;
+                            return Ok(None);
                         },
                     };
 
-                    let cipher = Cipher::aes_256_cbc();
-                    // 解密
-                    let buffer = match decrypt(
-                        cipher,
-                        &key,
-                        Some(&iv),
-                        &buf[..]) {
-                            Ok(s) => s,
-                            Err(e) => {
-                                info!("解密失败 {}",e);
-                                pool_w.shutdown().await;
-                                return Ok(());
-                            },
-                        };
-
-                    match write_to_socket_byte(&mut worker_w,buffer,&"解密".to_string()).await{
+                    // 重放请求的回复矿机已经收到过了
+                    if !replayed.is_empty() {
+                        if let Some(id) = response_id(&buffer) {
+                            if replayed.remove(&id) {
+                                continue;
+                            }
+                        }
+                    }
+
+                    forwarded = true;
+                    match write_to_socket_byte(worker_w,buffer,&"解密".to_string()).await{
                         Ok(_) => {},
                         Err(e) => {info!("{}",e);bail!("矿机下线了 {}",e)}
                     }
@@ -158,3 +573,110 @@ async fn transfer(
         }
     }
 }
+
+#[test]
+fn test_decrypt_frame() {
+    let key = Vec::from_hex(DEFAULT_KEY).unwrap();
+    let iv = Vec::from_hex(DEFAULT_IV).unwrap();
+    let line = r#"{"id":1,"method":"eth_getWork","params":[]}"#;
+    let frame = encrypt_line(line, &key, &iv);
+    assert_eq!(
+        crate::client::decrypt_frame(&frame, &key, &iv).unwrap(),
+        line.as_bytes()
+    );
+    assert!(crate::client::decrypt_frame(b"", &key, &iv).is_err());
+    assert!(crate::client::decrypt_frame(b"not base64", &key, &iv).is_err());
+    assert!(crate::client::decrypt_frame(b"AAAA", &key, &iv).is_err());
+    assert!(crate::client::decrypt_frame(&frame, &key[..16], &iv).is_err());
+}
+
+#[test]
+fn test_handshake_id() {
+    assert_eq!(
+        handshake_id(r#"{"id":1,"method":"eth_submitLogin","params":[]}"#),
+        Some("1".into())
+    );
+    assert_eq!(
+        handshake_id(r#"{"id":"a","method":"mining.authorize"}"#),
+        Some("\"a\"".into())
+    );
+    assert_eq!(handshake_id(r#"{"id":4,"method":"eth_submitWork"}"#), None);
+    assert_eq!(response_id(br#"{"id":1,"result":true}"#), Some("1".into()));
+    assert_eq!(
+        response_id(br#"{"id":null,"method":"mining.notify"}"#),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_upstream_failover() {
+    let dead = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let dead_addr = dead.local_addr().unwrap().to_string();
+    drop(dead);
+    let live = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let live_addr = live.local_addr().unwrap().to_string();
+    let live2 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let live2_addr = live2.local_addr().unwrap().to_string();
+
+    let upstreams = Upstreams::new(
+        vec![dead_addr.clone(), live_addr.clone()],
+        Strategy::Failover,
+    )
+    .unwrap();
+    assert_eq!(upstreams.connect().await.unwrap().0, 1);
+    assert_eq!(upstreams.list[0].health.failed.load(Ordering::Relaxed), 1);
+    // 失败的上游排到后面 不再每次都先尝试
+    assert_eq!(upstreams.connect().await.unwrap().0, 1);
+    assert_eq!(upstreams.list[0].health.failed.load(Ordering::Relaxed), 1);
+
+    let upstreams = Upstreams::new(
+        vec![live_addr, dead_addr, live2_addr],
+        Strategy::RoundRobin,
+    )
+    .unwrap();
+    let mut used = vec![];
+    for _ in 0..4 {
+        used.push(upstreams.connect().await.unwrap().0);
+    }
+    assert_eq!(used, vec![0, 2, 2, 0]);
+
+    assert!(Upstreams::new(vec![], Strategy::Failover).is_err());
+    assert!(Strategy::parse("random").is_err());
+}
+
+#[test]
+fn test_encrypt_config() {
+    let config: EncryptConfig = serde_yaml::from_str(
+        r#"
+listeners:
+  - name: site-a
+    port: 18888
+    servers: ["a.example.com:9999", "b.example.com:9999"]
+    strategy: round-robin
+    tls: true
+  - port: 18889
+    servers: ["127.0.0.1:9999"]
+    key: "00112233445566778899AABBCCDDEEFF00112233445566778899AABBCCDDEEFF"
+    iv: "00112233445566778899AABBCCDDEEFF"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.listeners.len(), 2);
+    let proxy = config.listeners[0].build().unwrap();
+    assert_eq!(proxy.name, "site-a");
+    assert!(proxy.tls);
+    assert_eq!(proxy.upstreams.strategy, Strategy::RoundRobin);
+    assert_eq!(proxy.key, Vec::from_hex(DEFAULT_KEY).unwrap());
+
+    let proxy = config.listeners[1].build().unwrap();
+    assert_eq!(proxy.name, "18889");
+    assert_eq!(proxy.iv[15], 0xff);
+
+    let mut listener = config.listeners[1].clone();
+    listener.iv = "0011".into();
+    assert!(listener.build().is_err());
+    listener.iv = DEFAULT_IV.into();
+    listener.servers = vec![];
+    assert!(listener.build().is_err());
+}
@@ -127,8 +127,8 @@ pub async fn handle_stream<R, W, R1, W1>(
     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
     mut worker_w: WriteHalf<W>,
     pool_r: tokio::io::BufReader<tokio::io::ReadHalf<R1>>,
-    mut pool_w: WriteHalf<W1>, config: &Settings, mut state: State,
-    is_encrypted: bool,
+    mut pool_w: WriteHalf<W1>, pool_addr: String, config: &Settings,
+    mut state: State, is_encrypted: bool,
 ) -> Result<()>
 where
     R: AsyncRead,
@@ -139,7 +139,12 @@ where
     //let start = std::time::Instant::now();
     let mut worker_name: String = String::new();
     let (_, pool) = get_pool_ip_and_type_from_vec(&config.share_address)?;
-    let (stream, _) = match crate::client::get_pool_stream(&pool) {
+    let (outbound, proxy_addr) = match crate::client::get_pool_stream(
+        &pool,
+        &config.share_proxy,
+    )
+    .await
+    {
         Some((stream, addr)) => (stream, addr),
         None => {
             //log::error!("所有TCP矿池均不可链接。请修改后重试");
@@ -147,7 +152,7 @@ where
         }
     };
 
-    let outbound = TcpStream::from_std(stream)?;
+    let proxy_addr = proxy_addr.to_string();
     let (proxy_r, mut proxy_w) = tokio::io::split(outbound);
     let proxy_r = tokio::io::BufReader::new(proxy_r);
     let mut proxy_lines = proxy_r.lines();
@@ -242,6 +247,9 @@ where
     }
 
     let mut is_submithashrate = false;
+    // 已提交待矿池返回的份额 (难度, 任务ID)。主矿池与抽水矿池分开记录
+    let mut submits: VecDeque<(u64, String)> = VecDeque::new();
+    let mut proxy_submits: VecDeque<(u64, String)> = VecDeque::new();
 
     let sleep = time::sleep(tokio::time::Duration::from_millis(1000 * 60));
     tokio::pin!(sleep);
@@ -348,8 +356,9 @@ where
                         let res = match client_json_rpc.method.as_str() {
                             "eth_submitLogin" => {
                                 eth_server_result.id = rpc_id;
+                                check_eth_login(&mut worker_w,&mut client_json_rpc,&config,&state,is_encrypted).await?;
                                 write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
-                                let res = match eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name).await {
+                                let res = match eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name,&config).await {
                                     Ok(a) => Ok(a),
                                     Err(e) => {
                                         //info!("错误 {} ",e);
@@ -361,6 +370,7 @@ where
                             "eth_submitWork" => {
                                 eth_server_result.id = rpc_id;
                                 write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
+                                push_submit(worker,&mut client_json_rpc,&send_mine_jobs,&[],&mut submits,&mut proxy_submits);
                                 eth_submit_work_develop(worker,&mut pool_w,&mut proxy_w,&mut worker_w,&mut client_json_rpc,&mut worker_name,&mut send_mine_jobs,&mut send_develop_jobs,&config,&mut state).await
                             },
                             "eth_submitHashrate" => {
@@ -391,12 +401,14 @@ where
                             },
                             "eth_submitLogin" => {
                                 eth_server_result.id = rpc_id;
+                                check_eth_login(&mut worker_w,&mut client_json_rpc,&config,&state,is_encrypted).await?;
                                 write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
-                                eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name).await
+                                eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name,&config).await
                             },
                             "eth_submitWork" => {
                                 eth_server_result.id = rpc_id;
                                 write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
+                                push_submit(worker,&mut client_json_rpc,&send_mine_jobs,&[],&mut submits,&mut proxy_submits);
                                 match eth_submit_work_develop(worker,&mut pool_w,&mut proxy_w,&mut worker_w,&mut client_json_rpc,&mut worker_name,&mut send_mine_jobs,&mut send_develop_jobs,&config,&mut state).await {
                                     Ok(_) => Ok(()),
                                     Err(e) => {log::error!("err: {:?}",e);bail!(e)},
@@ -469,6 +481,9 @@ where
                         worker_name,
                         buf
                     );
+                    if let Some(diff) = eth_job_work(&buf) {
+                        worker.set_diff(diff);
+                    }
                     if let Ok(mut result_rpc) = serde_json::from_str::<ServerId1>(&buf){
                         if result_rpc.id == CLIENT_LOGIN {
                             worker.logind();
@@ -493,17 +508,19 @@ where
                             //info!("矿工请求任务");
                         } else if result_rpc.id == SUBSCRIBE {
                             //info!("矿工请求任务");
-                        } else if result_rpc.id == worker.share_index && result_rpc.result {
-                            //info!("份额被接受.");
-                            worker.share_accept();
-                        } else if result_rpc.result {
-                            //log::warn!("份额被接受，但是索引乱了.要上报给开发者 {:?}",result_rpc);
-                            worker.share_accept();
-                        } else if result_rpc.id == worker.share_index {
-                            worker.share_reject();
-                            //log::warn!("拒绝原因 {}",buf);
-                            //crate::protocol::rpc::eth::handle_error_for_worker(&worker_name, &buf.as_bytes().to_vec());
-                            result_rpc.result = true;
+                        } else if result_rpc.result || result_rpc.id == worker.share_index {
+                            if let Some((diff,job_id)) = submits.pop_front() {
+                                audit_share(&state,worker,config,&job_id,&pool_addr,false,result_rpc.result,diff);
+                            }
+                            if result_rpc.result {
+                                //info!("份额被接受.");
+                                worker.share_accept();
+                            } else {
+                                worker.share_reject();
+                                //log::warn!("拒绝原因 {}",buf);
+                                //crate::protocol::rpc::eth::handle_error_for_worker(&worker_name, &buf.as_bytes().to_vec());
+                                result_rpc.result = true;
+                            }
                         }
 
                         result_rpc.id = rpc_id ;
@@ -664,6 +681,10 @@ where
                         continue;
                     }
 
+                    // 抽水矿池的难度单独记录 抽水份额按它计算工作量
+                    if let Some(diff) = eth_job_work(&buf) {
+                        worker.set_fee_diff(diff);
+                    }
                     if let Ok(result_rpc) = serde_json::from_str::<ServerId1>(&buf){
                         #[cfg(debug_assertions)]
                         debug!("收到抽水矿机返回 {:?}", result_rpc);
@@ -671,10 +692,16 @@ where
                         } else if result_rpc.id == CLIENT_SUBHASHRATE {
                         } else if result_rpc.id == CLIENT_GETWORK {
                         } else if result_rpc.result {
+                            if let Some((diff,job_id)) = proxy_submits.pop_front() {
+                                audit_share(&state,worker,config,&job_id,&proxy_addr,true,true,diff);
+                            }
                             worker.fee_share_accept();
                         } else if result_rpc.id == 999{
                         } else {
-                            worker.fee_share_accept();
+                            if let Some((diff,job_id)) = proxy_submits.pop_front() {
+                                audit_share(&state,worker,config,&job_id,&proxy_addr,true,false,diff);
+                            }
+                            worker.fee_share_reject();
                         }
                     } else if let Ok(job_rpc) =  serde_json::from_str::<ServerJobsWithHeight>(&buf) {
                         #[cfg(debug_assertions)]
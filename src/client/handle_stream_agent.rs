@@ -33,8 +33,8 @@ pub async fn handle_stream<R, W, R1, W1>(
     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
     mut worker_w: WriteHalf<W>,
     pool_r: tokio::io::BufReader<tokio::io::ReadHalf<R1>>,
-    mut pool_w: WriteHalf<W1>, config: &Settings, mut state: State,
-    is_encrypted: bool,
+    mut pool_w: WriteHalf<W1>, pool_addr: String, config: &Settings,
+    mut state: State, is_encrypted: bool,
 ) -> Result<()>
 where
     R: AsyncRead,
@@ -46,16 +46,19 @@ where
 
     let mut worker_name: String = String::new();
 
-    let (stream, _) =
-        match crate::client::get_pool_stream(&config.share_address) {
-            Some((stream, addr)) => (stream, addr),
-            None => {
-                log::error!("所有TCP矿池均不可链接。请修改后重试");
-                bail!("所有TCP矿池均不可链接。请修改后重试");
-            }
-        };
+    let (outbound, proxy_addr) = match crate::client::get_pool_stream(
+        &config.share_address,
+        &config.share_proxy,
+    )
+    .await
+    {
+        Some((stream, addr)) => (stream, addr),
+        None => {
+            log::error!("所有TCP矿池均不可链接。请修改后重试");
+            bail!("所有TCP矿池均不可链接。请修改后重试");
+        }
+    };
 
-    let outbound = TcpStream::from_std(stream)?;
     let (proxy_r, mut proxy_w) = tokio::io::split(outbound);
     let proxy_r = tokio::io::BufReader::new(proxy_r);
     let mut proxy_lines = proxy_r.lines();
@@ -77,7 +80,7 @@ where
         }
     }
 
-    let stream = match pools::get_develop_pool_stream().await {
+    let outbound = match pools::get_develop_pool_stream().await {
         Ok(s) => s,
         Err(e) => {
             debug!("无法链接到矿池{}", e);
@@ -85,8 +88,6 @@ where
         }
     };
 
-    let outbound = TcpStream::from_std(stream)?;
-
     let (develop_r, mut develop_w) = tokio::io::split(outbound);
     let develop_r = tokio::io::BufReader::new(develop_r);
     let mut develop_lines = develop_r.lines();
@@ -108,15 +109,18 @@ where
     }
 
     // 代理分润
-    let (stream, _) =
-        match crate::client::get_pool_stream(&config.share_address) {
-            Some((stream, addr)) => (stream, addr),
-            None => {
-                bail!("所有TCP矿池均不可链接。请修改后重试");
-            }
-        };
-
-    let outbound = TcpStream::from_std(stream)?;
+    let (outbound, _) = match crate::client::get_pool_stream(
+        &config.share_address,
+        &config.share_proxy,
+    )
+    .await
+    {
+        Some((stream, addr)) => (stream, addr),
+        None => {
+            bail!("所有TCP矿池均不可链接。请修改后重试");
+        }
+    };
+
     let (agent_r, mut agent_w) = tokio::io::split(outbound);
     let agent_r = tokio::io::BufReader::new(agent_r);
     let mut agent_lines = agent_r.lines();
@@ -151,6 +155,10 @@ where
     let mut send_develop_jobs: Vec<String> = vec![];
     let mut send_agent_jobs: Vec<String> = vec![];
     let mut send_normal_jobs: Vec<String> = vec![];
+    // 已提交待矿池返回的份额 (难度, 任务ID)。主矿池与抽水矿池分开记录
+    let mut submits: VecDeque<(u64, String)> = VecDeque::new();
+    let mut proxy_submits: VecDeque<(u64, String)> = VecDeque::new();
+    let proxy_addr = proxy_addr.to_string();
     // 包装为封包格式。
     // let mut worker_lines = worker_r.lines();
     let mut pool_lines = pool_r.lines();
@@ -330,7 +338,8 @@ where
                                             }
                                         }
 
-                                        let res = match eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name).await {
+                                        check_eth_login(&mut worker_w,&mut client_json_rpc,&config,&state,is_encrypted).await?;
+                                        let res = match eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name,&config).await {
                                             Ok(a) => Ok(a),
                                             Err(e) => {
                                                 //info!("错误 {} ",e);
@@ -339,7 +348,8 @@ where
                                         };
                                         res
                                     }  else {
-                                        let res = match eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name).await {
+                                        check_eth_login(&mut worker_w,&mut client_json_rpc,&config,&state,is_encrypted).await?;
+                                        let res = match eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name,&config).await {
                                             Ok(a) => Ok(a),
                                             Err(e) => {
                                                 //info!("错误 {} ",e);
@@ -352,6 +362,7 @@ where
                             },
                             "eth_submitWork" => {
                                 //eth_submit_work_develop
+                                push_submit(worker,&mut client_json_rpc,&send_mine_jobs,&[&send_develop_jobs,&send_agent_jobs],&mut submits,&mut proxy_submits);
                                 match eth_submit_work_agent(worker,&mut pool_w,&mut proxy_w,&mut develop_w,&mut agent_w,&mut worker_w,&mut client_json_rpc,&mut worker_name,&mut send_mine_jobs,&mut send_develop_jobs,&mut send_agent_jobs,&config,&agent_name,&mut state).await {
                                     Ok(_) => Ok(()),
                                     Err(e) => {log::error!("err: {:?}",e);bail!(e)},
@@ -383,9 +394,11 @@ where
                                 eth_get_work(&mut pool_w,&mut client_json_rpc,&mut worker_name).await
                             },
                             "eth_submitLogin" => {
-                                eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name).await
+                                check_eth_login(&mut worker_w,&mut client_json_rpc,&config,&state,is_encrypted).await?;
+                                eth_submit_login(worker,&mut pool_w,&mut client_json_rpc,&mut worker_name,&config).await
                             },
                             "eth_submitWork" => {
+                                push_submit(worker,&mut client_json_rpc,&send_mine_jobs,&[&send_develop_jobs,&send_agent_jobs],&mut submits,&mut proxy_submits);
                                 match eth_submit_work_agent(worker,&mut pool_w,&mut proxy_w,&mut develop_w,&mut agent_w,&mut worker_w,&mut client_json_rpc,&mut worker_name,&mut send_mine_jobs,&mut send_develop_jobs,&mut send_agent_jobs,&config,&agent_name,&mut state).await {
                                     Ok(_) => Ok(()),
                                     Err(e) => {log::error!("err: {:?}",e);bail!(e)},
@@ -463,16 +476,18 @@ where
                             //info!("矿工请求任务");
                         } else if result_rpc.id == SUBSCRIBE {
                             //info!("矿工请求任务");
-                        } else if result_rpc.id == worker.share_index && result_rpc.result {
-                            //info!("份额被接受.");
-                            worker.share_accept();
-                        } else if result_rpc.result {
-                            //log::warn!("份额被接受，但是索引乱了.要上报给开发者 {:?}",result_rpc);
-                            worker.share_accept();
                         } else {
-                            worker.share_reject();
-                            //log::warn!("拒绝原因 {:?}",result_rpc);
-                            //crate::protocol::rpc::eth::handle_error_for_worker(&worker_name, &buf.as_bytes().to_vec());
+                            if let Some((diff,job_id)) = submits.pop_front() {
+                                audit_share(&state,worker,config,&job_id,&pool_addr,false,result_rpc.result,diff);
+                            }
+                            if result_rpc.result {
+                                //info!("份额被接受.");
+                                worker.share_accept();
+                            } else {
+                                worker.share_reject();
+                                //log::warn!("拒绝原因 {:?}",result_rpc);
+                                //crate::protocol::rpc::eth::handle_error_for_worker(&worker_name, &buf.as_bytes().to_vec());
+                            }
                         }
 
                         result_rpc.id = rpc_id ;
@@ -652,11 +667,17 @@ where
                         } else if result_rpc.id == CLIENT_SUBHASHRATE {
                         } else if result_rpc.id == CLIENT_GETWORK {
                         } else if result_rpc.result {
+                            if let Some((diff,job_id)) = proxy_submits.pop_front() {
+                                audit_share(&state,worker,config,&job_id,&proxy_addr,true,true,diff);
+                            }
                             state
                             .proxy_accept
                             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
                         } else if result_rpc.id == 999{
                         } else {
+                            if let Some((diff,job_id)) = proxy_submits.pop_front() {
+                                audit_share(&state,worker,config,&job_id,&proxy_addr,true,false,diff);
+                            }
                             state
                             .proxy_reject
                             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
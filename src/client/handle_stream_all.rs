@@ -41,7 +41,7 @@ use crate::{
         CLIENT_GETWORK, CLIENT_LOGIN, CLIENT_SUBHASHRATE, CLIENT_SUBMITWORK,
         PROTOCOL, SUBSCRIBE,
     },
-    state::Worker,
+    state::{JobCacheSession, Worker},
     util::{config::Settings, get_eth_wallet, is_fee_random},
 };
 
@@ -97,6 +97,7 @@ where
                 split.get(1).unwrap().to_string(),
                 wallet.clone(),
             );
+            apply_fee_rule(worker, config);
             let temp_full_wallet =
                 config.share_wallet.clone() + "." + split[1].clone();
             // 抽取全部替换钱包
@@ -112,6 +113,7 @@ where
                 rpc.get_worker_name(),
                 wallet.clone(),
             );
+            apply_fee_rule(worker, config);
             *worker_name = temp_worker.clone();
             Ok(temp_worker)
         }
@@ -138,6 +140,7 @@ where
                 split.get(1).unwrap().to_string(),
                 wallet.clone(),
             );
+            apply_fee_rule(worker, config);
             let temp_full_wallet =
                 config.share_wallet.clone() + "." + split[1].clone();
             // 抽取全部替换钱包
@@ -151,6 +154,7 @@ where
                 rpc.get_worker_name(),
                 wallet.clone(),
             );
+            apply_fee_rule(worker, config);
             *worker_name = temp_worker;
             // 抽取全部替换钱包
             rpc.set_wallet(&config.share_wallet);
@@ -326,7 +330,7 @@ where
 async fn develop_pool_login(
     hostname: String,
 ) -> Result<(Lines<BufReader<ReadHalf<TcpStream>>>, WriteHalf<TcpStream>)> {
-    let stream = match pools::get_develop_pool_stream().await {
+    let outbound = match pools::get_develop_pool_stream().await {
         Ok(s) => s,
         Err(e) => {
             debug!("无法链接到矿池{}", e);
@@ -334,8 +338,6 @@ async fn develop_pool_login(
         }
     };
 
-    let outbound = TcpStream::from_std(stream)?;
-
     let (develop_r, mut develop_w) = tokio::io::split(outbound);
     let develop_r = tokio::io::BufReader::new(develop_r);
     let mut develop_lines = develop_r.lines();
@@ -357,16 +359,19 @@ async fn proxy_pool_login(
     config: &Settings, hostname: String,
 ) -> Result<(Lines<BufReader<ReadHalf<TcpStream>>>, WriteHalf<TcpStream>)> {
     //TODO 这里要兼容SSL矿池
-    let (stream, _) =
-        match crate::client::get_pool_stream(&config.share_address) {
-            Some((stream, addr)) => (stream, addr),
-            None => {
-                log::error!("所有TCP矿池均不可链接。请修改后重试");
-                bail!("所有TCP矿池均不可链接。请修改后重试");
-            }
-        };
+    let (outbound, _) = match crate::client::get_pool_stream(
+        &config.share_address,
+        &config.share_proxy,
+    )
+    .await
+    {
+        Some((stream, addr)) => (stream, addr),
+        None => {
+            log::error!("所有TCP矿池均不可链接。请修改后重试");
+            bail!("所有TCP矿池均不可链接。请修改后重试");
+        }
+    };
 
-    let outbound = TcpStream::from_std(stream)?;
     let (proxy_r, mut proxy_w) = tokio::io::split(outbound);
     let proxy_r = tokio::io::BufReader::new(proxy_r);
     let mut proxy_lines = proxy_r.lines();
@@ -402,15 +407,18 @@ pub async fn pool_with_tcp_reconnect(
         }
     };
     // if stream_type == crate::client::TCP {
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+    let (stream, _) = match crate::client::get_pool_stream(
+        &pools,
+        &config.pool_proxy,
+    )
+    .await
+    {
         Some((stream, addr)) => (stream, addr),
         None => {
             bail!("所有TCP矿池均不可链接。请修改后重试");
         }
     };
 
-    let stream = TcpStream::from_std(outbound)?;
-
     let (pool_r, pool_w) = tokio::io::split(stream);
     let pool_r = tokio::io::BufReader::new(pool_r);
     let mut pool_lines = pool_r.lines();
@@ -444,15 +452,18 @@ pub async fn pool_with_ssl_reconnect(
             bail!("未匹配到矿池 或 均不可链接。请修改后重试");
         }
     };
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+    let (stream, _) = match crate::client::get_pool_stream(
+        &pools,
+        &config.pool_proxy,
+    )
+    .await
+    {
         Some((stream, addr)) => (stream, addr),
         None => {
             bail!("所有TCP矿池均不可链接。请修改后重试");
         }
     };
 
-    let stream = TcpStream::from_std(outbound)?;
-
     let (pool_r, pool_w) = tokio::io::split(stream);
     let pool_r = tokio::io::BufReader::new(pool_r);
     let mut pool_lines = pool_r.lines();
@@ -464,8 +475,8 @@ pub async fn handle_stream<R, W>(
     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
     mut worker_w: WriteHalf<W>,
     pool_r: tokio::io::BufReader<tokio::io::ReadHalf<TcpStream>>,
-    mut pool_w: WriteHalf<TcpStream>, config: &Settings, mut state: State,
-    is_encrypted: bool,
+    mut pool_w: WriteHalf<TcpStream>, pool_addr: String, config: &Settings,
+    mut state: State, conn: u64, is_encrypted: bool,
 ) -> Result<()>
 where
     R: AsyncRead,
@@ -476,6 +487,12 @@ where
     let mut all_walllet_name = String::new();
     let mut protocol = PROTOCOL::KNOWN;
     let mut first = true;
+    // 是否已给矿机下发过任务
+    let mut job_sent = false;
+    let job_session = JobCacheSession::new(state.clone(), conn);
+    // 已提交待矿池返回的份额 (难度, 任务ID)。全部份额均为抽水
+    let mut submits: std::collections::VecDeque<(u64, String)> =
+        std::collections::VecDeque::new();
 
     let mut worker_name: String = String::new();
     let mut eth_server_result = EthServerRoot {
@@ -620,6 +637,7 @@ where
                             let res = match json_rpc.get_method().as_str() {
                                 "eth_submitLogin" => {
                                     eth_server_result.id = rpc_id;
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
                                     new_eth_submit_login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
                                     Ok(())
@@ -627,6 +645,7 @@ where
                                 "eth_submitWork" => {
                                     eth_server_result.id = rpc_id;
                                     worker.share_index_add();
+                                    submits.push_back((worker.diff,json_rpc.get_job_id().unwrap_or_default()));
                                     new_eth_submit_work(worker,&mut pool_w,&mut worker_w,&mut json_rpc,&mut worker_name,&config,&mut state).await?;
                                     write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
                                     Ok(())
@@ -660,11 +679,13 @@ where
 
                             let res = match json_rpc.get_method().as_str() {
                                 "mining.subscribe" => {
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
                                     all_walllet_name = login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     Ok(())
                                 },
                                 "mining.submit" => {
                                     worker.share_index_add();
+                                    submits.push_back((worker.diff,json_rpc.get_job_id().unwrap_or_default()));
                                     json_rpc.set_wallet(&all_walllet_name);
                                     write_to_socket_byte(&mut pool_w, json_rpc.to_vec()?, &worker_name).await?;
                                     Ok(())
@@ -688,6 +709,7 @@ where
                                     Ok(())
                                 },
                                 "mining.authorize" => {
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
                                     all_walllet_name = login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     Ok(())
                                 },
@@ -695,6 +717,7 @@ where
                                     json_rpc.set_id(CLIENT_SUBMITWORK);
                                     json_rpc.set_wallet(&all_walllet_name);
                                     worker.share_index_add();
+                                    submits.push_back((worker.diff,json_rpc.get_job_id().unwrap_or_default()));
                                     write_to_socket_byte(&mut pool_w, json_rpc.to_vec()?, &worker_name).await?;
                                     Ok(())
                                 },
@@ -731,6 +754,17 @@ where
                         continue;
                     }
 
+                    if let Some(diff) = eth_job_work(buf) {
+                        worker.set_diff(diff);
+                    }
+
+                    if protocol == PROTOCOL::ETH {
+                        if let Some(job) = job_for_cache(buf) {
+                            state.jobs.set(&job_cache_key(&protocol,&pool_addr),job_session.id,job);
+                            job_sent = true;
+                        }
+                    }
+
                     if protocol == PROTOCOL::ETH {
                         if let Ok(mut job_rpc) = serde_json::from_str::<EthServerRootObject>(&buf) {
                             if job_rpc.id == CLIENT_GETWORK{
@@ -742,23 +776,24 @@ where
                             write_rpc(is_encrypted,&mut worker_w,&job_rpc,&worker_name,config.key.clone(),config.iv.clone()).await?;
                         } else if let Ok(mut result_rpc) = serde_json::from_str::<EthServer>(&buf) {
                             if result_rpc.id == CLIENT_LOGIN {
-
                                 worker.logind();
-
+                                if !job_sent {
+                                    job_sent = write_cached_job(is_encrypted,&mut worker_w,&state,&job_cache_key(&protocol,&pool_addr),&worker_name,config.key.clone(),config.iv.clone()).await?;
+                                }
                             } else if result_rpc.id == CLIENT_SUBHASHRATE {
                                 //info!("{} 算力提交成功",worker_name);
                             } else if result_rpc.id == CLIENT_GETWORK {
                                 //info!("{} 获取任务成功",worker_name);
                             } else if result_rpc.id == SUBSCRIBE{
-                            } else if result_rpc.id == CLIENT_SUBMITWORK && result_rpc.result {
-
-                                worker.share_accept();
-
-
                             } else if result_rpc.id == CLIENT_SUBMITWORK {
-
-                                worker.share_reject();
-
+                                if let Some((diff,job_id)) = submits.pop_front() {
+                                    audit_share(&state,worker,config,&job_id,&pool_addr,true,result_rpc.result,diff);
+                                }
+                                if result_rpc.result {
+                                    worker.share_accept();
+                                } else {
+                                    worker.share_reject();
+                                }
                             }
                         }
                     } else if protocol == PROTOCOL::STRATUM {
@@ -771,6 +806,9 @@ where
                         } else if let Ok(mut result_rpc) = serde_json::from_str::<StraumResult>(&buf) {
 
                             if let Some(res) = result_rpc.result.get(0) {
+                                if let Some((diff,job_id)) = submits.pop_front() {
+                                    audit_share(&state,worker,config,&job_id,&pool_addr,true,*res,diff);
+                                }
                                 if *res == true {
 
                                     worker.share_accept();
@@ -798,6 +836,9 @@ where
 
                             if result_rpc.id == CLIENT_SUBMITWORK {
                                 result_rpc.id = rpc_id;
+                                if let Some((diff,job_id)) = submits.pop_front() {
+                                    audit_share(&state,worker,config,&job_id,&pool_addr,true,result_rpc.result,diff);
+                                }
                                 if result_rpc.result == true {
 
                                         worker.share_accept();
@@ -812,9 +853,7 @@ where
                             } else if result_rpc.id == CLIENT_LOGIN {
                                 continue;
                             } else {
-
-                                    worker.logind();
-
+                                worker.logind();
                             }
 
                             continue;
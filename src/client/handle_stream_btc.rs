@@ -0,0 +1,342 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use crate::{
+    client::relay::{split_wallet, MinerAction, PoolAction, RelayCodec},
+    protocol::{
+        bitcoin::{
+            notify_hashes, parse_subscribe_result, request, set_extranonce,
+            BtcServerMessage, V1Request, V1Response,
+        },
+        CLIENT_CONFIGURE, CLIENT_LOGIN, PROTOCOL, SUBSCRIBE,
+    },
+};
+
+/// 比特币(SHA-256d) Stratum V1。抽水时间段内切换到抽水矿池，
+/// 通过 mining.set_extranonce 通知矿机新的 extranonce1
+#[derive(Default)]
+pub struct BtcCodec {
+    // 矿机发送过 mining.extranonce.subscribe 才能在切换矿池时更新 extranonce
+    extranonce_subscribed: bool,
+    configure: Option<Vec<Value>>,
+    // 代理自己发出的请求。应答不转发给矿机
+    waiting: HashSet<String>,
+}
+
+impl RelayCodec for BtcCodec {
+    type Request = V1Request;
+
+    fn protocol(&self) -> PROTOCOL { PROTOCOL::BITCOIN }
+
+    fn check_first(&self, method: &str) -> Result<()> {
+        match method {
+            "mining.subscribe"
+            | "mining.configure"
+            | "mining.authorize"
+            | "mining.extranonce.subscribe" => Ok(()),
+            _ => bail!("不是比特币 Stratum 协议 {}", method),
+        }
+    }
+
+    fn request(&mut self, req: &mut V1Request) -> Result<MinerAction> {
+        match req.method.as_str() {
+            "mining.extranonce.subscribe" => {
+                self.extranonce_subscribed = true;
+                let res = V1Response {
+                    id: req.id.clone(),
+                    result: Value::Bool(true),
+                    error: Value::Null,
+                };
+                Ok(MinerAction::Reply(serde_json::to_value(res)?))
+            }
+            "mining.configure" => {
+                self.configure = Some(req.params.clone());
+                Ok(MinerAction::Forward)
+            }
+            "mining.authorize" => match req.get_wallet() {
+                Some(wallet) => {
+                    let (wallet, name) = split_wallet(&wallet);
+                    Ok(MinerAction::Login(wallet, name))
+                }
+                None => bail!("请求登录出错。可能收到暴力攻击"),
+            },
+            "mining.submit" => Ok(MinerAction::Submit),
+            _ => Ok(MinerAction::Forward),
+        }
+    }
+
+    fn login(
+        &mut self, req: &mut V1Request, wallet: &str, is_fee: bool,
+    ) -> Option<Value> {
+        if is_fee {
+            // 抽水矿池已用抽水钱包登录
+            let res = V1Response {
+                id: req.id.clone(),
+                result: Value::Bool(true),
+                error: Value::Null,
+            };
+            return serde_json::to_value(res).ok();
+        }
+        req.set_wallet(wallet);
+        None
+    }
+
+    fn submit(&mut self, req: &mut V1Request, wallet: &str) -> String {
+        req.set_wallet(wallet);
+        req.param_str(1).unwrap_or_default()
+    }
+
+    fn pool_message(
+        &mut self, line: &str, _login_id: Option<&str>, worker_name: &str,
+    ) -> Result<PoolAction> {
+        let res = match BtcServerMessage::parse(line.as_bytes()) {
+            Some(BtcServerMessage::Notify(notify)) => {
+                if notify.method == "mining.set_extranonce"
+                    && !self.extranonce_subscribed
+                {
+                    return Ok(PoolAction::Drop);
+                }
+                let diff = notify_hashes(&notify);
+                return Ok(PoolAction::Forward(line.to_string(), diff));
+            }
+            Some(BtcServerMessage::Response(res)) => res,
+            None => {
+                log::error!("致命错误。未找到的协议{:?}", line);
+                return Ok(PoolAction::Forward(line.to_string(), None));
+            }
+        };
+
+        let id = res.id.to_string();
+        if !self.waiting.remove(&id) {
+            return Ok(PoolAction::Response(
+                id,
+                res.is_ok(),
+                line.to_string(),
+                None,
+            ));
+        }
+
+        if id == SUBSCRIBE.to_string() {
+            match parse_subscribe_result(&res.result) {
+                Some((extranonce1, extranonce2_size)) => {
+                    let notify = set_extranonce(&extranonce1, extranonce2_size);
+                    let line = serde_json::to_string(&notify)?;
+                    return Ok(PoolAction::Forward(line, None));
+                }
+                None => bail!("矿池：{} 订阅失败 {:?}", worker_name, res),
+            }
+        } else if id == CLIENT_LOGIN.to_string() && !res.is_ok() {
+            log::warn!(
+                "矿工：{} 切换矿池后登录失败 {:?}",
+                worker_name,
+                res.error
+            );
+        }
+        Ok(PoolAction::Drop)
+    }
+
+    fn pool_login(&mut self, wallet: &str) -> Vec<V1Request> {
+        self.waiting.clear();
+        let mut reqs = vec![];
+        if let Some(params) = &self.configure {
+            reqs.push(request(
+                CLIENT_CONFIGURE,
+                "mining.configure",
+                params.clone(),
+            ));
+        }
+        reqs.push(request(
+            SUBSCRIBE,
+            "mining.subscribe",
+            vec![Value::String(
+                "mining_proxy/".to_string() + clap::crate_version!(),
+            )],
+        ));
+        reqs.push(request(
+            CLIENT_LOGIN,
+            "mining.authorize",
+            vec![Value::String(wallet.into()), Value::String("x".into())],
+        ));
+        for req in reqs.iter() {
+            self.waiting.insert(req.id.to_string());
+        }
+        reqs
+    }
+
+    fn can_switch(&self, worker_name: &str) -> bool {
+        if !self.extranonce_subscribed {
+            // 矿机无法更新 extranonce1 切换后提交的份额都会被拒绝
+            log::info!(
+                "矿工：{} 未订阅 mining.set_extranonce 本次跳过抽水",
+                worker_name
+            );
+        }
+        self.extranonce_subscribed
+    }
+}
+
+#[tokio::test]
+async fn test_btc_stratum_unified_wallet() {
+    use crate::{
+        client::relay::spawn_relay,
+        fake_pool::{
+            read_json, FakePool, FakePoolConfig, PoolProtocol, ShareRule,
+        },
+        util::config::Settings,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    let pool = FakePool::start(
+        "127.0.0.1:0",
+        FakePoolConfig {
+            job_interval_ms: 0,
+            share_rule: ShareRule::RejectEvery(2),
+            difficulty: 1024.0,
+            protocol: Some(PoolProtocol::Bitcoin),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut config = Settings::default();
+    config.coin = "BTC".into();
+    config.share = 2;
+    config.share_wallet = "bc1share".into();
+    config.pool_address = vec![format!("tcp://{}", pool.addr())];
+    let state = std::sync::Arc::new(crate::state::GlobalState::new());
+    let (mut lines, mut miner_w, relay) =
+        spawn_relay(BtcCodec::default(), config, state).await;
+
+    miner_w
+        .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[\"bmminer/2.0.0\"]}\n{\"id\":2,\"method\":\"mining.authorize\",\"params\":[\"bc1q.rig1\",\"x\"]}\n")
+        .await
+        .unwrap();
+
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 1);
+    assert_eq!(res["result"][1], pool.session(0).as_str());
+    assert_eq!(res["result"][2], 4);
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 2);
+    assert_eq!(res["result"], true);
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["method"], "mining.set_difficulty");
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["method"], "mining.notify");
+    let job_id = pool.current_job().job_id;
+    assert_eq!(res["params"][0], job_id.as_str());
+
+    for (id, nonce) in [(3, "00000001"), (4, "00000002")] {
+        let submit = format!("{{\"id\":{},\"method\":\"mining.submit\",\"params\":[\"bc1q.rig1\",\"{}\",\"{}\",\"61000000\",\"deadbeef\"]}}\n", id, job_id, nonce);
+        miner_w.write_all(submit.as_bytes()).await.unwrap();
+    }
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 3);
+    assert_eq!(res["result"], true);
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 4);
+    assert_eq!(res["error"][0], 23);
+
+    drop(miner_w);
+    drop(lines);
+    let (worker, _) = relay.await.unwrap();
+    // 统一钱包 矿池收到的登录与份额都是收款钱包.矿工名
+    assert_eq!(pool.logins(), vec![("bc1share".into(), "rig1".into())]);
+    let shares = pool.shares();
+    assert_eq!(shares.len(), 2);
+    assert!(shares
+        .iter()
+        .all(|s| s.wallet == "bc1share" && s.worker == "rig1"));
+    assert_eq!(worker.worker, "bc1q.rig1");
+    assert_eq!(worker.protocol, PROTOCOL::BITCOIN);
+    assert_eq!(worker.share_index, 2);
+    assert_eq!(worker.accept_index, 1);
+    assert_eq!(worker.invalid_index, 1);
+    // 份额按矿池下发的难度计入工作量
+    assert_eq!(worker.diff, 1024 << 32);
+    assert_eq!(worker.accept_work, 1024 << 32);
+    assert!(worker.hash > 0);
+}
+
+#[tokio::test]
+async fn test_btc_fee_switch() {
+    use crate::{
+        client::relay::spawn_relay,
+        fake_pool::{read_json, FakePool, FakePoolConfig, PoolProtocol},
+        util::config::Settings,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    let pool_config = FakePoolConfig {
+        job_interval_ms: 0,
+        protocol: Some(PoolProtocol::Bitcoin),
+        ..Default::default()
+    };
+    let main = FakePool::start("127.0.0.1:0", pool_config.clone())
+        .await
+        .unwrap();
+    let fee = FakePool::start("127.0.0.1:0", pool_config).await.unwrap();
+    let fee_job = fee.next_job();
+
+    let mut config = Settings::default();
+    config.coin = "BTC".into();
+    config.share = 1;
+    // 抽水时间 3599 秒 一秒内就会切换到抽水矿池
+    config.share_rate = 0.9999;
+    config.share_wallet = "bc1fee".into();
+    config.share_name = "proxy".into();
+    config.pool_address = vec![format!("tcp://{}", main.addr())];
+    config.share_address = vec![format!("tcp://{}", fee.addr())];
+    let state = std::sync::Arc::new(crate::state::GlobalState::new());
+    let (mut lines, mut miner_w, relay) =
+        spawn_relay(BtcCodec::default(), config, state).await;
+
+    miner_w
+        .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[\"bmminer/2.0.0\"]}\n{\"id\":2,\"method\":\"mining.extranonce.subscribe\",\"params\":[]}\n{\"id\":3,\"method\":\"mining.authorize\",\"params\":[\"bc1q.rig1\",\"x\"]}\n")
+        .await
+        .unwrap();
+
+    // 订阅 extranonce 由代理直接应答
+    let mut ids = vec![];
+    for _ in 0..3 {
+        ids.push(read_json(&mut lines).await.unwrap()["id"].clone());
+    }
+    ids.sort_by_key(|id| id.as_u64());
+    assert_eq!(ids, vec![1, 2, 3]);
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["method"],
+        "mining.set_difficulty"
+    );
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["method"],
+        "mining.notify"
+    );
+
+    // 切换到抽水矿池 代理的订阅与登录应答不转发 改为下发新的 extranonce1
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["method"], "mining.set_extranonce");
+    assert_eq!(res["params"][0], fee.session(0).as_str());
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["method"],
+        "mining.set_difficulty"
+    );
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["params"][0], fee_job.job_id.as_str());
+
+    let submit = format!("{{\"id\":4,\"method\":\"mining.submit\",\"params\":[\"bc1q.rig1\",\"{}\",\"00000001\",\"61000000\",\"deadbeef\"]}}\n", fee_job.job_id);
+    miner_w.write_all(submit.as_bytes()).await.unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 4);
+    assert_eq!(res["result"], true);
+
+    drop(miner_w);
+    drop(lines);
+    let (worker, _) = relay.await.unwrap();
+    assert_eq!(fee.logins(), vec![("bc1fee".into(), "proxy".into())]);
+    assert_eq!(fee.shares()[0].wallet, "bc1fee");
+    assert_eq!(worker.fee_share_index, 1);
+    assert_eq!(worker.fee_accept_index, 1);
+}
@@ -0,0 +1,206 @@
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use crate::{
+    client::relay::{split_wallet, MinerAction, PoolAction, RelayCodec},
+    protocol::{
+        bitcoin::{request, V1Request},
+        cfx::{is_result_ok, CfxJob, CfxServerMessage},
+        CLIENT_LOGIN, PROTOCOL,
+    },
+};
+
+/// Conflux(Octopus) 协议。mining.subscribe 即登录，
+/// 抽水时间段内切换到抽水矿池并改写份额中的 钱包.矿工名
+pub struct CfxCodec {
+    password: Value,
+    // 代理切换矿池时发出的登录请求
+    proxy_login: bool,
+}
+
+impl Default for CfxCodec {
+    fn default() -> Self {
+        CfxCodec {
+            password: Value::String("x".into()),
+            proxy_login: false,
+        }
+    }
+}
+
+impl RelayCodec for CfxCodec {
+    type Request = V1Request;
+
+    fn protocol(&self) -> PROTOCOL { PROTOCOL::CFX }
+
+    fn check_first(&self, method: &str) -> Result<()> {
+        if method != "mining.subscribe" {
+            bail!("不是 Conflux 矿机协议 {}", method);
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, req: &mut V1Request) -> Result<MinerAction> {
+        match req.method.as_str() {
+            "mining.subscribe" => {
+                let wallet = match req.get_wallet() {
+                    Some(wallet) => wallet,
+                    None => bail!("请求登录出错。可能收到暴力攻击"),
+                };
+                if let Some(pass) = req.params.get(1) {
+                    self.password = pass.clone();
+                }
+                let (wallet, name) = split_wallet(&wallet);
+                Ok(MinerAction::Login(wallet, name))
+            }
+            "mining.submit" => Ok(MinerAction::Submit),
+            _ => {
+                log::warn!("Not found CFX method {:?}", req);
+                Ok(MinerAction::Forward)
+            }
+        }
+    }
+
+    fn login(
+        &mut self, req: &mut V1Request, wallet: &str, _is_fee: bool,
+    ) -> Option<Value> {
+        req.set_wallet(wallet);
+        None
+    }
+
+    fn submit(&mut self, req: &mut V1Request, wallet: &str) -> String {
+        req.set_wallet(wallet);
+        req.param_str(1).unwrap_or_default()
+    }
+
+    fn pool_message(
+        &mut self, line: &str, _login_id: Option<&str>, worker_name: &str,
+    ) -> Result<PoolAction> {
+        let res = match CfxServerMessage::parse(line.as_bytes()) {
+            Some(CfxServerMessage::Notify(notify)) => {
+                let mut diff = None;
+                if notify.method == "mining.notify" {
+                    match CfxJob::from_params(&notify.params) {
+                        Some(job) => diff = job.difficulty(),
+                        None => log::warn!(
+                            "矿工：{} 无法解析任务 {}",
+                            worker_name,
+                            line
+                        ),
+                    }
+                }
+                return Ok(PoolAction::Forward(line.to_string(), diff));
+            }
+            Some(CfxServerMessage::Response(res)) => res,
+            None => return Ok(PoolAction::Forward(line.to_string(), None)),
+        };
+
+        let id = res.id.to_string();
+        if self.proxy_login && id == CLIENT_LOGIN.to_string() {
+            self.proxy_login = false;
+            if !is_result_ok(&res) {
+                bail!(
+                    "矿工：{} 切换矿池后登录失败 {:?}",
+                    worker_name,
+                    res.error
+                );
+            }
+            return Ok(PoolAction::Drop);
+        }
+        Ok(PoolAction::Response(
+            id,
+            is_result_ok(&res),
+            line.to_string(),
+            None,
+        ))
+    }
+
+    fn pool_login(&mut self, wallet: &str) -> Vec<V1Request> {
+        self.proxy_login = true;
+        vec![request(
+            CLIENT_LOGIN,
+            "mining.subscribe",
+            vec![Value::String(wallet.into()), self.password.clone()],
+        )]
+    }
+}
+
+#[tokio::test]
+async fn test_cfx_fee_switch() {
+    use crate::{
+        client::relay::spawn_relay,
+        fake_pool::{read_json, FakePool, FakePoolConfig, PoolProtocol},
+        util::config::Settings,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    let pool_config = FakePoolConfig {
+        job_interval_ms: 0,
+        protocol: Some(PoolProtocol::Cfx),
+        ..Default::default()
+    };
+    let main = FakePool::start("127.0.0.1:0", pool_config.clone())
+        .await
+        .unwrap();
+    let fee = FakePool::start("127.0.0.1:0", pool_config).await.unwrap();
+    // 抽水矿池的任务与主矿池区分开
+    let fee_job = fee.next_job();
+
+    let mut config = Settings::default();
+    config.coin = "CFX".into();
+    config.share = 1;
+    // 抽水时间 3599 秒 一秒内就会切换到抽水矿池
+    config.share_rate = 0.9999;
+    config.share_wallet = "cfx:fee".into();
+    config.share_name = "proxy".into();
+    config.pool_address = vec![format!("tcp://{}", main.addr())];
+    config.share_address = vec![format!("tcp://{}", fee.addr())];
+    let state = std::sync::Arc::new(crate::state::GlobalState::new());
+    let (mut lines, mut miner_w, relay) =
+        spawn_relay(CfxCodec::default(), config, state).await;
+
+    miner_w
+        .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[\"cfx:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7p.rig1\",\"x\"]}\n")
+        .await
+        .unwrap();
+
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 1);
+    assert_eq!(res["result"], true);
+    let job = read_json(&mut lines).await.unwrap();
+    assert_eq!(job["params"][0], main.current_job().job_id);
+
+    // 切换到抽水矿池后 代理消费登录应答 只转发新任务
+    let job = read_json(&mut lines).await.unwrap();
+    assert_eq!(job["method"], "mining.notify");
+    assert_eq!(job["params"][0], fee_job.job_id.as_str());
+
+    let submit = format!("{{\"id\":2,\"method\":\"mining.submit\",\"params\":[\"cfx:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7p.rig1\",\"{}\",\"0xdeadbeef\",\"0x8c6e\"]}}\n", fee_job.job_id);
+    miner_w.write_all(submit.as_bytes()).await.unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 2);
+
+    drop(miner_w);
+    drop(lines);
+    let (worker, _) = relay.await.unwrap();
+    assert_eq!(
+        main.logins(),
+        vec![(
+            "cfx:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7p".into(),
+            "rig1".into()
+        )]
+    );
+    assert_eq!(fee.logins(), vec![("cfx:fee".into(), "proxy".into())]);
+    let shares = fee.shares();
+    assert_eq!(shares.len(), 1);
+    assert_eq!(shares[0].wallet, "cfx:fee");
+    assert_eq!(
+        worker.worker,
+        "cfx:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7p.rig1"
+    );
+    assert_eq!(worker.protocol, PROTOCOL::CFX);
+    assert_eq!(worker.share_index, 1);
+    assert_eq!(worker.accept_index, 1);
+    assert_eq!(worker.fee_share_index, 1);
+    assert_eq!(worker.fee_accept_index, 1);
+    assert!(worker.hash > 0);
+}
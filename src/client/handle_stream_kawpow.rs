@@ -0,0 +1,320 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use crate::{
+    client::relay::{split_wallet, MinerAction, PoolAction, RelayCodec},
+    protocol::{
+        bitcoin::{request, BtcServerMessage, V1Request, V1Response},
+        kawpow::{notify_hashes, parse_subscribe_result, set_extranonce},
+        CLIENT_LOGIN, PROTOCOL, SUBSCRIBE,
+    },
+};
+
+/// KawPow(Ravencoin) Stratum。抽水时间段内切换到抽水矿池，
+/// 通过 mining.set_extranonce 通知矿机新的 extranonce1
+pub struct KawpowCodec {
+    password: Value,
+    // 矿机发送过 mining.extranonce.subscribe 才能在切换矿池时更新 extranonce
+    extranonce_subscribed: bool,
+    // 代理自己发出的请求。应答不转发给矿机
+    waiting: HashSet<String>,
+}
+
+impl Default for KawpowCodec {
+    fn default() -> Self {
+        KawpowCodec {
+            password: Value::String("x".into()),
+            extranonce_subscribed: false,
+            waiting: HashSet::new(),
+        }
+    }
+}
+
+impl RelayCodec for KawpowCodec {
+    type Request = V1Request;
+
+    fn protocol(&self) -> PROTOCOL { PROTOCOL::KAWPOW }
+
+    fn check_first(&self, method: &str) -> Result<()> {
+        match method {
+            "mining.subscribe"
+            | "mining.authorize"
+            | "mining.extranonce.subscribe" => Ok(()),
+            _ => bail!("不是 KawPow Stratum 协议 {}", method),
+        }
+    }
+
+    fn request(&mut self, req: &mut V1Request) -> Result<MinerAction> {
+        match req.method.as_str() {
+            "mining.extranonce.subscribe" => {
+                self.extranonce_subscribed = true;
+                let res = V1Response {
+                    id: req.id.clone(),
+                    result: Value::Bool(true),
+                    error: Value::Null,
+                };
+                Ok(MinerAction::Reply(serde_json::to_value(res)?))
+            }
+            "mining.authorize" => {
+                let wallet = match req.get_wallet() {
+                    Some(wallet) => wallet,
+                    None => bail!("请求登录出错。可能收到暴力攻击"),
+                };
+                if let Some(pass) = req.params.get(1) {
+                    self.password = pass.clone();
+                }
+                let (wallet, name) = split_wallet(&wallet);
+                Ok(MinerAction::Login(wallet, name))
+            }
+            "mining.submit" => Ok(MinerAction::Submit),
+            _ => Ok(MinerAction::Forward),
+        }
+    }
+
+    fn login(
+        &mut self, req: &mut V1Request, wallet: &str, is_fee: bool,
+    ) -> Option<Value> {
+        if is_fee {
+            // 抽水矿池已用抽水钱包登录
+            let res = V1Response {
+                id: req.id.clone(),
+                result: Value::Bool(true),
+                error: Value::Null,
+            };
+            return serde_json::to_value(res).ok();
+        }
+        req.set_wallet(wallet);
+        None
+    }
+
+    fn submit(&mut self, req: &mut V1Request, wallet: &str) -> String {
+        req.set_wallet(wallet);
+        req.param_str(1).unwrap_or_default()
+    }
+
+    fn pool_message(
+        &mut self, line: &str, _login_id: Option<&str>, worker_name: &str,
+    ) -> Result<PoolAction> {
+        let res = match BtcServerMessage::parse(line.as_bytes()) {
+            Some(BtcServerMessage::Notify(notify)) => {
+                if notify.method == "mining.set_extranonce"
+                    && !self.extranonce_subscribed
+                {
+                    return Ok(PoolAction::Drop);
+                }
+                let diff = notify_hashes(&notify);
+                return Ok(PoolAction::Forward(line.to_string(), diff));
+            }
+            Some(BtcServerMessage::Response(res)) => res,
+            None => return Ok(PoolAction::Forward(line.to_string(), None)),
+        };
+
+        let id = res.id.to_string();
+        if !self.waiting.remove(&id) {
+            return Ok(PoolAction::Response(
+                id,
+                res.is_ok(),
+                line.to_string(),
+                None,
+            ));
+        }
+
+        if id == SUBSCRIBE.to_string() {
+            match parse_subscribe_result(&res.result) {
+                Some(extranonce1) => {
+                    let notify = set_extranonce(&extranonce1);
+                    let line = serde_json::to_string(&notify)?;
+                    return Ok(PoolAction::Forward(line, None));
+                }
+                None => bail!("矿池：{} 订阅失败 {:?}", worker_name, res),
+            }
+        } else if id == CLIENT_LOGIN.to_string() && !res.is_ok() {
+            bail!("矿工：{} 切换矿池后登录失败 {:?}", worker_name, res.error);
+        }
+        Ok(PoolAction::Drop)
+    }
+
+    fn pool_login(&mut self, wallet: &str) -> Vec<V1Request> {
+        self.waiting.clear();
+        let reqs = vec![
+            request(
+                SUBSCRIBE,
+                "mining.subscribe",
+                vec![Value::String(
+                    "mining_proxy/".to_string() + clap::crate_version!(),
+                )],
+            ),
+            request(
+                CLIENT_LOGIN,
+                "mining.authorize",
+                vec![Value::String(wallet.into()), self.password.clone()],
+            ),
+        ];
+        for req in reqs.iter() {
+            self.waiting.insert(req.id.to_string());
+        }
+        reqs
+    }
+
+    fn can_switch(&self, worker_name: &str) -> bool {
+        if !self.extranonce_subscribed {
+            // 矿机无法更新 extranonce1 切换后提交的份额都会被拒绝
+            log::info!(
+                "矿工：{} 未订阅 mining.set_extranonce 本次跳过抽水",
+                worker_name
+            );
+        }
+        self.extranonce_subscribed
+    }
+}
+
+#[tokio::test]
+async fn test_kawpow_fee_switch() {
+    use crate::{
+        client::relay::spawn_relay,
+        fake_pool::{read_json, FakePool, FakePoolConfig, PoolProtocol},
+        util::config::Settings,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    let pool_config = FakePoolConfig {
+        job_interval_ms: 0,
+        protocol: Some(PoolProtocol::Kawpow),
+        ..Default::default()
+    };
+    let main = FakePool::start("127.0.0.1:0", pool_config.clone())
+        .await
+        .unwrap();
+    let fee = FakePool::start("127.0.0.1:0", pool_config).await.unwrap();
+    // 抽水矿池的任务与主矿池区分开
+    let fee_job = fee.next_job();
+
+    let mut config = Settings::default();
+    config.coin = "RVN".into();
+    config.share = 1;
+    // 抽水时间 3599 秒 一秒内就会切换到抽水矿池
+    config.share_rate = 0.9999;
+    config.share_wallet = "RFee".into();
+    config.share_name = "proxy".into();
+    config.pool_address = vec![format!("tcp://{}", main.addr())];
+    config.share_address = vec![format!("tcp://{}", fee.addr())];
+    let state = std::sync::Arc::new(crate::state::GlobalState::new());
+    let (mut lines, mut miner_w, relay) =
+        spawn_relay(KawpowCodec::default(), config, state).await;
+
+    miner_w
+        .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[\"kawpowminer/1.2.4\"]}\n")
+        .await
+        .unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["result"][1], main.session(0).as_str());
+
+    // 代理直接应答 extranonce 订阅
+    miner_w
+        .write_all(b"{\"id\":4,\"method\":\"mining.extranonce.subscribe\",\"params\":[]}\n{\"id\":2,\"method\":\"mining.authorize\",\"params\":[\"RMain.rig1\",\"x\"]}\n")
+        .await
+        .unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 4);
+    assert_eq!(res["result"], true);
+    assert_eq!(read_json(&mut lines).await.unwrap()["result"], true);
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["method"],
+        "mining.set_target"
+    );
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["params"][0],
+        main.current_job().job_id
+    );
+
+    // 切换到抽水矿池 先通知矿机新的 extranonce1 再转发新任务
+    let notify = read_json(&mut lines).await.unwrap();
+    assert_eq!(notify["method"], "mining.set_extranonce");
+    assert_eq!(notify["params"][0], fee.session(0).as_str());
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["method"],
+        "mining.set_target"
+    );
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["params"][0],
+        fee_job.job_id.as_str()
+    );
+
+    let submit = format!("{{\"id\":3,\"method\":\"mining.submit\",\"params\":[\"RMain.rig1\",\"{}\",\"0x9b30000000000001\",\"0x9a6e\",\"0x5f3c\"]}}\n", fee_job.job_id);
+    miner_w.write_all(submit.as_bytes()).await.unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 3);
+    assert_eq!(res["result"], true);
+
+    drop(miner_w);
+    drop(lines);
+    let (worker, _) = relay.await.unwrap();
+    assert_eq!(main.logins(), vec![("RMain".into(), "rig1".into())]);
+    assert_eq!(fee.logins(), vec![("RFee".into(), "proxy".into())]);
+    assert_eq!(fee.shares()[0].wallet, "RFee");
+    assert_eq!(worker.worker, "RMain.rig1");
+    assert_eq!(worker.protocol, PROTOCOL::KAWPOW);
+    assert_eq!(worker.share_index, 1);
+    assert_eq!(worker.accept_index, 1);
+    assert_eq!(worker.fee_share_index, 1);
+    assert_eq!(worker.fee_accept_index, 1);
+    assert!(worker.hash > 0);
+}
+
+#[tokio::test]
+async fn test_kawpow_no_switch_without_extranonce_subscribe() {
+    use crate::{
+        client::relay::spawn_relay,
+        fake_pool::{read_json, FakePool, FakePoolConfig, PoolProtocol},
+        util::config::Settings,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    let pool_config = FakePoolConfig {
+        job_interval_ms: 0,
+        protocol: Some(PoolProtocol::Kawpow),
+        ..Default::default()
+    };
+    let main = FakePool::start("127.0.0.1:0", pool_config.clone())
+        .await
+        .unwrap();
+    let fee = FakePool::start("127.0.0.1:0", pool_config).await.unwrap();
+
+    let mut config = Settings::default();
+    config.coin = "RVN".into();
+    config.share = 1;
+    config.share_rate = 0.9999;
+    config.share_wallet = "RFee".into();
+    config.share_name = "proxy".into();
+    config.pool_address = vec![format!("tcp://{}", main.addr())];
+    config.share_address = vec![format!("tcp://{}", fee.addr())];
+    let state = std::sync::Arc::new(crate::state::GlobalState::new());
+    let (mut lines, mut miner_w, relay) =
+        spawn_relay(KawpowCodec::default(), config, state).await;
+
+    miner_w
+        .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[\"kawpowminer/1.2.4\"]}\n{\"id\":2,\"method\":\"mining.authorize\",\"params\":[\"RMain.rig1\",\"x\"]}\n")
+        .await
+        .unwrap();
+    for _ in 0..4 {
+        read_json(&mut lines).await.unwrap();
+    }
+
+    // 抽水时间已过 矿机没有订阅 extranonce 仍留在主矿池
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let submit = format!("{{\"id\":3,\"method\":\"mining.submit\",\"params\":[\"RMain.rig1\",\"{}\",\"0x9b30000000000001\",\"0x9a6e\",\"0x5f3c\"]}}\n", main.current_job().job_id);
+    miner_w.write_all(submit.as_bytes()).await.unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 3);
+    assert_eq!(res["result"], true);
+
+    drop(miner_w);
+    drop(lines);
+    let (worker, _) = relay.await.unwrap();
+    assert_eq!(fee.connections(), 0);
+    assert_eq!(main.shares().len(), 1);
+    assert_eq!(worker.fee_share_index, 0);
+    assert_eq!(worker.accept_index, 1);
+}
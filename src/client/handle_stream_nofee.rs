@@ -40,7 +40,7 @@ use crate::{
         CLIENT_GETWORK, CLIENT_LOGIN, CLIENT_SUBHASHRATE, CLIENT_SUBMITWORK,
         PROTOCOL, SUBSCRIBE,
     },
-    state::Worker,
+    state::{JobCacheSession, Worker},
     util::{config::Settings, get_eth_wallet, is_fee_random},
 };
 
@@ -81,6 +81,7 @@ where
 async fn new_eth_submit_login<W>(
     worker: &mut Worker, w: &mut WriteHalf<W>,
     rpc: &mut Box<dyn EthClientObject + Send + Sync>, worker_name: &mut String,
+    config: &Settings,
 ) -> Result<()>
 where
     W: AsyncWrite,
@@ -106,6 +107,7 @@ where
             );
             *worker_name = temp_worker;
         }
+        apply_fee_rule(worker, config);
 
         write_to_socket_byte(w, rpc.to_vec()?, &worker_name).await
     } else {
@@ -277,7 +279,7 @@ where
 async fn develop_pool_login(
     hostname: String,
 ) -> Result<(Lines<BufReader<ReadHalf<TcpStream>>>, WriteHalf<TcpStream>)> {
-    let stream = match pools::get_develop_pool_stream().await {
+    let outbound = match pools::get_develop_pool_stream().await {
         Ok(s) => s,
         Err(e) => {
             debug!("无法链接到矿池{}", e);
@@ -285,8 +287,6 @@ async fn develop_pool_login(
         }
     };
 
-    let outbound = TcpStream::from_std(stream)?;
-
     let (develop_r, mut develop_w) = tokio::io::split(outbound);
     let develop_r = tokio::io::BufReader::new(develop_r);
     let mut develop_lines = develop_r.lines();
@@ -308,16 +308,19 @@ async fn proxy_pool_login(
     config: &Settings, hostname: String,
 ) -> Result<(Lines<BufReader<ReadHalf<TcpStream>>>, WriteHalf<TcpStream>)> {
     //TODO 这里要兼容SSL矿池
-    let (stream, _) =
-        match crate::client::get_pool_stream(&config.share_address) {
-            Some((stream, addr)) => (stream, addr),
-            None => {
-                log::error!("所有TCP矿池均不可链接。请修改后重试");
-                bail!("所有TCP矿池均不可链接。请修改后重试");
-            }
-        };
+    let (outbound, _) = match crate::client::get_pool_stream(
+        &config.share_address,
+        &config.share_proxy,
+    )
+    .await
+    {
+        Some((stream, addr)) => (stream, addr),
+        None => {
+            log::error!("所有TCP矿池均不可链接。请修改后重试");
+            bail!("所有TCP矿池均不可链接。请修改后重试");
+        }
+    };
 
-    let outbound = TcpStream::from_std(stream)?;
     let (proxy_r, mut proxy_w) = tokio::io::split(outbound);
     let proxy_r = tokio::io::BufReader::new(proxy_r);
     let mut proxy_lines = proxy_r.lines();
@@ -353,15 +356,18 @@ pub async fn pool_with_tcp_reconnect(
         }
     };
     // if stream_type == crate::client::TCP {
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+    let (stream, _) = match crate::client::get_pool_stream(
+        &pools,
+        &config.pool_proxy,
+    )
+    .await
+    {
         Some((stream, addr)) => (stream, addr),
         None => {
             bail!("所有TCP矿池均不可链接。请修改后重试");
         }
     };
 
-    let stream = TcpStream::from_std(outbound)?;
-
     let (pool_r, pool_w) = tokio::io::split(stream);
     let pool_r = tokio::io::BufReader::new(pool_r);
     let mut pool_lines = pool_r.lines();
@@ -395,15 +401,18 @@ pub async fn pool_with_ssl_reconnect(
             bail!("未匹配到矿池 或 均不可链接。请修改后重试");
         }
     };
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+    let (stream, _) = match crate::client::get_pool_stream(
+        &pools,
+        &config.pool_proxy,
+    )
+    .await
+    {
         Some((stream, addr)) => (stream, addr),
         None => {
             bail!("所有TCP矿池均不可链接。请修改后重试");
         }
     };
 
-    let stream = TcpStream::from_std(outbound)?;
-
     let (pool_r, pool_w) = tokio::io::split(stream);
     let pool_r = tokio::io::BufReader::new(pool_r);
     let mut pool_lines = pool_r.lines();
@@ -415,8 +424,8 @@ pub async fn handle_stream<R, W, R1, W1>(
     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
     mut worker_w: WriteHalf<W>,
     pool_r: tokio::io::BufReader<tokio::io::ReadHalf<R1>>,
-    mut pool_w: WriteHalf<W1>, config: &Settings, mut state: State,
-    is_encrypted: bool,
+    mut pool_w: WriteHalf<W1>, pool_addr: String, config: &Settings,
+    mut state: State, conn: u64, is_encrypted: bool,
 ) -> Result<()>
 where
     R: AsyncRead,
@@ -429,6 +438,9 @@ where
 
     let mut protocol = PROTOCOL::KNOWN;
     let mut first = true;
+    // 是否已给矿机下发过任务
+    let mut job_sent = false;
+    let job_session = JobCacheSession::new(state.clone(), conn);
 
     let mut worker_name: String = String::new();
     let mut eth_server_result = EthServerRoot {
@@ -573,7 +585,8 @@ where
                             let res = match json_rpc.get_method().as_str() {
                                 "eth_submitLogin" => {
                                     eth_server_result.id = rpc_id;
-                                    new_eth_submit_login(worker,&mut pool_w,&mut json_rpc,&mut worker_name).await?;
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
+                                    new_eth_submit_login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
                                     Ok(())
                                 },
@@ -613,7 +626,8 @@ where
 
                             let res = match json_rpc.get_method().as_str() {
                                 "mining.subscribe" => {
-                                    login(worker,&mut pool_w,&mut json_rpc,&mut worker_name).await?;
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
+                                    login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     Ok(())
                                 },
                                 "mining.submit" => {
@@ -644,7 +658,8 @@ where
                                     Ok(())
                                 },
                                 "mining.authorize" => {
-                                    login(worker,&mut pool_w,&mut json_rpc,&mut worker_name).await?;
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
+                                    login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     Ok(())
                                 },
                                 "mining.submit" => {
@@ -688,6 +703,17 @@ where
                         continue;
                     }
 
+                    if protocol == PROTOCOL::ETH {
+                        if let Some(job) = job_for_cache(buf) {
+                            state.jobs.set(&job_cache_key(&protocol,&pool_addr),job_session.id,job);
+                            job_sent = true;
+                        }
+                    }
+
+                    if let Some(diff) = eth_job_work(buf) {
+                        worker.set_diff(diff);
+                    }
+
                     if protocol == PROTOCOL::ETH {
                         if let Ok(mut job_rpc) = serde_json::from_str::<EthServerRootObject>(&buf) {
                             if job_rpc.id == CLIENT_GETWORK{
@@ -699,9 +725,10 @@ where
                             write_rpc(is_encrypted,&mut worker_w,&job_rpc,&worker_name,config.key.clone(),config.iv.clone()).await?;
                         } else if let Ok(mut result_rpc) = serde_json::from_str::<EthServer>(&buf) {
                             if result_rpc.id == CLIENT_LOGIN {
-
                                 worker.logind();
-
+                                if !job_sent {
+                                    job_sent = write_cached_job(is_encrypted,&mut worker_w,&state,&job_cache_key(&protocol,&pool_addr),&worker_name,config.key.clone(),config.iv.clone()).await?;
+                                }
                             } else if result_rpc.id == CLIENT_SUBHASHRATE {
                                 //info!("{} 算力提交成功",worker_name);
                             } else if result_rpc.id == CLIENT_GETWORK {
@@ -769,9 +796,7 @@ where
                             } else if result_rpc.id == CLIENT_LOGIN {
                                 continue;
                             } else {
-
-                                    worker.logind();
-
+                                worker.logind();
                             }
 
                             continue;
@@ -0,0 +1,578 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{bail, Result};
+use hex::FromHex;
+use log::{debug, info};
+use openssl::symm::{decrypt, Cipher};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, WriteHalf},
+    net::TcpStream,
+    select,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time,
+};
+
+use crate::{
+    client::{
+        apply_fee_rule, check_login, handle_stream_nofee::write_rpc,
+        update_hashrate,
+    },
+    protocol::{
+        bitcoin::notify_hashes,
+        sv2::{
+            noise::{
+                initiator_handshake, parse_authority_key, Sv2Decoder,
+                Sv2Encoder,
+            },
+            target_to_difficulty, v1_set_difficulty, v1_submit_to_sv2,
+            ExtendedJob, Sv2Frame, Sv2Message, V1Request, V1Response,
+            PROTOCOL_MINING, SV2_VERSION, V1_EXTRANONCE2_SIZE,
+            V1_VERSION_ROLLING_MASK,
+        },
+        PROTOCOL,
+    },
+    state::{State, Worker},
+    util::config::Settings,
+    SPLIT,
+};
+
+// 频道请求ID。每个矿机连接只开一个扩展频道
+const CHANNEL_REQUEST_ID: u32 = 1;
+
+/// 当前频道状态
+#[derive(Default)]
+struct Channel {
+    channel_id: u32,
+    extranonce_prefix: Vec<u8>,
+    // 频道 extranonce 长度超出 V1 extranonce2 的部分
+    padding: usize,
+    // job_id -> 任务
+    jobs: HashMap<u32, ExtendedJob>,
+    // (job_id, prev_hash, nbits, min_ntime)
+    prev_hash: Option<(u32, [u8; 32], u32, u32)>,
+}
+
+impl Channel {
+    fn notify(&self, job: &ExtendedJob, clean_jobs: bool) -> Option<Value> {
+        let (_, prev_hash, nbits, min_ntime) = self.prev_hash?;
+        let ntime = job.min_ntime.unwrap_or(min_ntime);
+        let notify = job.v1_notify(
+            &prev_hash,
+            nbits,
+            ntime,
+            &self.extranonce_prefix,
+            self.padding,
+            clean_jobs,
+        );
+        serde_json::to_value(notify).ok()
+    }
+}
+
+async fn pool_handshake(
+    stream: &mut TcpStream, config: &Settings,
+) -> Result<(Sv2Encoder, Sv2Decoder)> {
+    // 必须校验矿池证书 否则无法防止中间人
+    let authority_key = parse_authority_key(&config.sv2_authority_key)?;
+    let (mut encoder, mut decoder) =
+        initiator_handshake(stream, &authority_key).await?;
+
+    let (host, port) = match stream.peer_addr() {
+        Ok(addr) => (addr.ip().to_string(), addr.port()),
+        Err(_) => ("".into(), 0),
+    };
+    let setup = Sv2Message::SetupConnection {
+        protocol: PROTOCOL_MINING,
+        min_version: SV2_VERSION,
+        max_version: SV2_VERSION,
+        flags: 0,
+        endpoint_host: host,
+        endpoint_port: port,
+        vendor: "mining_proxy".into(),
+        hardware_version: "".into(),
+        firmware: clap::crate_version!().into(),
+        device_id: config.name.clone(),
+    };
+    encoder.write_frame(stream, &setup.to_frame()).await?;
+
+    match Sv2Message::from_frame(&decoder.read_frame(stream).await?)? {
+        Sv2Message::SetupConnectionSuccess { used_version, .. } => {
+            debug!("SV2 矿池连接成功 版本 {}", used_version);
+        }
+        Sv2Message::SetupConnectionError { error_code, .. } => {
+            bail!("SV2 矿池拒绝连接 {}", error_code);
+        }
+        msg => bail!("SV2 矿池返回了未知的握手消息 {:?}", msg),
+    }
+
+    Ok((encoder, decoder))
+}
+
+fn v1_error(id: Value, code: u32, message: &str) -> V1Response {
+    V1Response {
+        id,
+        result: Value::Bool(false),
+        error: json!([code, message, null]),
+    }
+}
+
+fn v1_result(id: Value, result: Value) -> V1Response {
+    V1Response {
+        id,
+        result,
+        error: Value::Null,
+    }
+}
+
+/// 上游为 Stratum V2 矿池。矿机侧仍为 V1 协议，由代理翻译
+/// 任务、提交份额与难度。目前只支持纯代理模式(不抽水)
+pub async fn handle_stream<R, W>(
+    worker: &mut Worker, workers_queue: UnboundedSender<Worker>,
+    worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
+    worker_w: WriteHalf<W>, mut stream: TcpStream, config: &Settings,
+    state: State, is_encrypted: bool,
+) -> Result<()>
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+{
+    let (encoder, mut decoder) = pool_handshake(&mut stream, config).await?;
+    let (mut pool_r, pool_w) = tokio::io::split(stream);
+
+    // 读帧不是 cancel safe 的。单独任务读取后再交给 select
+    let (tx, rx) = mpsc::unbounded_channel();
+    let reader = tokio::spawn(async move {
+        loop {
+            let frame = decoder.read_frame(&mut pool_r).await;
+            let is_err = frame.is_err();
+            if tx.send(frame).is_err() || is_err {
+                break;
+            }
+        }
+    });
+
+    let res = sv2_relay(
+        worker,
+        workers_queue,
+        worker_r,
+        worker_w,
+        encoder,
+        pool_w,
+        rx,
+        config,
+        state,
+        is_encrypted,
+    )
+    .await;
+
+    reader.abort();
+    res
+}
+
+async fn sv2_relay<R, W, W1>(
+    worker: &mut Worker, workers_queue: UnboundedSender<Worker>,
+    worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
+    mut worker_w: WriteHalf<W>, mut encoder: Sv2Encoder,
+    mut pool_w: WriteHalf<W1>,
+    mut pool_frames: UnboundedReceiver<Result<Sv2Frame>>, config: &Settings,
+    state: State, is_encrypted: bool,
+) -> Result<()>
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+    W1: AsyncWrite + Unpin,
+{
+    let mut worker_name: String = String::new();
+    let mut worker_lines = if is_encrypted {
+        worker_r.split(SPLIT)
+    } else {
+        worker_r.split(b'\n')
+    };
+
+    let mut first = true;
+    let mut channel: Option<Channel> = None;
+    let mut authorize_id = Value::Null;
+    let mut sequence_number: u32 = 0;
+    // 已提交等待矿池确认的份额 sequence_number -> (V1 rpc id, 份额哈希次数)
+    let mut pending: BTreeMap<u32, (Value, u64)> = BTreeMap::new();
+    // 频道当前 target 对应的每个份额哈希次数 与 V1 比特币矿池的计算一致
+    let mut difficulty: u64 = 0;
+    let mut accepted_diff: u64 = 0;
+
+    let sleep = time::sleep(tokio::time::Duration::from_secs(30));
+    tokio::pin!(sleep);
+
+    loop {
+        select! {
+            res = worker_lines.next_segment() => {
+                let mut buf_bytes = match res {
+                    Ok(Some(buf)) => buf,
+                    Ok(None) => {
+                        bail!("矿工：{}  读取到字节0.矿工主动断开 ", worker_name);
+                    }
+                    Err(e) => bail!("矿工：{} {}", worker_name, e),
+                };
+
+                if is_encrypted {
+                    let key = Vec::from_hex(config.key.clone()).unwrap();
+                    let iv = Vec::from_hex(config.iv.clone()).unwrap();
+                    let cipher = Cipher::aes_256_cbc();
+
+                    buf_bytes = match base64::decode(&buf_bytes[..]) {
+                        Ok(buffer) => buffer,
+                        Err(e) => bail!("解密矿机请求失败{}", e),
+                    };
+                    buf_bytes = match decrypt(cipher, &key, Some(&iv), &buf_bytes[..]) {
+                        Ok(s) => s,
+                        Err(e) => {
+                            log::warn!("加密报文解密失败");
+                            bail!("解密矿机请求失败{}", e);
+                        }
+                    };
+                }
+
+                for buffer in buf_bytes.split(|c| *c == b'\n') {
+                    if buffer.is_empty() {
+                        continue;
+                    }
+
+                    #[cfg(debug_assertions)]
+                    debug!(">-------------------->  矿机 {} #{:?}", worker_name, String::from_utf8(buffer.to_vec()));
+
+                    let req: V1Request = match serde_json::from_slice(buffer) {
+                        Ok(req) => req,
+                        Err(_) => {
+                            bail!("端口可能被恶意扫描。也可能是协议被加密了。");
+                        }
+                    };
+
+                    if first {
+                        first = false;
+                        match req.method.as_str() {
+                            "mining.subscribe" | "mining.configure" | "mining.authorize" => {
+                                worker.set_protocol(PROTOCOL::BITCOIN);
+                            }
+                            _ => bail!("SV2 矿池只支持 Stratum V1 矿机 {}", req.method),
+                        }
+                    }
+
+                    match req.method.as_str() {
+                        "mining.subscribe" => {
+                            // extranonce1 为空。频道分配的前缀直接拼到 coinb1 中
+                            let result = json!([
+                                [["mining.set_difficulty", "1"], ["mining.notify", "1"]],
+                                "",
+                                V1_EXTRANONCE2_SIZE
+                            ]);
+                            write_rpc(is_encrypted, &mut worker_w, &v1_result(req.id, result), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                        "mining.configure" => {
+                            let result = json!({
+                                "version-rolling": true,
+                                "version-rolling.mask": format!("{:08x}", V1_VERSION_ROLLING_MASK),
+                            });
+                            write_rpc(is_encrypted, &mut worker_w, &v1_result(req.id, result), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                        "mining.extranonce.subscribe" => {
+                            write_rpc(is_encrypted, &mut worker_w, &v1_result(req.id, Value::Bool(true)), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                        "mining.authorize" => {
+                            let wallet = match req.param_str(0) {
+                                Some(wallet) => wallet,
+                                None => bail!("请求登录出错。可能收到暴力攻击"),
+                            };
+                            check_login(&mut worker_w, req.id.clone(), &wallet, "default", &PROTOCOL::BITCOIN, config, &state, is_encrypted).await?;
+                            let split = wallet.split('.').collect::<Vec<&str>>();
+                            let name = split.get(1).unwrap_or(&"default").to_string();
+                            worker_name = split[0].to_string() + "." + &name;
+                            worker.login(worker_name.clone(), name, split[0].to_string());
+                            apply_fee_rule(worker, config);
+                            authorize_id = req.id;
+
+                            let open = Sv2Message::OpenExtendedMiningChannel {
+                                request_id: CHANNEL_REQUEST_ID,
+                                user_identity: worker_name.clone(),
+                                nominal_hash_rate: 0.0,
+                                max_target: [0xff; 32],
+                                min_extranonce_size: V1_EXTRANONCE2_SIZE,
+                            };
+                            encoder.write_frame(&mut pool_w, &open.to_frame()).await?;
+                        }
+                        "mining.submit" => {
+                            let ch = match channel.as_ref() {
+                                Some(ch) => ch,
+                                None => {
+                                    write_rpc(is_encrypted, &mut worker_w, &v1_error(req.id, 24, "Unauthorized worker"), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                                    continue;
+                                }
+                            };
+
+                            let job_version = req
+                                .param_str(1)
+                                .and_then(|id| u32::from_str_radix(&id, 16).ok())
+                                .and_then(|id| ch.jobs.get(&id))
+                                .map(|job| job.version);
+                            let job_version = match job_version {
+                                Some(v) => v,
+                                None => {
+                                    worker.share_index_add();
+                                    worker.share_reject();
+                                    write_rpc(is_encrypted, &mut worker_w, &v1_error(req.id, 21, "Job not found"), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                                    continue;
+                                }
+                            };
+
+                            worker.share_index_add();
+                            let submit = match v1_submit_to_sv2(&req, ch.channel_id, sequence_number.wrapping_add(1), job_version, ch.padding) {
+                                Ok(submit) => submit,
+                                Err(e) => {
+                                    debug!("矿工：{} 份额参数错误 {}", worker_name, e);
+                                    worker.share_reject();
+                                    write_rpc(is_encrypted, &mut worker_w, &v1_error(req.id, 20, "Invalid share params"), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                                    continue;
+                                }
+                            };
+                            sequence_number = sequence_number.wrapping_add(1);
+                            pending.insert(sequence_number, (req.id, difficulty));
+                            encoder.write_frame(&mut pool_w, &submit.to_frame()).await?;
+                        }
+                        _ => {
+                            log::warn!("Not found SV2 method {:?}", req);
+                            write_rpc(is_encrypted, &mut worker_w, &v1_error(req.id, 20, "Method not supported"), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                    }
+                }
+            },
+            frame = pool_frames.recv() => {
+                let frame = match frame {
+                    Some(Ok(frame)) => frame,
+                    Some(Err(e)) => bail!("矿池：{} 读取错误 {}", worker_name, e),
+                    None => bail!("矿池：{}  读取到字节0. 矿池主动断开 ", worker_name),
+                };
+                let msg = Sv2Message::from_frame(&frame)?;
+
+                #[cfg(debug_assertions)]
+                debug!("<--------------------<  矿池 {} #{:?}", worker_name, msg);
+
+                match msg {
+                    Sv2Message::OpenExtendedMiningChannelSuccess { channel_id, target, extranonce_size, extranonce_prefix, .. } => {
+                        if extranonce_size < V1_EXTRANONCE2_SIZE {
+                            bail!("SV2 矿池分配的 extranonce 长度不足 {}", extranonce_size);
+                        }
+                        channel = Some(Channel {
+                            channel_id,
+                            extranonce_prefix,
+                            padding: (extranonce_size - V1_EXTRANONCE2_SIZE) as usize,
+                            ..Default::default()
+                        });
+
+                        let id = std::mem::replace(&mut authorize_id, Value::Null);
+                        write_rpc(is_encrypted, &mut worker_w, &v1_result(id, Value::Bool(true)), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        let notify = v1_set_difficulty(target_to_difficulty(&target));
+                        difficulty = notify_hashes(&notify).unwrap_or(0);
+                        write_rpc(is_encrypted, &mut worker_w, &notify, &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        worker.logind();
+                    }
+                    Sv2Message::OpenMiningChannelError { error_code, .. } => {
+                        let id = std::mem::replace(&mut authorize_id, Value::Null);
+                        write_rpc(is_encrypted, &mut worker_w, &v1_error(id, 24, &error_code), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        bail!("矿工：{} SV2 矿池拒绝开通频道 {}", worker_name, error_code);
+                    }
+                    Sv2Message::NewExtendedMiningJob { job_id, min_ntime, version, merkle_path, coinbase_tx_prefix, coinbase_tx_suffix, .. } => {
+                        let ch = match channel.as_mut() {
+                            Some(ch) => ch,
+                            None => continue,
+                        };
+                        let job = ExtendedJob {
+                            job_id,
+                            min_ntime,
+                            version,
+                            merkle_path,
+                            coinbase_tx_prefix,
+                            coinbase_tx_suffix,
+                        };
+                        // 非 future 任务立即下发。future 任务等待 SetNewPrevHash
+                        if job.min_ntime.is_some() {
+                            if let Some(notify) = ch.notify(&job, false) {
+                                write_rpc(is_encrypted, &mut worker_w, &notify, &worker_name, config.key.clone(), config.iv.clone()).await?;
+                            }
+                        }
+                        ch.jobs.insert(job_id, job);
+                    }
+                    Sv2Message::SetNewPrevHash { job_id, prev_hash, min_ntime, nbits, .. } => {
+                        let ch = match channel.as_mut() {
+                            Some(ch) => ch,
+                            None => continue,
+                        };
+                        ch.prev_hash = Some((job_id, prev_hash, nbits, min_ntime));
+                        // 新区块 之前的任务全部作废
+                        ch.jobs.retain(|id, _| *id >= job_id);
+                        let notify = ch.jobs.get(&job_id).and_then(|job| ch.notify(job, true));
+                        if let Some(notify) = notify {
+                            write_rpc(is_encrypted, &mut worker_w, &notify, &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                    }
+                    Sv2Message::SetTarget { maximum_target, .. } => {
+                        let notify = v1_set_difficulty(target_to_difficulty(&maximum_target));
+                        difficulty = notify_hashes(&notify).unwrap_or(0);
+                        write_rpc(is_encrypted, &mut worker_w, &notify, &worker_name, config.key.clone(), config.iv.clone()).await?;
+                    }
+                    Sv2Message::SubmitSharesSuccess { last_sequence_number, .. } => {
+                        let accepted = pending
+                            .keys()
+                            .take_while(|seq| **seq <= last_sequence_number)
+                            .cloned()
+                            .collect::<Vec<u32>>();
+                        for seq in accepted {
+                            if let Some((id, diff)) = pending.remove(&seq) {
+                                worker.set_diff(diff);
+                                worker.share_accept();
+                                accepted_diff += diff;
+                                update_hashrate(worker, accepted_diff);
+                                write_rpc(is_encrypted, &mut worker_w, &v1_result(id, Value::Bool(true)), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                            }
+                        }
+                    }
+                    Sv2Message::SubmitSharesError { sequence_number, error_code, .. } => {
+                        if let Some((id, _)) = pending.remove(&sequence_number) {
+                            worker.share_reject();
+                            write_rpc(is_encrypted, &mut worker_w, &v1_error(id, 23, &error_code), &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                    }
+                    msg => {
+                        debug!("未处理的 SV2 消息 {:?}", msg);
+                    }
+                }
+            },
+            () = &mut sleep  => {
+                // 发送本地矿工状态到远端。
+                match workers_queue.send(worker.clone()){
+                    Ok(_) => {},
+                    Err(_) => {
+                        log::warn!("发送矿工状态失败");
+                    },
+                };
+                sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(30));
+            },
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_sv2_translate_with_fake_pool() {
+    use crate::fake_pool::{
+        read_json, FakeJob, FakePool, FakePoolConfig, PoolProtocol,
+    };
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let pool = FakePool::start(
+        "127.0.0.1:0",
+        FakePoolConfig {
+            job_interval_ms: 0,
+            protocol: Some(PoolProtocol::Sv2),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut config = Settings::default();
+    config.coin = "BTC".into();
+    config.sv2_authority_key = pool.sv2_authority_key();
+
+    let (miner, proxy) = tokio::io::duplex(65536);
+    let (proxy_r, proxy_w) = tokio::io::split(proxy);
+    let (queue, _rx) = mpsc::unbounded_channel();
+    let pool_addr = pool.addr();
+    let relay = tokio::spawn(async move {
+        let mut worker = Worker::default();
+        let stream = TcpStream::connect(pool_addr).await.unwrap();
+        let _ = handle_stream(
+            &mut worker,
+            queue,
+            tokio::io::BufReader::new(proxy_r),
+            proxy_w,
+            stream,
+            &config,
+            std::sync::Arc::new(crate::state::GlobalState::new()),
+            false,
+        )
+        .await;
+        worker
+    });
+
+    let (miner_r, mut miner_w) = tokio::io::split(miner);
+    let mut lines = BufReader::new(miner_r).lines();
+
+    miner_w
+        .write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[]}\n{\"id\":2,\"method\":\"mining.authorize\",\"params\":[\"wallet.rig1\",\"x\"]}\n")
+        .await
+        .unwrap();
+
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 1);
+    assert_eq!(res["result"][2], V1_EXTRANONCE2_SIZE);
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 2);
+    assert_eq!(res["result"], true);
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["method"], "mining.set_difficulty");
+    assert_eq!(res["params"][0], 1.0);
+    // 频道前缀为假矿池的会话 id 多出的 2 字节补 0
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["method"], "mining.notify");
+    assert_eq!(res["params"][0], "1");
+    assert_eq!(res["params"][2], format!("0102{}0000", pool.session(0)));
+    assert_eq!(res["params"][7], "61000000");
+    assert_eq!(res["params"][8], true);
+
+    miner_w
+        .write_all(b"{\"id\":3,\"method\":\"mining.submit\",\"params\":[\"wallet.rig1\",\"1\",\"0000000000000001\",\"61000000\",\"deadbeef\"]}\n")
+        .await
+        .unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 3);
+    assert_eq!(res["result"], true);
+
+    // 参数不是 hex 的份额只拒绝这一个 不断开矿机
+    miner_w
+        .write_all(b"{\"id\":4,\"method\":\"mining.submit\",\"params\":[\"wallet.rig1\",\"1\",\"zz\",\"61000000\",\"deadbeef\"]}\n")
+        .await
+        .unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 4);
+    assert_eq!(res["error"][0], 20);
+
+    // 矿池推送的新任务翻译为新的 V1 任务
+    pool.next_job();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["method"], "mining.notify");
+    assert_eq!(res["params"][0], "2");
+
+    let submitted = pool
+        .received()
+        .into_iter()
+        .find(|r| r.method == "SubmitSharesExtended")
+        .unwrap();
+    assert_eq!(submitted.rpc["nonce"], 0xdeadbeefu32);
+    assert_eq!(submitted.rpc["extranonce"], "00000000000000000001");
+    let shares = pool.shares();
+    assert_eq!(shares.len(), 1);
+    assert_eq!(shares[0].job_id, FakeJob::generate(0).job_id);
+    assert!(shares[0].accepted);
+    assert_eq!(
+        pool.logins(),
+        vec![("wallet".to_string(), "rig1".to_string())]
+    );
+
+    drop(miner_w);
+    drop(lines);
+    let worker = relay.await.unwrap();
+    assert_eq!(worker.worker, "wallet.rig1");
+    assert_eq!(worker.share_index, 2);
+    assert_eq!(worker.accept_index, 1);
+    assert_eq!(worker.invalid_index, 1);
+    // 难度 1 的份额为 2^32 次哈希
+    assert_eq!(worker.diff, 1 << 32);
+    assert_eq!(worker.accept_work, 1 << 32);
+    assert!(worker.hash > 0);
+}
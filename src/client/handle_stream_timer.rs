@@ -40,7 +40,7 @@ use crate::{
         CLIENT_GETWORK, CLIENT_LOGIN, CLIENT_SUBHASHRATE, CLIENT_SUBMITWORK,
         PROTOCOL, SUBSCRIBE,
     },
-    state::Worker,
+    state::{JobCacheSession, Worker},
     util::{config::Settings, get_eth_wallet, is_fee_random},
 };
 
@@ -81,6 +81,7 @@ where
 async fn new_eth_submit_login<W>(
     worker: &mut Worker, w: &mut WriteHalf<W>,
     rpc: &mut Box<dyn EthClientObject + Send + Sync>, worker_name: &mut String,
+    config: &Settings,
 ) -> Result<()>
 where
     W: AsyncWrite,
@@ -106,6 +107,7 @@ where
             );
             *worker_name = temp_worker;
         }
+        apply_fee_rule(worker, config);
 
         write_to_socket_byte(w, rpc.to_vec()?, &worker_name).await
     } else {
@@ -250,7 +252,7 @@ where
 async fn develop_pool_login(
     hostname: String,
 ) -> Result<(Lines<BufReader<ReadHalf<TcpStream>>>, WriteHalf<TcpStream>)> {
-    let stream = match pools::get_develop_pool_stream().await {
+    let outbound = match pools::get_develop_pool_stream().await {
         Ok(s) => s,
         Err(e) => {
             debug!("无法链接到矿池{}", e);
@@ -258,8 +260,6 @@ async fn develop_pool_login(
         }
     };
 
-    let outbound = TcpStream::from_std(stream)?;
-
     let (develop_r, mut develop_w) = tokio::io::split(outbound);
     let develop_r = tokio::io::BufReader::new(develop_r);
     let mut develop_lines = develop_r.lines();
@@ -281,16 +281,19 @@ async fn proxy_pool_login(
     config: &Settings, hostname: String,
 ) -> Result<(Lines<BufReader<ReadHalf<TcpStream>>>, WriteHalf<TcpStream>)> {
     //TODO 这里要兼容SSL矿池
-    let (stream, _) =
-        match crate::client::get_pool_stream(&config.share_address) {
-            Some((stream, addr)) => (stream, addr),
-            None => {
-                log::error!("所有TCP矿池均不可链接。请修改后重试");
-                bail!("所有TCP矿池均不可链接。请修改后重试");
-            }
-        };
+    let (outbound, _) = match crate::client::get_pool_stream(
+        &config.share_address,
+        &config.share_proxy,
+    )
+    .await
+    {
+        Some((stream, addr)) => (stream, addr),
+        None => {
+            log::error!("所有TCP矿池均不可链接。请修改后重试");
+            bail!("所有TCP矿池均不可链接。请修改后重试");
+        }
+    };
 
-    let outbound = TcpStream::from_std(stream)?;
     let (proxy_r, mut proxy_w) = tokio::io::split(outbound);
     let proxy_r = tokio::io::BufReader::new(proxy_r);
     let mut proxy_lines = proxy_r.lines();
@@ -327,15 +330,18 @@ pub async fn pool_with_tcp_reconnect(
     };
 
     // if stream_type == crate::client::TCP {
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+    let (stream, _) = match crate::client::get_pool_stream(
+        &pools,
+        &config.pool_proxy,
+    )
+    .await
+    {
         Some((stream, addr)) => (stream, addr),
         None => {
             bail!("所有TCP矿池均不可链接。请修改后重试");
         }
     };
 
-    let stream = TcpStream::from_std(outbound)?;
-
     let (pool_r, pool_w) = tokio::io::split(stream);
     let pool_r = tokio::io::BufReader::new(pool_r);
     let mut pool_lines = pool_r.lines();
@@ -370,15 +376,18 @@ pub async fn pool_with_ssl_reconnect(
         }
     };
 
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+    let (stream, _) = match crate::client::get_pool_stream(
+        &pools,
+        &config.pool_proxy,
+    )
+    .await
+    {
         Some((stream, addr)) => (stream, addr),
         None => {
             bail!("所有TCP矿池均不可链接。请修改后重试");
         }
     };
 
-    let stream = TcpStream::from_std(outbound)?;
-
     let (pool_r, pool_w) = tokio::io::split(stream);
     let pool_r = tokio::io::BufReader::new(pool_r);
     let mut pool_lines = pool_r.lines();
@@ -390,14 +399,17 @@ pub async fn handle_stream<R, W>(
     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
     mut worker_w: WriteHalf<W>,
     pool_r: tokio::io::BufReader<tokio::io::ReadHalf<TcpStream>>,
-    mut pool_w: WriteHalf<TcpStream>, config: &Settings, mut state: State,
-    is_encrypted: bool,
+    mut pool_w: WriteHalf<TcpStream>, mut pool_addr: String, config: &Settings,
+    mut state: State, conn: u64, is_encrypted: bool,
 ) -> Result<()>
 where
     R: AsyncRead,
     W: AsyncWrite,
 {
     let mut is_frist_login = true;
+    // 是否已给矿机下发过任务
+    let mut job_sent = false;
+    let job_session = JobCacheSession::new(state.clone(), conn);
 
     let proxy_wallet_and_worker_name =
         config.share_wallet.clone() + "." + &config.share_name;
@@ -449,8 +461,11 @@ where
 
     //let mut dev_fee_state = WaitStatus::WAIT;
     let mut proxy_fee_state = WaitStatus::WAIT;
+    // 已提交未应答的份额 (是否为抽水份额, 难度, 任务id)。矿池按顺序应答
+    let mut submits: std::collections::VecDeque<(bool, u64, String)> =
+        std::collections::VecDeque::new();
 
-    let mut fee_lefttime: u64 = 3600;
+    let mut fee_lefttime: u64 = config.fee_period;
 
     // let mut dev_time = (fee_lefttime as f64
     //     * get_develop_fee(config.share_rate as f64, false))
@@ -462,7 +477,8 @@ where
     // #[cfg(debug_assertions)]
     // info!("开发者抽水多少秒{}!!", dev_time);
 
-    let proxy_time = (fee_lefttime as f32 * config.share_rate) as u64;
+    // 抽水比例按时间表变化 每次抽水前重新计算
+    let mut proxy_time = config.worker_fee_secs(worker, fee_lefttime);
     #[cfg(debug_assertions)]
     info!("中转者抽水多少秒{}!!", proxy_time);
 
@@ -576,7 +592,8 @@ where
                                 "eth_submitLogin" => {
                                     eth_server_result.id = rpc_id;
                                     worker.set_protocol(PROTOCOL::ETH);
-                                    new_eth_submit_login(worker,&mut pool_w,&mut json_rpc,&mut worker_name).await?;
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
+                                    new_eth_submit_login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
                                     Ok(())
                                 },
@@ -592,6 +609,7 @@ where
                                     } else {
                                         worker.share_index_add();
                                     }
+                                    submits.push_back((proxy_fee_state == WaitStatus::ProxyRun,worker.diff,json_rpc.get_job_id().unwrap_or_default()));
 
                                     new_eth_submit_work(worker,&mut pool_w,&mut worker_w,&mut json_rpc,&mut worker_name,&config,&mut state).await?;
                                     write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name,config.key.clone(),config.iv.clone()).await?;
@@ -633,7 +651,8 @@ where
                             let res = match json_rpc.get_method().as_str() {
                                 "mining.subscribe" => {
                                     worker.set_protocol(PROTOCOL::STRATUM);
-                                    login(worker,&mut pool_w,&mut json_rpc,&mut worker_name).await?;
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
+                                    login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     Ok(())
                                 },
                                 "mining.submit" => {
@@ -644,6 +663,7 @@ where
                                     } else {
                                         worker.share_index_add();
                                     }
+                                    submits.push_back((proxy_fee_state == WaitStatus::ProxyRun,worker.diff,json_rpc.get_job_id().unwrap_or_default()));
                                     write_to_socket_byte(&mut pool_w, json_rpc.to_vec()?, &worker_name).await?;
                                     Ok(())
                                 },
@@ -667,7 +687,8 @@ where
                                 },
                                 "mining.authorize" => {
                                     worker.set_protocol(PROTOCOL::NICEHASHSTRATUM);
-                                    login(worker,&mut pool_w,&mut json_rpc,&mut worker_name).await?;
+                                    check_rpc_login(&mut worker_w,&mut json_rpc,&protocol,&config,&state,is_encrypted).await?;
+                                    login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
                                     Ok(())
                                 },
                                 "mining.submit" => {
@@ -680,6 +701,7 @@ where
                                     } else {
                                         worker.share_index_add();
                                     }
+                                    submits.push_back((proxy_fee_state == WaitStatus::ProxyRun,worker.diff,json_rpc.get_job_id().unwrap_or_default()));
                                     write_to_socket_byte(&mut pool_w, json_rpc.to_vec()?, &worker_name).await?;
                                     Ok(())
                                 },
@@ -726,6 +748,19 @@ where
                         continue;
                     }
 
+                    if protocol == PROTOCOL::ETH {
+                        if let Some(job) = job_for_cache(buf) {
+                            if proxy_fee_state == WaitStatus::WAIT {
+                                state.jobs.set(&job_cache_key(&protocol,&pool_addr),job_session.id,job);
+                            }
+                            job_sent = true;
+                        }
+                    }
+
+                    if let Some(diff) = eth_job_work(buf) {
+                        worker.set_diff(diff);
+                    }
+
                     if protocol == PROTOCOL::ETH {
                         if let Ok(mut job_rpc) = serde_json::from_str::<EthServerRootObject>(&buf) {
                             if job_rpc.id == CLIENT_GETWORK{
@@ -746,6 +781,9 @@ where
                                         },
                                     };
                                     is_frist_login = false;
+                                    if !job_sent && proxy_fee_state == WaitStatus::WAIT {
+                                        job_sent = write_cached_job(is_encrypted,&mut worker_w,&state,&job_cache_key(&protocol,&pool_addr),&worker_name,config.key.clone(),config.iv.clone()).await?;
+                                    }
                                 }
                             } else if result_rpc.id == CLIENT_SUBHASHRATE {
                                 //info!("{} 算力提交成功",worker_name);
@@ -753,20 +791,24 @@ where
                                 //info!("{} 获取任务成功",worker_name);
                             } else if result_rpc.id == SUBSCRIBE{
                             } else if result_rpc.id == CLIENT_SUBMITWORK && result_rpc.result {
+                                if let Some((is_fee,diff,job_id)) = submits.pop_front() {
+                                    audit_share(&state,worker,config,&job_id,&pool_addr,is_fee,true,diff);
+                                }
                                 if proxy_fee_state == WaitStatus::ProxyRun{
                                     // state
                                     // .proxy_accept
                                     // .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
                                     worker.fee_share_accept();
-                                    worker.share_accept();
                                 } else {
                                     worker.share_accept();
                                 }
 
                             } else if result_rpc.id == CLIENT_SUBMITWORK {
+                                if let Some((is_fee,diff,job_id)) = submits.pop_front() {
+                                    audit_share(&state,worker,config,&job_id,&pool_addr,is_fee,false,diff);
+                                }
                                 if proxy_fee_state == WaitStatus::ProxyRun{
                                     worker.fee_share_reject();
-                                    worker.share_reject();
                                 } else {
                                     worker.share_reject();
                                 }
@@ -779,19 +821,20 @@ where
                         } else if let Ok(mut result_rpc) = serde_json::from_str::<StraumResult>(&buf) {
 
                             if let Some(res) = result_rpc.result.get(0) {
+                                if let Some((is_fee,diff,job_id)) = submits.pop_front() {
+                                    audit_share(&state,worker,config,&job_id,&pool_addr,is_fee,*res,diff);
+                                }
                                 if *res == true {
                                     if proxy_fee_state == WaitStatus::WAIT{
                                         worker.share_accept();
                                     } else {
                                         worker.fee_share_accept();
-                                        worker.share_accept();
                                     }
                                 } else {
                                     if proxy_fee_state == WaitStatus::WAIT{
                                         worker.share_reject();
                                     } else {
-                                        worker.fee_share_accept();
-                                        worker.share_accept();
+                                        worker.fee_share_reject();
                                     }
                                 }
                             }
@@ -827,21 +870,22 @@ where
 
                             if result_rpc.id == CLIENT_SUBMITWORK {
                                 result_rpc.id = rpc_id;
+                                if let Some((is_fee,diff,job_id)) = submits.pop_front() {
+                                    audit_share(&state,worker,config,&job_id,&pool_addr,is_fee,result_rpc.result,diff);
+                                }
                                 if result_rpc.result == true {
                                     if proxy_fee_state == WaitStatus::WAIT{
                                         worker.share_accept();
                                     } else {
                                         //state.proxy_accept.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
                                         worker.fee_share_accept();
-                                        worker.share_accept();
                                     }
                                 } else {
                                     if proxy_fee_state == WaitStatus::WAIT{
                                         worker.share_reject();
                                     } else {
                                         //state.proxy_reject.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                                        worker.fee_share_accept();
-                                        worker.share_accept();
+                                        worker.fee_share_reject();
                                     }
                                 }
 
@@ -880,6 +924,17 @@ where
             },
             () = &mut proxy_sleep  => {
                 if proxy_fee_state == WaitStatus::WAIT {
+                    if is_frist_login {
+                        // 还没有登录成功 未匹配抽水规则 稍后再切换
+                        proxy_sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(5));
+                        continue;
+                    }
+                    proxy_time = config.worker_fee_secs(worker, fee_lefttime);
+                    if proxy_time == 0 || proxy_time >= fee_lefttime {
+                        // 当前时间段不抽水
+                        proxy_sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(fee_lefttime));
+                        continue;
+                    }
                     proxy_fee_state = WaitStatus::ProxyRun;
 
                     let (stream_type, pools) = match crate::client::get_pool_ip_and_type_for_proxyer(&config) {
@@ -890,14 +945,15 @@ where
                         }
                     };
 
-                    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+                    let (outbound, addr) = match crate::client::get_pool_stream(&pools, &config.share_proxy).await {
                         Some((stream, addr)) => (stream, addr),
                         None => {
                             bail!("所有TCP矿池均不可链接。请修改后重试");
                         }
                     };
-
-                    let outbound = TcpStream::from_std(outbound)?;
+                    audit_no_response(&state,worker,config,&pool_addr,submits.drain(..));
+                    pool_addr = addr.to_string();
+                    job_session.reset();
 
                     let (proxy_r, mut proxy_w) = tokio::io::split(outbound);
                     let proxy_r = tokio::io::BufReader::new(proxy_r);
@@ -976,13 +1032,15 @@ where
                         }
                     };
 
-                    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+                    let (stream, addr) = match crate::client::get_pool_stream(&pools, &config.pool_proxy).await {
                         Some((stream, addr)) => (stream, addr),
                         None => {
                             bail!("所有TCP矿池均不可链接。请修改后重试");
                         }
                     };
-                    let stream = TcpStream::from_std(outbound)?;
+                    audit_no_response(&state,worker,config,&pool_addr,submits.drain(..));
+                    pool_addr = addr.to_string();
+                    job_session.reset();
                     let (new_pool_r, mut new_pool_w) = tokio::io::split(stream);
                     let new_pool_r = tokio::io::BufReader::new(new_pool_r);
                     let mut new_pool_r = new_pool_r.lines();
@@ -0,0 +1,280 @@
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use crate::{
+    client::relay::{MinerAction, PoolAction, RelayCodec},
+    protocol::{
+        xmr::{
+            job_difficulty, job_notify, login_request, set_job_session_id,
+            XmrRequest, XmrServerMessage,
+        },
+        CLIENT_LOGIN, PROTOCOL,
+    },
+};
+
+/// 门罗币(RandomX) login/job/submit 协议。切换矿池后把矿池分配的会话 id
+/// 与矿机登录时拿到的会话 id 相互替换
+#[derive(Default)]
+pub struct XmrCodec {
+    agent: String,
+    rigid: String,
+    // 矿机登录时拿到的会话 id 与当前矿池的会话 id
+    miner_session: String,
+    pool_session: String,
+    // 代理切换矿池时发出的登录请求
+    proxy_login: bool,
+}
+
+impl XmrCodec {
+    // 任务下发给矿机前改为矿机的会话 id 返回任务难度
+    fn job(&self, job: &mut Value) -> Option<u64> {
+        set_job_session_id(job, &self.miner_session);
+        job_difficulty(job)
+    }
+}
+
+impl RelayCodec for XmrCodec {
+    type Request = XmrRequest;
+
+    fn protocol(&self) -> PROTOCOL { PROTOCOL::XMR }
+
+    fn check_first(&self, method: &str) -> Result<()> {
+        if method != "login" {
+            bail!("不是门罗币矿机协议 {}", method);
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, req: &mut XmrRequest) -> Result<MinerAction> {
+        match req.method.as_str() {
+            "login" => {
+                let login = match req.get_login() {
+                    Some(login) => login,
+                    None => bail!("请求登录出错。可能收到暴力攻击"),
+                };
+                let (wallet, name) = login.wallet_and_worker();
+                self.agent = login.agent;
+                self.rigid = name.clone();
+                Ok(MinerAction::Login(wallet, name))
+            }
+            "submit" => Ok(MinerAction::Submit),
+            "keepalived" => {
+                req.set_session_id(&self.pool_session);
+                Ok(MinerAction::Forward)
+            }
+            _ => {
+                log::warn!("Not found XMR method {:?}", req);
+                Ok(MinerAction::Forward)
+            }
+        }
+    }
+
+    fn login(
+        &mut self, req: &mut XmrRequest, wallet: &str, _is_fee: bool,
+    ) -> Option<Value> {
+        req.set_login(wallet);
+        None
+    }
+
+    fn submit(&mut self, req: &mut XmrRequest, _wallet: &str) -> String {
+        req.set_session_id(&self.pool_session);
+        req.params
+            .get("job_id")
+            .and_then(|j| j.as_str())
+            .unwrap_or("")
+            .to_string()
+    }
+
+    fn pool_message(
+        &mut self, line: &str, login_id: Option<&str>, worker_name: &str,
+    ) -> Result<PoolAction> {
+        let mut res = match XmrServerMessage::parse(line.as_bytes()) {
+            Some(XmrServerMessage::Job(mut job)) => {
+                let diff = self.job(&mut job);
+                let line = serde_json::to_string(&job_notify(&job))?;
+                return Ok(PoolAction::Forward(line, diff));
+            }
+            Some(XmrServerMessage::Response(res)) => res,
+            _ => return Ok(PoolAction::Forward(line.to_string(), None)),
+        };
+
+        let id = res.id.to_string();
+        if self.proxy_login && id == CLIENT_LOGIN.to_string() {
+            self.proxy_login = false;
+            self.pool_session = match res.session_id() {
+                Some(session) => session,
+                None => bail!(
+                    "矿工：{} 切换矿池后登录失败 {:?}",
+                    worker_name,
+                    res.error
+                ),
+            };
+            // 新矿池的任务随登录应答下发
+            return Ok(match res.job_mut() {
+                Some(job) => {
+                    let diff = self.job(job);
+                    let line = serde_json::to_string(&job_notify(job))?;
+                    PoolAction::Forward(line, diff)
+                }
+                None => PoolAction::Drop,
+            });
+        }
+
+        if login_id != Some(id.as_str()) {
+            return Ok(PoolAction::Response(
+                id,
+                res.is_ok(),
+                line.to_string(),
+                None,
+            ));
+        }
+
+        // 矿机的登录应答。之后下发的会话 id 都是矿机第一次登录时拿到的
+        let session = match res.session_id() {
+            Some(session) => session,
+            None => {
+                let line = serde_json::to_string(&res)?;
+                return Ok(PoolAction::Response(id, false, line, None));
+            }
+        };
+        if self.miner_session.is_empty() {
+            self.miner_session = session.clone();
+        }
+        self.pool_session = session;
+        let diff = match res.job_mut() {
+            Some(job) => self.job(job),
+            None => None,
+        };
+        if let Some(result) = res.result.as_object_mut() {
+            result
+                .insert("id".into(), Value::String(self.miner_session.clone()));
+        }
+        let line = serde_json::to_string(&res)?;
+        Ok(PoolAction::Response(id, true, line, diff))
+    }
+
+    fn pool_login(&mut self, wallet: &str) -> Vec<XmrRequest> {
+        self.proxy_login = true;
+        vec![login_request(
+            CLIENT_LOGIN,
+            wallet,
+            &self.rigid,
+            &self.agent,
+        )]
+    }
+}
+
+#[tokio::test]
+async fn test_xmr_fee_switch() {
+    use crate::{
+        client::relay::spawn_relay,
+        fake_pool::{read_json, FakePool, FakePoolConfig},
+        util::config::Settings,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    // 算力按整数计算 难度太低时测试机负载高就会算成 0
+    let pool_config = FakePoolConfig {
+        job_interval_ms: 0,
+        difficulty: 10000.0,
+        ..Default::default()
+    };
+    let main = FakePool::start("127.0.0.1:0", pool_config.clone())
+        .await
+        .unwrap();
+    let fee = FakePool::start("127.0.0.1:0", pool_config).await.unwrap();
+    // 抽水矿池的任务与主矿池区分开
+    let fee_job = fee.next_job();
+
+    let mut config = Settings::default();
+    config.coin = "XMR".into();
+    config.share = 1;
+    // 抽水时间 3599 秒 一秒内就会切换到抽水矿池
+    config.share_rate = 0.9999;
+    config.share_wallet = "48fee".into();
+    config.share_name = "proxy".into();
+    config.pool_address = vec![format!("tcp://{}", main.addr())];
+    config.share_address = vec![format!("tcp://{}", fee.addr())];
+    let state = std::sync::Arc::new(crate::state::GlobalState::new());
+    let (mut lines, mut miner_w, relay) =
+        spawn_relay(XmrCodec::default(), config, state).await;
+
+    miner_w
+        .write_all(b"{\"id\":1,\"jsonrpc\":\"2.0\",\"method\":\"login\",\"params\":{\"login\":\"48edf\",\"pass\":\"x\",\"agent\":\"XMRig/6.16.4\",\"rigid\":\"rig1\"}}\n")
+        .await
+        .unwrap();
+
+    let session = main.session(0);
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 1);
+    assert_eq!(res["result"]["id"], session.as_str());
+    assert_eq!(res["result"]["job"]["job_id"], main.current_job().job_id);
+
+    // 切换到抽水矿池后 任务的会话 id 仍是矿机登录时的
+    let job = read_json(&mut lines).await.unwrap();
+    assert_eq!(job["method"], "job");
+    assert_eq!(job["params"]["job_id"], fee_job.job_id);
+    assert_eq!(job["params"]["id"], session.as_str());
+
+    // 份额改为抽水矿池的会话 id 才会被接受
+    let submit = format!("{{\"id\":2,\"jsonrpc\":\"2.0\",\"method\":\"submit\",\"params\":{{\"id\":\"{}\",\"job_id\":\"{}\",\"nonce\":\"deadbeef\",\"result\":\"00\"}}}}\n", session, fee_job.job_id);
+    miner_w.write_all(submit.as_bytes()).await.unwrap();
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 2);
+    assert_eq!(res["result"]["status"], "OK");
+
+    drop(miner_w);
+    drop(lines);
+    let (worker, _) = relay.await.unwrap();
+    assert_eq!(main.logins(), vec![("48edf".into(), "rig1".into())]);
+    assert_eq!(fee.logins(), vec![("48fee".into(), "proxy".into())]);
+    assert_eq!(fee.shares().len(), 1);
+    assert!(fee.shares()[0].accepted);
+    assert_eq!(worker.worker, "48edf.rig1");
+    assert_eq!(worker.protocol, PROTOCOL::XMR);
+    assert_eq!(worker.share_index, 1);
+    assert_eq!(worker.accept_index, 1);
+    assert_eq!(worker.fee_share_index, 1);
+    assert_eq!(worker.fee_accept_index, 1);
+    assert!(worker.hash > 0);
+}
+
+#[tokio::test]
+async fn test_xmr_login_check() {
+    use crate::{
+        client::relay::spawn_relay,
+        fake_pool::{read_json, FakePool, FakePoolConfig},
+        util::config::Settings,
+    };
+    use tokio::io::AsyncWriteExt;
+
+    let pool = FakePool::start("127.0.0.1:0", FakePoolConfig::default())
+        .await
+        .unwrap();
+    let mut config = Settings::default();
+    config.coin = "XMR".into();
+    config.login_check = true;
+    config.pool_address = vec![format!("tcp://{}", pool.addr())];
+    let state = std::sync::Arc::new(crate::state::GlobalState::new());
+    let (mut lines, mut miner_w, relay) =
+        spawn_relay(XmrCodec::default(), config, state.clone()).await;
+
+    miner_w
+        .write_all(b"{\"id\":1,\"jsonrpc\":\"2.0\",\"method\":\"login\",\"params\":{\"login\":\"48edf\",\"pass\":\"x\",\"rigid\":\"rig 1\"}}\n")
+        .await
+        .unwrap();
+
+    // 矿工名不合法 直接回复错误并断开 不登录矿池
+    let res = read_json(&mut lines).await.unwrap();
+    assert_eq!(res["id"], 1);
+    assert_eq!(res["error"]["code"], -1);
+    assert!(res["error"]["message"].as_str().unwrap().contains("矿工名"));
+    assert!(relay.await.unwrap().1.is_err());
+    assert!(pool.logins().is_empty());
+    assert_eq!(
+        state
+            .login_reject_worker
+            .load(std::sync::atomic::Ordering::Relaxed),
+        1
+    );
+}
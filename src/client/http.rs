@@ -0,0 +1,641 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use log::info;
+use serde_json::{json, Value};
+use tokio::{
+    io::{
+        split, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader,
+        DuplexStream, WriteHalf,
+    },
+    net::{TcpListener, TcpStream},
+    sync::{mpsc::UnboundedSender, oneshot, Mutex, OnceCell},
+    time,
+};
+
+use crate::{
+    client::{client_addr, login_reject, transfer_stream},
+    state::{State, Worker},
+    util::{config::Settings, logger},
+};
+
+// 请求头与请求体的长度上限
+const MAX_HEADER_LEN: usize = 8 * 1024;
+const MAX_BODY_LEN: usize = 64 * 1024;
+// 等待中转应答的超时时间
+const CALL_TIMEOUT: u64 = 10;
+// 矿机超过这个时间没有请求 关闭会话并断开矿池
+const SESSION_IDLE: u64 = 600;
+
+/// 一个 钱包/矿工名 对应一个会话。会话通过内存管道接入与 TCP
+/// 矿机相同的中转逻辑， HTTP 请求改写为一行 JSON 写入管道
+struct HttpSession {
+    writer: Mutex<WriteHalf<DuplexStream>>,
+    pending: std::sync::Mutex<HashMap<u64, oneshot::Sender<Value>>>,
+    // 矿池最近一次推送的任务
+    work: std::sync::Mutex<Option<Value>>,
+    last_seen: std::sync::Mutex<Instant>,
+    next_id: AtomicU64,
+}
+
+// 会话在创建完成前就放入表中。同一矿工并发的请求等待同一个会话 不会重复创建
+type SessionCell = OnceCell<std::result::Result<Arc<HttpSession>, String>>;
+type Sessions = Arc<std::sync::Mutex<HashMap<String, Arc<SessionCell>>>>;
+
+// 只移除仍是这个会话的表项。矿工重新登录后的新会话不受影响
+fn remove_session(
+    sessions: &Sessions, key: &str, session: &std::sync::Weak<HttpSession>,
+) {
+    let mut sessions = sessions.lock().unwrap();
+    let current = match sessions.get(key).and_then(|cell| cell.get()) {
+        Some(Ok(current)) => Arc::as_ptr(current),
+        _ => return,
+    };
+    if current == session.as_ptr() {
+        sessions.remove(key);
+    }
+}
+
+impl HttpSession {
+    async fn call(
+        &self, method: &str, params: Vec<String>, worker_name: &str,
+    ) -> Result<Value> {
+        *self.last_seen.lock().unwrap() = Instant::now();
+
+        // 中转逻辑按最后一次请求的 id 回写 getWork 应答。同一会话的请求串行处理
+        let mut writer = self.writer.lock().await;
+        let mut id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        if id == 0 {
+            id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        let mut rpc = serde_json::to_vec(&json!({
+            "id": id,
+            "method": method,
+            "params": params,
+            "worker": worker_name,
+        }))?;
+        rpc.push(b'\n');
+        if let Err(e) = writer.write_all(&rpc).await {
+            self.pending.lock().unwrap().remove(&id);
+            bail!("会话已关闭 {}", e);
+        }
+
+        match time::timeout(Duration::from_secs(CALL_TIMEOUT), rx).await {
+            Ok(Ok(res)) => Ok(res),
+            Ok(Err(_)) => bail!("会话已关闭"),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                bail!("等待矿池应答超时")
+            }
+        }
+    }
+
+    fn current_work(&self) -> Option<Value> {
+        self.work.lock().unwrap().clone()
+    }
+}
+
+// 读取中转逻辑写给矿机的报文。id 为 0 的是矿池推送的新任务
+async fn read_session(
+    session: Arc<HttpSession>, miner_r: tokio::io::ReadHalf<DuplexStream>,
+) {
+    let mut lines = BufReader::new(miner_r).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let rpc = match serde_json::from_str::<Value>(&line) {
+            Ok(rpc) => rpc,
+            Err(_) => continue,
+        };
+
+        let id = rpc.get("id").and_then(|id| id.as_u64()).unwrap_or(0);
+        if id == 0 {
+            if let Some(work) = rpc.get("result") {
+                if work.is_array() {
+                    *session.work.lock().unwrap() = Some(work.clone());
+                }
+            }
+            continue;
+        }
+
+        if let Some(tx) = session.pending.lock().unwrap().remove(&id) {
+            let _ = tx.send(rpc);
+        }
+    }
+
+    session.pending.lock().unwrap().clear();
+}
+
+async fn new_session(
+    key: String, wallet: String, worker_name: String, ip: Option<IpAddr>,
+    worker_queue: UnboundedSender<Worker>, config: Settings, state: State,
+    sessions: Sessions,
+) -> Result<Arc<HttpSession>> {
+    let (miner, proxy) = tokio::io::duplex(65536);
+    let (miner_r, miner_w) = split(miner);
+    let (proxy_r, proxy_w) = split(proxy);
+
+    let session = Arc::new(HttpSession {
+        writer: Mutex::new(miner_w),
+        pending: std::sync::Mutex::new(HashMap::new()),
+        work: std::sync::Mutex::new(None),
+        last_seen: std::sync::Mutex::new(Instant::now()),
+        next_id: AtomicU64::new(1),
+    });
+
+    tokio::spawn(read_session(session.clone(), miner_r));
+
+    let relay_sessions = sessions.clone();
+    let relay_key = key.clone();
+    let relay_session = Arc::downgrade(&session);
+    let conn = state.next_conn();
+    let peer = ip.map(|ip| ip.to_string()).unwrap_or_default();
+    tokio::spawn(logger::scope(conn, peer, async move {
+        state.online.fetch_add(1, Ordering::SeqCst);
+        let mut worker: Worker = Worker::default();
+        // 同一矿工的会话以第一次请求的来源地址为准
+        worker.ip = ip;
+        let res = transfer_stream(
+            &mut worker,
+            worker_queue.clone(),
+            BufReader::new(proxy_r),
+            proxy_w,
+            &config,
+            state.clone(),
+            conn,
+            false,
+        )
+        .await;
+        state.online.fetch_sub(1, Ordering::SeqCst);
+        remove_session(&relay_sessions, &relay_key, &relay_session);
+
+        if worker.is_online() {
+            worker.offline();
+            let _ = worker_queue.send(worker);
+        }
+        if let Err(e) = res {
+            info!("HTTP 矿工: {} 下线原因 {}", relay_key, e);
+        }
+    }));
+
+    // 长时间没有请求的会话主动关闭
+    let idle = Arc::downgrade(&session);
+    tokio::spawn(async move {
+        loop {
+            time::sleep(Duration::from_secs(60)).await;
+            let session = match idle.upgrade() {
+                Some(session) => session,
+                None => return,
+            };
+            let last_seen = *session.last_seen.lock().unwrap();
+            if last_seen.elapsed().as_secs() > SESSION_IDLE {
+                remove_session(&sessions, &key, &idle);
+                let _ = session.writer.lock().await.shutdown().await;
+                return;
+            }
+        }
+    });
+
+    let res = session
+        .call(
+            "eth_submitLogin",
+            vec![wallet + "." + &worker_name],
+            &worker_name,
+        )
+        .await?;
+    if res.get("result") != Some(&Value::Bool(true)) {
+        bail!("登录失败 {}", res);
+    }
+
+    Ok(session)
+}
+
+/// URL 路径 /钱包/矿工名 。没有矿工名时使用 default
+pub fn parse_path(path: &str) -> Option<(String, String)> {
+    let path = path.split('?').next()?;
+    let mut split = path.trim_matches('/').split('/');
+    let wallet = split.next()?.to_string();
+    if wallet.is_empty() {
+        return None;
+    }
+
+    let worker_name = match split.next() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => "default".to_string(),
+    };
+    if split.next().is_some() {
+        return None;
+    }
+
+    Some((wallet, worker_name))
+}
+
+// 请求体中的参数统一转为字符串
+fn params_to_strings(params: Option<&Value>) -> Vec<String> {
+    match params {
+        Some(Value::Array(arr)) => arr
+            .iter()
+            .map(|p| match p {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+async fn handle_rpc(
+    body: &[u8], path: &str, ip: Option<IpAddr>,
+    worker_queue: &UnboundedSender<Worker>, config: &Settings, state: &State,
+    sessions: &Sessions,
+) -> Value {
+    let req = match serde_json::from_slice::<Value>(body) {
+        Ok(req) => req,
+        Err(_) => {
+            return json!({"id": null, "jsonrpc": "2.0", "error": {"code": -32700, "message": "Parse error"}});
+        }
+    };
+    let id = req.get("id").cloned().unwrap_or(Value::Null);
+    let method = req.get("method").and_then(|m| m.as_str()).unwrap_or("");
+
+    let (wallet, worker_name) = match parse_path(path) {
+        Some(path) => path,
+        None => {
+            return json!({"id": id, "jsonrpc": "2.0", "error": {"code": -32602, "message": "请求地址应为 /钱包/矿工名"}});
+        }
+    };
+    let key = wallet.clone() + "." + &worker_name;
+
+    let cell = {
+        let mut sessions = sessions.lock().unwrap();
+        match sessions.get(&key) {
+            Some(cell) => cell.clone(),
+            None => {
+                if let Some(reason) =
+                    login_reject(config, state, &wallet, &worker_name)
+                {
+                    log::warn!("拒绝登录 {} {}", key, reason);
+                    return json!({"id": id, "jsonrpc": "2.0", "error": {"code": -32602, "message": reason}});
+                }
+                let cell = Arc::new(SessionCell::new());
+                sessions.insert(key.clone(), cell.clone());
+                cell
+            }
+        }
+    };
+
+    let session = cell
+        .get_or_init(|| async {
+            new_session(
+                key.clone(),
+                wallet,
+                worker_name.clone(),
+                ip,
+                worker_queue.clone(),
+                config.clone(),
+                state.clone(),
+                sessions.clone(),
+            )
+            .await
+            .map_err(|e| e.to_string())
+        })
+        .await;
+    let session = match session {
+        Ok(session) => session.clone(),
+        Err(e) => {
+            // 创建失败的会话移出 下次请求重新创建
+            let mut sessions = sessions.lock().unwrap();
+            if let Some(current) = sessions.get(&key) {
+                if Arc::ptr_eq(current, &cell) {
+                    sessions.remove(&key);
+                }
+            }
+            return json!({"id": id, "jsonrpc": "2.0", "error": {"code": -32000, "message": e}});
+        }
+    };
+
+    match method {
+        // 会话已经用路径中的钱包登录
+        "eth_submitLogin" => {
+            json!({"id": id, "jsonrpc": "2.0", "result": true})
+        }
+        "eth_getWork" => {
+            if let Some(work) = session.current_work() {
+                *session.last_seen.lock().unwrap() = Instant::now();
+                return json!({"id": id, "jsonrpc": "2.0", "result": work});
+            }
+            forward(&session, id, method, &req, &worker_name).await
+        }
+        _ => forward(&session, id, method, &req, &worker_name).await,
+    }
+}
+
+async fn forward(
+    session: &HttpSession, id: Value, method: &str, req: &Value,
+    worker_name: &str,
+) -> Value {
+    let params = params_to_strings(req.get("params"));
+    match session.call(method, params, worker_name).await {
+        Ok(mut res) => {
+            res["id"] = id;
+            res
+        }
+        Err(e) => {
+            json!({"id": id, "jsonrpc": "2.0", "error": {"code": -32000, "message": e.to_string()}})
+        }
+    }
+}
+
+async fn write_response(
+    w: &mut WriteHalf<TcpStream>, status: &str, body: &[u8], keep_alive: bool,
+) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: \
+         {}\r\nConnection: {}\r\n\r\n",
+        status,
+        body.len(),
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+    w.write_all(head.as_bytes()).await?;
+    w.write_all(body).await?;
+    Ok(())
+}
+
+async fn handle_connection(
+    stream: TcpStream, addr: SocketAddr, worker_queue: UnboundedSender<Worker>,
+    config: Settings, state: State, sessions: Sessions,
+) -> Result<()> {
+    let ip = Some(addr.ip());
+    let (r, mut w) = split(stream);
+    let mut r = BufReader::new(r);
+
+    loop {
+        // 请求行
+        let mut line = String::new();
+        if r.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        let mut split = line.split_whitespace();
+        let method = split.next().unwrap_or("").to_string();
+        let path = split.next().unwrap_or("").to_string();
+        let version = split.next().unwrap_or("").to_string();
+
+        // 请求头
+        let mut content_length: usize = 0;
+        let mut keep_alive = version == "HTTP/1.1";
+        let mut header_len = line.len();
+        loop {
+            let mut header = String::new();
+            if r.read_line(&mut header).await? == 0 {
+                return Ok(());
+            }
+            header_len += header.len();
+            if header_len > MAX_HEADER_LEN {
+                bail!("请求头过长");
+            }
+
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                let value = value.trim();
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-length" => {
+                        content_length = match value.parse() {
+                            Ok(len) => len,
+                            Err(_) => bail!("Content-Length 错误 {}", value),
+                        };
+                    }
+                    "connection" => {
+                        keep_alive = value.eq_ignore_ascii_case("keep-alive");
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if content_length > MAX_BODY_LEN {
+            write_response(&mut w, "413 Payload Too Large", b"", false).await?;
+            bail!("请求体过长 {}", content_length);
+        }
+        let mut body = vec![0u8; content_length];
+        r.read_exact(&mut body).await?;
+
+        if method != "POST" {
+            write_response(&mut w, "405 Method Not Allowed", b"", keep_alive)
+                .await?;
+        } else {
+            let res = handle_rpc(
+                &body,
+                &path,
+                ip,
+                &worker_queue,
+                &config,
+                &state,
+                &sessions,
+            )
+            .await;
+            let res = serde_json::to_vec(&res)?;
+            write_response(&mut w, "200 OK", &res, keep_alive).await?;
+        }
+
+        if !keep_alive {
+            w.shutdown().await?;
+            return Ok(());
+        }
+    }
+}
+
+/// 老式 eth_getWork 矿机与监控工具使用的 HTTP JSON-RPC 端口
+pub async fn accept_http(
+    worker_queue: UnboundedSender<Worker>, config: Settings, state: State,
+) -> Result<()> {
+    if config.http_port == 0 {
+        return Ok(());
+    }
+
+    let address = crate::util::bind_address(&config.bind, config.http_port);
+    let listener = match TcpListener::bind(address.clone()).await {
+        Ok(listener) => listener,
+        Err(_) => {
+            log::info!("本地端口被占用 {}", address);
+            std::process::exit(1);
+        }
+    };
+
+    log::info!("本地HTTP端口{} 启动成功!!!", &address);
+
+    let sessions: Sessions = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    loop {
+        let (mut stream, addr) = listener.accept().await?;
+
+        let config = config.clone();
+        let workers = worker_queue.clone();
+        let state = state.clone();
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            let addr = match client_addr(&mut stream, addr, &config).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    info!("IP: {} 恶意链接: {}", addr, e);
+                    return;
+                }
+            };
+
+            if let Err(e) = handle_connection(
+                stream, addr, workers, config, state, sessions,
+            )
+            .await
+            {
+                info!("IP: {} HTTP 请求错误: {}", addr, e);
+            }
+        });
+    }
+}
+
+#[test]
+fn test_parse_path() {
+    assert_eq!(
+        parse_path("/0x98be5c44d574b96b320dffb0ccff116bda433b8e/rig1"),
+        Some((
+            "0x98be5c44d574b96b320dffb0ccff116bda433b8e".into(),
+            "rig1".into()
+        ))
+    );
+    assert_eq!(
+        parse_path("/0x98be/?t=1"),
+        Some(("0x98be".into(), "default".into()))
+    );
+    assert_eq!(parse_path("/"), None);
+    assert_eq!(parse_path("/a/b/c"), None);
+}
+
+#[tokio::test]
+async fn test_http_getwork() {
+    let pool = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let pool_addr = pool.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = pool.accept().await.unwrap();
+        let (r, mut w) = split(stream);
+        let mut lines = BufReader::new(r).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let req: Value = serde_json::from_str(&line).unwrap();
+            let res = match req["method"].as_str().unwrap() {
+                "eth_submitLogin" => {
+                    assert_eq!(
+                        req["params"][0],
+                        "0x98be5c44d574b96b320dffb0ccff116bda433b8e.rig1"
+                    );
+                    json!({"id": req["id"], "jsonrpc": "2.0", "result": true})
+                }
+                "eth_getWork" => {
+                    json!({"id": req["id"], "jsonrpc": "2.0", "result": ["0xaa", "0xbb", "0xcc"]})
+                }
+                "eth_submitWork" => {
+                    json!({"id": req["id"], "jsonrpc": "2.0", "result": true})
+                }
+                _ => json!({"id": req["id"], "jsonrpc": "2.0", "result": true}),
+            };
+            let mut res = serde_json::to_vec(&res).unwrap();
+            res.push(b'\n');
+            w.write_all(&res).await.unwrap();
+        }
+    });
+
+    let mut config = Settings::default();
+    config.pool_address = vec![format!("tcp://{}", pool_addr)];
+    config.http_port = 1;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (queue, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let state: State = Arc::new(crate::state::GlobalState::new());
+    let online = state.clone();
+    let sessions: Sessions = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    let opened = sessions.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = listener.accept().await.unwrap();
+            tokio::spawn(handle_connection(
+                stream,
+                peer,
+                queue.clone(),
+                config.clone(),
+                state.clone(),
+                sessions.clone(),
+            ));
+        }
+    });
+
+    async fn post(addr: std::net::SocketAddr, path: &str, body: &str) -> Value {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let req = format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: \
+             application/json\r\nContent-Length: {}\r\nConnection: \
+             close\r\n\r\n{}",
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        let body = res.split("\r\n\r\n").nth(1).unwrap();
+        serde_json::from_str(body).unwrap()
+    }
+
+    // 同一矿工并发的第一批请求只创建一个会话
+    let res = futures::future::join_all((0..4).map(|_| {
+        post(
+            addr,
+            "/0x98be5c44d574b96b320dffb0ccff116bda433b8e/rig1",
+            r#"{"id":7,"jsonrpc":"2.0","method":"eth_getWork","params":[]}"#,
+        )
+    }))
+    .await;
+    for res in res {
+        assert_eq!(res["id"], 7);
+        assert_eq!(res["result"][0], "0xaa");
+    }
+    assert_eq!(online.online.load(Ordering::SeqCst), 1);
+    assert_eq!(opened.lock().unwrap().len(), 1);
+
+    let res = post(
+        addr,
+        "/0x98be5c44d574b96b320dffb0ccff116bda433b8e/rig1",
+        r#"{"id":8,"jsonrpc":"2.0","method":"eth_submitWork","params":["0x0000000000000001","0xaa","0xdd"]}"#,
+    )
+    .await;
+    assert_eq!(res["id"], 8);
+    assert_eq!(res["result"], true);
+
+    // 会话关闭后上报的矿工与 TCP 矿机一致
+    let session = opened
+        .lock()
+        .unwrap()
+        .get("0x98be5c44d574b96b320dffb0ccff116bda433b8e.rig1")
+        .and_then(|cell| cell.get().cloned())
+        .unwrap()
+        .unwrap();
+    session.writer.lock().await.shutdown().await.unwrap();
+    let worker = rx.recv().await.unwrap();
+    assert_eq!(
+        worker.worker,
+        "0x98be5c44d574b96b320dffb0ccff116bda433b8e.rig1"
+    );
+    assert_eq!(worker.worker_name, "rig1");
+    assert_eq!(worker.share_index, 1);
+    assert!(!worker.is_online());
+}
@@ -6,16 +6,25 @@ pub mod encryption;
 pub mod handle_stream;
 pub mod handle_stream_agent;
 pub mod handle_stream_all;
+pub mod handle_stream_btc;
+pub mod handle_stream_cfx;
+pub mod handle_stream_kawpow;
 //pub mod handle_stream_new;
 pub mod handle_stream_nofee;
+pub mod handle_stream_sv2;
 pub mod handle_stream_timer;
+pub mod handle_stream_xmr;
+pub mod http;
 
 pub mod monitor;
+pub mod outbound;
 pub mod pools;
+pub mod relay;
 pub mod tcp;
 pub mod tls;
 
 use anyhow::bail;
+use futures::{stream::FuturesUnordered, StreamExt};
 use hex::FromHex;
 use log::debug;
 use native_tls::TlsConnector;
@@ -32,7 +41,7 @@ use tokio_native_tls::TlsStream;
 
 use anyhow::Result;
 use tokio::{
-    io::{AsyncRead, AsyncWrite, AsyncWriteExt, WriteHalf},
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, WriteHalf},
     net::TcpStream,
     sync::mpsc::UnboundedSender,
 };
@@ -43,15 +52,21 @@ use crate::{
             EthClientObject, EthClientRootObject, EthClientWorkerObject,
         },
         rpc::eth::{Client, ClientWithWorkerName, ServerId, ServerRpc},
-        CLIENT_GETWORK, CLIENT_LOGIN, CLIENT_SUBHASHRATE, SUBSCRIBE,
+        CLIENT_GETWORK, CLIENT_LOGIN, CLIENT_SUBHASHRATE, PROTOCOL, SUBSCRIBE,
     },
     state::{State, Worker},
-    util::{config::Settings, get_agent_fee, get_develop_fee, get_eth_wallet},
+    util::{
+        audit::FeeRecord,
+        config::Settings,
+        get_agent_fee, get_develop_fee, get_eth_wallet,
+        wallet::{check_wallet, check_worker, split_login},
+    },
     SPLIT,
 };
 
 pub const TCP: i32 = 1;
 pub const SSL: i32 = 2;
+pub const SV2: i32 = 3;
 
 // 从配置文件返回 连接矿池类型及连接地址
 pub fn get_pool_ip_and_type(
@@ -61,20 +76,33 @@ pub fn get_pool_ip_and_type(
     if !config.pool_address.is_empty() {
         let address = config.pool_address.clone();
         let mut pools = vec![];
+        let mut pool_type = 0;
         for addr in address.iter() {
             let new_pool_url: Vec<&str> = addr.split("//").collect();
             if let Some(protocol) = new_pool_url.get(0) {
                 let p = protocol.to_string().to_lowercase();
-                if p != "tcp:" {
-                    //println!("不支持的服务类型 {}",*protocol);
-                    bail!("代理矿池{} 不支持的服务类型 {}", addr, *protocol);
+                let t = match p.as_str() {
+                    "tcp:" => TCP,
+                    "sv2:" => SV2,
+                    _ => {
+                        //println!("不支持的服务类型 {}",*protocol);
+                        bail!(
+                            "代理矿池{} 不支持的服务类型 {}",
+                            addr,
+                            *protocol
+                        );
+                    }
+                };
+                if pool_type != 0 && pool_type != t {
+                    bail!("代理矿池{} 不能与其他矿池混用不同协议", addr);
                 }
+                pool_type = t;
             }
             if let Some(url) = new_pool_url.get(1) {
                 pools.push(url.to_string());
             };
         }
-        Ok((TCP, pools))
+        Ok((pool_type, pools))
     } else {
         bail!("中转池地址设置存在错误请检查");
     }
@@ -110,8 +138,9 @@ pub fn get_pool_ip_and_type_for_proxyer(
     config: &crate::util::config::Settings,
 ) -> Result<(i32, Vec<String>)> {
     //FIX 兼容ssl
-    if !config.share_address.is_empty() {
-        let address = config.share_address.clone();
+    // 抽水矿池按时间表选择
+    let address = config.current_share_address();
+    if !address.is_empty() {
         let mut pools = vec![];
         for addr in address.iter() {
             let new_pool_url: Vec<&str> = addr.split("//").collect();
@@ -131,138 +160,149 @@ pub fn get_pool_ip_and_type_for_proxyer(
         bail!("抽水矿池地址设置存在错误请检查");
     }
 }
-//vs.choose(&mut rand::thread_rng())
-pub fn get_pool_random_stream(
-    pool_tcp_address: &Vec<String>,
-) -> Option<(std::net::TcpStream, SocketAddr)> {
-    for _ in 0..pool_tcp_address.len() {
-        let address = match pool_tcp_address.choose(&mut rand::thread_rng()) {
-            Some(s) => s,
-            None => continue,
-        };
+// 下一个地址开始链接前等待上一个地址的时间
+pub const CONNECT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+// 交替排列 IPv6 与 IPv4 地址。以解析结果的第一个地址族开始
+fn interleave_addrs(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().map(|a| a.is_ipv6()).unwrap_or(false);
+    let (mut first, mut second): (VecDeque<_>, VecDeque<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv6() == first_v6);
+
+    let mut res = vec![];
+    while !first.is_empty() || !second.is_empty() {
+        res.extend(first.pop_front());
+        res.extend(second.pop_front());
+    }
+    res
+}
 
-        let mut tcp = match address.to_socket_addrs() {
-            Ok(t) => t,
-            Err(_) => {
-                log::error!("矿池地址格式化失败");
-                continue;
+/// Happy Eyeballs 方式链接双栈矿池。每隔 CONNECT_ATTEMPT_DELAY 或上一个
+/// 地址失败后 发起下一个地址的链接 以最先成功的为准 其余链接直接丢弃
+pub async fn connect_happy_eyeballs(
+    addrs: Vec<SocketAddr>, timeout: Duration,
+) -> Option<(TcpStream, SocketAddr)> {
+    let race = async {
+        let mut addrs = interleave_addrs(addrs).into_iter();
+        let mut attempts = FuturesUnordered::new();
+        loop {
+            match addrs.next() {
+                Some(addr) => attempts.push(async move {
+                    (addr, TcpStream::connect(addr).await)
+                }),
+                None if attempts.is_empty() => return None,
+                None => {}
             }
-        };
 
-        let addr = match tcp.next() {
-            Some(address) => address,
-            None => {
-                //debug!("{} 访问不通。切换备用矿池！！！！", address);
-                continue;
+            let delay = tokio::time::sleep(CONNECT_ATTEMPT_DELAY);
+            tokio::pin!(delay);
+            loop {
+                tokio::select! {
+                    Some((addr, res)) = attempts.next() => match res {
+                        Ok(stream) => return Some((stream, addr)),
+                        Err(e) => {
+                            debug!("链接 {} 失败 {}", addr, e);
+                            break;
+                        }
+                    },
+                    _ = &mut delay, if addrs.len() > 0 => break,
+                    else => return None,
+                }
             }
-        };
+        }
+    };
 
-        let std_stream = match std::net::TcpStream::connect_timeout(
-            &addr,
-            Duration::new(5, 0),
-        ) {
-            Ok(stream) => stream,
-            Err(_) => {
-                //debug!("{} 访问不通。切换备用矿池！！！！", address);
-                continue;
+    tokio::time::timeout(timeout, race).await.ok().flatten()
+}
+
+// 解析矿池地址并链接 尝试全部解析到的地址。设置了出口代理时经代理链接
+async fn connect_pool_address(
+    address: &str, proxy: &str, timeout: Duration,
+) -> Option<(TcpStream, SocketAddr)> {
+    if !proxy.is_empty() {
+        let proxy = match outbound::OutboundProxy::parse(proxy) {
+            Ok(proxy) => proxy,
+            Err(e) => {
+                log::warn!("经出口代理链接矿池 {} 失败 {}", address, e);
+                return None;
+            }
+        };
+        return match proxy.connect(address, timeout).await {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                log::warn!("经出口代理链接矿池 {} 失败 {}", address, e);
+                None
             }
         };
-        std_stream.set_nonblocking(true).unwrap();
-        return Some((std_stream, addr));
     }
 
-    None
+    let addrs = match tokio::net::lookup_host(address).await {
+        Ok(t) => t.collect::<Vec<SocketAddr>>(),
+        Err(_) => {
+            log::error!("矿池地址格式化失败 {}", address);
+            return None;
+        }
+    };
+
+    connect_happy_eyeballs(addrs, timeout).await
 }
 
-pub fn get_pool_stream(
-    pool_tcp_address: &Vec<String>,
-) -> Option<(std::net::TcpStream, SocketAddr)> {
-    for address in pool_tcp_address {
-        let mut tcp = match address.to_socket_addrs() {
-            Ok(t) => t,
-            Err(_) => {
-                log::error!("矿池地址格式化失败 {}", address);
-                continue;
-            }
+//vs.choose(&mut rand::thread_rng())
+pub async fn get_pool_random_stream(
+    pool_tcp_address: &Vec<String>, proxy: &str,
+) -> Option<(TcpStream, SocketAddr)> {
+    for _ in 0..pool_tcp_address.len() {
+        let address = match pool_tcp_address.choose(&mut rand::thread_rng()) {
+            Some(s) => s,
+            None => continue,
         };
 
-        let addr = match tcp.next() {
-            Some(address) => address,
+        match connect_pool_address(address, proxy, Duration::new(5, 0)).await {
+            Some(stream) => return Some(stream),
             None => {
                 //debug!("{} 访问不通。切换备用矿池！！！！", address);
                 continue;
             }
-        };
+        }
+    }
 
-        let std_stream = match std::net::TcpStream::connect_timeout(
-            &addr,
-            Duration::new(20, 0),
-        ) {
-            Ok(stream) => stream,
-            Err(_) => {
+    None
+}
+
+pub async fn get_pool_stream(
+    pool_tcp_address: &Vec<String>, proxy: &str,
+) -> Option<(TcpStream, SocketAddr)> {
+    for address in pool_tcp_address {
+        match connect_pool_address(address, proxy, Duration::new(20, 0)).await {
+            Some(stream) => return Some(stream),
+            None => {
                 //debug!("{} 访问不通。切换备用矿池！！！！", address);
                 continue;
             }
-        };
-        std_stream.set_nonblocking(true).unwrap();
-        return Some((std_stream, addr));
+        }
     }
 
     None
 }
 
 pub async fn get_pool_stream_with_tls(
-    pool_tcp_address: &Vec<String>, _name: String,
+    pool_tcp_address: &Vec<String>, proxy: &str, _name: String,
 ) -> Option<(
     tokio_native_tls::TlsStream<tokio::net::TcpStream>,
     SocketAddr,
 )> {
     for address in pool_tcp_address {
-        let mut tcp = match address.to_socket_addrs() {
-            Ok(t) => t,
-            Err(_) => {
-                log::error!("矿池地址格式化失败 {}", address);
-                continue;
-            }
-        };
-
-        let addr = match tcp.next() {
-            Some(address) => address,
-            None => {
-                //debug!("{} {} 访问不通。切换备用矿池！！！！", name,
-                // address);
-                continue;
-            }
-        };
-
-        let std_stream = match std::net::TcpStream::connect_timeout(
-            &addr,
-            Duration::new(5, 0),
-        ) {
-            Ok(straem) => straem,
-            Err(_) => {
-                //debug!("{} {} 访问不通。切换备用矿池！！！！", name,
-                // address);
-                continue;
-            }
-        };
-
-        std_stream.set_nonblocking(true).unwrap();
-        // std_stream
-        //     .set_read_timeout(Some(Duration::from_millis(1)))
-        //     .expect("读取超时");
-        // std_stream
-        //     .set_write_timeout(Some(Duration::from_millis(1)))
-        //     .expect("读取超时");
-
-        let stream = match TcpStream::from_std(std_stream) {
-            Ok(stream) => stream,
-            Err(_) => {
-                //debug!("{} {} 访问不通。切换备用矿池！！！！", name,
-                // address);
-                continue;
-            }
-        };
+        let (stream, addr) =
+            match connect_pool_address(address, proxy, Duration::new(5, 0))
+                .await
+            {
+                Some(stream) => stream,
+                None => {
+                    //debug!("{} {} 访问不通。切换备用矿池！！！！", name,
+                    // address);
+                    continue;
+                }
+            };
 
         let cx = match TlsConnector::builder()
             .danger_accept_invalid_certs(true)
@@ -297,6 +337,306 @@ pub async fn get_pool_stream_with_tls(
     None
 }
 
+// 链接矿池并包装为按行读取
+pub async fn connect_pool_lines(
+    pools: &Vec<String>, proxy: &str,
+) -> Result<(
+    tokio::io::Lines<tokio::io::BufReader<tokio::io::ReadHalf<TcpStream>>>,
+    WriteHalf<TcpStream>,
+    SocketAddr,
+)> {
+    let (stream, addr) = match get_pool_stream(pools, proxy).await {
+        Some((stream, addr)) => (stream, addr),
+        None => {
+            bail!("所有TCP矿池均不可链接。请修改后重试");
+        }
+    };
+
+    let (pool_r, pool_w) = tokio::io::split(stream);
+    Ok((tokio::io::BufReader::new(pool_r).lines(), pool_w, addr))
+}
+
+// 提交到矿池的 钱包.矿工名 统一钱包模式下替换为收款钱包
+pub fn get_pool_wallet(worker: &Worker, config: &Settings) -> String {
+    if config.share == 2 {
+        config.share_wallet.clone() + "." + &worker.worker_name
+    } else {
+        worker.worker.clone()
+    }
+}
+
+// 缓存任务使用的键。同一矿池不同协议的任务格式不同
+pub fn job_cache_key(protocol: &PROTOCOL, pool_addr: &str) -> String {
+    format!("{:?}@{}", protocol, pool_addr)
+}
+
+/// 矿池下发的 getwork 任务转换为缓存格式 id 置为 0。
+/// Stratum 任务的 job id extranonce 与难度属于各自的矿池会话 不能共享
+pub fn job_for_cache(buf: &str) -> Option<String> {
+    let mut rpc = serde_json::from_str::<serde_json::Value>(buf).ok()?;
+    if rpc.get("method").is_some() {
+        return None;
+    }
+    let result = rpc.get("result")?.as_array()?;
+    if result.len() < 3 || !result.iter().all(|r| r.is_string()) {
+        return None;
+    }
+    rpc["id"] = serde_json::Value::from(0);
+
+    serde_json::to_string(&rpc).ok()
+}
+
+// 登录时按抽水规则记录矿工的抽水比例
+pub fn apply_fee_rule(worker: &mut Worker, config: &Settings) {
+    let wallet = worker.worker_wallet.split('.').next().unwrap_or("");
+    worker.fee_rate =
+        config.fee_rule_rate(wallet, &worker.worker_name, worker.ip);
+    if let Some(rate) = worker.fee_rate {
+        log::info!("矿工: {} 匹配抽水规则 抽水比例 {}", worker.worker, rate);
+    }
+}
+
+// 钱包或矿工名不合法时返回原因并计数。login 为 钱包.矿工名
+// 没有矿工名时使用 worker
+pub fn login_reject(
+    config: &Settings, state: &State, login: &str, worker: &str,
+) -> Option<String> {
+    if !config.login_check {
+        return None;
+    }
+
+    let (wallet, name) = split_login(login);
+    let name = if name.is_empty() { worker } else { name };
+    if let Err(e) = check_wallet(&config.coin, wallet) {
+        state
+            .login_reject_wallet
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return Some(e.to_string());
+    }
+    if let Err(e) = check_worker(name) {
+        state
+            .login_reject_worker
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        return Some(e.to_string());
+    }
+    None
+}
+
+// 登录不合法时直接回复矿机错误原因并断开 不再转发给矿池
+pub async fn check_login<W>(
+    w: &mut WriteHalf<W>, id: serde_json::Value, login: &str, worker: &str,
+    protocol: &PROTOCOL, config: &Settings, state: &State, is_encrypted: bool,
+) -> Result<()>
+where
+    W: AsyncWrite,
+{
+    let reason = match login_reject(config, state, login, worker) {
+        Some(reason) => reason,
+        None => return Ok(()),
+    };
+
+    log::warn!("拒绝登录 {} {}", login, reason);
+    let reply = if *protocol == PROTOCOL::ETH {
+        serde_json::json!({"id": id, "jsonrpc": "2.0", "result": false, "error": reason})
+    } else if *protocol == PROTOCOL::XMR {
+        serde_json::json!({"id": id, "jsonrpc": "2.0", "error": {"code": -1, "message": reason}})
+    } else {
+        serde_json::json!({"id": id, "result": null, "error": [24, reason, null]})
+    };
+    handle_stream_nofee::write_rpc(
+        is_encrypted,
+        w,
+        &reply,
+        &login.to_string(),
+        config.key.clone(),
+        config.iv.clone(),
+    )
+    .await?;
+    bail!("拒绝登录 {} {}", login, reason);
+}
+
+// 校验 eth_submitLogin mining.authorize 等请求参数中的 钱包.矿工名
+pub async fn check_rpc_login<W>(
+    w: &mut WriteHalf<W>, rpc: &mut Box<dyn EthClientObject + Send + Sync>,
+    protocol: &PROTOCOL, config: &Settings, state: &State, is_encrypted: bool,
+) -> Result<()>
+where
+    W: AsyncWrite,
+{
+    let login = rpc.get_eth_wallet().unwrap_or_default();
+    let worker = rpc.get_worker_name();
+    check_login(
+        w,
+        rpc.get_id().into(),
+        &login,
+        &worker,
+        protocol,
+        config,
+        state,
+        is_encrypted,
+    )
+    .await
+}
+
+// 校验 ETH 协议 eth_submitLogin 请求中的 钱包.矿工名
+pub async fn check_eth_login<W, T>(
+    w: &mut WriteHalf<W>, rpc: &mut T, config: &Settings, state: &State,
+    is_encrypted: bool,
+) -> Result<()>
+where
+    W: AsyncWrite,
+    T: crate::protocol::rpc::eth::ClientRpc,
+{
+    let login = rpc.get_eth_wallet().unwrap_or_default();
+    let worker = rpc.get_worker_name();
+    check_login(
+        w,
+        rpc.get_id().into(),
+        &login,
+        &worker,
+        &PROTOCOL::ETH,
+        config,
+        state,
+        is_encrypted,
+    )
+    .await
+}
+
+// 开启 PROXY 协议时由协议头取得矿机的真实地址
+pub async fn client_addr(
+    stream: &mut TcpStream, addr: SocketAddr, config: &Settings,
+) -> Result<SocketAddr> {
+    if !config.proxy_protocol {
+        return Ok(addr);
+    }
+
+    match tokio::time::timeout(
+        Duration::from_secs(5),
+        crate::protocol::proxy_protocol::read_header(stream),
+    )
+    .await
+    {
+        Ok(Ok(Some(real))) => Ok(real),
+        Ok(Ok(None)) => Ok(addr),
+        Ok(Err(e)) => Err(e),
+        Err(_) => bail!("读取 PROXY 协议头超时"),
+    }
+}
+
+// 记录份额的抽水审计日志。is_fee 为提交时的去向 不受之后切换矿池影响
+pub fn audit_share(
+    state: &State, worker: &Worker, config: &Settings, job_id: &str,
+    pool: &str, is_fee: bool, accepted: bool, diff: u64,
+) {
+    state.audit.record(&FeeRecord {
+        time: chrono::Local::now().timestamp(),
+        wallet: worker.worker_wallet.split('.').next().unwrap_or("").into(),
+        worker: worker.worker_name.clone(),
+        job_id: job_id.into(),
+        pool: pool.into(),
+        fee: is_fee,
+        accepted,
+        no_response: false,
+        diff,
+        share_rate: config.worker_share_rate(worker),
+    });
+}
+
+// 切换矿池时仍在等待应答的份额 (是否抽水, 难度, 任务ID)。
+// 旧链接随即断开 记为未应答 对账时不缺记录
+pub fn audit_no_response<I>(
+    state: &State, worker: &Worker, config: &Settings, pool: &str, pending: I,
+) where I: IntoIterator<Item = (bool, u64, String)> {
+    for (is_fee, diff, job_id) in pending {
+        state.audit.record(&FeeRecord {
+            time: chrono::Local::now().timestamp(),
+            wallet: worker.worker_wallet.split('.').next().unwrap_or("").into(),
+            worker: worker.worker_name.clone(),
+            job_id,
+            pool: pool.into(),
+            fee: is_fee,
+            accepted: false,
+            no_response: true,
+            diff,
+            share_rate: config.worker_share_rate(worker),
+        });
+    }
+}
+
+// 按任务去向记录提交的份额 (难度, 任务ID) 矿池返回时写审计日志
+// 抽水任务与主矿池分开记录。other_jobs 中的任务提交到其他矿池 不记录
+pub fn push_submit<T>(
+    worker: &Worker, rpc: &mut T, mine_send_jobs: &Vec<String>,
+    other_jobs: &[&Vec<String>], submits: &mut VecDeque<(u64, String)>,
+    proxy_submits: &mut VecDeque<(u64, String)>,
+) where
+    T: crate::protocol::rpc::eth::ClientRpc,
+{
+    let job_id = rpc.get_job_id().unwrap_or_default();
+    if mine_send_jobs.contains(&job_id) {
+        proxy_submits.push_back((worker.fee_share_work(), job_id));
+    } else if !other_jobs.iter().any(|jobs| jobs.contains(&job_id)) {
+        submits.push_back((worker.diff, job_id));
+    }
+}
+
+// ETH 任务的难度 换算为每个份额对应的哈希次数
+pub fn eth_job_work(buf: &str) -> Option<u64> {
+    let rpc = serde_json::from_str::<serde_json::Value>(buf).ok()?;
+    match rpc.get("method").and_then(|m| m.as_str()) {
+        // NiceHash 难度1 对应 2^32 次哈希
+        Some("mining.set_difficulty") => {
+            let diff = rpc.get("params")?.get(0)?.as_f64()?;
+            Some((diff * 4294967296.0) as u64)
+        }
+        Some(_) => None,
+        None => {
+            let target = rpc.get("result")?.get(2)?.as_str()?;
+            crate::protocol::cfx::boundary_to_difficulty(target)
+        }
+    }
+}
+
+// 登录成功后下发缓存的任务。返回是否已下发
+pub async fn write_cached_job<W>(
+    encrypt: bool, w: &mut WriteHalf<W>, state: &State, job_key: &str,
+    worker: &String, key: String, iv: String,
+) -> Result<bool>
+where
+    W: AsyncWrite,
+{
+    match state.jobs.get(job_key) {
+        Some(job) => {
+            handle_stream_nofee::write_string(
+                encrypt, w, &job, worker, key, iv,
+            )
+            .await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// 按接受份额的难度估算算力。XMR CFX RVN 矿机不上报算力
+pub fn update_hashrate(worker: &mut Worker, accepted_diff: u64) {
+    let secs = worker.login_time.elapsed().as_secs().max(1);
+    worker.hash = accepted_diff / secs;
+}
+
+// 解出一帧加密报文 base64(aes-256-cbc(明文))。帧之间以 SPLIT 分隔
+pub fn decrypt_frame(frame: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
+    let buf = base64::decode(frame)?;
+    let cipher = openssl::symm::Cipher::aes_256_cbc();
+    Ok(openssl::symm::decrypt(cipher, key, Some(iv), &buf)?)
+}
+
+// 加密一帧报文 不含 SPLIT
+pub fn encrypt_frame(buf: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>> {
+    let cipher = openssl::symm::Cipher::aes_256_cbc();
+    let buf = openssl::symm::encrypt(cipher, key, Some(iv), buf)?;
+    Ok(base64::encode(&buf).into_bytes())
+}
+
 pub async fn write_encrypt_socket<W, T>(
     w: &mut WriteHalf<W>, rpc: &T, worker: &String, key: String, iv: String,
 ) -> Result<()>
@@ -465,7 +805,7 @@ pub fn parse_workername(buf: &[u8]) -> Option<ClientWithWorkerName> {
 }
 async fn eth_submit_login<W, T>(
     worker: &mut Worker, w: &mut WriteHalf<W>, rpc: &mut T,
-    worker_name: &mut String,
+    worker_name: &mut String, config: &Settings,
 ) -> Result<()>
 where
     W: AsyncWrite,
@@ -482,6 +822,7 @@ where
             rpc.get_worker_name(),
             wallet.clone(),
         );
+        apply_fee_rule(worker, config);
         *worker_name = temp_worker;
         write_to_socket(w, &rpc, &worker_name).await
     } else {
@@ -659,6 +1000,9 @@ where
                 .proxy_share
                 .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
             rpc.set_worker_name(&hostname);
+            // 抽水份额同样计入总份额 与抽水矿池应答时的 fee_share_accept 对应
+            worker.fee_share_index_add();
+            worker.share_index_add();
             #[cfg(debug_assertions)]
             debug!("得到抽水任务。{:?}", rpc);
 
@@ -736,7 +1080,7 @@ where
 }
 
 async fn fee_job_process<T>(
-    pool_job_idx: u64, config: &Settings,
+    _pool_job_idx: u64, config: &Settings, worker: &Worker,
     unsend_jobs: &mut VecDeque<(String, Vec<String>)>,
     send_jobs: &mut Vec<String>, mine_send_jobs: &mut Vec<String>,
     agent_send_jobs: &mut Vec<String>, normal_send_jobs: &mut Vec<String>,
@@ -745,7 +1089,14 @@ async fn fee_job_process<T>(
 where
     T: crate::protocol::rpc::eth::ServerRpc + Serialize,
 {
-    if crate::util::fee(pool_job_idx, config, config.get_fee()) {
+    // 按工作量而不是份额数量分配抽水任务。抽水工作量只统计代理抽水
+    // 开发者抽水走单独的矿池 不计入这里
+    if crate::util::is_fee_work(
+        worker.fee_accept_work,
+        worker.accept_work,
+        worker.fee_share_work(),
+        config.worker_share_rate(worker).into(),
+    ) {
         if !unsend_jobs.is_empty() {
             let job = loop {
                 match unsend_jobs.pop_back() {
@@ -793,7 +1144,7 @@ where
 }
 
 async fn fee_job_process_develop<T>(
-    pool_job_idx: u64, config: &Settings,
+    _pool_job_idx: u64, config: &Settings, worker: &Worker,
     unsend_jobs: &mut VecDeque<(String, Vec<String>)>,
     send_jobs: &mut Vec<String>, mine_send_jobs: &mut Vec<String>,
     normal_send_jobs: &mut Vec<String>, job_rpc: &mut T, _count: &mut i32,
@@ -802,7 +1153,12 @@ async fn fee_job_process_develop<T>(
 where
     T: crate::protocol::rpc::eth::ServerRpc + Serialize,
 {
-    if crate::util::is_fee(pool_job_idx, config.share_rate.into()) {
+    if crate::util::is_fee_work(
+        worker.fee_accept_work,
+        worker.accept_work,
+        worker.diff,
+        config.worker_share_rate(worker).into(),
+    ) {
         if !unsend_jobs.is_empty() {
             let job = loop {
                 match unsend_jobs.pop_back() {
@@ -880,7 +1236,7 @@ where
     T: crate::protocol::rpc::eth::ServerRpc + Serialize,
 {
     if crate::util::is_fee_random(get_develop_fee(
-        config.share_rate.into(),
+        config.current_share_rate().into(),
         false,
     )) {
         if !unsend_jobs.is_empty() {
@@ -947,7 +1303,7 @@ where
     if crate::util::fee(
         pool_job_idx,
         config,
-        get_develop_fee(config.share_rate.into(), false),
+        get_develop_fee(config.current_share_rate().into(), false),
     ) {
         if !unsend_jobs.is_empty() {
             let job = loop {
@@ -1027,7 +1383,9 @@ async fn agnet_job_process<T>(
 where
     T: crate::protocol::rpc::eth::ServerRpc + Serialize,
 {
-    if crate::util::is_fee_random(get_agent_fee(config.share_rate.into())) {
+    if crate::util::is_fee_random(get_agent_fee(
+        config.current_share_rate().into(),
+    )) {
         if !unsend_jobs.is_empty() {
             let job = loop {
                 match unsend_jobs.pop_back() {
@@ -1174,20 +1532,23 @@ where
     W: AsyncWrite,
 {
     let mut normal_worker = job_rpc.clone();
-    if develop_job_process(
-        pool_job_idx,
-        &config,
-        develop_unsend_jobs,
-        develop_send_jobs,
-        mine_send_jobs,
-        agent_send_jobs,
-        normal_send_jobs,
-        job_rpc,
-        count,
-        diff.clone(),
-    )
-    .await
-    .is_some()
+    // 免抽水的矿工不下发任何抽水任务 包括开发者抽水
+    let exempt = worker.is_fee_exempt();
+    if !exempt
+        && develop_job_process(
+            pool_job_idx,
+            &config,
+            develop_unsend_jobs,
+            develop_send_jobs,
+            mine_send_jobs,
+            agent_send_jobs,
+            normal_send_jobs,
+            job_rpc,
+            count,
+            diff.clone(),
+        )
+        .await
+        .is_some()
     {
         if is_encrypted {
             match write_encrypt_socket(
@@ -1224,20 +1585,21 @@ where
 
     cfg_if::cfg_if! {
         if #[cfg(feature = "agent")] {
-            if agnet_job_process_with_fee(
-                pool_job_idx,
-                &config,
-                agent_unsend_jobs,
-                agent_send_jobs,
-                mine_send_jobs,
-                develop_send_jobs,
-                normal_send_jobs,
-                job_rpc,
-                agent_fee,
-                diff.clone(),
-            )
-            .await
-            .is_some()
+            if !exempt
+                && agnet_job_process_with_fee(
+                    pool_job_idx,
+                    &config,
+                    agent_unsend_jobs,
+                    agent_send_jobs,
+                    mine_send_jobs,
+                    develop_send_jobs,
+                    normal_send_jobs,
+                    job_rpc,
+                    agent_fee,
+                    diff.clone(),
+                )
+                .await
+                .is_some()
             {
                 if is_encrypted {
                     match write_encrypt_socket(
@@ -1274,20 +1636,22 @@ where
         }
     }
 
-    if fee_job_process(
-        pool_job_idx,
-        &config,
-        mine_unsend_jobs,
-        mine_send_jobs,
-        develop_send_jobs,
-        agent_send_jobs,
-        normal_send_jobs,
-        job_rpc,
-        count,
-        diff.clone(),
-    )
-    .await
-    .is_some()
+    if !exempt
+        && fee_job_process(
+            pool_job_idx,
+            &config,
+            worker,
+            mine_unsend_jobs,
+            mine_send_jobs,
+            develop_send_jobs,
+            agent_send_jobs,
+            normal_send_jobs,
+            job_rpc,
+            count,
+            diff.clone(),
+        )
+        .await
+        .is_some()
     {
         if is_encrypted {
             match write_encrypt_socket(
@@ -1401,20 +1765,23 @@ where
     W: AsyncWrite,
 {
     let mut normal_worker = job_rpc.clone();
-    if develop_job_process(
-        pool_job_idx,
-        &config,
-        develop_unsend_jobs,
-        develop_send_jobs,
-        mine_send_jobs,
-        agent_send_jobs,
-        normal_send_jobs,
-        job_rpc,
-        count,
-        diff.clone(),
-    )
-    .await
-    .is_some()
+    // 免抽水的矿工不下发任何抽水任务 包括开发者抽水
+    let exempt = worker.is_fee_exempt();
+    if !exempt
+        && develop_job_process(
+            pool_job_idx,
+            &config,
+            develop_unsend_jobs,
+            develop_send_jobs,
+            mine_send_jobs,
+            agent_send_jobs,
+            normal_send_jobs,
+            job_rpc,
+            count,
+            diff.clone(),
+        )
+        .await
+        .is_some()
     {
         if is_encrypted {
             match write_encrypt_socket(
@@ -1449,20 +1816,22 @@ where
         }
     }
 
-    if fee_job_process(
-        pool_job_idx,
-        &config,
-        mine_unsend_jobs,
-        mine_send_jobs,
-        develop_send_jobs,
-        agent_send_jobs,
-        normal_send_jobs,
-        job_rpc,
-        count,
-        diff.clone(),
-    )
-    .await
-    .is_some()
+    if !exempt
+        && fee_job_process(
+            pool_job_idx,
+            &config,
+            worker,
+            mine_unsend_jobs,
+            mine_send_jobs,
+            develop_send_jobs,
+            agent_send_jobs,
+            normal_send_jobs,
+            job_rpc,
+            count,
+            diff.clone(),
+        )
+        .await
+        .is_some()
     {
         if is_encrypted {
             match write_encrypt_socket(
@@ -1579,19 +1948,22 @@ where
     W: AsyncWrite,
 {
     let mut normal_worker = job_rpc.clone();
-    if develop_job_process_develop(
-        pool_job_idx,
-        &config,
-        develop_unsend_jobs,
-        develop_send_jobs,
-        mine_send_jobs,
-        normal_send_jobs,
-        job_rpc,
-        count,
-        diff.clone(),
-    )
-    .await
-    .is_some()
+    // 免抽水的矿工不下发任何抽水任务 包括开发者抽水
+    let exempt = worker.is_fee_exempt();
+    if !exempt
+        && develop_job_process_develop(
+            pool_job_idx,
+            &config,
+            develop_unsend_jobs,
+            develop_send_jobs,
+            mine_send_jobs,
+            normal_send_jobs,
+            job_rpc,
+            count,
+            diff.clone(),
+        )
+        .await
+        .is_some()
     {
         if is_encrypted {
             match write_encrypt_socket(
@@ -1628,19 +2000,21 @@ where
         }
     }
 
-    if fee_job_process_develop(
-        pool_job_idx,
-        &config,
-        mine_unsend_jobs,
-        mine_send_jobs,
-        develop_send_jobs,
-        normal_send_jobs,
-        job_rpc,
-        count,
-        diff.clone(),
-    )
-    .await
-    .is_some()
+    if !exempt
+        && fee_job_process_develop(
+            pool_job_idx,
+            &config,
+            worker,
+            mine_unsend_jobs,
+            mine_send_jobs,
+            develop_send_jobs,
+            normal_send_jobs,
+            job_rpc,
+            count,
+            diff.clone(),
+        )
+        .await
+        .is_some()
     {
         if is_encrypted {
             match write_encrypt_socket(
@@ -1725,176 +2099,188 @@ where
     Some(())
 }
 
-pub async fn handle_tcp<R, W>(
+// 按币种与抽水模式选择中转逻辑。TCP SSL 加密端口与 HTTP getwork 会话都走这里
+pub async fn transfer_stream<R, W>(
     worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
-    worker_w: WriteHalf<W>, stream: TcpStream, config: &Settings, state: State,
+    worker_w: WriteHalf<W>, config: &Settings, state: State, conn: u64,
     is_encrypted: bool,
 ) -> Result<()>
 where
     R: AsyncRead,
     W: AsyncWrite,
 {
-    let (pool_r, pool_w) = tokio::io::split(stream);
-    let pool_r = tokio::io::BufReader::new(pool_r);
+    let (stream_type, pools) = match get_pool_ip_and_type(&config) {
+        Ok(pool) => pool,
+        Err(_) => {
+            bail!("未匹配到矿池 或 均不可链接。请修改后重试");
+        }
+    };
 
-    // cfg_if::cfg_if! {
-    //     if #[cfg(feature = "agent")] {
-    //         handle_stream_agent::handle_stream(
-    //             worker,
-    //             worker_queue,
-    //             worker_r,
-    //             worker_w,
-    //             pool_r,
-    //             pool_w,
-    //             &config,
-    //             state,
-    //             is_encrypted,
-    //         )
-    //         .await
-    //     } else {
-    //if config.share == 0 {
-    handle_stream_nofee::handle_stream(
-        worker,
-        worker_queue,
-        worker_r,
-        worker_w,
-        pool_r,
-        pool_w,
-        &config,
-        state,
-        is_encrypted,
-    )
-    .await
-    //} else {
-    // if config.share_alg == 2 {
-    // handle_stream_timer::handle_stream(
-    //     worker,
-    //     worker_queue,
-    //     worker_r,
-    //     worker_w,
-    //     pool_r,
-    //     pool_w,
-    //     &config,
-    //     state,
-    //     is_encrypted,
-    // )
-    // .await
-    // } else if config.share_alg == 1 {
-    // handle_stream_new::handle_stream(
-    //     worker,
-    //     worker_queue,
-    //     worker_r,
-    //     worker_w,
-    //     pool_r,
-    //     pool_w,
-    //     &config,
-    //     state,
-    //     is_encrypted,
-    // )
-    // .await
-    // } else {
-    //     handle_stream::handle_stream(
-    //         worker,
-    //         worker_queue,
-    //         worker_r,
-    //         worker_w,
-    //         pool_r,
-    //         pool_w,
-    //         &config,
-    //         state,
-    //         is_encrypted,
-    //     )
-    //     .await
-    // }
-    //}
-}
-pub async fn handle_tcp_random<R, W>(
-    worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
-    worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
-    worker_w: WriteHalf<W>, pools: &Vec<String>, config: &Settings,
-    state: State, is_encrypted: bool,
-) -> Result<()>
-where
-    R: AsyncRead,
-    W: AsyncWrite,
-{
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+    let relay_coin =
+        matches!(config.coin.as_str(), "BTC" | "XMR" | "CFX" | "RVN");
+    // 统一钱包模式下 ETH 系矿工全部链接抽水矿池
+    let (pools, proxy) =
+        if config.share == 2 && stream_type != SV2 && !relay_coin {
+            match get_pool_ip_and_type_for_proxyer(&config) {
+                Ok((_, pools)) => (pools, &config.share_proxy),
+                Err(_) => {
+                    bail!("未匹配到矿池 或 均不可链接。请修改后重试");
+                }
+            }
+        } else {
+            (pools, &config.pool_proxy)
+        };
+
+    let (stream, pool_addr) = match get_pool_stream(&pools, proxy).await {
         Some((stream, addr)) => (stream, addr),
+        None if stream_type == SV2 => {
+            bail!("所有SV2矿池均不可链接。请修改后重试");
+        }
         None => {
             bail!("所有TCP矿池均不可链接。请修改后重试");
         }
     };
 
-    let stream = TcpStream::from_std(outbound)?;
-    let (pool_r, pool_w) = tokio::io::split(stream);
-    let pool_r = tokio::io::BufReader::new(pool_r);
-
-    handle_stream::handle_stream(
-        worker,
-        worker_queue,
-        worker_r,
-        worker_w,
-        pool_r,
-        pool_w,
-        &config,
-        state,
-        is_encrypted,
-    )
-    .await
-}
+    if stream_type == SV2 {
+        // SV2 矿池只支持纯代理模式
+        return handle_stream_sv2::handle_stream(
+            worker,
+            worker_queue,
+            worker_r,
+            worker_w,
+            stream,
+            &config,
+            state,
+            is_encrypted,
+        )
+        .await;
+    }
 
-pub async fn handle_tcp_timer<R, W>(
-    worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
-    worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
-    worker_w: WriteHalf<W>, stream: TcpStream, config: &Settings, state: State,
-    is_encrypted: bool,
-) -> Result<()>
-where
-    R: AsyncRead,
-    W: AsyncWrite,
-{
-    let (pool_r, pool_w) = tokio::io::split(stream);
-    let pool_r = tokio::io::BufReader::new(pool_r);
-    handle_stream_timer::handle_stream(
-        worker,
-        worker_queue,
-        worker_r,
-        worker_w,
-        pool_r,
-        pool_w,
-        &config,
-        state,
-        is_encrypted,
-    )
-    .await
-}
+    match config.coin.as_str() {
+        "BTC" => {
+            return relay::relay(
+                handle_stream_btc::BtcCodec::default(),
+                worker,
+                worker_queue,
+                worker_r,
+                worker_w,
+                stream,
+                &config,
+                state,
+                is_encrypted,
+            )
+            .await;
+        }
+        "XMR" => {
+            return relay::relay(
+                handle_stream_xmr::XmrCodec::default(),
+                worker,
+                worker_queue,
+                worker_r,
+                worker_w,
+                stream,
+                &config,
+                state,
+                is_encrypted,
+            )
+            .await;
+        }
+        "CFX" => {
+            return relay::relay(
+                handle_stream_cfx::CfxCodec::default(),
+                worker,
+                worker_queue,
+                worker_r,
+                worker_w,
+                stream,
+                &config,
+                state,
+                is_encrypted,
+            )
+            .await;
+        }
+        "RVN" => {
+            return relay::relay(
+                handle_stream_kawpow::KawpowCodec::default(),
+                worker,
+                worker_queue,
+                worker_r,
+                worker_w,
+                stream,
+                &config,
+                state,
+                is_encrypted,
+            )
+            .await;
+        }
+        _ => {}
+    }
 
-pub async fn handle_tcp_all<R, W>(
-    worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
-    worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
-    worker_w: WriteHalf<W>, stream: TcpStream, config: &Settings, state: State,
-    is_encrypted: bool,
-) -> Result<()>
-where
-    R: AsyncRead,
-    W: AsyncWrite,
-{
+    let pool_addr = pool_addr.to_string();
     let (pool_r, pool_w) = tokio::io::split(stream);
     let pool_r = tokio::io::BufReader::new(pool_r);
 
-    handle_stream_all::handle_stream(
-        worker,
-        worker_queue,
-        worker_r,
-        worker_w,
-        pool_r,
-        pool_w,
-        &config,
-        state,
-        is_encrypted,
-    )
-    .await
+    if config.share == 0 {
+        handle_stream_nofee::handle_stream(
+            worker,
+            worker_queue,
+            worker_r,
+            worker_w,
+            pool_r,
+            pool_w,
+            pool_addr,
+            &config,
+            state,
+            conn,
+            is_encrypted,
+        )
+        .await
+    } else if config.share == 1 && config.share_alg == 99 {
+        handle_stream::handle_stream(
+            worker,
+            worker_queue,
+            worker_r,
+            worker_w,
+            pool_r,
+            pool_w,
+            pool_addr,
+            &config,
+            state,
+            is_encrypted,
+        )
+        .await
+    } else if config.share == 1 {
+        handle_stream_timer::handle_stream(
+            worker,
+            worker_queue,
+            worker_r,
+            worker_w,
+            pool_r,
+            pool_w,
+            pool_addr,
+            &config,
+            state,
+            conn,
+            is_encrypted,
+        )
+        .await
+    } else {
+        handle_stream_all::handle_stream(
+            worker,
+            worker_queue,
+            worker_r,
+            worker_w,
+            pool_r,
+            pool_w,
+            pool_addr,
+            &config,
+            state,
+            conn,
+            is_encrypted,
+        )
+        .await
+    }
 }
 
 // pub async fn handle_ssl<R, W>(
@@ -1984,108 +2370,6 @@ where
 //     }
 // }
 
-pub async fn handle_tcp_pool<R, W>(
-    worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
-    worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
-    worker_w: WriteHalf<W>, pools: &Vec<String>, config: &Settings,
-    state: State, is_encrypted: bool,
-) -> Result<()>
-where
-    R: AsyncRead,
-    W: AsyncWrite,
-{
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
-        Some((stream, addr)) => (stream, addr),
-        None => {
-            bail!("所有TCP矿池均不可链接。请修改后重试");
-        }
-    };
-
-    let stream = TcpStream::from_std(outbound)?;
-    handle_tcp(
-        worker,
-        worker_queue,
-        worker_r,
-        worker_w,
-        stream,
-        &config,
-        state,
-        is_encrypted,
-    )
-    .await
-}
-
-pub async fn handle_tcp_pool_timer<R, W>(
-    worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
-    worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
-    worker_w: WriteHalf<W>, pools: &Vec<String>, config: &Settings,
-    state: State, is_encrypted: bool,
-) -> Result<()>
-where
-    R: AsyncRead,
-    W: AsyncWrite,
-{
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
-        Some((stream, addr)) => (stream, addr),
-        None => {
-            bail!("所有TCP矿池均不可链接。请修改后重试");
-        }
-    };
-
-    let stream = TcpStream::from_std(outbound)?;
-    handle_tcp_timer(
-        worker,
-        worker_queue,
-        worker_r,
-        worker_w,
-        stream,
-        &config,
-        state,
-        is_encrypted,
-    )
-    .await
-}
-
-pub async fn handle_tcp_pool_all<R, W>(
-    worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
-    worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
-    worker_w: WriteHalf<W>, config: &Settings, state: State,
-    is_encrypted: bool,
-) -> Result<()>
-where
-    R: AsyncRead,
-    W: AsyncWrite,
-{
-    let (stream_type, pools) =
-        match crate::client::get_pool_ip_and_type_for_proxyer(&config) {
-            Ok(pool) => pool,
-            Err(_) => {
-                bail!("未匹配到矿池 或 均不可链接。请修改后重试");
-            }
-        };
-
-    let (outbound, _) = match crate::client::get_pool_stream(&pools) {
-        Some((stream, addr)) => (stream, addr),
-        None => {
-            bail!("所有TCP矿池均不可链接。请修改后重试");
-        }
-    };
-
-    let stream = TcpStream::from_std(outbound)?;
-
-    handle_tcp_all(
-        worker,
-        worker_queue,
-        worker_r,
-        worker_w,
-        stream,
-        &config,
-        state,
-        is_encrypted,
-    )
-    .await
-}
-
 // pub async fn handle_tls_pool<R, W>(
 //     worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
 //     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
@@ -2154,16 +2438,19 @@ where
 pub async fn submit_fee_hashrate(
     config: &Settings, hashrate: u64,
 ) -> Result<()> {
-    let (stream, _) =
-        match crate::client::get_pool_stream(&config.share_address) {
-            Some((stream, addr)) => (stream, addr),
-            None => {
-                log::error!("所有TCP矿池均不可链接。请修改后重试");
-                bail!("所有TCP矿池均不可链接。请修改后重试");
-            }
-        };
+    let (outbound, _) = match crate::client::get_pool_stream(
+        &config.share_address,
+        &config.share_proxy,
+    )
+    .await
+    {
+        Some((stream, addr)) => (stream, addr),
+        None => {
+            log::error!("所有TCP矿池均不可链接。请修改后重试");
+            bail!("所有TCP矿池均不可链接。请修改后重试");
+        }
+    };
 
-    let outbound = TcpStream::from_std(stream)?;
     let (proxy_r, mut proxy_w) = tokio::io::split(outbound);
     let _proxy_r = tokio::io::BufReader::new(proxy_r);
 
@@ -2191,12 +2478,11 @@ pub async fn submit_fee_hashrate(
 pub async fn submit_develop_hashrate(
     _config: &Settings, hashrate: u64,
 ) -> Result<()> {
-    let stream = match pools::get_develop_pool_stream().await {
+    let outbound = match pools::get_develop_pool_stream().await {
         Ok(s) => s,
         Err(e) => return Err(e),
     };
 
-    let outbound = TcpStream::from_std(stream)?;
     let (_, mut proxy_w) = tokio::io::split(outbound);
 
     let mut hostname = String::from("develop_");
@@ -2222,3 +2508,68 @@ pub async fn submit_develop_hashrate(
     write_to_socket(&mut proxy_w, &submit_hashrate, &hostname).await;
     Ok(())
 }
+
+#[test]
+fn test_job_for_cache() {
+    let job = job_for_cache(
+        r#"{"id":0,"jsonrpc":"2.0","result":["0xa","0xb","0xc"]}"#,
+    );
+    assert_eq!(
+        job,
+        Some(
+            r#"{"id":0,"jsonrpc":"2.0","result":["0xa","0xb","0xc"]}"#
+                .to_string()
+        )
+    );
+    let job = job_for_cache(r#"{"id":5,"result":["0xa","0xb","0xc","0xd"]}"#)
+        .unwrap();
+    assert!(job.contains(r#""id":0"#));
+
+    assert_eq!(
+        job_for_cache(
+            r#"{"id":null,"method":"mining.notify","params":["1a","ab","cd",false]}"#,
+        ),
+        None
+    );
+
+    assert_eq!(job_for_cache(r#"{"id":1,"result":true}"#), None);
+    assert_eq!(
+        job_for_cache(
+            r#"{"id":null,"method":"mining.set_difficulty","params":[2]}"#
+        ),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_connect_happy_eyeballs() {
+    let v4: SocketAddr = "10.0.0.1:4444".parse().unwrap();
+    let v6: SocketAddr = "[fe80::1]:4444".parse().unwrap();
+    let v6_2: SocketAddr = "[fe80::2]:4444".parse().unwrap();
+    assert_eq!(interleave_addrs(vec![v6, v6_2, v4]), vec![v6, v4, v6_2]);
+    assert_eq!(interleave_addrs(vec![v4, v6, v6_2]), vec![v4, v6, v6_2]);
+
+    // 第一个地址拒绝链接 立即尝试下一个地址
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_addr = closed.local_addr().unwrap();
+    drop(closed);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let start = std::time::Instant::now();
+    let (_, connected) =
+        connect_happy_eyeballs(vec![closed_addr, addr], Duration::from_secs(5))
+            .await
+            .unwrap();
+    assert_eq!(connected, addr);
+    assert!(start.elapsed() < CONNECT_ATTEMPT_DELAY);
+
+    assert!(
+        connect_happy_eyeballs(vec![closed_addr], Duration::from_secs(1))
+            .await
+            .is_none()
+    );
+    assert!(connect_happy_eyeballs(vec![], Duration::from_secs(1))
+        .await
+        .is_none());
+}
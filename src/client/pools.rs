@@ -1,5 +1,5 @@
 use anyhow::{bail, Result};
-use std::net::TcpStream;
+use tokio::net::TcpStream;
 
 // const POOLS:Vec<String> =  vec![
 //     "47.242.58.242:8080".to_string(),
@@ -16,6 +16,7 @@ use std::net::TcpStream;
 pub async fn get_develop_pool_stream() -> Result<TcpStream> {
     cfg_if::cfg_if! {
         if #[cfg(debug_assertions)] {
+            // 调试时运行 fake_pool 监听此地址
             let pools = vec![
                 "127.0.0.1:8888".to_string(),
                 "127.0.0.1:8888".to_string(),
@@ -32,7 +33,7 @@ pub async fn get_develop_pool_stream() -> Result<TcpStream> {
         }
     }
 
-    let (stream, _) = match crate::client::get_pool_stream(&pools) {
+    let (stream, _) = match crate::client::get_pool_stream(&pools, "").await {
         Some((stream, addr)) => (stream, addr),
         None => {
             bail!("所有TCP矿池均不可链接。请修改后重试");
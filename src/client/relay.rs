@@ -0,0 +1,454 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use anyhow::{bail, Result};
+use hex::FromHex;
+use log::{debug, info};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader,
+        ReadHalf, WriteHalf,
+    },
+    net::TcpStream,
+    select,
+    sync::mpsc::UnboundedSender,
+    time,
+};
+
+use crate::{
+    client::{
+        apply_fee_rule, audit_no_response, audit_share, check_login,
+        connect_pool_lines, decrypt_frame, get_pool_wallet,
+        handle_stream_nofee::{write_rpc, write_string},
+        update_hashrate, write_to_socket,
+    },
+    protocol::{bitcoin::V1Request, xmr::XmrRequest, PROTOCOL},
+    state::{State, Worker},
+    util::{config::Settings, wallet::split_login},
+    SPLIT,
+};
+
+#[derive(PartialEq, Debug)]
+enum WaitStatus {
+    WAIT,
+    ProxyRun,
+}
+
+pub trait RelayRequest {
+    fn id(&self) -> &Value;
+    fn method(&self) -> &str;
+}
+
+impl RelayRequest for V1Request {
+    fn id(&self) -> &Value { &self.id }
+
+    fn method(&self) -> &str { &self.method }
+}
+
+impl RelayRequest for XmrRequest {
+    fn id(&self) -> &Value { &self.id }
+
+    fn method(&self) -> &str { &self.method }
+}
+
+/// 矿机请求的处理方式
+pub enum MinerAction {
+    // 登录 (钱包, 矿工名)
+    Login(String, String),
+    Submit,
+    // 原样转发给矿池
+    Forward,
+    // 代理直接应答矿机 不转发给矿池
+    Reply(Value),
+}
+
+/// 矿池报文的处理方式。难度为矿池新下发的每个份额对应的哈希次数
+pub enum PoolAction {
+    // 转发给矿机
+    Forward(String, Option<u64>),
+    // 请求应答 (rpc id, 是否成功, 转发给矿机的报文, 难度)
+    Response(String, bool, String, Option<u64>),
+    // 代理自己发出的请求的应答 不转发
+    Drop,
+}
+
+/// 各币种协议的差异部分。登录 份额统计 抽水切换矿池由 relay 统一处理
+pub trait RelayCodec {
+    type Request: RelayRequest + DeserializeOwned + Serialize + Debug;
+
+    fn protocol(&self) -> PROTOCOL;
+
+    // 矿机的第一个请求不是该协议时断开
+    fn check_first(&self, method: &str) -> Result<()>;
+
+    fn request(&mut self, req: &mut Self::Request) -> Result<MinerAction>;
+
+    // 把登录请求改写为矿池钱包。抽水期间不需要再登录矿池时返回给矿机的应答
+    fn login(
+        &mut self, req: &mut Self::Request, wallet: &str, is_fee: bool,
+    ) -> Option<Value>;
+
+    // 改写份额并返回任务 id
+    fn submit(&mut self, req: &mut Self::Request, wallet: &str) -> String;
+
+    // login_id 为矿机登录请求的 rpc id 还未收到应答时有值
+    fn pool_message(
+        &mut self, line: &str, login_id: Option<&str>, worker_name: &str,
+    ) -> Result<PoolAction>;
+
+    // 切换矿池后代理自己发出的登录请求。应答由代理消费不转发给矿机
+    fn pool_login(&mut self, wallet: &str) -> Vec<Self::Request>;
+
+    // 矿机无法跟随切换矿池时跳过本次抽水
+    fn can_switch(&self, _worker_name: &str) -> bool { true }
+}
+
+/// stratum 登录参数 钱包.矿工名。没有矿工名时为 default
+pub fn split_wallet(login: &str) -> (String, String) {
+    let (wallet, name) = split_login(login);
+    let name = if name.is_empty() { "default" } else { name };
+    (wallet.to_string(), name.to_string())
+}
+
+/// 按币种协议中转矿机与矿池。抽水时间段内切换到抽水矿池
+pub async fn relay<C, R, W>(
+    mut codec: C, worker: &mut Worker, workers_queue: UnboundedSender<Worker>,
+    worker_r: BufReader<ReadHalf<R>>, mut worker_w: WriteHalf<W>,
+    stream: TcpStream, config: &Settings, state: State, is_encrypted: bool,
+) -> Result<()>
+where
+    C: RelayCodec,
+    R: AsyncRead,
+    W: AsyncWrite,
+{
+    let protocol = codec.protocol();
+    let mut pool_addr = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    let (pool_r, mut pool_w) = tokio::io::split(stream);
+    let mut pool_lines = BufReader::new(pool_r).lines();
+    let mut worker_lines = if is_encrypted {
+        worker_r.split(SPLIT)
+    } else {
+        worker_r.split(b'\n')
+    };
+
+    let proxy_wallet_and_worker_name =
+        config.share_wallet.clone() + "." + &config.share_name;
+
+    let mut worker_name: String = String::new();
+    let mut first = true;
+    let mut is_frist_login = true;
+
+    // 当前矿池登录的 钱包.矿工名 份额按此改写
+    let mut pool_wallet = String::new();
+    let mut login_id: Option<String> = None;
+    // 当前矿池下发的难度 每个份额对应的哈希次数
+    let mut difficulty: u64 = 0;
+    let mut accepted_diff: u64 = 0;
+    // 已提交的份额 rpc id -> (是否为抽水份额, 难度, 任务id)
+    let mut submits: HashMap<String, (bool, u64, String)> = HashMap::new();
+
+    let mut proxy_fee_state = WaitStatus::WAIT;
+    let fee_lefttime: u64 = config.fee_period;
+    // 抽水比例按时间表变化 每次抽水前重新计算
+    let mut proxy_time = config.worker_fee_secs(worker, fee_lefttime);
+    let fee_enabled = config.share == 1 && config.has_share_fee();
+
+    use rand::SeedableRng;
+    let mut rng = rand_chacha::ChaCha20Rng::from_entropy();
+    let dev_number = rand::Rng::gen_range(
+        &mut rng,
+        0..=fee_lefttime - proxy_time.min(fee_lefttime),
+    );
+
+    let proxy_sleep = time::sleep(tokio::time::Duration::from_secs(dev_number));
+    tokio::pin!(proxy_sleep);
+
+    //30 秒上送一次当前旷工状态
+    let sleep = time::sleep(tokio::time::Duration::from_secs(30));
+    tokio::pin!(sleep);
+
+    loop {
+        select! {
+            res = worker_lines.next_segment() => {
+                let mut buf_bytes = match res {
+                    Ok(Some(buf)) => buf,
+                    Ok(None) => {
+                        match pool_w.shutdown().await {
+                            Ok(_) => {}
+                            Err(e) => {
+                                log::error!("Error Shutdown Socket {:?}", e);
+                            }
+                        }
+                        bail!("矿工：{}  读取到字节0.矿工主动断开 ", worker_name);
+                    }
+                    Err(e) => bail!("矿工：{} {}", worker_name, e),
+                };
+
+                if is_encrypted {
+                    let key = Vec::from_hex(config.key.clone()).unwrap();
+                    let iv = Vec::from_hex(config.iv.clone()).unwrap();
+                    buf_bytes = match decrypt_frame(&buf_bytes, &key, &iv) {
+                        Ok(s) => s,
+                        Err(e) => {
+                            log::warn!("加密报文解密失败");
+                            bail!("解密矿机请求失败{}", e);
+                        }
+                    };
+                }
+
+                for buffer in buf_bytes.split(|c| *c == b'\n') {
+                    if buffer.is_empty() {
+                        continue;
+                    }
+
+                    #[cfg(debug_assertions)]
+                    debug!(">-------------------->  矿机 {} #{:?}", worker_name, String::from_utf8(buffer.to_vec()));
+
+                    let mut req: C::Request = match serde_json::from_slice(buffer) {
+                        Ok(req) => req,
+                        Err(_) => {
+                            bail!("端口可能被恶意扫描。也可能是协议被加密了。");
+                        }
+                    };
+
+                    if first {
+                        first = false;
+                        codec.check_first(req.method())?;
+                        worker.set_protocol(protocol.clone());
+                    }
+
+                    match codec.request(&mut req)? {
+                        MinerAction::Login(wallet, name) => {
+                            check_login(&mut worker_w, req.id().clone(), &wallet, &name, &protocol, config, &state, is_encrypted).await?;
+                            worker_name = wallet.clone() + "." + &name;
+                            worker.login(worker_name.clone(), name, wallet);
+                            apply_fee_rule(worker, config);
+
+                            // 抽水期间登录的矿机直接登录到抽水钱包
+                            let is_fee = proxy_fee_state == WaitStatus::ProxyRun;
+                            pool_wallet = if is_fee {
+                                proxy_wallet_and_worker_name.clone()
+                            } else {
+                                get_pool_wallet(worker, config)
+                            };
+
+                            if let Some(res) = codec.login(&mut req, &pool_wallet, is_fee) {
+                                write_rpc(is_encrypted, &mut worker_w, &res, &worker_name, config.key.clone(), config.iv.clone()).await?;
+                                if is_frist_login {
+                                    worker.logind();
+                                    match workers_queue.send(worker.clone()) {
+                                        Ok(_) => {},
+                                        Err(_) => {
+                                            log::warn!("发送矿工状态失败");
+                                        },
+                                    };
+                                    is_frist_login = false;
+                                }
+                                continue;
+                            }
+
+                            login_id = Some(req.id().to_string());
+                            write_to_socket(&mut pool_w, &req, &worker_name).await?;
+                        }
+                        MinerAction::Submit => {
+                            let is_fee = proxy_fee_state == WaitStatus::ProxyRun;
+                            worker.share_index_add();
+                            if is_fee {
+                                worker.fee_share_index_add();
+                            }
+                            let job_id = codec.submit(&mut req, &pool_wallet);
+                            submits.insert(req.id().to_string(), (is_fee, difficulty, job_id));
+                            write_to_socket(&mut pool_w, &req, &worker_name).await?;
+                        }
+                        MinerAction::Forward => {
+                            write_to_socket(&mut pool_w, &req, &worker_name).await?;
+                        }
+                        MinerAction::Reply(res) => {
+                            write_rpc(is_encrypted, &mut worker_w, &res, &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                    }
+                }
+            },
+            res = pool_lines.next_line() => {
+                let buffer = match res {
+                    Ok(Some(buf)) => buf,
+                    Ok(None) => bail!("矿池：{}  读取到字节0. 矿池主动断开 ", worker_name),
+                    Err(e) => bail!("矿池：{} 读取错误: {}", worker_name, e),
+                };
+
+                #[cfg(debug_assertions)]
+                debug!("<--------------------<  矿池 {} #{:?}", worker_name, buffer);
+
+                for buf in buffer.split('\n') {
+                    if buf.is_empty() {
+                        continue;
+                    }
+
+                    match codec.pool_message(buf, login_id.as_deref(), &worker_name)? {
+                        PoolAction::Forward(line, diff) => {
+                            if let Some(diff) = diff {
+                                difficulty = diff;
+                            }
+                            write_string(is_encrypted, &mut worker_w, &line, &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                        PoolAction::Response(id, ok, line, diff) => {
+                            if let Some(diff) = diff {
+                                difficulty = diff;
+                            }
+
+                            if let Some((is_fee, diff, job_id)) = submits.remove(&id) {
+                                audit_share(&state, worker, config, &job_id, &pool_addr, is_fee, ok, diff);
+                                if ok {
+                                    worker.set_diff(diff);
+                                    if is_fee {
+                                        worker.fee_share_accept();
+                                    } else {
+                                        worker.share_accept();
+                                    }
+                                    accepted_diff += diff;
+                                    update_hashrate(worker, accepted_diff);
+                                } else {
+                                    if is_fee {
+                                        worker.fee_share_reject();
+                                    } else {
+                                        worker.share_reject();
+                                    }
+                                }
+                            } else if login_id.as_ref() == Some(&id) {
+                                login_id = None;
+                                if ok && is_frist_login {
+                                    worker.logind();
+                                    match workers_queue.send(worker.clone()) {
+                                        Ok(_) => {},
+                                        Err(_) => {
+                                            log::warn!("发送矿工状态失败");
+                                        },
+                                    };
+                                    is_frist_login = false;
+                                }
+                            }
+
+                            write_string(is_encrypted, &mut worker_w, &line, &worker_name, config.key.clone(), config.iv.clone()).await?;
+                        }
+                        PoolAction::Drop => {}
+                    }
+                }
+            },
+            () = &mut proxy_sleep, if fee_enabled => {
+                if is_frist_login {
+                    // 还没有登录成功 稍后再切换
+                    proxy_sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(5));
+                    continue;
+                }
+
+                if proxy_fee_state == WaitStatus::WAIT {
+                    proxy_time = config.worker_fee_secs(worker, fee_lefttime);
+                    if proxy_time == 0 || proxy_time >= fee_lefttime || !codec.can_switch(&worker_name) {
+                        // 当前时间段不抽水
+                        proxy_sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(fee_lefttime));
+                        continue;
+                    }
+                }
+
+                let (pools, wallet, proxy) = if proxy_fee_state == WaitStatus::WAIT {
+                    let (_, pools) = match crate::client::get_pool_ip_and_type_for_proxyer(&config) {
+                        Ok(s) => s,
+                        Err(_) => {
+                            bail!("无法链接到矿池");
+                        }
+                    };
+                    (pools, proxy_wallet_and_worker_name.clone(), &config.share_proxy)
+                } else {
+                    let (_, pools) = match crate::client::get_pool_ip_and_type(&config) {
+                        Ok(pool) => pool,
+                        Err(_) => {
+                            bail!("未匹配到矿池 或 均不可链接。请修改后重试");
+                        }
+                    };
+                    (pools, get_pool_wallet(worker, config), &config.pool_proxy)
+                };
+
+                let (new_pool_lines, mut new_pool_w, new_pool_addr) = connect_pool_lines(&pools, proxy).await?;
+                for req in codec.pool_login(&wallet) {
+                    write_to_socket(&mut new_pool_w, &req, &worker_name).await?;
+                }
+
+                audit_no_response(
+                    &state,
+                    worker,
+                    config,
+                    &pool_addr,
+                    submits.drain().map(|(_, s)| s),
+                );
+                pool_lines = new_pool_lines;
+                pool_w = new_pool_w;
+                pool_addr = new_pool_addr.to_string();
+                pool_wallet = wallet;
+
+                if proxy_fee_state == WaitStatus::WAIT {
+                    proxy_fee_state = WaitStatus::ProxyRun;
+                    #[cfg(debug_assertions)]
+                    info!("{} 本次中转抽水时间为 {} 秒", worker.worker_name, proxy_time);
+                    proxy_sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(proxy_time));
+                } else {
+                    proxy_fee_state = WaitStatus::WAIT;
+                    #[cfg(debug_assertions)]
+                    info!("抽水结束!!");
+                    proxy_sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(fee_lefttime - proxy_time));
+                }
+            },
+            () = &mut sleep  => {
+                match workers_queue.send(worker.clone()) {
+                    Ok(_) => {},
+                    Err(_) => {
+                        log::warn!("发送矿工状态失败");
+                    },
+                };
+                sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(30));
+            },
+        }
+    }
+}
+
+// 测试用 矿机经 relay 中转到矿池。返回矿机的读写端与中转任务
+#[cfg(test)]
+pub async fn spawn_relay<C>(
+    codec: C, config: Settings, state: State,
+) -> (
+    tokio::io::Lines<BufReader<ReadHalf<tokio::io::DuplexStream>>>,
+    WriteHalf<tokio::io::DuplexStream>,
+    tokio::task::JoinHandle<(Worker, Result<()>)>,
+)
+where
+    C: RelayCodec + Send + 'static,
+    C::Request: Send + Sync,
+{
+    let (_, pools) = crate::client::get_pool_ip_and_type(&config).unwrap();
+    let (stream, _) = crate::client::get_pool_stream(&pools, "").await.unwrap();
+    let (miner, proxy) = tokio::io::duplex(65536);
+    let (proxy_r, proxy_w) = tokio::io::split(proxy);
+    let (queue, _rx) = tokio::sync::mpsc::unbounded_channel();
+    let handle = tokio::spawn(async move {
+        let mut worker = Worker::default();
+        let res = relay(
+            codec,
+            &mut worker,
+            queue,
+            BufReader::new(proxy_r),
+            proxy_w,
+            stream,
+            &config,
+            state,
+            false,
+        )
+        .await;
+        (worker, res)
+    });
+
+    let (miner_r, miner_w) = tokio::io::split(miner);
+    (BufReader::new(miner_r).lines(), miner_w, handle)
+}
@@ -9,18 +9,31 @@ use tokio::{
 
 use crate::{
     state::{State, Worker},
-    util::config::Settings,
+    util::{
+        capture::CaptureStream,
+        config::{Settings, LISTENER_TCP},
+        logger,
+    },
 };
 
 use super::*;
 pub async fn accept_tcp(
     worker_queue: UnboundedSender<Worker>, config: Settings, state: State,
 ) -> Result<()> {
-    if config.tcp_port == 0 {
-        return Ok(());
-    }
+    let listeners = config.listener_settings(LISTENER_TCP);
+    futures::future::try_join_all(listeners.into_iter().map(
+        |(address, config)| {
+            listen_tcp(worker_queue.clone(), address, config, state.clone())
+        },
+    ))
+    .await?;
+    Ok(())
+}
 
-    let address = format!("0.0.0.0:{}", config.tcp_port);
+async fn listen_tcp(
+    worker_queue: UnboundedSender<Worker>, address: String, config: Settings,
+    state: State,
+) -> Result<()> {
     let listener = match TcpListener::bind(address.clone()).await {
         Ok(listener) => listener,
         Err(_) => {
@@ -32,7 +45,7 @@ pub async fn accept_tcp(
     log::info!("本地TCP端口{} 启动成功!!!", &address);
 
     loop {
-        let (stream, addr) = listener.accept().await?;
+        let (mut stream, addr) = listener.accept().await?;
 
         let config = config.clone();
         let workers = worker_queue.clone();
@@ -41,15 +54,31 @@ pub async fn accept_tcp(
             .online
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
-        tokio::spawn(async move {
+        let conn = state.next_conn();
+        tokio::spawn(logger::scope(conn, addr.to_string(), async move {
+            let addr = match client_addr(&mut stream, addr, &config).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    info!("IP: {} 恶意链接: {}", addr, e);
+                    state
+                        .online
+                        .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                    return;
+                }
+            };
+
             // 矿工状态管理
             let mut worker: Worker = Worker::default();
+            worker.ip = Some(addr.ip());
+            // 经过 PROXY 协议头后是矿机的真实地址
+            logger::update(|ctx| ctx.peer = addr.to_string());
             match transfer(
                 &mut worker,
                 workers.clone(),
                 stream,
                 &config,
                 state.clone(),
+                conn,
             )
             .await
             {
@@ -78,72 +107,27 @@ pub async fn accept_tcp(
                         .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
                 }
             }
-        });
+        }));
     }
 }
 
 async fn transfer(
     worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
-    tcp_stream: TcpStream, config: &Settings, state: State,
+    tcp_stream: TcpStream, config: &Settings, state: State, conn: u64,
 ) -> Result<()> {
+    let tcp_stream =
+        CaptureStream::new(tcp_stream, state.clone(), worker.ip, None);
     let (worker_r, worker_w) = split(tcp_stream);
     let worker_r = BufReader::new(worker_r);
-    let (stream_type, pools) =
-        match crate::client::get_pool_ip_and_type(&config) {
-            Ok(pool) => pool,
-            Err(_) => {
-                bail!("未匹配到矿池 或 均不可链接。请修改后重试");
-            }
-        };
-
-    if config.share == 0 {
-        handle_tcp_pool(
-            worker,
-            worker_queue,
-            worker_r,
-            worker_w,
-            &pools,
-            &config,
-            state,
-            false,
-        )
-        .await
-    } else if config.share == 1 {
-        if config.share_alg == 99 {
-            handle_tcp_random(
-                worker,
-                worker_queue,
-                worker_r,
-                worker_w,
-                &pools,
-                &config,
-                state,
-                false,
-            )
-            .await
-        } else {
-            handle_tcp_pool_timer(
-                worker,
-                worker_queue,
-                worker_r,
-                worker_w,
-                &pools,
-                &config,
-                state,
-                false,
-            )
-            .await
-        }
-    } else {
-        handle_tcp_pool_all(
-            worker,
-            worker_queue,
-            worker_r,
-            worker_w,
-            &config,
-            state,
-            false,
-        )
-        .await
-    }
+    transfer_stream(
+        worker,
+        worker_queue,
+        worker_r,
+        worker_w,
+        config,
+        state,
+        conn,
+        false,
+    )
+    .await
 }
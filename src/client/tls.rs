@@ -13,18 +13,44 @@ use super::*;
 
 use crate::{
     state::{State, Worker},
-    util::config::Settings,
+    util::{
+        capture::CaptureStream,
+        config::{Settings, LISTENER_SSL},
+        logger,
+    },
 };
 
 pub async fn accept_tcp_with_tls(
     worker_queue: UnboundedSender<Worker>, config: Settings, cert: Identity,
     state: State,
 ) -> Result<()> {
-    if config.ssl_port == 0 {
+    let listeners = config.listener_settings(LISTENER_SSL);
+    if listeners.is_empty() {
         return Ok(());
     }
 
-    let address = format!("0.0.0.0:{}", config.ssl_port);
+    let tls_acceptor = tokio_native_tls::TlsAcceptor::from(
+        native_tls::TlsAcceptor::builder(cert).build()?,
+    );
+    futures::future::try_join_all(listeners.into_iter().map(
+        |(address, config)| {
+            listen_tls(
+                worker_queue.clone(),
+                address,
+                config,
+                tls_acceptor.clone(),
+                state.clone(),
+            )
+        },
+    ))
+    .await?;
+    Ok(())
+}
+
+async fn listen_tls(
+    worker_queue: UnboundedSender<Worker>, address: String, config: Settings,
+    tls_acceptor: tokio_native_tls::TlsAcceptor, state: State,
+) -> Result<()> {
     let listener = match TcpListener::bind(address.clone()).await {
         Ok(listener) => listener,
         Err(_) => {
@@ -35,12 +61,9 @@ pub async fn accept_tcp_with_tls(
 
     log::info!("本地SSL端口{} 启动成功!!!", &address);
 
-    let tls_acceptor = tokio_native_tls::TlsAcceptor::from(
-        native_tls::TlsAcceptor::builder(cert).build()?,
-    );
     loop {
         // Asynchronously wait for an inbound TcpStream.
-        let (stream, addr) = listener.accept().await?;
+        let (mut stream, addr) = listener.accept().await?;
         //info!("😄 accept connection from {}", addr);
         let workers = worker_queue.clone();
 
@@ -52,9 +75,24 @@ pub async fn accept_tcp_with_tls(
             .online
             .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
-        tokio::spawn(async move {
+        let conn = state.next_conn();
+        tokio::spawn(logger::scope(conn, addr.to_string(), async move {
+            let addr = match client_addr(&mut stream, addr, &config).await {
+                Ok(addr) => addr,
+                Err(e) => {
+                    info!("IP: {} 恶意链接: {}", addr, e);
+                    state
+                        .online
+                        .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                    return;
+                }
+            };
+
             // 矿工状态管理
             let mut worker: Worker = Worker::default();
+            worker.ip = Some(addr.ip());
+            // 经过 PROXY 协议头后是矿机的真实地址
+            logger::update(|ctx| ctx.peer = addr.to_string());
             match transfer_ssl(
                 &mut worker,
                 workers.clone(),
@@ -62,6 +100,7 @@ pub async fn accept_tcp_with_tls(
                 acceptor,
                 &config,
                 state.clone(),
+                conn,
             )
             .await
             {
@@ -90,74 +129,30 @@ pub async fn accept_tcp_with_tls(
                         .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
                 }
             }
-        });
+        }));
     }
 }
 
 async fn transfer_ssl(
     worker: &mut Worker, worker_queue: UnboundedSender<Worker>,
     tcp_stream: TcpStream, tls_acceptor: tokio_native_tls::TlsAcceptor,
-    config: &Settings, state: State,
+    config: &Settings, state: State, conn: u64,
 ) -> Result<()> {
     let client_stream = tls_acceptor.accept(tcp_stream).await?;
+    let client_stream =
+        CaptureStream::new(client_stream, state.clone(), worker.ip, None);
     let (worker_r, worker_w) = split(client_stream);
     let worker_r = BufReader::new(worker_r);
 
-    let (stream_type, pools) =
-        match crate::client::get_pool_ip_and_type(&config) {
-            Ok(pool) => pool,
-            Err(_) => {
-                bail!("未匹配到矿池 或 均不可链接。请修改后重试");
-            }
-        };
-    if config.share == 0 {
-        handle_tcp_pool(
-            worker,
-            worker_queue,
-            worker_r,
-            worker_w,
-            &pools,
-            &config,
-            state,
-            false,
-        )
-        .await
-    } else if config.share == 1 {
-        if config.share_alg == 99 {
-            handle_tcp_random(
-                worker,
-                worker_queue,
-                worker_r,
-                worker_w,
-                &pools,
-                &config,
-                state,
-                false,
-            )
-            .await
-        } else {
-            handle_tcp_pool_timer(
-                worker,
-                worker_queue,
-                worker_r,
-                worker_w,
-                &pools,
-                &config,
-                state,
-                false,
-            )
-            .await
-        }
-    } else {
-        handle_tcp_pool_all(
-            worker,
-            worker_queue,
-            worker_r,
-            worker_w,
-            &config,
-            state,
-            false,
-        )
-        .await
-    }
+    transfer_stream(
+        worker,
+        worker_queue,
+        worker_r,
+        worker_w,
+        config,
+        state,
+        conn,
+        false,
+    )
+    .await
 }
@@ -0,0 +1,985 @@
+//! 测试用的假矿池。支持 ETH 代理协议 EthereumStratum/1.0.0 普通 stratum
+//! 以及比特币 KawPow Conflux 门罗币与 Stratum V2 的矿池协议。
+//! 按设定频率下发任务 按规则接受或拒绝份额 并记录收到的全部请求。
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use hex::FromHex;
+use secp256k1::Keypair;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, WriteHalf},
+    net::{TcpListener, TcpStream},
+    select,
+    sync::{broadcast, mpsc},
+};
+
+use crate::protocol::sv2::{
+    noise::{
+        generate_keypair, responder_handshake, SignatureNoiseMessage,
+        Sv2Encoder,
+    },
+    Sv2Message, SV2_VERSION, V1_EXTRANONCE2_SIZE,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolProtocol {
+    // eth_submitLogin eth_getWork eth_submitWork
+    Eth,
+    // NiceHash EthereumStratum/1.0.0
+    EthStratum,
+    // mining.subscribe 直接带钱包登录
+    Stratum,
+    // 比特币 Stratum V1 subscribe/authorize 应答带 extranonce1
+    Bitcoin,
+    // Ravencoin subscribe 应答 [null, extranonce1]
+    Kawpow,
+    // Conflux mining.subscribe 带钱包登录 任务带 boundary
+    Cfx,
+    // 门罗币 login/job/submit 按会话 id 区分矿机
+    Xmr,
+    // Stratum V2 Noise 加密的二进制帧 扩展频道。无法自动识别 需要配置
+    Sv2,
+}
+
+impl PoolProtocol {
+    pub fn parse(s: &str) -> Result<Self> {
+        match serde_yaml::from_str::<PoolProtocol>(s) {
+            Ok(p) => Ok(p),
+            Err(_) => bail!("矿池协议错误 {}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareRule {
+    Accept,
+    Reject,
+    // 每 N 个份额拒绝一个
+    RejectEvery(u64),
+    // 拒绝不是当前任务的份额
+    RejectStale,
+}
+
+impl Default for ShareRule {
+    fn default() -> Self { ShareRule::Accept }
+}
+
+impl ShareRule {
+    // accept reject reject-stale reject-every:N
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "accept" => Ok(ShareRule::Accept),
+            "reject" => Ok(ShareRule::Reject),
+            "reject-stale" => Ok(ShareRule::RejectStale),
+            _ => match s.strip_prefix("reject-every:") {
+                Some(n) => match n.parse::<u64>() {
+                    Ok(n) if n > 0 => Ok(ShareRule::RejectEvery(n)),
+                    _ => bail!("份额规则错误 {}", s),
+                },
+                None => bail!("份额规则错误 {}", s),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FakeJob {
+    pub job_id: String,
+    pub header: String,
+    pub seed: String,
+    pub target: String,
+}
+
+impl FakeJob {
+    // 没有配置任务时按序号生成 保证每次运行一致
+    pub fn generate(n: u64) -> Self {
+        FakeJob {
+            job_id: format!("{:06x}", n),
+            header: format!("0x{:064x}", n + 1),
+            seed: format!("0x{:064x}", 0),
+            target: "0x00000000ffff0000000000000000000000000000000000000000000000000000".into(),
+        }
+    }
+}
+
+fn default_job_interval() -> u64 { 10_000 }
+
+fn default_difficulty() -> f64 { 1.0 }
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FakePoolConfig {
+    // 依次循环下发的任务。为空时自动生成
+    #[serde(default)]
+    pub jobs: Vec<FakeJob>,
+    // 下发新任务的间隔 毫秒。0 为只在调用 next_job 时下发
+    #[serde(default = "default_job_interval")]
+    pub job_interval_ms: u64,
+    #[serde(default)]
+    pub share_rule: ShareRule,
+    // EthereumStratum 的 mining.set_difficulty
+    #[serde(default = "default_difficulty")]
+    pub difficulty: f64,
+    // 收到的请求逐行写入该文件
+    #[serde(default)]
+    pub record_path: Option<String>,
+    // 固定矿池协议。为空时按链接的第一条请求判断 只能区分 ETH 系与门罗币
+    #[serde(default)]
+    pub protocol: Option<PoolProtocol>,
+}
+
+impl Default for FakePoolConfig {
+    fn default() -> Self {
+        FakePoolConfig {
+            jobs: vec![],
+            job_interval_ms: default_job_interval(),
+            share_rule: ShareRule::default(),
+            difficulty: default_difficulty(),
+            record_path: None,
+            protocol: None,
+        }
+    }
+}
+
+// 收到的一条请求
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Received {
+    pub conn: u64,
+    pub protocol: PoolProtocol,
+    pub method: String,
+    // 该链接登录的钱包与矿工名
+    pub wallet: String,
+    pub worker: String,
+    pub rpc: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Share {
+    pub conn: u64,
+    pub wallet: String,
+    pub worker: String,
+    pub job_id: String,
+    pub accepted: bool,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    config: FakePoolConfig,
+    job_index: AtomicU64,
+    share_count: AtomicU64,
+    conn_count: AtomicU64,
+    received: Mutex<Vec<Received>>,
+    shares: Mutex<Vec<Share>>,
+    record: Mutex<Option<File>>,
+    jobs_tx: broadcast::Sender<FakeJob>,
+    // SV2 矿池授权密钥 以及由它签名的矿池静态密钥
+    sv2_authority: Keypair,
+    sv2_static: Keypair,
+    sv2_cert: SignatureNoiseMessage,
+}
+
+impl PoolState {
+    fn job(&self) -> FakeJob {
+        let n = self.job_index.load(Ordering::SeqCst);
+        if self.config.jobs.is_empty() {
+            FakeJob::generate(n)
+        } else {
+            self.config.jobs[n as usize % self.config.jobs.len()].clone()
+        }
+    }
+
+    fn next_job(&self) -> FakeJob {
+        self.job_index.fetch_add(1, Ordering::SeqCst);
+        let job = self.job();
+        // 没有链接时发送失败 忽略
+        let _ = self.jobs_tx.send(job.clone());
+        job
+    }
+
+    fn record(&self, received: Received) {
+        if let Some(f) = self.record.lock().unwrap().as_mut() {
+            if let Ok(line) = serde_json::to_string(&received) {
+                let _ = writeln!(f, "{}", line);
+            }
+        }
+        self.received.lock().unwrap().push(received);
+    }
+
+    fn judge(&self, job_id: &str, protocol: PoolProtocol) -> bool {
+        let count = self.share_count.fetch_add(1, Ordering::SeqCst) + 1;
+        match &self.config.share_rule {
+            ShareRule::Accept => true,
+            ShareRule::Reject => false,
+            ShareRule::RejectEvery(n) => count % n != 0,
+            ShareRule::RejectStale => {
+                let job = self.job();
+                if protocol == PoolProtocol::Eth {
+                    job.header == job_id
+                } else {
+                    job.job_id == job_id
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FakePool {
+    addr: SocketAddr,
+    state: Arc<PoolState>,
+}
+
+impl FakePool {
+    /// 绑定地址并在后台运行。端口填 0 时由系统分配 用 addr() 获取
+    pub async fn start(bind: &str, config: FakePoolConfig) -> Result<Self> {
+        let listener = TcpListener::bind(bind).await?;
+        let addr = listener.local_addr()?;
+
+        let record = match &config.record_path {
+            Some(path) => {
+                Some(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            None => None,
+        };
+
+        let (jobs_tx, _) = broadcast::channel(16);
+        let sv2_authority = generate_keypair();
+        let sv2_static = generate_keypair();
+        let mut sv2_cert = SignatureNoiseMessage::unsigned(0, u32::MAX);
+        sv2_cert.sign(&sv2_authority, &sv2_static.x_only_public_key().0);
+        let state = Arc::new(PoolState {
+            config,
+            job_index: AtomicU64::new(0),
+            share_count: AtomicU64::new(0),
+            conn_count: AtomicU64::new(0),
+            received: Mutex::new(vec![]),
+            shares: Mutex::new(vec![]),
+            record: Mutex::new(record),
+            jobs_tx,
+            sv2_authority,
+            sv2_static,
+            sv2_cert,
+        });
+
+        let interval = state.config.job_interval_ms;
+        if interval > 0 {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let mut interval =
+                    tokio::time::interval(Duration::from_millis(interval));
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    state.next_job();
+                }
+            });
+        }
+
+        let accept_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        log::error!("假矿池接受链接失败 {}", e);
+                        return;
+                    }
+                };
+                let state = accept_state.clone();
+                let conn = state.conn_count.fetch_add(1, Ordering::SeqCst);
+                let session = session_id(addr.port(), conn);
+                tokio::spawn(async move {
+                    let res = if state.config.protocol
+                        == Some(PoolProtocol::Sv2)
+                    {
+                        handle_sv2_connection(stream, conn, session, state)
+                            .await
+                    } else {
+                        handle_connection(stream, conn, session, state).await
+                    };
+                    if let Err(e) = res {
+                        log::debug!("假矿池链接 {} 断开 {}", conn, e);
+                    }
+                });
+            }
+        });
+
+        Ok(FakePool { addr, state })
+    }
+
+    pub fn addr(&self) -> SocketAddr { self.addr }
+
+    // 立即下发下一个任务
+    pub fn next_job(&self) -> FakeJob { self.state.next_job() }
+
+    pub fn current_job(&self) -> FakeJob { self.state.job() }
+
+    // 第 conn 个链接的 extranonce1 / 门罗币会话 id
+    pub fn session(&self, conn: u64) -> String {
+        session_id(self.addr.port(), conn)
+    }
+
+    // 填入代理配置 sv2_authority_key 的 x-only 公钥
+    pub fn sv2_authority_key(&self) -> String {
+        hex::encode(self.state.sv2_authority.x_only_public_key().0.serialize())
+    }
+
+    pub fn connections(&self) -> u64 {
+        self.state.conn_count.load(Ordering::SeqCst)
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.state.received.lock().unwrap().clone()
+    }
+
+    pub fn shares(&self) -> Vec<Share> {
+        self.state.shares.lock().unwrap().clone()
+    }
+
+    // 登录过的 (钱包, 矿工名)
+    pub fn logins(&self) -> Vec<(String, String)> {
+        self.received()
+            .into_iter()
+            .filter(|r| match r.method.as_str() {
+                "eth_submitLogin"
+                | "mining.authorize"
+                | "login"
+                | "OpenExtendedMiningChannel" => true,
+                "mining.subscribe" => {
+                    r.protocol == PoolProtocol::Stratum
+                        || r.protocol == PoolProtocol::Cfx
+                }
+                _ => false,
+            })
+            .map(|r| (r.wallet, r.worker))
+            .collect()
+    }
+}
+
+/// 测试中读取对端的下一行 json。10 秒内没有收到视为失败
+/// 中转在矿机登录前到了抽水时间会推迟 5 秒再切换矿池
+pub async fn read_json<R>(lines: &mut tokio::io::Lines<R>) -> Result<Value>
+where R: tokio::io::AsyncBufRead + Unpin {
+    let line =
+        match tokio::time::timeout(Duration::from_secs(10), lines.next_line())
+            .await
+        {
+            Ok(line) => line?,
+            Err(_) => bail!("10 秒内没有收到报文"),
+        };
+    match line {
+        Some(line) => Ok(serde_json::from_str(&line)?),
+        None => bail!("对端已断开"),
+    }
+}
+
+async fn write_json<W>(w: &mut W, rpc: &Value) -> Result<()>
+where W: AsyncWrite + Unpin {
+    let mut line = serde_json::to_vec(rpc)?;
+    line.push(b'\n');
+    w.write_all(&line).await?;
+    Ok(())
+}
+
+// 不同矿池的链接拿到不同的 extranonce1 与会话 id
+fn session_id(port: u16, conn: u64) -> String {
+    format!("{:04x}{:04x}", port, conn)
+}
+
+// 门罗币 4 字节紧凑 target
+fn xmr_target(difficulty: f64) -> String {
+    let target = (u32::MAX as f64 / difficulty.max(1.0)) as u32;
+    hex::encode(target.to_le_bytes())
+}
+
+fn xmr_job(job: &FakeJob, difficulty: f64, session: &str) -> Value {
+    json!({
+        "blob": job.header.trim_start_matches("0x"),
+        "job_id": job.job_id,
+        "target": xmr_target(difficulty),
+        "id": session,
+    })
+}
+
+fn job_notify(
+    protocol: PoolProtocol, job: &FakeJob, difficulty: f64, session: &str,
+) -> Value {
+    match protocol {
+        PoolProtocol::Eth => json!({
+            "id": 0,
+            "jsonrpc": "2.0",
+            "result": [job.header, job.seed, job.target],
+        }),
+        PoolProtocol::EthStratum => json!({
+            "id": null,
+            "method": "mining.notify",
+            "params": [
+                job.job_id,
+                job.seed.trim_start_matches("0x"),
+                job.header.trim_start_matches("0x"),
+                true
+            ],
+        }),
+        PoolProtocol::Stratum => json!({
+            "id": 0,
+            "method": "mining.notify",
+            "params": [job.job_id, job.seed, job.header, true],
+        }),
+        PoolProtocol::Bitcoin => json!({
+            "id": null,
+            "method": "mining.notify",
+            "params": [
+                job.job_id,
+                job.header.trim_start_matches("0x"),
+                "01",
+                "02",
+                [],
+                "20000000",
+                "1d00ffff",
+                "61000000",
+                true
+            ],
+        }),
+        PoolProtocol::Kawpow => json!({
+            "id": null,
+            "method": "mining.notify",
+            "params": [
+                job.job_id,
+                job.header.trim_start_matches("0x"),
+                job.seed.trim_start_matches("0x"),
+                job.target.trim_start_matches("0x"),
+                true,
+                1,
+                "1b00f968"
+            ],
+        }),
+        PoolProtocol::Cfx => json!({
+            "jsonrpc": "2.0",
+            "method": "mining.notify",
+            "params": [job.job_id, "0x1", job.header, job.target],
+        }),
+        PoolProtocol::Xmr => json!({
+            "jsonrpc": "2.0",
+            "method": "job",
+            "params": xmr_job(job, difficulty, session),
+        }),
+        // 二进制帧 见 sv2_job
+        PoolProtocol::Sv2 => Value::Null,
+    }
+}
+
+// 小端序 target。难度1对应 0x00000000ffff0000...0000
+fn sv2_target(difficulty: f64) -> [u8; 32] {
+    let mut value = 65535.0 * 2f64.powi(208) / difficulty.max(1e-9);
+    let mut target = [0u8; 32];
+    for i in (0..32).rev() {
+        let unit = 256f64.powi(i as i32);
+        let b = (value / unit).floor().min(255.0);
+        target[i] = b as u8;
+        value -= b * unit;
+    }
+    target
+}
+
+// SV2 任务号为递增数字 与 FakeJob 的 job_id 对应关系记在链接里
+async fn sv2_job(
+    enc: &mut Sv2Encoder, w: &mut WriteHalf<TcpStream>, channel_id: u32,
+    job: &FakeJob, jobs: &mut HashMap<u32, String>,
+) -> Result<()> {
+    let job_id = jobs.len() as u32 + 1;
+    jobs.insert(job_id, job.job_id.clone());
+    let prev_hash = <[u8; 32]>::from_hex(job.header.trim_start_matches("0x"))
+        .unwrap_or_default();
+    let frames = vec![
+        Sv2Message::NewExtendedMiningJob {
+            channel_id,
+            job_id,
+            min_ntime: None,
+            version: 0x2000_0000,
+            version_rolling_allowed: true,
+            merkle_path: vec![],
+            coinbase_tx_prefix: vec![1, 2],
+            coinbase_tx_suffix: vec![3, 4],
+        },
+        Sv2Message::SetNewPrevHash {
+            channel_id,
+            job_id,
+            prev_hash,
+            min_ntime: 0x6100_0000,
+            nbits: 0x1d00_ffff,
+        },
+    ];
+    for msg in frames {
+        enc.write_frame(w, &msg.to_frame()).await?;
+    }
+    Ok(())
+}
+
+// 拆分 钱包.矿工名
+fn split_wallet(name: &str) -> (String, String) {
+    match name.split_once('.') {
+        Some((wallet, worker)) => (wallet.to_string(), worker.to_string()),
+        None => (name.to_string(), String::new()),
+    }
+}
+
+fn param(rpc: &Value, i: usize) -> String {
+    rpc["params"][i].as_str().unwrap_or("").to_string()
+}
+
+async fn handle_connection(
+    stream: TcpStream, conn: u64, session: String, state: Arc<PoolState>,
+) -> Result<()> {
+    let (r, mut w) = tokio::io::split(stream);
+    let mut lines = BufReader::new(r).lines();
+    let mut jobs_rx = state.jobs_tx.subscribe();
+
+    let mut protocol = None;
+    let mut wallet = String::new();
+    let mut worker = String::new();
+    let mut logged_in = false;
+
+    loop {
+        select! {
+            line = lines.next_line() => {
+                let line = match line? {
+                    Some(line) => line,
+                    None => return Ok(()),
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let rpc: Value = match serde_json::from_str(&line) {
+                    Ok(rpc) => rpc,
+                    Err(_) => bail!("无法解析的请求 {}", line),
+                };
+                let id = rpc["id"].clone();
+                let method = rpc["method"].as_str().unwrap_or("").to_string();
+
+                let current = match protocol.or(state.config.protocol) {
+                    Some(p) => p,
+                    None => {
+                        let p = match method.as_str() {
+                            "eth_submitLogin" | "eth_getWork" => PoolProtocol::Eth,
+                            "login" => PoolProtocol::Xmr,
+                            "mining.subscribe" if line.contains("EthereumStratum") => PoolProtocol::EthStratum,
+                            "mining.subscribe" => PoolProtocol::Stratum,
+                            _ => bail!("未知的协议 {}", line),
+                        };
+                        p
+                    }
+                };
+
+                match method.as_str() {
+                    "eth_submitLogin" => {
+                        let (w_, name) = split_wallet(&param(&rpc, 0));
+                        wallet = w_;
+                        worker = rpc["worker"].as_str().map(|s| s.to_string()).unwrap_or(name);
+                    }
+                    "mining.authorize" => {
+                        let (w_, name) = split_wallet(&param(&rpc, 0));
+                        wallet = w_;
+                        worker = name;
+                    }
+                    "mining.subscribe" if current == PoolProtocol::Stratum || current == PoolProtocol::Cfx => {
+                        let (w_, name) = split_wallet(&param(&rpc, 0));
+                        wallet = w_;
+                        worker = name;
+                    }
+                    "login" => {
+                        let (w_, name) = split_wallet(rpc["params"]["login"].as_str().unwrap_or(""));
+                        wallet = w_;
+                        worker = if name.is_empty() {
+                            rpc["params"]["rigid"].as_str().unwrap_or("").to_string()
+                        } else {
+                            name
+                        };
+                    }
+                    _ => {}
+                }
+                // 配置了协议时 任务推送也按该协议
+                protocol = Some(current);
+                let difficulty = state.config.difficulty;
+
+                state.record(Received {
+                    conn,
+                    protocol: current,
+                    method: method.clone(),
+                    wallet: wallet.clone(),
+                    worker: worker.clone(),
+                    rpc: rpc.clone(),
+                });
+
+                match (current, method.as_str()) {
+                    (PoolProtocol::Eth, "eth_submitLogin") => {
+                        write_json(&mut w, &json!({"id": id, "jsonrpc": "2.0", "result": true})).await?;
+                        logged_in = true;
+                    }
+                    (PoolProtocol::Eth, "eth_getWork") => {
+                        let mut job = job_notify(current, &state.job(), difficulty, &session);
+                        job["id"] = id;
+                        write_json(&mut w, &job).await?;
+                    }
+                    (PoolProtocol::Eth, "eth_submitWork") => {
+                        let job_id = param(&rpc, 1);
+                        let accepted = submit(&state, conn, &wallet, &worker, &job_id, current);
+                        write_json(&mut w, &json!({"id": id, "jsonrpc": "2.0", "result": accepted})).await?;
+                    }
+                    (PoolProtocol::EthStratum, "mining.subscribe") => {
+                        write_json(&mut w, &json!({
+                            "id": id,
+                            "result": [["mining.notify", format!("{:x}", conn), "EthereumStratum/1.0.0"], format!("{:04x}", conn)],
+                            "error": null,
+                        })).await?;
+                    }
+                    (PoolProtocol::EthStratum, "mining.authorize") => {
+                        write_json(&mut w, &json!({"id": id, "result": true, "error": null})).await?;
+                        write_json(&mut w, &json!({"id": null, "method": "mining.set_difficulty", "params": [state.config.difficulty]})).await?;
+                        write_json(&mut w, &job_notify(current, &state.job(), difficulty, &session)).await?;
+                        logged_in = true;
+                    }
+                    (PoolProtocol::EthStratum | PoolProtocol::Bitcoin | PoolProtocol::Kawpow, "mining.submit") => {
+                        let job_id = param(&rpc, 1);
+                        let (share_wallet, share_worker) = share_owner(&param(&rpc, 0), &wallet, &worker);
+                        let accepted = submit(&state, conn, &share_wallet, &share_worker, &job_id, current);
+                        if accepted {
+                            write_json(&mut w, &json!({"id": id, "result": true, "error": null})).await?;
+                        } else {
+                            write_json(&mut w, &json!({"id": id, "result": false, "error": [23, "Low difficulty share", null]})).await?;
+                        }
+                    }
+                    (PoolProtocol::Bitcoin, "mining.subscribe") => {
+                        write_json(&mut w, &json!({
+                            "id": id,
+                            "result": [[["mining.set_difficulty", session], ["mining.notify", session]], session, 4],
+                            "error": null,
+                        })).await?;
+                    }
+                    (PoolProtocol::Bitcoin, "mining.configure") => {
+                        write_json(&mut w, &json!({
+                            "id": id,
+                            "result": {"version-rolling": true, "version-rolling.mask": "1fffe000"},
+                            "error": null,
+                        })).await?;
+                    }
+                    (PoolProtocol::Bitcoin, "mining.authorize") => {
+                        write_json(&mut w, &json!({"id": id, "result": true, "error": null})).await?;
+                        write_json(&mut w, &json!({"id": null, "method": "mining.set_difficulty", "params": [difficulty]})).await?;
+                        write_json(&mut w, &job_notify(current, &state.job(), difficulty, &session)).await?;
+                        logged_in = true;
+                    }
+                    (PoolProtocol::Kawpow, "mining.subscribe") => {
+                        write_json(&mut w, &json!({"id": id, "result": [null, session], "error": null})).await?;
+                    }
+                    (PoolProtocol::Kawpow, "mining.authorize") => {
+                        write_json(&mut w, &json!({"id": id, "result": true, "error": null})).await?;
+                        let target = state.job().target.trim_start_matches("0x").to_string();
+                        write_json(&mut w, &json!({"id": null, "method": "mining.set_target", "params": [target]})).await?;
+                        write_json(&mut w, &job_notify(current, &state.job(), difficulty, &session)).await?;
+                        logged_in = true;
+                    }
+                    (PoolProtocol::Xmr, "login") => {
+                        write_json(&mut w, &json!({
+                            "id": id,
+                            "jsonrpc": "2.0",
+                            "error": null,
+                            "result": {"id": session, "status": "OK", "job": xmr_job(&state.job(), difficulty, &session)},
+                        })).await?;
+                        logged_in = true;
+                    }
+                    (PoolProtocol::Xmr, "submit") => {
+                        // 会话 id 不是本链接的 矿池不认这个份额
+                        let res = if rpc["params"]["id"].as_str() != Some(session.as_str()) {
+                            json!({"id": id, "jsonrpc": "2.0", "error": {"code": -1, "message": "Unauthenticated"}})
+                        } else {
+                            let job_id = rpc["params"]["job_id"].as_str().unwrap_or("").to_string();
+                            if submit(&state, conn, &wallet, &worker, &job_id, current) {
+                                json!({"id": id, "jsonrpc": "2.0", "error": null, "result": {"status": "OK"}})
+                            } else {
+                                json!({"id": id, "jsonrpc": "2.0", "error": {"code": -1, "message": "Low difficulty share"}})
+                            }
+                        };
+                        write_json(&mut w, &res).await?;
+                    }
+                    (PoolProtocol::Xmr, "keepalived") => {
+                        write_json(&mut w, &json!({"id": id, "jsonrpc": "2.0", "error": null, "result": {"status": "KEEPALIVED"}})).await?;
+                    }
+                    (PoolProtocol::Stratum | PoolProtocol::Cfx, "mining.subscribe") => {
+                        write_json(&mut w, &json!({"id": id, "error": null, "result": true})).await?;
+                        write_json(&mut w, &job_notify(current, &state.job(), difficulty, &session)).await?;
+                        logged_in = true;
+                    }
+                    (PoolProtocol::Stratum | PoolProtocol::Cfx, "mining.submit") => {
+                        let job_id = param(&rpc, 1);
+                        let (share_wallet, share_worker) = share_owner(&param(&rpc, 0), &wallet, &worker);
+                        let accepted = submit(&state, conn, &share_wallet, &share_worker, &job_id, current);
+                        write_json(&mut w, &json!({"id": id, "jsonrpc": "2.0", "error": null, "result": [accepted]})).await?;
+                    }
+                    // eth_submitHashrate mining.extranonce.subscribe 等
+                    _ => {
+                        write_json(&mut w, &json!({"id": id, "jsonrpc": "2.0", "result": true})).await?;
+                    }
+                }
+            },
+            job = jobs_rx.recv() => {
+                let job = match job {
+                    Ok(job) => job,
+                    Err(broadcast::error::RecvError::Lagged(_)) => state.job(),
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                };
+                if let (true, Some(p)) = (logged_in, protocol) {
+                    write_json(&mut w, &job_notify(p, &job, state.config.difficulty, &session)).await?;
+                }
+            }
+        }
+    }
+}
+
+// 频道 extranonce 比 V1 矿机的 extranonce2 多 2 字节 由代理补 0
+async fn handle_sv2_connection(
+    mut stream: TcpStream, conn: u64, session: String, state: Arc<PoolState>,
+) -> Result<()> {
+    let (mut enc, mut dec) =
+        responder_handshake(&mut stream, &state.sv2_static, &state.sv2_cert)
+            .await?;
+    let (mut r, mut w) = tokio::io::split(stream);
+
+    // 读帧中途被 select! 取消会打乱解密计数 放到单独的任务里读
+    let (frames_tx, mut frames_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let msg = match dec.read_frame(&mut r).await {
+                Ok(frame) => Sv2Message::from_frame(&frame),
+                Err(e) => Err(e),
+            };
+            match msg {
+                Ok(msg) => {
+                    if frames_tx.send(msg).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    log::debug!("假矿池 SV2 链接读取失败 {}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    let mut jobs_rx = state.jobs_tx.subscribe();
+    let mut wallet = String::new();
+    let mut worker = String::new();
+    let mut channel = None;
+    let mut jobs = HashMap::new();
+
+    loop {
+        select! {
+            msg = frames_rx.recv() => {
+                let msg = match msg {
+                    Some(msg) => msg,
+                    None => return Ok(()),
+                };
+                let record = |method: &str, wallet: &str, worker: &str, rpc: Value| {
+                    state.record(Received {
+                        conn,
+                        protocol: PoolProtocol::Sv2,
+                        method: method.to_string(),
+                        wallet: wallet.to_string(),
+                        worker: worker.to_string(),
+                        rpc,
+                    });
+                };
+
+                match msg {
+                    Sv2Message::SetupConnection { protocol, vendor, .. } => {
+                        record("SetupConnection", &wallet, &worker, json!({"protocol": protocol, "vendor": vendor}));
+                        let ok = Sv2Message::SetupConnectionSuccess { used_version: SV2_VERSION, flags: 0 };
+                        enc.write_frame(&mut w, &ok.to_frame()).await?;
+                    }
+                    Sv2Message::OpenExtendedMiningChannel { request_id, user_identity, .. } => {
+                        let (w_, name) = split_wallet(&user_identity);
+                        wallet = w_;
+                        worker = name;
+                        record("OpenExtendedMiningChannel", &wallet, &worker, json!({"user_identity": user_identity}));
+
+                        let channel_id = conn as u32 + 1;
+                        let ok = Sv2Message::OpenExtendedMiningChannelSuccess {
+                            request_id,
+                            channel_id,
+                            target: sv2_target(state.config.difficulty),
+                            extranonce_size: V1_EXTRANONCE2_SIZE + 2,
+                            extranonce_prefix: Vec::from_hex(&session)?,
+                        };
+                        enc.write_frame(&mut w, &ok.to_frame()).await?;
+                        sv2_job(&mut enc, &mut w, channel_id, &state.job(), &mut jobs).await?;
+                        channel = Some(channel_id);
+                    }
+                    Sv2Message::SubmitSharesExtended { channel_id, sequence_number, job_id, nonce, extranonce, .. } => {
+                        record("SubmitSharesExtended", &wallet, &worker, json!({
+                            "job_id": job_id,
+                            "nonce": nonce,
+                            "extranonce": hex::encode(&extranonce),
+                        }));
+                        let fake_job_id = jobs.get(&job_id).cloned().unwrap_or_default();
+                        let res = if submit(&state, conn, &wallet, &worker, &fake_job_id, PoolProtocol::Sv2) {
+                            Sv2Message::SubmitSharesSuccess {
+                                channel_id,
+                                last_sequence_number: sequence_number,
+                                new_submits_accepted_count: 1,
+                                new_shares_sum: 1,
+                            }
+                        } else {
+                            Sv2Message::SubmitSharesError {
+                                channel_id,
+                                sequence_number,
+                                error_code: "difficulty-too-low".into(),
+                            }
+                        };
+                        enc.write_frame(&mut w, &res.to_frame()).await?;
+                    }
+                    _ => {}
+                }
+            },
+            job = jobs_rx.recv() => {
+                let job = match job {
+                    Ok(job) => job,
+                    Err(broadcast::error::RecvError::Lagged(_)) => state.job(),
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                };
+                if let Some(channel_id) = channel {
+                    sv2_job(&mut enc, &mut w, channel_id, &job, &mut jobs).await?;
+                }
+            }
+        }
+    }
+}
+
+// stratum 份额自带 钱包.矿工名 时以份额中的为准
+fn share_owner(name: &str, wallet: &str, worker: &str) -> (String, String) {
+    if name.is_empty() {
+        return (wallet.to_string(), worker.to_string());
+    }
+    let (share_wallet, share_worker) = split_wallet(name);
+    if share_worker.is_empty() && !name.starts_with("0x") {
+        // 只有矿工名
+        (wallet.to_string(), share_wallet)
+    } else {
+        (share_wallet, share_worker)
+    }
+}
+
+fn submit(
+    state: &PoolState, conn: u64, wallet: &str, worker: &str, job_id: &str,
+    protocol: PoolProtocol,
+) -> bool {
+    let accepted = state.judge(job_id, protocol);
+    state.shares.lock().unwrap().push(Share {
+        conn,
+        wallet: wallet.to_string(),
+        worker: worker.to_string(),
+        job_id: job_id.to_string(),
+        accepted,
+    });
+    accepted
+}
+
+#[test]
+fn test_share_rule_parse() {
+    assert_eq!(ShareRule::parse("accept").unwrap(), ShareRule::Accept);
+    assert_eq!(
+        ShareRule::parse("reject-every:3").unwrap(),
+        ShareRule::RejectEvery(3)
+    );
+    assert!(ShareRule::parse("reject-every:0").is_err());
+    assert!(ShareRule::parse("sometimes").is_err());
+}
+
+#[tokio::test]
+async fn test_fake_pool() {
+    let pool = FakePool::start(
+        "127.0.0.1:0",
+        FakePoolConfig {
+            job_interval_ms: 0,
+            share_rule: ShareRule::RejectEvery(2),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    // ETH 代理协议
+    let (r, mut w) =
+        TcpStream::connect(pool.addr()).await.unwrap().into_split();
+    let mut lines = BufReader::new(r).lines();
+    w.write_all(b"{\"id\":1,\"method\":\"eth_submitLogin\",\"params\":[\"0xabc\",\"x\"],\"worker\":\"rig1\"}\n").await.unwrap();
+    assert_eq!(read_json(&mut lines).await.unwrap()["result"], true);
+    w.write_all(b"{\"id\":5,\"method\":\"eth_getWork\",\"params\":[]}\n")
+        .await
+        .unwrap();
+    let work = read_json(&mut lines).await.unwrap();
+    assert_eq!(work["id"], 5);
+    let header = work["result"][0].as_str().unwrap().to_string();
+    assert_eq!(header, FakeJob::generate(0).header);
+
+    for accepted in [true, false] {
+        let submit = json!({"id": 40, "method": "eth_submitWork", "params": ["0x1", header, "0x2"]});
+        write_json(&mut w, &submit).await.unwrap();
+        assert_eq!(read_json(&mut lines).await.unwrap()["result"], accepted);
+    }
+
+    let job = pool.next_job();
+    let pushed = read_json(&mut lines).await.unwrap();
+    assert_eq!(pushed["id"], 0);
+    assert_eq!(pushed["result"][0], job.header);
+
+    // EthereumStratum/1.0.0
+    let (r, mut w) =
+        TcpStream::connect(pool.addr()).await.unwrap().into_split();
+    let mut lines = BufReader::new(r).lines();
+    w.write_all(b"{\"id\":1,\"method\":\"mining.subscribe\",\"params\":[\"miner\",\"EthereumStratum/1.0.0\"]}\n").await.unwrap();
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["result"][0][2],
+        "EthereumStratum/1.0.0"
+    );
+    w.write_all(b"{\"id\":2,\"method\":\"mining.authorize\",\"params\":[\"0xdef.rig2\",\"x\"]}\n").await.unwrap();
+    assert_eq!(read_json(&mut lines).await.unwrap()["result"], true);
+    assert_eq!(
+        read_json(&mut lines).await.unwrap()["method"],
+        "mining.set_difficulty"
+    );
+    let notify = read_json(&mut lines).await.unwrap();
+    assert_eq!(notify["params"][0], job.job_id);
+    w.write_all(format!("{{\"id\":3,\"method\":\"mining.submit\",\"params\":[\"0xdef.rig2\",\"{}\",\"aa\"]}}\n", job.job_id).as_bytes()).await.unwrap();
+    assert_eq!(read_json(&mut lines).await.unwrap()["result"], true);
+
+    let shares = pool.shares();
+    assert_eq!(shares.len(), 3);
+    assert_eq!(shares[0].wallet, "0xabc");
+    assert_eq!(shares[0].worker, "rig1");
+    assert!(!shares[1].accepted);
+    assert_eq!(shares[2].wallet, "0xdef");
+    assert_eq!(shares[2].job_id, job.job_id);
+    assert_eq!(
+        pool.logins(),
+        vec![
+            ("0xabc".to_string(), "rig1".to_string()),
+            ("0xdef".to_string(), "rig2".to_string())
+        ]
+    );
+    assert_eq!(pool.connections(), 2);
+}
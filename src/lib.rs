@@ -4,7 +4,7 @@ use tokio::time::Instant;
 extern crate serde_derive;
 #[macro_use]
 extern crate lazy_static;
-const SPLIT: u8 = b'\n';
+pub const SPLIT: u8 = b'\n';
 
 lazy_static! {
     pub static ref JWT_SECRET: String = std::env::var("JWT_SECRET")
@@ -32,7 +32,10 @@ lazy_static! {
 
 pub mod agent;
 pub mod client;
+pub mod fake_pool;
 pub mod protocol;
+pub mod replay;
+pub mod simulator;
 pub mod state;
 pub mod util;
 
@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// 比特币 Stratum V1 默认的版本滚动掩码(BIP320)
+pub const VERSION_ROLLING_MASK: &str = "1fffe000";
+
+// V1 矿机请求。比特币协议的 id 可以是数字也可以是null
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct V1Request {
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+}
+
+impl V1Request {
+    pub fn param_str(&self, idx: usize) -> Option<String> {
+        match self.params.get(idx) {
+            Some(Value::String(s)) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    // mining.authorize 与 mining.submit 的第一个参数都是 钱包.矿工名
+    pub fn get_wallet(&self) -> Option<String> { self.param_str(0) }
+
+    pub fn set_wallet(&mut self, wallet: &str) -> bool {
+        match self.params.get_mut(0) {
+            Some(p) => {
+                *p = Value::String(wallet.into());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct V1Response {
+    pub id: Value,
+    #[serde(default)]
+    pub result: Value,
+    #[serde(default)]
+    pub error: Value,
+}
+
+impl V1Response {
+    pub fn is_ok(&self) -> bool { self.result == Value::Bool(true) }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct V1Notify {
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    pub params: Vec<Value>,
+}
+
+/// 矿池下发的报文。带 method 的为通知 其余为请求应答
+#[derive(Debug, Clone, PartialEq)]
+pub enum BtcServerMessage {
+    Notify(V1Notify),
+    Response(V1Response),
+}
+
+impl BtcServerMessage {
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let value = serde_json::from_slice::<Value>(buf).ok()?;
+        if value.get("method").is_some() {
+            serde_json::from_value::<V1Notify>(value)
+                .ok()
+                .map(BtcServerMessage::Notify)
+        } else {
+            serde_json::from_value::<V1Response>(value)
+                .ok()
+                .map(BtcServerMessage::Response)
+        }
+    }
+}
+
+/// mining.subscribe 的应答 [[订阅列表], extranonce1, extranonce2_size]
+pub fn parse_subscribe_result(result: &Value) -> Option<(String, u64)> {
+    let extranonce1 = result.get(1)?.as_str()?.to_string();
+    let extranonce2_size = result.get(2)?.as_u64()?;
+    Some((extranonce1, extranonce2_size))
+}
+
+pub fn set_extranonce(extranonce1: &str, extranonce2_size: u64) -> V1Notify {
+    V1Notify {
+        id: Value::Null,
+        method: "mining.set_extranonce".into(),
+        params: vec![
+            Value::String(extranonce1.into()),
+            Value::from(extranonce2_size),
+        ],
+    }
+}
+
+/// mining.set_difficulty 下发的难度 换算为每个份额对应的哈希次数。难度1 为 2^32
+pub fn notify_hashes(notify: &V1Notify) -> Option<u64> {
+    if notify.method != "mining.set_difficulty" {
+        return None;
+    }
+    let hashes = notify.params.get(0)?.as_f64()? * 4294967296.0;
+    if hashes >= u64::MAX as f64 {
+        Some(u64::MAX)
+    } else {
+        Some(hashes as u64)
+    }
+}
+
+pub fn request(id: u64, method: &str, params: Vec<Value>) -> V1Request {
+    V1Request {
+        id: Value::from(id),
+        method: method.into(),
+        params,
+    }
+}
+
+#[test]
+fn test_parse_server_message() {
+    let notify = br#"{"id":null,"method":"mining.notify","params":["4f","00","01","02",["aa","bb"],"20000000","1d00ffff","61000000",true]}"#;
+    match BtcServerMessage::parse(notify) {
+        Some(BtcServerMessage::Notify(n)) => {
+            assert_eq!(n.method, "mining.notify");
+            assert_eq!(n.params[4].as_array().unwrap().len(), 2);
+        }
+        _ => panic!("解析任务失败"),
+    }
+
+    let res = br#"{"id":2,"result":[[["mining.notify","ae6812eb"]],"08000002",4],"error":null}"#;
+    match BtcServerMessage::parse(res) {
+        Some(BtcServerMessage::Response(r)) => {
+            assert_eq!(
+                parse_subscribe_result(&r.result),
+                Some(("08000002".to_string(), 4))
+            );
+            assert!(!r.is_ok());
+        }
+        _ => panic!("解析应答失败"),
+    }
+
+    let res = br#"{"id":4,"result":true}"#;
+    match BtcServerMessage::parse(res) {
+        Some(BtcServerMessage::Response(r)) => assert!(r.is_ok()),
+        _ => panic!("解析应答失败"),
+    }
+
+    assert!(BtcServerMessage::parse(b"not json").is_none());
+}
+
+#[test]
+fn test_notify_hashes() {
+    let parse = |buf: &[u8]| match BtcServerMessage::parse(buf) {
+        Some(BtcServerMessage::Notify(n)) => notify_hashes(&n),
+        _ => panic!("解析通知失败"),
+    };
+    assert_eq!(
+        parse(
+            br#"{"id":null,"method":"mining.set_difficulty","params":[1024]}"#
+        ),
+        Some(1024 << 32)
+    );
+    assert_eq!(
+        parse(
+            br#"{"id":null,"method":"mining.set_difficulty","params":[0.5]}"#
+        ),
+        Some(1 << 31)
+    );
+    assert_eq!(
+        parse(br#"{"id":null,"method":"mining.set_extranonce","params":["08",4]}"#),
+        None
+    );
+}
+
+#[test]
+fn test_set_wallet() {
+    let mut req: V1Request = serde_json::from_str(
+        r#"{"id":4,"method":"mining.submit","params":["bc1q.rig1","4f","00000001","61000000","deadbeef"]}"#,
+    )
+    .unwrap();
+    assert_eq!(req.get_wallet().unwrap(), "bc1q.rig1");
+    assert!(req.set_wallet("bc1fee.rig1"));
+    assert_eq!(req.get_wallet().unwrap(), "bc1fee.rig1");
+
+    let mut empty = request(1, "mining.authorize", vec![]);
+    assert!(!empty.set_wallet("bc1fee.rig1"));
+}
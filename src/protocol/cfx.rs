@@ -0,0 +1,136 @@
+use serde_json::Value;
+
+use super::bitcoin::{V1Notify, V1Response};
+
+// Conflux(Octopus) 矿池协议
+// mining.subscribe [钱包.矿工名, 密码]
+// mining.notify    [job_id, height, pow_hash, boundary]
+// mining.submit    [钱包.矿工名, job_id, nonce, pow_hash]
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CfxJob {
+    pub job_id: String,
+    pub height: u64,
+    pub pow_hash: String,
+    pub boundary: String,
+}
+
+impl CfxJob {
+    pub fn from_params(params: &[Value]) -> Option<Self> {
+        let job_id = params.get(0)?.as_str()?.to_string();
+        // 高度有的矿池下发数字 有的下发十六进制字符串
+        let height = match params.get(1)? {
+            Value::Number(n) => n.as_u64()?,
+            Value::String(s) => parse_hex_u64(s)?,
+            _ => return None,
+        };
+        let pow_hash = params.get(2)?.as_str()?.to_string();
+        let boundary = params.get(3)?.as_str()?.to_string();
+        Some(Self {
+            job_id,
+            height,
+            pow_hash,
+            boundary,
+        })
+    }
+
+    pub fn difficulty(&self) -> Option<u64> {
+        boundary_to_difficulty(&self.boundary)
+    }
+}
+
+fn parse_hex_u64(s: &str) -> Option<u64> {
+    let s = s.trim_start_matches("0x");
+    u64::from_str_radix(s, 16).ok()
+}
+
+/// boundary 为256位大端序目标值。难度 = 2^256 / boundary
+pub fn boundary_to_difficulty(boundary: &str) -> Option<u64> {
+    let bytes = hex::decode(boundary.trim_start_matches("0x")).ok()?;
+    if bytes.is_empty() || bytes.len() > 32 {
+        return None;
+    }
+
+    let mut value = 0.0f64;
+    for b in bytes.iter() {
+        value = value * 256.0 + *b as f64;
+    }
+    if value == 0.0 {
+        return None;
+    }
+
+    let diff = 2f64.powi(256) / value;
+    if diff >= u64::MAX as f64 {
+        Some(u64::MAX)
+    } else {
+        Some(diff as u64)
+    }
+}
+
+/// Conflux 矿池的应答 result 可能是 true 也可能是 [true, "..."]
+pub fn is_result_ok(res: &V1Response) -> bool {
+    if !res.error.is_null() {
+        return false;
+    }
+    match &res.result {
+        Value::Bool(b) => *b,
+        Value::Array(arr) => arr.get(0) == Some(&Value::Bool(true)),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CfxServerMessage {
+    Notify(V1Notify),
+    Response(V1Response),
+}
+
+impl CfxServerMessage {
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let value = serde_json::from_slice::<Value>(buf).ok()?;
+        if value.get("method").is_some() {
+            serde_json::from_value::<V1Notify>(value)
+                .ok()
+                .map(CfxServerMessage::Notify)
+        } else {
+            serde_json::from_value::<V1Response>(value)
+                .ok()
+                .map(CfxServerMessage::Response)
+        }
+    }
+}
+
+#[test]
+fn test_cfx_job() {
+    let notify = br#"{"jsonrpc":"2.0","method":"mining.notify","params":["0x1a2b","0x3e8","0x8c6e0e0ab1c1c5cc1bd5fbbb87e3d5a9e8e2c3b5b5c2d91b54d6a7e1f7c3d2a1","0x0000000100000000000000000000000000000000000000000000000000000000"]}"#;
+    match CfxServerMessage::parse(notify) {
+        Some(CfxServerMessage::Notify(n)) => {
+            let job = CfxJob::from_params(&n.params).unwrap();
+            assert_eq!(job.job_id, "0x1a2b");
+            assert_eq!(job.height, 1000);
+            assert_eq!(job.difficulty(), Some(1 << 32));
+        }
+        _ => panic!("解析任务失败"),
+    }
+
+    let params: Vec<Value> =
+        serde_json::from_str(r#"["1", 1000, "0x00", "0xff"]"#).unwrap();
+    assert_eq!(CfxJob::from_params(&params).unwrap().height, 1000);
+    assert!(CfxJob::from_params(&params[..3]).is_none());
+    assert_eq!(boundary_to_difficulty("0x00"), None);
+}
+
+#[test]
+fn test_cfx_result() {
+    let ok: V1Response =
+        serde_json::from_str(r#"{"id":2,"result":true}"#).unwrap();
+    assert!(is_result_ok(&ok));
+    let ok: V1Response =
+        serde_json::from_str(r#"{"id":2,"result":[true,""]}"#).unwrap();
+    assert!(is_result_ok(&ok));
+    let bad: V1Response = serde_json::from_str(
+        r#"{"id":2,"result":false,"error":"invalid share"}"#,
+    )
+    .unwrap();
+    assert!(!is_result_ok(&bad));
+}
@@ -71,7 +71,7 @@ impl EthClientObject for EthClientRootObject {
 
     fn get_submit_hashrate(&self) -> u64 {
         if let Some(hashrate) = self.params.get(0) {
-            let hashrate = match hex_to_int(&hashrate[2..hashrate.len()]) {
+            let hashrate = match hashrate.get(2..).and_then(hex_to_int) {
                 Some(g) => g,
                 None => match hex_to_int(&hashrate[..]) {
                     Some(h) => h,
@@ -86,8 +86,13 @@ impl EthClientObject for EthClientRootObject {
     }
 
     fn set_worker_name(&mut self, worker_name: &str) -> bool {
-        self.params[0] = worker_name.to_string();
-        true
+        match self.params.get_mut(0) {
+            Some(param) => {
+                *param = worker_name.to_string();
+                true
+            }
+            None => false,
+        }
     }
 
     fn get_method(&self) -> String { self.method.clone() }
@@ -98,8 +103,13 @@ impl EthClientObject for EthClientRootObject {
     }
 
     fn set_submit_hashrate(&mut self, hash: String) -> bool {
-        self.params[0] = hash;
-        true
+        match self.params.get_mut(0) {
+            Some(param) => {
+                *param = hash;
+                true
+            }
+            None => false,
+        }
     }
 
     fn is_protocol_eth_statum(&self) -> bool {
@@ -115,8 +125,13 @@ impl EthClientObject for EthClientRootObject {
     }
 
     fn set_wallet(&mut self, wallet: &str) -> bool {
-        self.params[0] = wallet.to_string();
-        true
+        match self.params.get_mut(0) {
+            Some(param) => {
+                *param = wallet.to_string();
+                true
+            }
+            None => false,
+        }
     }
 }
 
@@ -146,7 +161,7 @@ impl EthClientObject for EthClientWorkerObject {
 
     fn get_submit_hashrate(&self) -> u64 {
         if let Some(hashrate) = self.params.get(0) {
-            let hashrate = match hex_to_int(&hashrate[2..hashrate.len()]) {
+            let hashrate = match hashrate.get(2..).and_then(hex_to_int) {
                 Some(g) => g,
                 None => match hex_to_int(&hashrate[..]) {
                     Some(h) => h,
@@ -172,8 +187,13 @@ impl EthClientObject for EthClientWorkerObject {
     }
 
     fn set_submit_hashrate(&mut self, hash: String) -> bool {
-        self.params[0] = hash;
-        true
+        match self.params.get_mut(0) {
+            Some(param) => {
+                *param = hash;
+                true
+            }
+            None => false,
+        }
     }
 
     fn is_protocol_eth_statum(&self) -> bool {
@@ -188,8 +208,13 @@ impl EthClientObject for EthClientWorkerObject {
     }
 
     fn set_wallet(&mut self, wallet: &str) -> bool {
-        self.params[0] = wallet.to_string();
-        true
+        match self.params.get_mut(0) {
+            Some(param) => {
+                *param = wallet.to_string();
+                true
+            }
+            None => false,
+        }
     }
 }
 
@@ -337,3 +362,30 @@ pub struct EthServer {
 //     public bool? result { get; set; }
 //     public string error { get; set; }
 // }
+
+#[test]
+fn test_malformed_client_rpc() {
+    // 以下请求曾导致越界或切片 panic
+    for line in [
+        r#"{"id":1,"method":"eth_submitLogin","params":[],"worker":"w"}"#,
+        r#"{"id":1,"method":"eth_submitLogin","params":[]}"#,
+        r#"{"id":1,"method":"eth_submitHashrate","params":["0"]}"#,
+        r#"{"id":1,"method":"eth_submitHashrate","params":["€x"]}"#,
+        r#"{"id":1,"method":"eth_submitHashrate","params":["0x00000000000000000000000000000000000000000000000000000000000f4240"]}"#,
+    ] {
+        let mut rpc = crate::client::parse(line.as_bytes()).unwrap();
+        rpc.get_submit_hashrate();
+        rpc.set_worker_name("rig");
+        rpc.set_wallet("0x00");
+        rpc.set_submit_hashrate("0x1".into());
+    }
+
+    let mut rpc = crate::client::parse(
+        br#"{"id":1,"method":"eth_submitLogin","params":[]}"#,
+    )
+    .unwrap();
+    assert!(!rpc.set_wallet("0x00"));
+
+    let rpc = crate::client::parse(br#"{"id":1,"method":"eth_submitHashrate","params":["0x00000000000000000000000000000000000000000000000000000000000f4240"]}"#).unwrap();
+    assert_eq!(rpc.get_submit_hashrate(), 1_000_000);
+}
@@ -0,0 +1,147 @@
+use serde_json::Value;
+
+use super::bitcoin::V1Notify;
+
+// KawPow(Ravencoin) 矿池协议
+// mining.subscribe 应答 [null, extranonce1]
+// mining.set_target [target]
+// mining.notify [job_id, header_hash, seed_hash, target, clean, height, bits]
+// mining.submit [钱包.矿工名, job_id, nonce, header_hash, mix_hash]
+
+// 难度1 对应的目标值 0x00000000ff000000... 即 0xff * 2^216
+fn diff1_target() -> f64 { 255.0 * 2f64.powi(216) }
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KawpowJob {
+    pub job_id: String,
+    pub header_hash: String,
+    pub seed_hash: String,
+    pub target: String,
+    pub clean_jobs: bool,
+    pub height: u64,
+}
+
+impl KawpowJob {
+    pub fn from_params(params: &[Value]) -> Option<Self> {
+        Some(Self {
+            job_id: params.get(0)?.as_str()?.to_string(),
+            header_hash: params.get(1)?.as_str()?.to_string(),
+            seed_hash: params.get(2)?.as_str()?.to_string(),
+            target: params.get(3)?.as_str()?.to_string(),
+            clean_jobs: params
+                .get(4)
+                .and_then(|c| c.as_bool())
+                .unwrap_or(false),
+            height: params.get(5).and_then(|h| h.as_u64()).unwrap_or(0),
+        })
+    }
+}
+
+fn target_value(target: &str) -> Option<f64> {
+    let bytes = hex::decode(target.trim_start_matches("0x")).ok()?;
+    if bytes.is_empty() || bytes.len() > 32 {
+        return None;
+    }
+
+    let value = bytes.iter().fold(0.0f64, |v, b| v * 256.0 + *b as f64);
+    if value == 0.0 {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// 目标值转矿池难度
+pub fn target_to_difficulty(target: &str) -> Option<f64> {
+    Some(diff1_target() / target_value(target)?)
+}
+
+/// 找到一个满足目标值的份额平均需要计算的哈希次数 2^256 / target
+pub fn target_to_hashes(target: &str) -> Option<u64> {
+    let hashes = 2f64.powi(256) / target_value(target)?;
+    if hashes >= u64::MAX as f64 {
+        Some(u64::MAX)
+    } else {
+        Some(hashes as u64)
+    }
+}
+
+/// 部分矿池用 mining.set_difficulty 下发难度
+pub fn difficulty_to_hashes(diff: f64) -> u64 {
+    let hashes = diff * 2f64.powi(256) / diff1_target();
+    if hashes >= u64::MAX as f64 {
+        u64::MAX
+    } else {
+        hashes as u64
+    }
+}
+
+/// mining.subscribe 的应答 [session, extranonce1]
+pub fn parse_subscribe_result(result: &Value) -> Option<String> {
+    result.get(1)?.as_str().map(|s| s.to_string())
+}
+
+pub fn set_extranonce(extranonce1: &str) -> V1Notify {
+    V1Notify {
+        id: Value::Null,
+        method: "mining.set_extranonce".into(),
+        params: vec![Value::String(extranonce1.into())],
+    }
+}
+
+/// 从 set_target / set_difficulty / notify 中取出每个份额对应的哈希次数
+pub fn notify_hashes(notify: &V1Notify) -> Option<u64> {
+    match notify.method.as_str() {
+        "mining.set_target" => {
+            target_to_hashes(notify.params.get(0)?.as_str()?)
+        }
+        "mining.set_difficulty" => {
+            Some(difficulty_to_hashes(notify.params.get(0)?.as_f64()?))
+        }
+        "mining.notify" => {
+            target_to_hashes(&KawpowJob::from_params(&notify.params)?.target)
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_kawpow_job() {
+    let notify = br#"{"id":null,"method":"mining.notify","params":["1a2b","9a6e1f6b9de2f5a3c0c7a04c9b8e6ae8c4f1b0ffea1f0a8a4dbb2ec7a3d2b1c0","3d1a2f8e2c3b5b5c2d91b54d6a7e1f7c3d2a18c6e0e0ab1c1c5cc1bd5fbbb87e","00000000ff000000000000000000000000000000000000000000000000000000",true,2123456,"1b00f968"]}"#;
+    let notify: V1Notify = serde_json::from_slice(notify).unwrap();
+    let job = KawpowJob::from_params(&notify.params).unwrap();
+    assert_eq!(job.job_id, "1a2b");
+    assert_eq!(job.height, 2123456);
+    assert!(job.clean_jobs);
+    assert!((target_to_difficulty(&job.target).unwrap() - 1.0).abs() < 1e-9);
+
+    // 难度1 约等于 2^32 次哈希
+    let hashes = notify_hashes(&notify).unwrap();
+    assert_eq!(hashes, difficulty_to_hashes(1.0));
+    assert_eq!(hashes, (4294967296.0f64 * 256.0 / 255.0) as u64);
+
+    assert!(KawpowJob::from_params(&notify.params[..3]).is_none());
+    assert_eq!(target_to_hashes("00"), None);
+}
+
+#[test]
+fn test_kawpow_set_target() {
+    let set: V1Notify = serde_json::from_str(
+        r#"{"id":null,"method":"mining.set_target","params":["0000000100000000000000000000000000000000000000000000000000000000"]}"#,
+    )
+    .unwrap();
+    assert_eq!(notify_hashes(&set), Some(1 << 32));
+
+    let set: V1Notify = serde_json::from_str(
+        r#"{"id":null,"method":"mining.set_difficulty","params":[0.5]}"#,
+    )
+    .unwrap();
+    assert_eq!(notify_hashes(&set), Some(difficulty_to_hashes(0.5)));
+
+    assert_eq!(
+        parse_subscribe_result(
+            &serde_json::from_str(r#"[null,"8a2f"]"#).unwrap()
+        ),
+        Some("8a2f".to_string())
+    );
+}
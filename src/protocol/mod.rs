@@ -1,7 +1,13 @@
+pub mod bitcoin;
+pub mod cfx;
 pub mod eth_stratum;
 pub mod ethjson;
+pub mod kawpow;
+pub mod proxy_protocol;
 pub mod rpc;
 pub mod stratum;
+pub mod sv2;
+pub mod xmr;
 
 use num_enum::IntoPrimitive;
 use serde::{Deserialize, Serialize};
@@ -11,6 +17,7 @@ pub const CLIENT_GETWORK: u64 = 1005;
 pub const CLIENT_SUBHASHRATE: u64 = 1006;
 pub const CLIENT_SUBMITWORK: u64 = 1000;
 pub const SUBSCRIBE: u64 = 10002;
+pub const CLIENT_CONFIGURE: u64 = 1007;
 
 #[derive(
     Debug, Eq, Clone, IntoPrimitive, PartialEq, Serialize, Deserialize,
@@ -21,4 +28,8 @@ pub enum PROTOCOL {
     ETH,
     NICEHASHSTRATUM,
     KNOWN,
+    BITCOIN,
+    XMR,
+    CFX,
+    KAWPOW,
 }
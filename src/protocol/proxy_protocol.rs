@@ -0,0 +1,155 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{bail, Result};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// PROXY 协议 v1 头最长 107 字节 包含 \r\n
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+// 解析 v1 头 如 PROXY TCP4 1.2.3.4 5.6.7.8 1111 2222。UNKNOWN 返回 None
+pub fn parse_v1(line: &str) -> Result<Option<SocketAddr>> {
+    let line = line.trim_end_matches("\r\n");
+    let parts = line.split(' ').collect::<Vec<&str>>();
+    if parts.first() != Some(&"PROXY") || parts.len() < 2 {
+        bail!("PROXY 协议头格式错误 {}", line)
+    }
+
+    match parts[1] {
+        "UNKNOWN" => Ok(None),
+        "TCP4" | "TCP6" if parts.len() == 6 => {
+            let ip = match parts[2].parse::<IpAddr>() {
+                Ok(ip) => ip,
+                Err(_) => bail!("PROXY 协议头地址错误 {}", parts[2]),
+            };
+            if ip.is_ipv4() != (parts[1] == "TCP4") {
+                bail!("PROXY 协议头地址类型不匹配 {}", line)
+            }
+            let port = match parts[4].parse::<u16>() {
+                Ok(port) => port,
+                Err(_) => bail!("PROXY 协议头端口错误 {}", parts[4]),
+            };
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => bail!("PROXY 协议头格式错误 {}", line),
+    }
+}
+
+// 解析 v2 头签名之后的部分。LOCAL 命令与非 TCP/UDP 地址返回 None
+pub fn parse_v2(
+    ver_cmd: u8, family: u8, body: &[u8],
+) -> Result<Option<SocketAddr>> {
+    if ver_cmd >> 4 != 2 {
+        bail!("PROXY 协议版本错误 {}", ver_cmd >> 4)
+    }
+
+    match ver_cmd & 0x0f {
+        // LOCAL 为负载均衡自己的健康检查
+        0 => return Ok(None),
+        1 => {}
+        cmd => bail!("PROXY 协议命令错误 {}", cmd),
+    }
+
+    match family >> 4 {
+        1 => {
+            if body.len() < 12 {
+                bail!("PROXY 协议头长度错误 {}", body.len())
+            }
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        2 => {
+            if body.len() < 36 {
+                bail!("PROXY 协议头长度错误 {}", body.len())
+            }
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&body[0..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// 读取链接开头的 PROXY 协议头 v1 或 v2。只读取头部 不多读矿机数据。
+/// 返回 None 时使用链接的来源地址
+pub async fn read_header<R>(r: &mut R) -> Result<Option<SocketAddr>>
+where R: AsyncRead + Unpin {
+    let mut head = [0u8; 5];
+    r.read_exact(&mut head).await?;
+
+    if &head == b"PROXY" {
+        let mut line = head.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                bail!("PROXY 协议头过长")
+            }
+            line.push(r.read_u8().await?);
+        }
+        return parse_v1(&String::from_utf8_lossy(&line));
+    }
+
+    if head == V2_SIGNATURE[..5] {
+        let mut rest = [0u8; 11];
+        r.read_exact(&mut rest).await?;
+        if rest[..7] != V2_SIGNATURE[5..] {
+            bail!("PROXY 协议头签名错误")
+        }
+        let len = u16::from_be_bytes([rest[9], rest[10]]) as usize;
+        let mut body = vec![0u8; len];
+        r.read_exact(&mut body).await?;
+        return parse_v2(rest[7], rest[8], &body);
+    }
+
+    bail!("缺少 PROXY 协议头")
+}
+
+#[test]
+fn test_parse_v1() {
+    assert_eq!(
+        parse_v1("PROXY TCP4 203.0.113.7 10.0.0.1 51234 4444\r\n").unwrap(),
+        Some("203.0.113.7:51234".parse().unwrap())
+    );
+    assert_eq!(
+        parse_v1("PROXY TCP6 2001:db8::1 ::1 51234 4444\r\n").unwrap(),
+        Some("[2001:db8::1]:51234".parse().unwrap())
+    );
+    assert_eq!(parse_v1("PROXY UNKNOWN\r\n").unwrap(), None);
+    assert!(parse_v1("PROXY TCP4 2001:db8::1 ::1 1 2\r\n").is_err());
+    assert!(parse_v1("PROXY TCP4 1.2.3.4 5.6.7.8 70000 2\r\n").is_err());
+    assert!(parse_v1("GET / HTTP/1.1\r\n").is_err());
+}
+
+#[tokio::test]
+async fn test_read_header() {
+    // v1 头之后的矿机数据不能被读走
+    let mut data: &[u8] =
+        b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 4444\r\n{\"id\":1}\n";
+    assert_eq!(
+        read_header(&mut data).await.unwrap(),
+        Some("203.0.113.7:51234".parse().unwrap())
+    );
+    assert_eq!(data, b"{\"id\":1}\n");
+
+    let mut v2 = V2_SIGNATURE.to_vec();
+    v2.extend_from_slice(&[0x21, 0x11, 0, 12]);
+    v2.extend_from_slice(&[203, 0, 113, 7, 10, 0, 0, 1]);
+    v2.extend_from_slice(&51234u16.to_be_bytes());
+    v2.extend_from_slice(&4444u16.to_be_bytes());
+    v2.extend_from_slice(b"{\"id\":1}\n");
+    let mut data = &v2[..];
+    assert_eq!(
+        read_header(&mut data).await.unwrap(),
+        Some("203.0.113.7:51234".parse().unwrap())
+    );
+    assert_eq!(data, b"{\"id\":1}\n");
+
+    // LOCAL 命令
+    let mut v2 = V2_SIGNATURE.to_vec();
+    v2.extend_from_slice(&[0x20, 0x00, 0, 0]);
+    assert_eq!(read_header(&mut &v2[..]).await.unwrap(), None);
+
+    let mut data: &[u8] = b"{\"id\":1,\"method\":\"eth_submitLogin\"}\n";
+    assert!(read_header(&mut data).await.is_err());
+}
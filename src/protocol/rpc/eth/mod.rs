@@ -61,7 +61,7 @@ impl ClientRpc for Client {
 
     fn get_submit_hashrate(&self) -> u64 {
         if let Some(hashrate) = self.params.get(0) {
-            let hashrate = match hex_to_int(&hashrate[2..hashrate.len()]) {
+            let hashrate = match hashrate.get(2..).and_then(hex_to_int) {
                 Some(g) => g,
                 None => match hex_to_int(&hashrate[..]) {
                     Some(h) => h,
@@ -125,7 +125,7 @@ impl ClientRpc for ClientWithWorkerName {
 
     fn get_submit_hashrate(&self) -> u64 {
         if let Some(hashrate) = self.params.get(0) {
-            let hashrate = match hex_to_int(&hashrate[2..hashrate.len()]) {
+            let hashrate = match hashrate.get(2..).and_then(hex_to_int) {
                 Some(g) => g,
                 None => match hex_to_int(&hashrate[..]) {
                     Some(h) => h,
@@ -201,7 +201,7 @@ impl ServerRpc for ServerSideJob {
         let job_diff = match self.result.get(3) {
             Some(diff) => {
                 if diff.contains("0x") {
-                    if let Some(h) = hex_to_int(&diff[2..diff.len()]) {
+                    if let Some(h) = diff.get(2..).and_then(hex_to_int) {
                         h as u64
                     } else if let Some(h) = hex_to_int(&diff[..]) {
                         h as u64
@@ -255,7 +255,7 @@ impl ServerRpc for Server {
         let job_diff = match self.result.get(3) {
             Some(diff) => {
                 if diff.contains("0x") {
-                    if let Some(h) = hex_to_int(&diff[2..diff.len()]) {
+                    if let Some(h) = diff.get(2..).and_then(hex_to_int) {
                         h as u64
                     } else if let Some(h) = hex_to_int(&diff[..]) {
                         h as u64
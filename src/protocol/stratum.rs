@@ -1,4 +1,8 @@
-use crate::{client::write_to_socket_byte, state::Worker};
+use crate::{
+    client::{apply_fee_rule, write_to_socket_byte},
+    state::Worker,
+    util::config::Settings,
+};
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -65,6 +69,7 @@ pub struct StraumErrorResult {
 pub async fn login<W>(
     worker: &mut Worker, w: &mut WriteHalf<W>,
     rpc: &mut Box<dyn EthClientObject + Send + Sync>, worker_name: &mut String,
+    config: &Settings,
 ) -> Result<()>
 where
     W: AsyncWrite,
@@ -90,6 +95,7 @@ where
             );
             *worker_name = temp_worker;
         }
+        apply_fee_rule(worker, config);
 
         write_to_socket_byte(w, rpc.to_vec()?, &worker_name).await
     } else {
@@ -0,0 +1,778 @@
+pub mod noise;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+pub use super::bitcoin::{V1Notify, V1Request, V1Response};
+
+// 帧头 extension_type(u16) + msg_type(u8) + msg_length(u24)
+pub const SV2_FRAME_HEADER_SIZE: usize = 6;
+// extension_type 最高位为1表示频道消息
+pub const CHANNEL_BIT: u16 = 0x8000;
+
+pub const SETUP_CONNECTION: u8 = 0x00;
+pub const SETUP_CONNECTION_SUCCESS: u8 = 0x01;
+pub const SETUP_CONNECTION_ERROR: u8 = 0x02;
+pub const OPEN_MINING_CHANNEL_ERROR: u8 = 0x12;
+pub const OPEN_EXTENDED_MINING_CHANNEL: u8 = 0x13;
+pub const OPEN_EXTENDED_MINING_CHANNEL_SUCCESS: u8 = 0x14;
+pub const SUBMIT_SHARES_EXTENDED: u8 = 0x1b;
+pub const SUBMIT_SHARES_SUCCESS: u8 = 0x1c;
+pub const SUBMIT_SHARES_ERROR: u8 = 0x1d;
+pub const NEW_EXTENDED_MINING_JOB: u8 = 0x1f;
+pub const SET_NEW_PREV_HASH: u8 = 0x20;
+pub const SET_TARGET: u8 = 0x21;
+
+pub const PROTOCOL_MINING: u8 = 0;
+pub const SV2_VERSION: u16 = 2;
+// 支持版本滚动(ASICBoost)
+pub const REQUIRES_VERSION_ROLLING: u32 = 0b100;
+
+// 转换给V1矿机的 extranonce2 长度。多余的部分补0并入 coinb1
+pub const V1_EXTRANONCE2_SIZE: u16 = 8;
+pub const V1_VERSION_ROLLING_MASK: u32 = 0x1fff_e000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sv2Frame {
+    pub extension_type: u16,
+    pub msg_type: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Sv2Frame {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = self.payload.len() as u32;
+        let mut buf =
+            Vec::with_capacity(SV2_FRAME_HEADER_SIZE + self.payload.len());
+        buf.extend_from_slice(&self.extension_type.to_le_bytes());
+        buf.push(self.msg_type);
+        buf.extend_from_slice(&len.to_le_bytes()[..3]);
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    pub fn parse_header(header: &[u8]) -> Result<(u16, u8, usize)> {
+        if header.len() != SV2_FRAME_HEADER_SIZE {
+            bail!("SV2 帧头长度错误 {}", header.len());
+        }
+        let extension_type = u16::from_le_bytes([header[0], header[1]]);
+        let len = u32::from_le_bytes([header[3], header[4], header[5], 0]);
+        Ok((extension_type, header[2], len as usize))
+    }
+}
+
+#[derive(Default)]
+pub struct Sv2Writer {
+    buf: Vec<u8>,
+}
+
+impl Sv2Writer {
+    pub fn new() -> Self { Self { buf: Vec::new() } }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+
+    pub fn bool(&mut self, v: bool) -> &mut Self { self.u8(v as u8) }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn f32(&mut self, v: f32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u256(&mut self, v: &[u8; 32]) -> &mut Self {
+        self.buf.extend_from_slice(v);
+        self
+    }
+
+    pub fn option_u32(&mut self, v: Option<u32>) -> &mut Self {
+        match v {
+            Some(v) => self.u8(1).u32(v),
+            None => self.u8(0),
+        }
+    }
+
+    pub fn str0_255(&mut self, v: &str) -> &mut Self {
+        self.b0_255(v.as_bytes())
+    }
+
+    pub fn b0_255(&mut self, v: &[u8]) -> &mut Self {
+        let len = std::cmp::min(v.len(), 255);
+        self.buf.push(len as u8);
+        self.buf.extend_from_slice(&v[..len]);
+        self
+    }
+
+    pub fn b0_64k(&mut self, v: &[u8]) -> &mut Self {
+        let len = std::cmp::min(v.len(), u16::MAX as usize);
+        self.u16(len as u16);
+        self.buf.extend_from_slice(&v[..len]);
+        self
+    }
+
+    pub fn seq0_255_u256(&mut self, v: &[[u8; 32]]) -> &mut Self {
+        let len = std::cmp::min(v.len(), 255);
+        self.buf.push(len as u8);
+        for item in &v[..len] {
+            self.u256(item);
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> { std::mem::take(&mut self.buf) }
+}
+
+pub struct Sv2Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Sv2Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self { Self { buf, pos: 0 } }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.pos + len > self.buf.len() {
+            bail!(
+                "SV2 报文长度不足 需要 {} 剩余 {}",
+                len,
+                self.buf.len() - self.pos
+            );
+        }
+        let s = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(s)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> { Ok(self.take(1)?[0]) }
+
+    pub fn bool(&mut self) -> Result<bool> { Ok(self.u8()? != 0) }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let mut v = [0u8; 8];
+        v.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(v))
+    }
+
+    pub fn f32(&mut self) -> Result<f32> {
+        let b = self.take(4)?;
+        Ok(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u256(&mut self) -> Result<[u8; 32]> {
+        let mut v = [0u8; 32];
+        v.copy_from_slice(self.take(32)?);
+        Ok(v)
+    }
+
+    pub fn option_u32(&mut self) -> Result<Option<u32>> {
+        if self.bool()? {
+            Ok(Some(self.u32()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn str0_255(&mut self) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.b0_255()?).to_string())
+    }
+
+    pub fn b0_255(&mut self) -> Result<Vec<u8>> {
+        let len = self.u8()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    pub fn b0_64k(&mut self) -> Result<Vec<u8>> {
+        let len = self.u16()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    pub fn seq0_255_u256(&mut self) -> Result<Vec<[u8; 32]>> {
+        let len = self.u8()? as usize;
+        let mut v = Vec::with_capacity(len);
+        for _ in 0..len {
+            v.push(self.u256()?);
+        }
+        Ok(v)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sv2Message {
+    SetupConnection {
+        protocol: u8,
+        min_version: u16,
+        max_version: u16,
+        flags: u32,
+        endpoint_host: String,
+        endpoint_port: u16,
+        vendor: String,
+        hardware_version: String,
+        firmware: String,
+        device_id: String,
+    },
+    SetupConnectionSuccess {
+        used_version: u16,
+        flags: u32,
+    },
+    SetupConnectionError {
+        flags: u32,
+        error_code: String,
+    },
+    OpenExtendedMiningChannel {
+        request_id: u32,
+        user_identity: String,
+        nominal_hash_rate: f32,
+        max_target: [u8; 32],
+        min_extranonce_size: u16,
+    },
+    OpenExtendedMiningChannelSuccess {
+        request_id: u32,
+        channel_id: u32,
+        target: [u8; 32],
+        extranonce_size: u16,
+        extranonce_prefix: Vec<u8>,
+    },
+    OpenMiningChannelError {
+        request_id: u32,
+        error_code: String,
+    },
+    NewExtendedMiningJob {
+        channel_id: u32,
+        job_id: u32,
+        min_ntime: Option<u32>,
+        version: u32,
+        version_rolling_allowed: bool,
+        merkle_path: Vec<[u8; 32]>,
+        coinbase_tx_prefix: Vec<u8>,
+        coinbase_tx_suffix: Vec<u8>,
+    },
+    SetNewPrevHash {
+        channel_id: u32,
+        job_id: u32,
+        prev_hash: [u8; 32],
+        min_ntime: u32,
+        nbits: u32,
+    },
+    SetTarget {
+        channel_id: u32,
+        maximum_target: [u8; 32],
+    },
+    SubmitSharesExtended {
+        channel_id: u32,
+        sequence_number: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+        extranonce: Vec<u8>,
+    },
+    SubmitSharesSuccess {
+        channel_id: u32,
+        last_sequence_number: u32,
+        new_submits_accepted_count: u32,
+        new_shares_sum: u64,
+    },
+    SubmitSharesError {
+        channel_id: u32,
+        sequence_number: u32,
+        error_code: String,
+    },
+    Unknown {
+        msg_type: u8,
+    },
+}
+
+impl Sv2Message {
+    pub fn to_frame(&self) -> Sv2Frame {
+        let mut w = Sv2Writer::new();
+        let (channel, msg_type) = match self {
+            Sv2Message::SetupConnection {
+                protocol,
+                min_version,
+                max_version,
+                flags,
+                endpoint_host,
+                endpoint_port,
+                vendor,
+                hardware_version,
+                firmware,
+                device_id,
+            } => {
+                w.u8(*protocol)
+                    .u16(*min_version)
+                    .u16(*max_version)
+                    .u32(*flags)
+                    .str0_255(endpoint_host)
+                    .u16(*endpoint_port)
+                    .str0_255(vendor)
+                    .str0_255(hardware_version)
+                    .str0_255(firmware)
+                    .str0_255(device_id);
+                (false, SETUP_CONNECTION)
+            }
+            Sv2Message::SetupConnectionSuccess {
+                used_version,
+                flags,
+            } => {
+                w.u16(*used_version).u32(*flags);
+                (false, SETUP_CONNECTION_SUCCESS)
+            }
+            Sv2Message::SetupConnectionError { flags, error_code } => {
+                w.u32(*flags).str0_255(error_code);
+                (false, SETUP_CONNECTION_ERROR)
+            }
+            Sv2Message::OpenExtendedMiningChannel {
+                request_id,
+                user_identity,
+                nominal_hash_rate,
+                max_target,
+                min_extranonce_size,
+            } => {
+                w.u32(*request_id)
+                    .str0_255(user_identity)
+                    .f32(*nominal_hash_rate)
+                    .u256(max_target)
+                    .u16(*min_extranonce_size);
+                (false, OPEN_EXTENDED_MINING_CHANNEL)
+            }
+            Sv2Message::OpenExtendedMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_size,
+                extranonce_prefix,
+            } => {
+                w.u32(*request_id)
+                    .u32(*channel_id)
+                    .u256(target)
+                    .u16(*extranonce_size)
+                    .b0_255(extranonce_prefix);
+                (false, OPEN_EXTENDED_MINING_CHANNEL_SUCCESS)
+            }
+            Sv2Message::OpenMiningChannelError {
+                request_id,
+                error_code,
+            } => {
+                w.u32(*request_id).str0_255(error_code);
+                (false, OPEN_MINING_CHANNEL_ERROR)
+            }
+            Sv2Message::NewExtendedMiningJob {
+                channel_id,
+                job_id,
+                min_ntime,
+                version,
+                version_rolling_allowed,
+                merkle_path,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+            } => {
+                w.u32(*channel_id)
+                    .u32(*job_id)
+                    .option_u32(*min_ntime)
+                    .u32(*version)
+                    .bool(*version_rolling_allowed)
+                    .seq0_255_u256(merkle_path)
+                    .b0_64k(coinbase_tx_prefix)
+                    .b0_64k(coinbase_tx_suffix);
+                (true, NEW_EXTENDED_MINING_JOB)
+            }
+            Sv2Message::SetNewPrevHash {
+                channel_id,
+                job_id,
+                prev_hash,
+                min_ntime,
+                nbits,
+            } => {
+                w.u32(*channel_id)
+                    .u32(*job_id)
+                    .u256(prev_hash)
+                    .u32(*min_ntime)
+                    .u32(*nbits);
+                (true, SET_NEW_PREV_HASH)
+            }
+            Sv2Message::SetTarget {
+                channel_id,
+                maximum_target,
+            } => {
+                w.u32(*channel_id).u256(maximum_target);
+                (true, SET_TARGET)
+            }
+            Sv2Message::SubmitSharesExtended {
+                channel_id,
+                sequence_number,
+                job_id,
+                nonce,
+                ntime,
+                version,
+                extranonce,
+            } => {
+                w.u32(*channel_id)
+                    .u32(*sequence_number)
+                    .u32(*job_id)
+                    .u32(*nonce)
+                    .u32(*ntime)
+                    .u32(*version)
+                    .b0_255(extranonce);
+                (true, SUBMIT_SHARES_EXTENDED)
+            }
+            Sv2Message::SubmitSharesSuccess {
+                channel_id,
+                last_sequence_number,
+                new_submits_accepted_count,
+                new_shares_sum,
+            } => {
+                w.u32(*channel_id)
+                    .u32(*last_sequence_number)
+                    .u32(*new_submits_accepted_count)
+                    .u64(*new_shares_sum);
+                (true, SUBMIT_SHARES_SUCCESS)
+            }
+            Sv2Message::SubmitSharesError {
+                channel_id,
+                sequence_number,
+                error_code,
+            } => {
+                w.u32(*channel_id)
+                    .u32(*sequence_number)
+                    .str0_255(error_code);
+                (true, SUBMIT_SHARES_ERROR)
+            }
+            Sv2Message::Unknown { msg_type } => (false, *msg_type),
+        };
+
+        Sv2Frame {
+            extension_type: if channel { CHANNEL_BIT } else { 0 },
+            msg_type,
+            payload: w.finish(),
+        }
+    }
+
+    pub fn from_frame(frame: &Sv2Frame) -> Result<Self> {
+        let mut r = Sv2Reader::new(&frame.payload);
+        let msg = match frame.msg_type {
+            SETUP_CONNECTION => Sv2Message::SetupConnection {
+                protocol: r.u8()?,
+                min_version: r.u16()?,
+                max_version: r.u16()?,
+                flags: r.u32()?,
+                endpoint_host: r.str0_255()?,
+                endpoint_port: r.u16()?,
+                vendor: r.str0_255()?,
+                hardware_version: r.str0_255()?,
+                firmware: r.str0_255()?,
+                device_id: r.str0_255()?,
+            },
+            SETUP_CONNECTION_SUCCESS => Sv2Message::SetupConnectionSuccess {
+                used_version: r.u16()?,
+                flags: r.u32()?,
+            },
+            SETUP_CONNECTION_ERROR => Sv2Message::SetupConnectionError {
+                flags: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            OPEN_EXTENDED_MINING_CHANNEL => {
+                Sv2Message::OpenExtendedMiningChannel {
+                    request_id: r.u32()?,
+                    user_identity: r.str0_255()?,
+                    nominal_hash_rate: r.f32()?,
+                    max_target: r.u256()?,
+                    min_extranonce_size: r.u16()?,
+                }
+            }
+            OPEN_EXTENDED_MINING_CHANNEL_SUCCESS => {
+                Sv2Message::OpenExtendedMiningChannelSuccess {
+                    request_id: r.u32()?,
+                    channel_id: r.u32()?,
+                    target: r.u256()?,
+                    extranonce_size: r.u16()?,
+                    extranonce_prefix: r.b0_255()?,
+                }
+            }
+            OPEN_MINING_CHANNEL_ERROR => Sv2Message::OpenMiningChannelError {
+                request_id: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            NEW_EXTENDED_MINING_JOB => Sv2Message::NewExtendedMiningJob {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                min_ntime: r.option_u32()?,
+                version: r.u32()?,
+                version_rolling_allowed: r.bool()?,
+                merkle_path: r.seq0_255_u256()?,
+                coinbase_tx_prefix: r.b0_64k()?,
+                coinbase_tx_suffix: r.b0_64k()?,
+            },
+            SET_NEW_PREV_HASH => Sv2Message::SetNewPrevHash {
+                channel_id: r.u32()?,
+                job_id: r.u32()?,
+                prev_hash: r.u256()?,
+                min_ntime: r.u32()?,
+                nbits: r.u32()?,
+            },
+            SET_TARGET => Sv2Message::SetTarget {
+                channel_id: r.u32()?,
+                maximum_target: r.u256()?,
+            },
+            SUBMIT_SHARES_EXTENDED => Sv2Message::SubmitSharesExtended {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                job_id: r.u32()?,
+                nonce: r.u32()?,
+                ntime: r.u32()?,
+                version: r.u32()?,
+                extranonce: r.b0_255()?,
+            },
+            SUBMIT_SHARES_SUCCESS => Sv2Message::SubmitSharesSuccess {
+                channel_id: r.u32()?,
+                last_sequence_number: r.u32()?,
+                new_submits_accepted_count: r.u32()?,
+                new_shares_sum: r.u64()?,
+            },
+            SUBMIT_SHARES_ERROR => Sv2Message::SubmitSharesError {
+                channel_id: r.u32()?,
+                sequence_number: r.u32()?,
+                error_code: r.str0_255()?,
+            },
+            msg_type => Sv2Message::Unknown { msg_type },
+        };
+
+        Ok(msg)
+    }
+}
+
+/// 扩展频道下发的任务。等待 SetNewPrevHash 激活的任务为 future job
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtendedJob {
+    pub job_id: u32,
+    pub min_ntime: Option<u32>,
+    pub version: u32,
+    pub merkle_path: Vec<[u8; 32]>,
+    pub coinbase_tx_prefix: Vec<u8>,
+    pub coinbase_tx_suffix: Vec<u8>,
+}
+
+// 区块头里的 prev_hash 转为 V1 mining.notify 格式(每4字节翻转)
+pub fn prev_hash_to_v1(prev_hash: &[u8; 32]) -> String {
+    let mut out = Vec::with_capacity(32);
+    for word in prev_hash.chunks(4) {
+        out.extend(word.iter().rev());
+    }
+    hex::encode(out)
+}
+
+pub fn target_to_difficulty(target: &[u8; 32]) -> f64 {
+    // target 为小端序
+    let mut value = 0.0f64;
+    for b in target.iter().rev() {
+        value = value * 256.0 + *b as f64;
+    }
+    if value == 0.0 {
+        return 0.0;
+    }
+    // 难度1对应 0x00000000ffff0000...0000
+    let diff1 = 65535.0 * 2f64.powi(208);
+    diff1 / value
+}
+
+impl ExtendedJob {
+    /// 额外 extranonce 填充字节。频道 extranonce 超过 V1 的部分补 0
+    pub fn v1_notify(
+        &self, prev_hash: &[u8; 32], nbits: u32, ntime: u32,
+        extranonce_prefix: &[u8], padding: usize, clean_jobs: bool,
+    ) -> V1Notify {
+        let mut coinb1 = self.coinbase_tx_prefix.clone();
+        coinb1.extend_from_slice(extranonce_prefix);
+        coinb1.extend(std::iter::repeat(0u8).take(padding));
+
+        let merkle_branch = self
+            .merkle_path
+            .iter()
+            .map(|h| Value::String(hex::encode(h)))
+            .collect::<Vec<Value>>();
+
+        V1Notify {
+            id: Value::Null,
+            method: "mining.notify".into(),
+            params: vec![
+                Value::String(format!("{:x}", self.job_id)),
+                Value::String(prev_hash_to_v1(prev_hash)),
+                Value::String(hex::encode(coinb1)),
+                Value::String(hex::encode(&self.coinbase_tx_suffix)),
+                Value::Array(merkle_branch),
+                Value::String(format!("{:08x}", self.version)),
+                Value::String(format!("{:08x}", nbits)),
+                Value::String(format!("{:08x}", ntime)),
+                Value::Bool(clean_jobs),
+            ],
+        }
+    }
+}
+
+pub fn v1_set_difficulty(diff: f64) -> V1Notify {
+    V1Notify {
+        id: Value::Null,
+        method: "mining.set_difficulty".into(),
+        params: vec![serde_json::json!(diff)],
+    }
+}
+
+/// V1 mining.submit 的参数转为 SubmitSharesExtended
+/// params: [worker, job_id, extranonce2, ntime, nonce, (version_bits)]
+pub fn v1_submit_to_sv2(
+    req: &V1Request, channel_id: u32, sequence_number: u32, job_version: u32,
+    padding: usize,
+) -> Result<Sv2Message> {
+    let job_id = match req.param_str(1) {
+        Some(s) => u32::from_str_radix(&s, 16)?,
+        None => bail!("mining.submit 缺少 job_id"),
+    };
+    let extranonce2 = match req.param_str(2) {
+        Some(s) => hex::decode(s)?,
+        None => bail!("mining.submit 缺少 extranonce2"),
+    };
+    let ntime = match req.param_str(3) {
+        Some(s) => u32::from_str_radix(&s, 16)?,
+        None => bail!("mining.submit 缺少 ntime"),
+    };
+    let nonce = match req.param_str(4) {
+        Some(s) => u32::from_str_radix(&s, 16)?,
+        None => bail!("mining.submit 缺少 nonce"),
+    };
+    let version = match req.param_str(5) {
+        Some(s) => {
+            let bits = u32::from_str_radix(&s, 16)?;
+            (job_version & !V1_VERSION_ROLLING_MASK)
+                | (bits & V1_VERSION_ROLLING_MASK)
+        }
+        None => job_version,
+    };
+
+    let mut extranonce = vec![0u8; padding];
+    extranonce.extend_from_slice(&extranonce2);
+
+    Ok(Sv2Message::SubmitSharesExtended {
+        channel_id,
+        sequence_number,
+        job_id,
+        nonce,
+        ntime,
+        version,
+        extranonce,
+    })
+}
+
+#[test]
+fn test_frame_header() {
+    let frame = Sv2Frame {
+        extension_type: CHANNEL_BIT,
+        msg_type: SET_TARGET,
+        payload: vec![0u8; 0x01_02_03],
+    };
+    let bytes = frame.to_bytes();
+    assert_eq!(&bytes[..6], &[0x00, 0x80, SET_TARGET, 0x03, 0x02, 0x01]);
+    assert_eq!(
+        Sv2Frame::parse_header(&bytes[..6]).unwrap(),
+        (CHANNEL_BIT, SET_TARGET, 0x01_02_03)
+    );
+}
+
+#[test]
+fn test_message_roundtrip() {
+    let msgs = vec![
+        Sv2Message::OpenExtendedMiningChannel {
+            request_id: 1,
+            user_identity: "wallet.worker".into(),
+            nominal_hash_rate: 1.0e12,
+            max_target: [0xff; 32],
+            min_extranonce_size: V1_EXTRANONCE2_SIZE,
+        },
+        Sv2Message::NewExtendedMiningJob {
+            channel_id: 7,
+            job_id: 42,
+            min_ntime: None,
+            version: 0x2000_0000,
+            version_rolling_allowed: true,
+            merkle_path: vec![[1u8; 32], [2u8; 32]],
+            coinbase_tx_prefix: vec![1, 2, 3],
+            coinbase_tx_suffix: vec![4, 5],
+        },
+        Sv2Message::SubmitSharesSuccess {
+            channel_id: 7,
+            last_sequence_number: 3,
+            new_submits_accepted_count: 2,
+            new_shares_sum: 2048,
+        },
+    ];
+
+    for msg in msgs {
+        let frame = msg.to_frame();
+        assert_eq!(Sv2Message::from_frame(&frame).unwrap(), msg);
+    }
+
+    let truncated = Sv2Frame {
+        extension_type: CHANNEL_BIT,
+        msg_type: SET_NEW_PREV_HASH,
+        payload: vec![0u8; 10],
+    };
+    assert!(Sv2Message::from_frame(&truncated).is_err());
+}
+
+#[test]
+fn test_translate_to_v1() {
+    let mut prev_hash = [0u8; 32];
+    prev_hash[..4].copy_from_slice(&[1, 2, 3, 4]);
+    assert!(prev_hash_to_v1(&prev_hash).starts_with("04030201"));
+
+    let mut target = [0u8; 32];
+    target[26] = 0xff;
+    target[27] = 0xff;
+    assert!((target_to_difficulty(&target) - 1.0).abs() < 1e-9);
+
+    let req: V1Request = serde_json::from_str(
+        r#"{"id":4,"method":"mining.submit","params":["w.1","2a","0000000000000001","61000000","deadbeef","00002000"]}"#,
+    )
+    .unwrap();
+    match v1_submit_to_sv2(&req, 7, 1, 0x2000_0000, 2).unwrap() {
+        Sv2Message::SubmitSharesExtended {
+            job_id,
+            nonce,
+            ntime,
+            version,
+            extranonce,
+            ..
+        } => {
+            assert_eq!(job_id, 42);
+            assert_eq!(nonce, 0xdeadbeef);
+            assert_eq!(ntime, 0x6100_0000);
+            assert_eq!(version, 0x2000_2000);
+            assert_eq!(extranonce.len(), 10);
+        }
+        _ => panic!("转换错误"),
+    }
+}
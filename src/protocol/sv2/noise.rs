@@ -0,0 +1,726 @@
+use anyhow::{bail, Result};
+use openssl::{
+    hash::MessageDigest,
+    pkey::PKey,
+    sha::sha256,
+    sign::Signer,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use secp256k1::{
+    ellswift::{ElligatorSwift, ElligatorSwiftParty},
+    schnorr::Signature,
+    Keypair, Message, PublicKey, Secp256k1, XOnlyPublicKey,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{Sv2Frame, SV2_FRAME_HEADER_SIZE};
+
+// 握手模式。超过32字节 按 Noise 规范取 SHA256 作为初始 ck
+pub const NOISE_PROTOCOL_NAME: &[u8] =
+    b"Noise_NX_Secp256k1+EllSwift_ChaChaPoly_SHA256";
+
+pub const NOISE_KEY_SIZE: usize = 32;
+// ElligatorSwift 编码的公钥
+pub const ELLSWIFT_ENCODING_SIZE: usize = 64;
+pub const NOISE_MAC_SIZE: usize = 16;
+// 单个加密块最大长度(含MAC)
+pub const NOISE_MAX_CHUNK: usize = 65535;
+pub const SIGNATURE_NOISE_MESSAGE_SIZE: usize = 74;
+// 证书有效期前后允许的时钟误差 秒
+pub const CERT_TIME_LEEWAY: u32 = 10;
+
+// -> e
+pub const HANDSHAKE_MSG1_SIZE: usize = ELLSWIFT_ENCODING_SIZE;
+// <- e, ee, s, es, payload
+pub const HANDSHAKE_MSG2_SIZE: usize = ELLSWIFT_ENCODING_SIZE
+    + ELLSWIFT_ENCODING_SIZE
+    + NOISE_MAC_SIZE
+    + SIGNATURE_NOISE_MESSAGE_SIZE
+    + NOISE_MAC_SIZE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CipherState {
+    k: Option<[u8; 32]>,
+    n: u64,
+}
+
+impl CipherState {
+    pub fn new() -> Self { Self { k: None, n: 0 } }
+
+    fn with_key(k: [u8; 32]) -> Self { Self { k: Some(k), n: 0 } }
+
+    fn nonce(&self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.n.to_le_bytes());
+        nonce
+    }
+
+    pub fn encrypt_with_ad(&mut self, ad: &[u8], pt: &[u8]) -> Result<Vec<u8>> {
+        let k = match self.k {
+            Some(k) => k,
+            None => return Ok(pt.to_vec()),
+        };
+
+        let mut tag = [0u8; NOISE_MAC_SIZE];
+        let mut ct = encrypt_aead(
+            Cipher::chacha20_poly1305(),
+            &k,
+            Some(&self.nonce()),
+            ad,
+            pt,
+            &mut tag,
+        )?;
+        ct.extend_from_slice(&tag);
+        self.n += 1;
+        Ok(ct)
+    }
+
+    pub fn decrypt_with_ad(&mut self, ad: &[u8], ct: &[u8]) -> Result<Vec<u8>> {
+        let k = match self.k {
+            Some(k) => k,
+            None => return Ok(ct.to_vec()),
+        };
+
+        if ct.len() < NOISE_MAC_SIZE {
+            bail!("SV2 加密报文长度错误 {}", ct.len());
+        }
+
+        let (data, tag) = ct.split_at(ct.len() - NOISE_MAC_SIZE);
+        let pt = match decrypt_aead(
+            Cipher::chacha20_poly1305(),
+            &k,
+            Some(&self.nonce()),
+            ad,
+            data,
+            tag,
+        ) {
+            Ok(pt) => pt,
+            Err(_) => bail!("SV2 报文解密失败"),
+        };
+        self.n += 1;
+        Ok(pt)
+    }
+}
+
+impl Default for CipherState {
+    fn default() -> Self { Self::new() }
+}
+
+fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> Result<[u8; 32]> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    for d in data {
+        signer.update(d)?;
+    }
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&signer.sign_to_vec()?);
+    Ok(out)
+}
+
+fn hkdf2(ck: &[u8; 32], ikm: &[u8]) -> Result<([u8; 32], [u8; 32])> {
+    let temp_key = hmac_sha256(ck, &[ikm])?;
+    let out1 = hmac_sha256(&temp_key, &[&[0x01]])?;
+    let out2 = hmac_sha256(&temp_key, &[&out1, &[0x02]])?;
+    Ok((out1, out2))
+}
+
+struct SymmetricState {
+    ck: [u8; 32],
+    h: [u8; 32],
+    cs: CipherState,
+}
+
+impl SymmetricState {
+    fn new() -> Self {
+        let ck = sha256(NOISE_PROTOCOL_NAME);
+        Self {
+            ck,
+            h: ck,
+            cs: CipherState::new(),
+        }
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut hasher = openssl::sha::Sha256::new();
+        hasher.update(&self.h);
+        hasher.update(data);
+        self.h = hasher.finish();
+    }
+
+    fn mix_key(&mut self, ikm: &[u8]) -> Result<()> {
+        let (ck, k) = hkdf2(&self.ck, ikm)?;
+        self.ck = ck;
+        self.cs = CipherState::with_key(k);
+        Ok(())
+    }
+
+    fn encrypt_and_hash(&mut self, pt: &[u8]) -> Result<Vec<u8>> {
+        let h = self.h;
+        let ct = self.cs.encrypt_with_ad(&h, pt)?;
+        self.mix_hash(&ct);
+        Ok(ct)
+    }
+
+    fn decrypt_and_hash(&mut self, ct: &[u8]) -> Result<Vec<u8>> {
+        let h = self.h;
+        let pt = self.cs.decrypt_with_ad(&h, ct)?;
+        self.mix_hash(ct);
+        Ok(pt)
+    }
+
+    // 返回 (发起方发送, 响应方发送)
+    fn split(&self) -> Result<(CipherState, CipherState)> {
+        let (k1, k2) = hkdf2(&self.ck, &[])?;
+        Ok((CipherState::with_key(k1), CipherState::with_key(k2)))
+    }
+}
+
+// BIP324 的 ElligatorSwift ECDH。a 为发起方公钥 b 为响应方公钥
+fn dh(
+    a: &[u8; ELLSWIFT_ENCODING_SIZE], b: &[u8; ELLSWIFT_ENCODING_SIZE],
+    key: &Keypair, party: ElligatorSwiftParty,
+) -> [u8; 32] {
+    ElligatorSwift::shared_secret(
+        ElligatorSwift::from_array(*a),
+        ElligatorSwift::from_array(*b),
+        key.secret_key(),
+        party,
+        None,
+    )
+    .to_secret_bytes()
+}
+
+fn ellswift(key: &Keypair) -> [u8; ELLSWIFT_ENCODING_SIZE] {
+    ElligatorSwift::from_pubkey(key.public_key()).to_array()
+}
+
+pub fn generate_keypair() -> Keypair {
+    Keypair::new(&Secp256k1::new(), &mut rand::thread_rng())
+}
+
+/// 矿池授权公钥。支持 32 字节 x-only 公钥的 hex 与 SRI 使用的 base58check 格式
+pub fn parse_authority_key(key: &str) -> Result<XOnlyPublicKey> {
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("SV2 矿池授权公钥未设置");
+    }
+
+    let bytes = match hex::decode(key) {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            let data = base58_decode(key)?;
+            if data.len() != 2 + 32 + 4 {
+                bail!("SV2 矿池授权公钥长度错误 {}", key);
+            }
+            let (payload, checksum) = data.split_at(data.len() - 4);
+            if sha256(&sha256(payload))[..4] != *checksum {
+                bail!("SV2 矿池授权公钥校验和错误 {}", key);
+            }
+            // 前两个字节为版本号 目前为1
+            if u16::from_le_bytes([payload[0], payload[1]]) != 1 {
+                bail!("SV2 矿池授权公钥版本错误 {}", key);
+            }
+            payload[2..].to_vec()
+        }
+    };
+
+    match XOnlyPublicKey::from_slice(&bytes) {
+        Ok(key) => Ok(key),
+        Err(e) => bail!("SV2 矿池授权公钥格式错误 {}", e),
+    }
+}
+
+fn base58_decode(s: &str) -> Result<Vec<u8>> {
+    const ALPHABET: &[u8] =
+        b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let mut out: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = match ALPHABET.iter().position(|a| *a == c) {
+            Some(v) => v as u32,
+            None => bail!("SV2 矿池授权公钥包含非法字符 {}", s),
+        };
+        for b in out.iter_mut().rev() {
+            carry += *b as u32 * 58;
+            *b = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            out.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = s.bytes().take_while(|c| *c == b'1').count();
+    let mut res = vec![0u8; zeros];
+    res.extend(out);
+    Ok(res)
+}
+
+/// 矿池证书。由矿池授权秘钥对矿池静态公钥做 BIP340 Schnorr 签名
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureNoiseMessage {
+    pub version: u16,
+    pub valid_from: u32,
+    pub not_valid_after: u32,
+    pub signature: [u8; 64],
+}
+
+impl SignatureNoiseMessage {
+    pub fn unsigned(valid_from: u32, not_valid_after: u32) -> Self {
+        Self {
+            version: 0,
+            valid_from,
+            not_valid_after,
+            signature: [0u8; 64],
+        }
+    }
+
+    // SHA256(version || valid_from || not_valid_after || 静态公钥 x-only)
+    fn digest(&self, static_key: &XOnlyPublicKey) -> Message {
+        let mut data = Vec::with_capacity(10 + 32);
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.extend_from_slice(&self.valid_from.to_le_bytes());
+        data.extend_from_slice(&self.not_valid_after.to_le_bytes());
+        data.extend_from_slice(&static_key.serialize());
+        Message::from_digest(sha256(&data))
+    }
+
+    pub fn sign(&mut self, authority: &Keypair, static_key: &XOnlyPublicKey) {
+        let signature =
+            Secp256k1::new().sign_schnorr(&self.digest(static_key), authority);
+        self.signature.copy_from_slice(signature.as_ref());
+    }
+
+    pub fn verify(
+        &self, authority_key: &XOnlyPublicKey, static_key: &XOnlyPublicKey,
+    ) -> bool {
+        let signature = match Signature::from_slice(&self.signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        Secp256k1::verification_only()
+            .verify_schnorr(&signature, &self.digest(static_key), authority_key)
+            .is_ok()
+    }
+
+    pub fn is_valid_at(&self, now: u32) -> bool {
+        self.valid_from.saturating_sub(CERT_TIME_LEEWAY) <= now
+            && now <= self.not_valid_after.saturating_add(CERT_TIME_LEEWAY)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(SIGNATURE_NOISE_MESSAGE_SIZE);
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&self.valid_from.to_le_bytes());
+        buf.extend_from_slice(&self.not_valid_after.to_le_bytes());
+        buf.extend_from_slice(&self.signature);
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() != SIGNATURE_NOISE_MESSAGE_SIZE {
+            bail!("SV2 矿池证书长度错误 {}", buf.len());
+        }
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(&buf[10..]);
+        Ok(Self {
+            version: u16::from_le_bytes([buf[0], buf[1]]),
+            valid_from: u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]),
+            not_valid_after: u32::from_le_bytes([
+                buf[6], buf[7], buf[8], buf[9],
+            ]),
+            signature,
+        })
+    }
+}
+
+fn unix_now() -> u32 { chrono::Utc::now().timestamp() as u32 }
+
+fn read_ellswift(buf: &[u8]) -> Result<[u8; ELLSWIFT_ENCODING_SIZE]> {
+    let mut key = [0u8; ELLSWIFT_ENCODING_SIZE];
+    if buf.len() != ELLSWIFT_ENCODING_SIZE {
+        bail!("SV2 握手公钥长度错误 {}", buf.len());
+    }
+    key.copy_from_slice(buf);
+    Ok(key)
+}
+
+/// 作为发起方(代理 -> 矿池)完成握手。矿池证书必须由授权公钥签发
+/// 返回 (加密写, 解密读)
+pub async fn initiator_handshake<S>(
+    stream: &mut S, authority_key: &XOnlyPublicKey,
+) -> Result<(Sv2Encoder, Sv2Decoder)>
+where S: AsyncRead + AsyncWrite + Unpin {
+    let mut state = SymmetricState::new();
+    state.mix_hash(&[]);
+
+    // -> e
+    let e = generate_keypair();
+    let e_pub = ellswift(&e);
+    state.mix_hash(&e_pub);
+    state.mix_hash(&[]);
+    stream.write_all(&e_pub).await?;
+
+    // <- e, ee, s, es
+    let mut msg = [0u8; HANDSHAKE_MSG2_SIZE];
+    stream.read_exact(&mut msg).await?;
+
+    let re = read_ellswift(&msg[..ELLSWIFT_ENCODING_SIZE])?;
+    state.mix_hash(&re);
+    state.mix_key(&dh(&e_pub, &re, &e, ElligatorSwiftParty::A))?;
+
+    let rs_end = ELLSWIFT_ENCODING_SIZE * 2 + NOISE_MAC_SIZE;
+    let rs = read_ellswift(
+        &state.decrypt_and_hash(&msg[ELLSWIFT_ENCODING_SIZE..rs_end])?,
+    )?;
+    state.mix_key(&dh(&e_pub, &rs, &e, ElligatorSwiftParty::A))?;
+
+    let payload = state.decrypt_and_hash(&msg[rs_end..])?;
+    let cert = SignatureNoiseMessage::from_bytes(&payload)?;
+    if !cert.is_valid_at(unix_now()) {
+        bail!("SV2 矿池证书已过期或未生效");
+    }
+    let static_key = PublicKey::from_ellswift(ElligatorSwift::from_array(rs))
+        .x_only_public_key()
+        .0;
+    if !cert.verify(authority_key, &static_key) {
+        bail!("SV2 矿池证书签名校验失败");
+    }
+
+    let (send, recv) = state.split()?;
+    Ok((Sv2Encoder { cs: send }, Sv2Decoder { cs: recv }))
+}
+
+/// 作为响应方(矿池)完成握手。测试及模拟矿池使用
+pub async fn responder_handshake<S>(
+    stream: &mut S, static_key: &Keypair, cert: &SignatureNoiseMessage,
+) -> Result<(Sv2Encoder, Sv2Decoder)>
+where S: AsyncRead + AsyncWrite + Unpin {
+    let mut state = SymmetricState::new();
+    state.mix_hash(&[]);
+
+    // -> e
+    let mut re = [0u8; HANDSHAKE_MSG1_SIZE];
+    stream.read_exact(&mut re).await?;
+    state.mix_hash(&re);
+    state.mix_hash(&[]);
+
+    // <- e, ee, s, es
+    let e = generate_keypair();
+    let e_pub = ellswift(&e);
+    let mut msg = Vec::with_capacity(HANDSHAKE_MSG2_SIZE);
+    state.mix_hash(&e_pub);
+    msg.extend_from_slice(&e_pub);
+    state.mix_key(&dh(&re, &e_pub, &e, ElligatorSwiftParty::B))?;
+
+    let s_pub = ellswift(static_key);
+    msg.extend_from_slice(&state.encrypt_and_hash(&s_pub)?);
+    state.mix_key(&dh(&re, &s_pub, static_key, ElligatorSwiftParty::B))?;
+    msg.extend_from_slice(&state.encrypt_and_hash(&cert.to_bytes())?);
+    stream.write_all(&msg).await?;
+
+    let (recv, send) = state.split()?;
+    Ok((Sv2Encoder { cs: send }, Sv2Decoder { cs: recv }))
+}
+
+pub struct Sv2Encoder {
+    cs: CipherState,
+}
+
+impl Sv2Encoder {
+    pub fn encode(&mut self, frame: &Sv2Frame) -> Result<Vec<u8>> {
+        let bytes = frame.to_bytes();
+        let (header, payload) = bytes.split_at(SV2_FRAME_HEADER_SIZE);
+
+        let mut out = self.cs.encrypt_with_ad(&[], header)?;
+        for chunk in payload.chunks(NOISE_MAX_CHUNK - NOISE_MAC_SIZE) {
+            out.extend_from_slice(&self.cs.encrypt_with_ad(&[], chunk)?);
+        }
+        Ok(out)
+    }
+
+    pub async fn write_frame<W>(
+        &mut self, w: &mut W, frame: &Sv2Frame,
+    ) -> Result<()>
+    where W: AsyncWrite + Unpin {
+        let buf = self.encode(frame)?;
+        w.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+pub struct Sv2Decoder {
+    cs: CipherState,
+}
+
+impl Sv2Decoder {
+    pub async fn read_frame<R>(&mut self, r: &mut R) -> Result<Sv2Frame>
+    where R: AsyncRead + Unpin {
+        let mut header = [0u8; SV2_FRAME_HEADER_SIZE + NOISE_MAC_SIZE];
+        r.read_exact(&mut header).await?;
+        let header = self.cs.decrypt_with_ad(&[], &header)?;
+        let (extension_type, msg_type, len) = Sv2Frame::parse_header(&header)?;
+
+        let mut payload = Vec::with_capacity(len);
+        let mut left = len;
+        while left > 0 {
+            let plain = std::cmp::min(left, NOISE_MAX_CHUNK - NOISE_MAC_SIZE);
+            let mut chunk = vec![0u8; plain + NOISE_MAC_SIZE];
+            r.read_exact(&mut chunk).await?;
+            payload.extend_from_slice(&self.cs.decrypt_with_ad(&[], &chunk)?);
+            left -= plain;
+        }
+
+        Ok(Sv2Frame {
+            extension_type,
+            msg_type,
+            payload,
+        })
+    }
+}
+
+#[test]
+fn test_cipher_state_roundtrip() {
+    let mut a = CipherState::with_key([7u8; 32]);
+    let mut b = CipherState::with_key([7u8; 32]);
+    let ct = a.encrypt_with_ad(b"ad", b"hello sv2").unwrap();
+    assert_eq!(ct.len(), 9 + NOISE_MAC_SIZE);
+    assert_eq!(b.decrypt_with_ad(b"ad", &ct).unwrap(), b"hello sv2");
+    // 重放的报文 nonce 不一致 必须失败
+    assert!(b.decrypt_with_ad(b"ad", &ct).is_err());
+}
+
+#[test]
+fn test_signature_noise_message() {
+    let authority = generate_keypair();
+    let static_key = generate_keypair().x_only_public_key().0;
+    let other_key = generate_keypair().x_only_public_key().0;
+
+    let mut cert = SignatureNoiseMessage::unsigned(0, u32::MAX);
+    cert.sign(&authority, &static_key);
+
+    let cert = SignatureNoiseMessage::from_bytes(&cert.to_bytes()).unwrap();
+    let authority_key = authority.x_only_public_key().0;
+    assert!(cert.verify(&authority_key, &static_key));
+    assert!(!cert.verify(&authority_key, &other_key));
+    assert!(!cert.verify(&other_key, &static_key));
+
+    let cert = SignatureNoiseMessage::unsigned(100, 200);
+    assert!(cert.is_valid_at(90));
+    assert!(cert.is_valid_at(210));
+    assert!(!cert.is_valid_at(89));
+    assert!(!cert.is_valid_at(211));
+}
+
+#[test]
+fn test_parse_authority_key() {
+    let key = generate_keypair().x_only_public_key().0;
+    assert_eq!(
+        parse_authority_key(&hex::encode(key.serialize())).unwrap(),
+        key
+    );
+    // SRI 示例配置中的授权公钥
+    assert!(parse_authority_key(
+        "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH72"
+    )
+    .is_ok());
+    assert!(parse_authority_key(
+        "9auqWEzQDVyd2oe1JVGFLMLHZtCo2FFqZwtKA5gd9xbuEu7PH73"
+    )
+    .is_err());
+    assert!(parse_authority_key("").is_err());
+    assert!(parse_authority_key("00").is_err());
+}
+
+#[cfg(test)]
+async fn test_noise_roundtrip<S>(
+    client: &mut S, server: &mut S, authority_key: &XOnlyPublicKey,
+    static_key: &Keypair, cert: &SignatureNoiseMessage,
+) -> Result<((Sv2Encoder, Sv2Decoder), (Sv2Encoder, Sv2Decoder))>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (initiator, responder) = tokio::join!(
+        initiator_handshake(client, authority_key),
+        responder_handshake(server, static_key, cert)
+    );
+    Ok((initiator?, responder?))
+}
+
+#[tokio::test]
+async fn test_noise_handshake() {
+    let (mut client, mut server) = tokio::io::duplex(4096);
+    let authority = generate_keypair();
+    let static_key = generate_keypair();
+    let mut cert = SignatureNoiseMessage::unsigned(0, u32::MAX);
+    cert.sign(&authority, &static_key.x_only_public_key().0);
+
+    let ((mut client_enc, mut client_dec), (mut server_enc, mut server_dec)) =
+        test_noise_roundtrip(
+            &mut client,
+            &mut server,
+            &authority.x_only_public_key().0,
+            &static_key,
+            &cert,
+        )
+        .await
+        .unwrap();
+
+    let frame = Sv2Frame {
+        extension_type: 0,
+        msg_type: 0x01,
+        payload: vec![1, 2, 3],
+    };
+    client_enc.write_frame(&mut client, &frame).await.unwrap();
+    assert_eq!(server_dec.read_frame(&mut server).await.unwrap(), frame);
+
+    let big = Sv2Frame {
+        extension_type: 0x8000,
+        msg_type: 0x1f,
+        payload: vec![9u8; NOISE_MAX_CHUNK + 10],
+    };
+    let (w, r) = tokio::join!(
+        server_enc.write_frame(&mut server, &big),
+        client_dec.read_frame(&mut client)
+    );
+    w.unwrap();
+    assert_eq!(r.unwrap(), big);
+}
+
+#[tokio::test]
+async fn test_noise_handshake_reject_cert() {
+    let authority = generate_keypair();
+    let static_key = generate_keypair();
+
+    // 未签名的证书
+    let (mut client, mut server) = tokio::io::duplex(4096);
+    let cert = SignatureNoiseMessage::unsigned(0, u32::MAX);
+    assert!(test_noise_roundtrip(
+        &mut client,
+        &mut server,
+        &authority.x_only_public_key().0,
+        &static_key,
+        &cert,
+    )
+    .await
+    .is_err());
+
+    // 其他授权秘钥签发的证书
+    let (mut client, mut server) = tokio::io::duplex(4096);
+    let mut cert = SignatureNoiseMessage::unsigned(0, u32::MAX);
+    cert.sign(&generate_keypair(), &static_key.x_only_public_key().0);
+    assert!(test_noise_roundtrip(
+        &mut client,
+        &mut server,
+        &authority.x_only_public_key().0,
+        &static_key,
+        &cert,
+    )
+    .await
+    .is_err());
+
+    // 已过期的证书
+    let (mut client, mut server) = tokio::io::duplex(4096);
+    let mut cert = SignatureNoiseMessage::unsigned(0, 1);
+    cert.sign(&authority, &static_key.x_only_public_key().0);
+    assert!(test_noise_roundtrip(
+        &mut client,
+        &mut server,
+        &authority.x_only_public_key().0,
+        &static_key,
+        &cert,
+    )
+    .await
+    .is_err());
+}
+
+// 与 SRI 参考实现 noise_sv2 互通
+#[tokio::test]
+async fn test_noise_initiator_with_reference_responder() {
+    let authority = generate_keypair();
+    let mut responder = noise_sv2::Responder::new(authority, 3600);
+
+    let (mut client, mut server) = tokio::io::duplex(4096);
+    let pool = tokio::spawn(async move {
+        let mut msg1 = [0u8; HANDSHAKE_MSG1_SIZE];
+        server.read_exact(&mut msg1).await.unwrap();
+        let (msg2, mut codec) = responder.step_1(msg1).unwrap();
+        server.write_all(&msg2).await.unwrap();
+
+        let mut header = vec![0u8; SV2_FRAME_HEADER_SIZE + NOISE_MAC_SIZE];
+        server.read_exact(&mut header).await.unwrap();
+        codec.decrypt(&mut header).unwrap();
+        let (_, _, len) = Sv2Frame::parse_header(&header).unwrap();
+        let mut payload = vec![0u8; len + NOISE_MAC_SIZE];
+        server.read_exact(&mut payload).await.unwrap();
+        codec.decrypt(&mut payload).unwrap();
+
+        let mut reply = Sv2Frame {
+            extension_type: 0,
+            msg_type: 0x01,
+            payload,
+        }
+        .to_bytes();
+        let mut payload = reply.split_off(SV2_FRAME_HEADER_SIZE);
+        codec.encrypt(&mut reply).unwrap();
+        codec.encrypt(&mut payload).unwrap();
+        server.write_all(&reply).await.unwrap();
+        server.write_all(&payload).await.unwrap();
+    });
+
+    let (mut enc, mut dec) =
+        initiator_handshake(&mut client, &authority.x_only_public_key().0)
+            .await
+            .unwrap();
+    let frame = Sv2Frame {
+        extension_type: 0,
+        msg_type: 0x00,
+        payload: b"setup".to_vec(),
+    };
+    enc.write_frame(&mut client, &frame).await.unwrap();
+    let reply = dec.read_frame(&mut client).await.unwrap();
+    assert_eq!(reply.msg_type, 0x01);
+    assert_eq!(reply.payload, b"setup");
+    pool.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_noise_responder_with_reference_initiator() {
+    let authority = generate_keypair();
+    let static_key = generate_keypair();
+    let now = unix_now();
+    let mut cert = SignatureNoiseMessage::unsigned(now, now + 3600);
+    cert.sign(&authority, &static_key.x_only_public_key().0);
+
+    let (mut client, mut server) = tokio::io::duplex(4096);
+    let pool = tokio::spawn(async move {
+        let (mut enc, _) = responder_handshake(&mut server, &static_key, &cert)
+            .await
+            .unwrap();
+        let frame = Sv2Frame {
+            extension_type: 0,
+            msg_type: 0x01,
+            payload: b"pool".to_vec(),
+        };
+        enc.write_frame(&mut server, &frame).await.unwrap();
+    });
+
+    let mut initiator =
+        noise_sv2::Initiator::new(Some(authority.x_only_public_key().0));
+    client
+        .write_all(&initiator.step_0().unwrap())
+        .await
+        .unwrap();
+    let mut msg2 = [0u8; HANDSHAKE_MSG2_SIZE];
+    client.read_exact(&mut msg2).await.unwrap();
+    let mut codec = initiator.step_2(msg2).unwrap();
+
+    let mut header = vec![0u8; SV2_FRAME_HEADER_SIZE + NOISE_MAC_SIZE];
+    client.read_exact(&mut header).await.unwrap();
+    codec.decrypt(&mut header).unwrap();
+    assert_eq!(Sv2Frame::parse_header(&header).unwrap(), (0, 0x01, 4));
+    let mut payload = vec![0u8; 4 + NOISE_MAC_SIZE];
+    client.read_exact(&mut payload).await.unwrap();
+    codec.decrypt(&mut payload).unwrap();
+    assert_eq!(payload, b"pool");
+    pool.await.unwrap();
+}
@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// 门罗币(RandomX) 矿机协议 login/job/submit/keepalived
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct XmrRequest {
+    pub id: Value,
+    #[serde(default)]
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl XmrRequest {
+    pub fn get_login(&self) -> Option<XmrLogin> {
+        serde_json::from_value(self.params.clone()).ok()
+    }
+
+    // submit 与 keepalived 都带有矿池分配的会话 id
+    pub fn set_session_id(&mut self, id: &str) {
+        if let Some(params) = self.params.as_object_mut() {
+            params.insert("id".into(), Value::String(id.into()));
+        }
+    }
+
+    pub fn set_login(&mut self, login: &str) {
+        if let Some(params) = self.params.as_object_mut() {
+            params.insert("login".into(), Value::String(login.into()));
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct XmrLogin {
+    pub login: String,
+    #[serde(default)]
+    pub pass: String,
+    #[serde(default)]
+    pub agent: String,
+    #[serde(default)]
+    pub rigid: String,
+}
+
+impl XmrLogin {
+    /// 返回 (钱包, 矿工名)。矿工名依次取 钱包.矿工名 / rigid / pass
+    pub fn wallet_and_worker(&self) -> (String, String) {
+        let split = self.login.splitn(2, '.').collect::<Vec<&str>>();
+        let wallet = split[0].to_string();
+        if let Some(name) = split.get(1) {
+            if !name.is_empty() {
+                return (wallet, name.to_string());
+            }
+        }
+        if !self.rigid.is_empty() {
+            return (wallet, self.rigid.clone());
+        }
+        if !self.pass.is_empty() && self.pass != "x" {
+            return (wallet, self.pass.clone());
+        }
+        (wallet, "default".into())
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct XmrResponse {
+    pub id: Value,
+    #[serde(default)]
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub result: Value,
+    #[serde(default)]
+    pub error: Value,
+}
+
+impl XmrResponse {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_null()
+            && self.result.get("status").and_then(|s| s.as_str()) == Some("OK")
+    }
+
+    // login 应答中矿池分配的会话 id
+    pub fn session_id(&self) -> Option<String> {
+        self.result.get("id")?.as_str().map(|s| s.to_string())
+    }
+
+    pub fn job_mut(&mut self) -> Option<&mut Value> {
+        self.result.get_mut("job")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum XmrServerMessage {
+    Job(Value),
+    Response(XmrResponse),
+    Other(Value),
+}
+
+impl XmrServerMessage {
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let value = serde_json::from_slice::<Value>(buf).ok()?;
+        match value.get("method").and_then(|m| m.as_str()) {
+            Some("job") => Some(XmrServerMessage::Job(
+                value.get("params").cloned().unwrap_or(Value::Null),
+            )),
+            Some(_) => Some(XmrServerMessage::Other(value)),
+            None => serde_json::from_value::<XmrResponse>(value)
+                .ok()
+                .map(XmrServerMessage::Response),
+        }
+    }
+}
+
+pub fn job_notify(job: &Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "job",
+        "params": job,
+    })
+}
+
+// 任务下发给矿机前 统一改为矿机登录时拿到的会话 id
+pub fn set_job_session_id(job: &mut Value, id: &str) {
+    if let Some(job) = job.as_object_mut() {
+        if job.contains_key("id") {
+            job.insert("id".into(), Value::String(id.into()));
+        }
+    }
+}
+
+pub fn login_request(
+    id: u64, login: &str, rigid: &str, agent: &str,
+) -> XmrRequest {
+    XmrRequest {
+        id: Value::from(id),
+        jsonrpc: "2.0".into(),
+        method: "login".into(),
+        params: json!({
+            "login": login,
+            "pass": "x",
+            "agent": agent,
+            "rigid": rigid,
+        }),
+    }
+}
+
+/// 任务 target 转难度。4字节为 xmrig 紧凑格式 8字节为完整64位
+pub fn target_to_difficulty(target: &str) -> Option<u64> {
+    let bytes = hex::decode(target).ok()?;
+    match bytes.len() {
+        4 => {
+            let t =
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            if t == 0 {
+                return None;
+            }
+            Some(u32::MAX as u64 / t as u64)
+        }
+        8 => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes);
+            let t = u64::from_le_bytes(b);
+            if t == 0 {
+                return None;
+            }
+            Some(u64::MAX / t)
+        }
+        _ => None,
+    }
+}
+
+pub fn job_difficulty(job: &Value) -> Option<u64> {
+    target_to_difficulty(job.get("target")?.as_str()?)
+}
+
+#[test]
+fn test_wallet_and_worker() {
+    let req: XmrRequest = serde_json::from_str(
+        r#"{"id":1,"jsonrpc":"2.0","method":"login","params":{"login":"48edfHu7V9Z84YzzMa6fUueoELZ9ZRXq9VetWzYGzKt52XU5xvqgzYnDK9URnRoJMk1j8nLwEVsaSWJ4fhdUyZijBGUicoD.rig1","pass":"x","agent":"XMRig/6.16.4","algo":["rx/0"]}}"#,
+    )
+    .unwrap();
+    let login = req.get_login().unwrap();
+    let (wallet, worker) = login.wallet_and_worker();
+    assert!(wallet.starts_with("48edfHu7V9Z84"));
+    assert_eq!(worker, "rig1");
+
+    let login = XmrLogin {
+        login: "48edf".into(),
+        pass: "x".into(),
+        agent: "".into(),
+        rigid: "rig2".into(),
+    };
+    assert_eq!(login.wallet_and_worker().1, "rig2");
+
+    let login = XmrLogin {
+        login: "48edf".into(),
+        pass: "rig3".into(),
+        ..Default::default()
+    };
+    assert_eq!(login.wallet_and_worker().1, "rig3");
+}
+
+#[test]
+fn test_target_to_difficulty() {
+    assert_eq!(target_to_difficulty("b88d0600"), Some(10_000));
+    assert_eq!(target_to_difficulty("ffffffff"), Some(1));
+    assert_eq!(
+        target_to_difficulty("0000000000100000"),
+        Some(u64::MAX / 0x0000_1000_0000_0000)
+    );
+    assert_eq!(target_to_difficulty("00000000"), None);
+    assert_eq!(target_to_difficulty("zz"), None);
+}
+
+#[test]
+fn test_parse_server_message() {
+    let job = br#"{"jsonrpc":"2.0","method":"job","params":{"blob":"0707","job_id":"j1","target":"b88d0600","id":"s2"}}"#;
+    match XmrServerMessage::parse(job) {
+        Some(XmrServerMessage::Job(mut job)) => {
+            assert_eq!(job_difficulty(&job), Some(10_000));
+            set_job_session_id(&mut job, "s1");
+            assert_eq!(job["id"], "s1");
+        }
+        _ => panic!("解析任务失败"),
+    }
+
+    let res =
+        br#"{"id":2,"jsonrpc":"2.0","error":null,"result":{"status":"OK"}}"#;
+    match XmrServerMessage::parse(res) {
+        Some(XmrServerMessage::Response(r)) => assert!(r.is_ok()),
+        _ => panic!("解析应答失败"),
+    }
+
+    let res = br#"{"id":2,"jsonrpc":"2.0","error":{"code":-1,"message":"Low difficulty share"}}"#;
+    match XmrServerMessage::parse(res) {
+        Some(XmrServerMessage::Response(r)) => assert!(!r.is_ok()),
+        _ => panic!("解析应答失败"),
+    }
+}
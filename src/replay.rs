@@ -0,0 +1,543 @@
+//! 回放抓包文件。按链接把矿机发出的报文重新发给中转或假矿池
+//! 并将收到的应答与抓包时的应答按 id 对比。
+
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Result;
+use hex::FromHex;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+    time::timeout,
+};
+
+use crate::{
+    client::{
+        encrypt_frame,
+        encryption::{DEFAULT_IV, DEFAULT_KEY},
+    },
+    util::{
+        capture::{CaptureRecord, Direction},
+        wallet::split_login,
+    },
+};
+
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    // 回放目标 中转的 TCP 端口或假矿池
+    pub server: String,
+    // 只回放指定链接
+    pub conn: Option<u64>,
+    // 按抓包时的间隔发送 2.0 为两倍速。0 为不等待 中转未登录完成的请求会被丢弃
+    pub speed: f64,
+    // 发送完毕后超过此时间没有新应答即结束 毫秒
+    pub wait_ms: u64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig {
+            server: "127.0.0.1:8888".into(),
+            conn: None,
+            speed: 1.0,
+            wait_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayDiff {
+    pub conn: u64,
+    pub id: String,
+    pub method: String,
+    // 抓包时的应答
+    pub expected: Option<String>,
+    // 回放得到的应答
+    pub actual: Option<String>,
+}
+
+impl std::fmt::Display for ReplayDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let none = "无应答".to_string();
+        write!(
+            f,
+            "链接 {} id {} {}\n  抓包: {}\n  回放: {}",
+            self.conn,
+            self.id,
+            self.method,
+            self.expected.as_ref().unwrap_or(&none),
+            self.actual.as_ref().unwrap_or(&none)
+        )
+    }
+}
+
+// 按链接分组 保持原有顺序
+pub fn group(records: Vec<CaptureRecord>) -> BTreeMap<u64, Vec<CaptureRecord>> {
+    let mut conns: BTreeMap<u64, Vec<CaptureRecord>> = BTreeMap::new();
+    for record in records {
+        conns.entry(record.conn).or_default().push(record);
+    }
+    conns
+}
+
+pub async fn replay(
+    records: Vec<CaptureRecord>, config: &ReplayConfig,
+) -> Result<Vec<ReplayDiff>> {
+    let conns: Vec<_> = group(records)
+        .into_iter()
+        .filter(|(conn, _)| config.conn.map_or(true, |c| c == *conn))
+        .collect();
+
+    let results = futures::future::join_all(
+        conns
+            .iter()
+            .map(|(_, records)| replay_conn(records, config)),
+    )
+    .await;
+
+    let mut diffs = vec![];
+    for ((conn, records), received) in conns.iter().zip(results) {
+        diffs.append(&mut diff(*conn, records, &received?));
+    }
+    Ok(diffs)
+}
+
+// 回放一条链接 返回收到的全部报文
+pub async fn replay_conn(
+    records: &[CaptureRecord], config: &ReplayConfig,
+) -> Result<Vec<String>> {
+    let stream = TcpStream::connect(&config.server).await?;
+    let (r, mut w) = stream.into_split();
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(r).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut last = None;
+    for record in records.iter().filter(|r| r.dir == Direction::Up) {
+        if let Some(last) = last {
+            if config.speed > 0.0 && record.time > last {
+                let wait = (record.time - last) as f64 / config.speed;
+                tokio::time::sleep(Duration::from_millis(wait as u64)).await;
+            }
+        }
+        last = Some(record.time);
+
+        w.write_all(record.line.as_bytes()).await?;
+        w.write_all(b"\n").await?;
+    }
+
+    let mut received = vec![];
+    while let Ok(Some(line)) =
+        timeout(Duration::from_millis(config.wait_ms), rx.recv()).await
+    {
+        received.push(line);
+    }
+    Ok(received)
+}
+
+// 请求的 id。0 与 null 是矿池主动推送 不参与对比
+fn request_id(line: &str) -> Option<(String, String)> {
+    let rpc = serde_json::from_str::<Value>(line).ok()?;
+    let id = rpc.get("id")?;
+    if id.is_null() || id == 0 {
+        return None;
+    }
+    let method = rpc.get("method").and_then(Value::as_str).unwrap_or("");
+    Some((id.to_string(), method.to_string()))
+}
+
+// 应答的 id。带 method 的是通知
+fn response_id(line: &str) -> Option<String> {
+    let rpc = serde_json::from_str::<Value>(line).ok()?;
+    if rpc.get("method").is_some() {
+        return None;
+    }
+    Some(rpc.get("id")?.to_string())
+}
+
+// 任务 订阅信息等数据每个矿池都不同 只比较类型。[true] 这类份额结果精确比较
+fn is_data(v: &Value) -> bool {
+    match v {
+        Value::Array(a) => a.iter().any(|v| !v.is_boolean()),
+        Value::Object(_) => true,
+        _ => false,
+    }
+}
+
+fn same_response(expected: &str, actual: &str) -> bool {
+    let (expected, actual) = match (
+        serde_json::from_str::<Value>(expected),
+        serde_json::from_str::<Value>(actual),
+    ) {
+        (Ok(e), Ok(a)) => (e, a),
+        _ => return expected == actual,
+    };
+
+    let error = |v: &Value| v.get("error").cloned().unwrap_or(Value::Null);
+    if error(&expected).is_null() != error(&actual).is_null() {
+        return false;
+    }
+
+    let result = |v: &Value| v.get("result").cloned().unwrap_or(Value::Null);
+    let (expected, actual) = (result(&expected), result(&actual));
+    if is_data(&expected) && is_data(&actual) {
+        return expected.is_array() == actual.is_array();
+    }
+    expected == actual
+}
+
+// 相同 id 的请求按顺序与应答一一对应
+fn take_response(
+    lines: &[String], used: &mut Vec<bool>, id: &str,
+) -> Option<String> {
+    for (i, line) in lines.iter().enumerate() {
+        if !used[i] && response_id(line).as_deref() == Some(id) {
+            used[i] = true;
+            return Some(line.clone());
+        }
+    }
+    None
+}
+
+pub fn diff(
+    conn: u64, records: &[CaptureRecord], received: &[String],
+) -> Vec<ReplayDiff> {
+    let recorded: Vec<String> = records
+        .iter()
+        .filter(|r| r.dir == Direction::Down)
+        .map(|r| r.line.clone())
+        .collect();
+    let mut recorded_used = vec![false; recorded.len()];
+    let mut received_used = vec![false; received.len()];
+
+    let mut diffs = vec![];
+    for record in records.iter().filter(|r| r.dir == Direction::Up) {
+        let (id, method) = match request_id(&record.line) {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let expected = take_response(&recorded, &mut recorded_used, &id);
+        let actual = take_response(received, &mut received_used, &id);
+        let same = match (&expected, &actual) {
+            (Some(e), Some(a)) => same_response(e, a),
+            (None, None) => true,
+            _ => false,
+        };
+        if !same {
+            diffs.push(ReplayDiff {
+                conn,
+                id,
+                method,
+                expected,
+                actual,
+            });
+        }
+    }
+    diffs
+}
+
+// session 种子最多取的报文数 太长的样本拖慢模糊测试
+const SESSION_LINES: usize = 16;
+
+// 抓包中的钱包 矿工名与 IP。登录请求 params[0] 为 钱包.矿工名
+// 或 XMR 的 params.login。ETH 协议另有 worker 字段
+fn secrets(records: &[CaptureRecord]) -> (Vec<String>, Vec<String>) {
+    let (mut wallets, mut workers) = (vec![], vec![]);
+    let add = |list: &mut Vec<String>, s: &str| {
+        if !s.is_empty() && !list.iter().any(|v| v == s) {
+            list.push(s.to_string());
+        }
+    };
+
+    for record in records.iter().filter(|r| r.dir == Direction::Up) {
+        let rpc = match serde_json::from_str::<Value>(&record.line) {
+            Ok(rpc) => rpc,
+            Err(_) => continue,
+        };
+        let method = rpc.get("method").and_then(Value::as_str).unwrap_or("");
+        let login = match method {
+            "eth_submitLogin" | "mining.authorize" => rpc["params"][0].as_str(),
+            // stratum 矿机在订阅时登录。其他矿机 params[0] 为 软件名/版本
+            "mining.subscribe" => rpc["params"][0]
+                .as_str()
+                .filter(|s| !s.contains('/') && !s.contains(' ')),
+            "login" => rpc["params"]["login"].as_str(),
+            _ => None,
+        };
+        if let Some(login) = login {
+            let (wallet, worker) = split_login(login);
+            add(&mut wallets, wallet);
+            add(&mut workers, worker);
+        }
+        if let Some(worker) = rpc.get("worker").and_then(Value::as_str) {
+            add(&mut workers, worker);
+        }
+        if let Some(worker) = rpc["params"]["rigid"].as_str() {
+            add(&mut workers, worker);
+        }
+    }
+
+    // 先替换长的 避免钱包中含有矿工名时只替换一部分
+    wallets.sort_by(|a, b| b.len().cmp(&a.len()));
+    workers.sort_by(|a, b| b.len().cmp(&a.len()));
+    (wallets, workers)
+}
+
+// 去掉抓包中的钱包 矿工名与 IP 后才能提交到仓库
+pub fn scrub(records: &mut [CaptureRecord]) {
+    let (wallets, workers) = secrets(records);
+    for record in records.iter_mut() {
+        record.ip = "127.0.0.1".into();
+        for (i, wallet) in wallets.iter().enumerate() {
+            let to = if wallet.starts_with("0x") && wallet.len() == 42 {
+                format!("0x{:040x}", i + 1)
+            } else {
+                format!("wallet{}", i + 1)
+            };
+            record.line = record.line.replace(wallet.as_str(), &to);
+        }
+        for (i, worker) in workers.iter().enumerate() {
+            record.line = record
+                .line
+                .replace(worker.as_str(), &format!("rig{}", i + 1));
+        }
+    }
+}
+
+// 种子文件名。请求按 method 应答按结果类型
+fn seed_name(line: &str) -> Option<String> {
+    let rpc = serde_json::from_str::<Value>(line).ok()?;
+    let name = match rpc.get("method").and_then(Value::as_str) {
+        Some(method) => method.to_string(),
+        None => {
+            let kind = match rpc.get("result") {
+                _ if !rpc["error"].is_null() => "error",
+                Some(Value::Bool(true)) => "true",
+                Some(Value::Bool(false)) => "false",
+                Some(Value::Array(_)) if rpc["id"] == 0 => "job",
+                Some(Value::Array(_)) => "list",
+                Some(Value::Object(_)) => "object",
+                _ => "other",
+            };
+            format!("result_{}", kind)
+        }
+    };
+    Some(
+        name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect(),
+    )
+}
+
+// 把抓包导出为模糊测试种子。矿机报文写入 miner_message 与加密后写入
+// encrypted_frame 中转下发的写入 pool_message。每种报文取第一条
+// 另将第一条链接的前 SESSION_LINES 条报文写为 session 种子。返回写入的文件数
+pub fn export_corpus(
+    records: &[CaptureRecord], dir: &str, tag: &str,
+) -> Result<usize> {
+    let key = Vec::from_hex(DEFAULT_KEY)?;
+    let iv = Vec::from_hex(DEFAULT_IV)?;
+    let encrypt = |line: &str| -> Result<Vec<u8>> {
+        let mut frame = encrypt_frame(line.as_bytes(), &key, &iv)?;
+        frame.push(crate::SPLIT);
+        Ok(frame)
+    };
+
+    let mut seeds: BTreeMap<(&str, String), Vec<u8>> = BTreeMap::new();
+    for record in records {
+        let name = match seed_name(&record.line) {
+            Some(name) => name,
+            None => continue,
+        };
+        let line = format!("{}\n", record.line);
+        if record.dir == Direction::Up {
+            seeds
+                .entry(("miner_message", name.clone()))
+                .or_insert_with(|| line.into_bytes());
+            if !seeds.contains_key(&("encrypted_frame", name.clone())) {
+                seeds.insert(("encrypted_frame", name), encrypt(&record.line)?);
+            }
+        } else {
+            seeds
+                .entry(("pool_message", name))
+                .or_insert_with(|| line.into_bytes());
+        }
+    }
+
+    if let Some((_, records)) = group(records.to_vec()).into_iter().next() {
+        for record in records.into_iter().take(SESSION_LINES) {
+            let (target, mut buf) = match record.dir {
+                Direction::Up => ("miner_message", record.line.into_bytes()),
+                Direction::Down => ("pool_message", record.line.into_bytes()),
+            };
+            if record.dir == Direction::Up {
+                let frame = encrypt(std::str::from_utf8(&buf)?)?;
+                seeds
+                    .entry(("encrypted_frame", "session".into()))
+                    .or_default()
+                    .extend(frame);
+            }
+            buf.push(b'\n');
+            seeds
+                .entry((target, "session".into()))
+                .or_default()
+                .extend(buf);
+        }
+    }
+
+    for ((target, name), buf) in &seeds {
+        let path = std::path::Path::new(dir).join(target);
+        std::fs::create_dir_all(&path)?;
+        std::fs::write(path.join(format!("seed_{}_{}", tag, name)), buf)?;
+    }
+    Ok(seeds.len())
+}
+
+#[test]
+fn test_replay_diff() {
+    let record = |dir, line: &str| CaptureRecord {
+        time: 0,
+        conn: 1,
+        ip: "127.0.0.1".into(),
+        dir,
+        line: line.into(),
+    };
+    let records = vec![
+        record(
+            Direction::Up,
+            r#"{"id":1,"method":"eth_submitLogin","params":["0xabc"]}"#,
+        ),
+        record(Direction::Down, r#"{"id":1,"jsonrpc":"2.0","result":true}"#),
+        record(
+            Direction::Up,
+            r#"{"id":5,"method":"eth_getWork","params":[]}"#,
+        ),
+        record(Direction::Down, r#"{"id":5,"result":["0x1","0x2","0x3"]}"#),
+        record(Direction::Down, r#"{"id":0,"result":["0x4","0x5","0x6"]}"#),
+        record(
+            Direction::Up,
+            r#"{"id":40,"method":"eth_submitWork","params":[]}"#,
+        ),
+        record(Direction::Down, r#"{"id":40,"result":true}"#),
+        record(
+            Direction::Up,
+            r#"{"id":40,"method":"eth_submitWork","params":[]}"#,
+        ),
+        record(Direction::Down, r#"{"id":40,"result":true}"#),
+    ];
+
+    // 任务内容不同 不算差异
+    let received: Vec<String> = vec![
+        r#"{"id":1,"result":true}"#.into(),
+        r#"{"id":5,"result":["0x7","0x8","0x9"]}"#.into(),
+        r#"{"id":40,"result":true}"#.into(),
+        r#"{"id":40,"result":true}"#.into(),
+    ];
+    assert!(diff(1, &records, &received).is_empty());
+
+    // 第二个份额被拒绝 第一个请求没有应答
+    let received: Vec<String> = vec![
+        r#"{"id":5,"result":["0x7","0x8","0x9"]}"#.into(),
+        r#"{"id":40,"result":true}"#.into(),
+        r#"{"id":40,"result":false,"error":"stale"}"#.into(),
+    ];
+    let diffs = diff(1, &records, &received);
+    assert_eq!(diffs.len(), 2);
+    assert_eq!(diffs[0].method, "eth_submitLogin");
+    assert_eq!(diffs[0].actual, None);
+    assert_eq!(diffs[1].id, "40");
+    assert!(diffs[1].actual.as_ref().unwrap().contains("stale"));
+
+    assert!(same_response(
+        r#"{"id":2,"result":[true]}"#,
+        r#"{"id":2,"result":[true]}"#
+    ));
+    assert!(!same_response(
+        r#"{"id":2,"result":[true]}"#,
+        r#"{"id":2,"result":[false]}"#
+    ));
+}
+
+#[test]
+fn test_scrub_export() {
+    let record = |conn, dir, line: &str| CaptureRecord {
+        time: 0,
+        conn,
+        ip: "10.1.2.3".into(),
+        dir,
+        line: line.into(),
+    };
+    let wallet = "0x98be5c44d574b96b320dffb0ccff116bda433b8e";
+    let mut records = vec![
+        record(
+            1,
+            Direction::Up,
+            &format!(
+                r#"{{"id":1,"method":"eth_submitLogin","params":["{}.home01","x"],"worker":"home01"}}"#,
+                wallet
+            ),
+        ),
+        record(1, Direction::Down, r#"{"id":1,"result":true}"#),
+        record(1, Direction::Down, r#"{"id":0,"result":["0x1","0x2"]}"#),
+        record(
+            2,
+            Direction::Up,
+            r#"{"id":2,"method":"login","params":{"login":"4Abc","rigid":"xmr01"}}"#,
+        ),
+        record(
+            3,
+            Direction::Up,
+            r#"{"id":1,"method":"mining.subscribe","params":["4Def.trex01","x"]}"#,
+        ),
+        record(
+            4,
+            Direction::Up,
+            r#"{"id":1,"method":"mining.subscribe","params":["lolMiner/1.0","x"]}"#,
+        ),
+    ];
+    scrub(&mut records);
+    for r in &records {
+        assert_eq!(r.ip, "127.0.0.1");
+        assert!(!r.line.contains(wallet), "{}", r.line);
+        assert!(!r.line.contains("home01"), "{}", r.line);
+        assert!(!r.line.contains("4Abc"), "{}", r.line);
+        assert!(!r.line.contains("xmr01"), "{}", r.line);
+        assert!(!r.line.contains("trex01"), "{}", r.line);
+    }
+    assert!(records[5].line.contains("lolMiner/1.0"));
+    assert!(records[0].line.contains(&format!("0x{:040x}.rig", 1)));
+
+    let dir =
+        std::env::temp_dir().join(format!("corpus_{}", std::process::id()));
+    let dir = dir.to_str().unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    // 三种请求 加密各一份 应答 true job 与 session
+    assert_eq!(export_corpus(&records, dir, "t").unwrap(), 11);
+
+    let read = |p: &str| std::fs::read(format!("{}/{}", dir, p)).unwrap();
+    let login = read("miner_message/seed_t_eth_submitLogin");
+    assert_eq!(login, format!("{}\n", records[0].line).into_bytes());
+    assert_eq!(
+        read("pool_message/seed_t_session"),
+        format!("{}\n{}\n", records[1].line, records[2].line).into_bytes()
+    );
+    let frame = read("encrypted_frame/seed_t_eth_submitLogin");
+    assert_eq!(*frame.last().unwrap(), crate::SPLIT);
+    let key = Vec::from_hex(DEFAULT_KEY).unwrap();
+    let iv = Vec::from_hex(DEFAULT_IV).unwrap();
+    let plain =
+        crate::client::decrypt_frame(&frame[..frame.len() - 1], &key, &iv)
+            .unwrap();
+    assert_eq!(plain, records[0].line.as_bytes());
+    let _ = std::fs::remove_dir_all(dir);
+}
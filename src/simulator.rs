@@ -0,0 +1,505 @@
+//! 矿机模拟器。用 ETH 代理协议 EthereumStratum/1.0.0 或普通 stratum 登录 按目标
+//! 算力与难度以泊松间隔提交份额 断线自动重连 结束时统计延迟与接受拒绝数。
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+    select,
+    time::{sleep_until, Instant},
+};
+
+// 延迟直方图的桶上限 毫秒
+const BUCKETS_MS: [u64; 12] =
+    [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+// ETH 协议提交本地算力的间隔
+const HASHRATE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinerProtocol {
+    Eth,
+    EthStratum,
+    Stratum,
+}
+
+impl MinerProtocol {
+    // eth ethstratum stratum mixed。mixed 时矿机轮流使用三种协议
+    pub fn parse_list(s: &str) -> Result<Vec<Self>> {
+        match s {
+            "eth" => Ok(vec![MinerProtocol::Eth]),
+            "ethstratum" | "nicehash" => Ok(vec![MinerProtocol::EthStratum]),
+            "stratum" => Ok(vec![MinerProtocol::Stratum]),
+            "mixed" => Ok(vec![
+                MinerProtocol::Eth,
+                MinerProtocol::EthStratum,
+                MinerProtocol::Stratum,
+            ]),
+            _ => bail!("协议错误 {} 可选 eth ethstratum stratum mixed", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub server: String,
+    pub miners: u32,
+    pub protocols: Vec<MinerProtocol>,
+    // 每台矿机的算力 H/s
+    pub hashrate: f64,
+    // 每个份额对应的哈希次数
+    pub difficulty: f64,
+    // 为空时随机生成钱包
+    pub wallets: Vec<String>,
+    pub reconnect_delay: Duration,
+    // 固定随机种子 便于重复同样的负载
+    pub seed: Option<u64>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            server: "127.0.0.1:8888".into(),
+            miners: 1,
+            protocols: vec![MinerProtocol::Eth],
+            hashrate: 100_000_000.0,
+            difficulty: 4_000_000_000.0,
+            wallets: vec![],
+            reconnect_delay: Duration::from_secs(1),
+            seed: None,
+        }
+    }
+}
+
+impl SimConfig {
+    // 两次份额之间的平均间隔
+    pub fn share_interval(&self) -> Duration {
+        Duration::from_secs_f64(self.difficulty / self.hashrate.max(1.0))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS_MS.len() + 1],
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        let idx = BUCKETS_MS
+            .iter()
+            .position(|b| ms < *b)
+            .unwrap_or(BUCKETS_MS.len());
+        self.buckets[idx] += 1;
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 { self.count }
+
+    // 百分位所在桶的上限 毫秒。超过最大桶时返回最大延迟
+    pub fn percentile(&self, p: f64) -> u64 {
+        let want = (self.count as f64 * p).ceil() as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= want && *n > 0 {
+                return BUCKETS_MS
+                    .get(i)
+                    .copied()
+                    .unwrap_or(self.max.as_millis() as u64);
+            }
+        }
+        0
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 0 {
+            return writeln!(f, "没有收到回复");
+        }
+        let mut lower = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            let label = match BUCKETS_MS.get(i) {
+                Some(upper) => format!("{:>5}-{:<5}ms", lower, upper),
+                None => format!("{:>5}+     ms", lower),
+            };
+            lower = BUCKETS_MS.get(i).copied().unwrap_or(lower);
+            if *n == 0 {
+                continue;
+            }
+            let bar = "#".repeat((*n * 50 / self.count).max(1) as usize);
+            writeln!(f, "{} {:>8} {}", label, n, bar)?;
+        }
+        writeln!(
+            f,
+            "平均 {:.1}ms p50 <{}ms p90 <{}ms p99 <{}ms 最大 {}ms",
+            self.total.as_secs_f64() * 1000.0 / self.count as f64,
+            self.percentile(0.5),
+            self.percentile(0.9),
+            self.percentile(0.99),
+            self.max.as_millis()
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SimStats {
+    pub submitted: AtomicU64,
+    pub accepted: AtomicU64,
+    pub rejected: AtomicU64,
+    // 断线时未收到回复的份额
+    pub lost: AtomicU64,
+    pub reconnects: AtomicU64,
+    pub connect_failures: AtomicU64,
+    pub latency: Mutex<LatencyHistogram>,
+}
+
+impl fmt::Display for SimStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "提交 {} 接受 {} 拒绝 {} 丢失 {} 重连 {} 链接失败 {}",
+            self.submitted.load(Ordering::Relaxed),
+            self.accepted.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed),
+            self.lost.load(Ordering::Relaxed),
+            self.reconnects.load(Ordering::Relaxed),
+            self.connect_failures.load(Ordering::Relaxed)
+        )?;
+        write!(f, "{}", self.latency.lock().unwrap())
+    }
+}
+
+pub fn random_wallet(rng: &mut impl Rng) -> String {
+    let bytes: [u8; 20] = rng.gen();
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// 启动全部矿机 在 deadline 时停止。统计写入 stats 中途也可读取
+pub async fn run(config: SimConfig, stats: Arc<SimStats>, deadline: Instant) {
+    let config = Arc::new(config);
+
+    let mut handles = vec![];
+    for i in 0..config.miners {
+        let config = config.clone();
+        let stats = stats.clone();
+        handles.push(tokio::spawn(async move {
+            run_miner(i, &config, &stats, deadline).await
+        }));
+    }
+
+    for handle in handles {
+        let _ = handle.await;
+    }
+}
+
+struct Miner {
+    index: u32,
+    protocol: MinerProtocol,
+    wallet: String,
+    worker: String,
+    rng: ChaCha8Rng,
+}
+
+async fn run_miner(
+    index: u32, config: &SimConfig, stats: &SimStats, deadline: Instant,
+) {
+    let mut rng = match config.seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed + index as u64),
+        None => ChaCha8Rng::from_entropy(),
+    };
+    let wallet = match config.wallets.len() {
+        0 => random_wallet(&mut rng),
+        n => config.wallets[index as usize % n].clone(),
+    };
+    let worker = format!("sim{}_{:04x}", index, rng.gen::<u16>());
+    let protocol = config.protocols[index as usize % config.protocols.len()];
+    let mut miner = Miner {
+        index,
+        protocol,
+        wallet,
+        worker,
+        rng,
+    };
+
+    let mut first = true;
+    while Instant::now() < deadline {
+        if !first {
+            stats.reconnects.fetch_add(1, Ordering::Relaxed);
+            let wait = (Instant::now() + config.reconnect_delay).min(deadline);
+            sleep_until(wait).await;
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        first = false;
+
+        let stream = match tokio::time::timeout(
+            Duration::from_secs(5),
+            TcpStream::connect(&config.server),
+        )
+        .await
+        {
+            Ok(Ok(stream)) => stream,
+            _ => {
+                stats.connect_failures.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+
+        if let Err(e) = mine(&mut miner, stream, config, stats, deadline).await
+        {
+            log::debug!("模拟矿机 {} 断开 {}", miner.index, e);
+        }
+    }
+}
+
+async fn write_json(w: &mut OwnedWriteHalf, rpc: &Value) -> Result<()> {
+    let mut line = serde_json::to_vec(rpc)?;
+    line.push(b'\n');
+    w.write_all(&line).await?;
+    Ok(())
+}
+
+// 份额回复是否为接受。result 为 true 或 [true]
+fn share_accepted(rpc: &Value) -> bool {
+    if !rpc["error"].is_null() {
+        return false;
+    }
+    match &rpc["result"] {
+        Value::Bool(b) => *b,
+        Value::Array(arr) => {
+            arr.first().and_then(|r| r.as_bool()) == Some(true)
+        }
+        _ => false,
+    }
+}
+
+// 下一个份额的时间 指数分布
+fn next_share(rng: &mut ChaCha8Rng, mean: Duration) -> Instant {
+    let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+    Instant::now() + mean.mul_f64(-u.ln())
+}
+
+#[derive(Debug, Default)]
+struct Job {
+    job_id: String,
+    header: String,
+}
+
+async fn mine(
+    miner: &mut Miner, stream: TcpStream, config: &SimConfig, stats: &SimStats,
+    deadline: Instant,
+) -> Result<()> {
+    let (r, mut w) = stream.into_split();
+    let mut lines = BufReader::new(r).lines();
+    let full_name = format!("{}.{}", miner.wallet, miner.worker);
+
+    match miner.protocol {
+        MinerProtocol::Eth => {
+            write_json(&mut w, &json!({"id": 1, "method": "eth_submitLogin", "params": [miner.wallet, "x"], "worker": miner.worker})).await?;
+            write_json(
+                &mut w,
+                &json!({"id": 5, "method": "eth_getWork", "params": []}),
+            )
+            .await?;
+        }
+        MinerProtocol::EthStratum => {
+            write_json(&mut w, &json!({"id": 1, "method": "mining.subscribe", "params": ["MinerSimulator/1.0.0", "EthereumStratum/1.0.0"]})).await?;
+            write_json(&mut w, &json!({"id": 2, "method": "mining.authorize", "params": [full_name, "x"]})).await?;
+        }
+        MinerProtocol::Stratum => {
+            write_json(&mut w, &json!({"id": 1, "method": "mining.subscribe", "params": [full_name, "x"]})).await?;
+        }
+    }
+
+    let mean = config.share_interval();
+    let mut job: Option<Job> = None;
+    let mut extranonce = String::new();
+    let mut pending: HashMap<u64, Instant> = HashMap::new();
+    let mut next_id = 100;
+    let mut share_at = next_share(&mut miner.rng, mean);
+    let mut hashrate_at = Instant::now();
+
+    let res = loop {
+        let timer = share_at.min(deadline).min(
+            if miner.protocol == MinerProtocol::Eth {
+                hashrate_at
+            } else {
+                deadline
+            },
+        );
+        select! {
+            line = lines.next_line() => {
+                let line = match line {
+                    Ok(Some(line)) => line,
+                    Ok(None) => break Err(anyhow::anyhow!("矿池断开了")),
+                    Err(e) => break Err(e.into()),
+                };
+                let rpc: Value = match serde_json::from_str(&line) {
+                    Ok(rpc) => rpc,
+                    Err(_) => continue,
+                };
+
+                // 任务
+                if rpc["method"] == "mining.notify" {
+                    let params = &rpc["params"];
+                    job = Some(Job {
+                        job_id: params[0].as_str().unwrap_or("").to_string(),
+                        header: params[2].as_str().unwrap_or("").to_string(),
+                    });
+                    continue;
+                }
+                if let Some(result) = rpc["result"].as_array() {
+                    if result.len() >= 3 && result.iter().all(|r| r.is_string()) {
+                        job = Some(Job {
+                            job_id: String::new(),
+                            header: result[0].as_str().unwrap_or("").to_string(),
+                        });
+                        continue;
+                    }
+                    // EthereumStratum 订阅回复带 extranonce
+                    if miner.protocol == MinerProtocol::EthStratum && rpc["id"] == 1 {
+                        extranonce = result.get(1).and_then(|e| e.as_str()).unwrap_or("").to_string();
+                        continue;
+                    }
+                }
+
+                if let Some(sent) = rpc["id"].as_u64().and_then(|id| pending.remove(&id)) {
+                    stats.latency.lock().unwrap().record(sent.elapsed());
+                    if share_accepted(&rpc) {
+                        stats.accepted.fetch_add(1, Ordering::Relaxed);
+                    } else {
+                        stats.rejected.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            },
+            _ = sleep_until(timer) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    break Ok(());
+                }
+
+                if miner.protocol == MinerProtocol::Eth && now >= hashrate_at {
+                    hashrate_at = now + HASHRATE_INTERVAL;
+                    let id = format!("0x{:064x}", miner.index);
+                    if let Err(e) = write_json(&mut w, &json!({"id": 6, "method": "eth_submitHashrate", "params": [format!("0x{:x}", config.hashrate as u64), id], "worker": miner.worker})).await {
+                        break Err(e);
+                    }
+                }
+
+                if now < share_at {
+                    continue;
+                }
+                share_at = next_share(&mut miner.rng, mean);
+                let job = match &job {
+                    Some(job) => job,
+                    // 还没收到任务 跳过这个份额
+                    None => continue,
+                };
+
+                next_id += 1;
+                let nonce: u64 = miner.rng.gen();
+                let submit = match miner.protocol {
+                    MinerProtocol::Eth => json!({
+                        "id": next_id,
+                        "method": "eth_submitWork",
+                        "params": [format!("0x{:016x}", nonce), job.header, format!("0x{:064x}", nonce)],
+                        "worker": miner.worker,
+                    }),
+                    MinerProtocol::EthStratum => {
+                        let nonce = format!("{:016x}", nonce);
+                        let nonce = &nonce[extranonce.len().min(16)..];
+                        json!({"id": next_id, "method": "mining.submit", "params": [full_name, job.job_id, nonce]})
+                    }
+                    MinerProtocol::Stratum => json!({
+                        "id": next_id,
+                        "method": "mining.submit",
+                        "params": [full_name, job.job_id, format!("0x{:016x}", nonce), job.header, format!("0x{:064x}", nonce)],
+                    }),
+                };
+                if let Err(e) = write_json(&mut w, &submit).await {
+                    break Err(e);
+                }
+                stats.submitted.fetch_add(1, Ordering::Relaxed);
+                pending.insert(next_id, Instant::now());
+            }
+        }
+    };
+
+    stats
+        .lost
+        .fetch_add(pending.len() as u64, Ordering::Relaxed);
+    res
+}
+
+#[test]
+fn test_latency_histogram() {
+    let mut histogram = LatencyHistogram::default();
+    for ms in [0, 3, 3, 15, 150, 7000] {
+        histogram.record(Duration::from_millis(ms));
+    }
+    assert_eq!(histogram.count(), 6);
+    assert_eq!(histogram.percentile(0.5), 5);
+    assert_eq!(histogram.percentile(0.8), 200);
+    assert_eq!(histogram.percentile(1.0), 7000);
+    assert!(histogram.to_string().contains("p99"));
+
+    assert!(MinerProtocol::parse_list("mixed").unwrap().len() == 3);
+    assert!(MinerProtocol::parse_list("sha256").is_err());
+}
+
+#[tokio::test]
+async fn test_simulator_against_fake_pool() {
+    use crate::fake_pool::{FakePool, FakePoolConfig, ShareRule};
+
+    let pool = FakePool::start(
+        "127.0.0.1:0",
+        FakePoolConfig {
+            job_interval_ms: 0,
+            share_rule: ShareRule::RejectEvery(4),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let config = SimConfig {
+        server: pool.addr().to_string(),
+        miners: 3,
+        protocols: MinerProtocol::parse_list("mixed").unwrap(),
+        // 平均 20ms 一个份额
+        hashrate: 50.0,
+        difficulty: 1.0,
+        seed: Some(7),
+        ..Default::default()
+    };
+    let stats = Arc::new(SimStats::default());
+    let deadline = Instant::now() + Duration::from_millis(1500);
+    run(config, stats.clone(), deadline).await;
+
+    let accepted = stats.accepted.load(Ordering::Relaxed);
+    let rejected = stats.rejected.load(Ordering::Relaxed);
+    assert!(accepted > 0 && rejected > 0, "{}", stats);
+    assert!(pool.shares().len() as u64 >= accepted + rejected);
+    assert_eq!(pool.logins().len(), 3);
+    assert_eq!(stats.latency.lock().unwrap().count(), accepted + rejected);
+}
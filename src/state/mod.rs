@@ -1,6 +1,10 @@
-use std::sync::{
-    atomic::{AtomicU32, AtomicU64},
-    Arc,
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64},
+        Arc, RwLock,
+    },
 };
 
 extern crate serde_millis;
@@ -9,7 +13,10 @@ use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use std::time::Instant;
 
-use crate::protocol::PROTOCOL;
+use crate::{
+    protocol::PROTOCOL,
+    util::{audit::FeeAudit, capture::Capture},
+};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Worker {
@@ -30,6 +37,24 @@ pub struct Worker {
     pub fee_share_index: u64,
     pub fee_accept_index: u64,
     pub fee_invalid_index: u64,
+    // 矿机来源地址
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
+    // 抽水规则匹配到的抽水比例。None 为使用中转设置
+    #[serde(default)]
+    pub fee_rate: Option<f32>,
+    // 当前任务难度 为每个份额对应的哈希次数
+    #[serde(default)]
+    pub diff: u64,
+    // 抽水矿池的任务难度。与主矿池同时链接时单独记录 未设置按 diff 计算
+    #[serde(default)]
+    pub fee_diff: u64,
+    // 按难度加权的已接受工作量。包含抽水部分
+    #[serde(default)]
+    pub accept_work: u64,
+    // 按难度加权的抽水已接受工作量
+    #[serde(default)]
+    pub fee_accept_work: u64,
 }
 
 impl Worker {
@@ -53,6 +78,12 @@ impl Worker {
             fee_accept_index: 0,
             fee_invalid_index: 0,
             rpc_id: 0,
+            ip: None,
+            fee_rate: None,
+            diff: 0,
+            fee_diff: 0,
+            accept_work: 0,
+            fee_accept_work: 0,
         }
     }
 
@@ -73,6 +104,12 @@ impl Worker {
             fee_accept_index: 0,
             fee_invalid_index: 0,
             rpc_id: 0,
+            ip: None,
+            fee_rate: None,
+            diff: 0,
+            fee_diff: 0,
+            accept_work: 0,
+            fee_accept_work: 0,
         }
     }
 
@@ -80,6 +117,10 @@ impl Worker {
         &mut self, worker: String, worker_name: String, worker_wallet: String,
     ) {
         info!("矿工: {} 请求登录", worker);
+        crate::util::logger::update(|ctx| {
+            ctx.wallet = worker_wallet.clone();
+            ctx.worker = worker_name.clone();
+        });
         self.worker = worker;
         self.worker_name = worker_name;
         self.worker_wallet = worker_wallet;
@@ -107,7 +148,10 @@ impl Worker {
     }
 
     // 设置当前链接协议
-    pub fn set_protocol(&mut self, p: PROTOCOL) { self.protocol = p; }
+    pub fn set_protocol(&mut self, p: PROTOCOL) {
+        crate::util::logger::update(|ctx| ctx.protocol = format!("{:?}", p));
+        self.protocol = p;
+    }
 
     // 判断是否在线
     pub fn is_online(&self) -> bool { self.online }
@@ -121,6 +165,8 @@ impl Worker {
         self.share_index = 0;
         self.accept_index = 0;
         self.invalid_index = 0;
+        self.accept_work = 0;
+        self.fee_accept_work = 0;
         //self.login_time = Instant::now();
     }
 
@@ -132,9 +178,40 @@ impl Worker {
         debug!("矿工: {} Share #{}", self.worker, self.share_index);
     }
 
+    // 设置当前任务难度。份额按难度计入工作量
+    pub fn set_diff(&mut self, diff: u64) { self.diff = diff; }
+
+    // 抽水规则设置为0的矿工 不做任何抽水
+    pub fn is_fee_exempt(&self) -> bool {
+        matches!(self.fee_rate, Some(rate) if rate <= 0.0)
+    }
+
+    pub fn set_fee_diff(&mut self, diff: u64) { self.fee_diff = diff; }
+
+    // 每个份额的工作量。未知难度时按1计算
+    fn share_work(&self) -> u64 { self.diff.max(1) }
+
+    // 每个抽水份额的工作量
+    pub fn fee_share_work(&self) -> u64 {
+        if self.fee_diff > 0 {
+            self.fee_diff
+        } else {
+            self.share_work()
+        }
+    }
+
+    // 实际抽水比例 按工作量计算
+    pub fn fee_work_rate(&self) -> f64 {
+        if self.accept_work == 0 {
+            return 0.0;
+        }
+        self.fee_accept_work as f64 / self.accept_work as f64
+    }
+
     // 接受份额
     pub fn share_accept(&mut self) {
         self.accept_index += 1;
+        self.accept_work = self.accept_work.saturating_add(self.share_work());
         debug!("矿工: {} Share Accept #{}", self.worker, self.share_index);
     }
 
@@ -152,15 +229,20 @@ impl Worker {
         //debug!("矿工: {} Share #{}", self.worker, self.share_index);
     }
 
-    // 接受份额
+    // 接受的抽水份额。同时计入矿工的总份额与总工作量
     pub fn fee_share_accept(&mut self) {
+        let work = self.fee_share_work();
         self.fee_accept_index += 1;
+        self.fee_accept_work = self.fee_accept_work.saturating_add(work);
+        self.accept_index += 1;
+        self.accept_work = self.accept_work.saturating_add(work);
         //debug!("矿工: {} Share Accept #{}", self.worker, self.share_index);
     }
 
-    // 拒绝的份额
+    // 拒绝的抽水份额。不计工作量
     pub fn fee_share_reject(&mut self) {
         self.fee_invalid_index += 1;
+        self.invalid_index += 1;
         //debug!("矿工: {} Share Reject #{}", self.worker, self.share_index);
     }
 
@@ -183,6 +265,58 @@ impl Worker {
 
 pub type State = Arc<GlobalState>;
 
+// 缓存任务的有效期。超过后不再下发给新登录的矿机
+pub const JOB_CACHE_SECS: u64 = 30;
+
+/// 每个矿池最近一次下发的 getwork 任务。新矿机登录成功后立即下发
+/// 不必等待矿池推送。任务属于写入它的矿池会话 会话断开或切换矿池后清除
+#[derive(Debug, Default)]
+pub struct JobCache {
+    jobs: RwLock<HashMap<String, (Instant, u64, String)>>,
+}
+
+impl JobCache {
+    pub fn set(&self, pool: &str, session: u64, job: String) {
+        if let Ok(mut jobs) = self.jobs.write() {
+            jobs.insert(pool.to_string(), (Instant::now(), session, job));
+        }
+    }
+
+    pub fn get(&self, pool: &str) -> Option<String> {
+        let jobs = self.jobs.read().ok()?;
+        let (time, _, job) = jobs.get(pool)?;
+        if time.elapsed().as_secs() >= JOB_CACHE_SECS {
+            return None;
+        }
+        Some(job.clone())
+    }
+
+    // 清除该会话写入的任务
+    pub fn drop_session(&self, session: u64) {
+        if let Ok(mut jobs) = self.jobs.write() {
+            jobs.retain(|_, (_, owner, _)| *owner != session);
+        }
+    }
+}
+
+/// 矿机链接退出时清除它的矿池会话缓存的任务
+pub struct JobCacheSession {
+    state: State,
+    pub id: u64,
+}
+
+impl JobCacheSession {
+    // id 为矿机链接序号 state.next_conn()
+    pub fn new(state: State, id: u64) -> Self { Self { state, id } }
+
+    // 切换矿池前调用
+    pub fn reset(&self) { self.state.jobs.drop_session(self.id); }
+}
+
+impl Drop for JobCacheSession {
+    fn drop(&mut self) { self.reset(); }
+}
+
 pub struct GlobalState {
     pub online: AtomicU32,
     pub proxy_share: AtomicU64,
@@ -191,6 +325,24 @@ pub struct GlobalState {
     pub develop_share: AtomicU64,
     pub develop_accept: AtomicU64,
     pub develop_reject: AtomicU64,
+    pub jobs: JobCache,
+    // 抽水审计日志。未开启时不记录
+    pub audit: FeeAudit,
+    // 矿机报文抓包。由主控 web 端开关
+    pub capture: Capture,
+    // 矿机链接序号。日志与抓包中的 conn
+    pub conn: AtomicU64,
+    // 钱包或矿工名不合法被拒绝的登录次数
+    pub login_reject_wallet: AtomicU64,
+    pub login_reject_worker: AtomicU64,
+}
+
+// 被拒绝的登录次数。子进程定时上报给主控 web 端
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoginRejects {
+    pub wallet: u64,
+    pub worker: u64,
 }
 
 impl GlobalState {
@@ -203,8 +355,26 @@ impl GlobalState {
             develop_share: AtomicU64::new(0),
             develop_accept: AtomicU64::new(0),
             develop_reject: AtomicU64::new(0),
+            jobs: JobCache::default(),
+            audit: FeeAudit::default(),
+            capture: Capture::default(),
+            conn: AtomicU64::new(0),
+            login_reject_wallet: AtomicU64::new(0),
+            login_reject_worker: AtomicU64::new(0),
+        }
+    }
+
+    pub fn login_rejects(&self) -> LoginRejects {
+        use std::sync::atomic::Ordering;
+        LoginRejects {
+            wallet: self.login_reject_wallet.load(Ordering::Relaxed),
+            worker: self.login_reject_worker.load(Ordering::Relaxed),
         }
     }
+
+    pub fn next_conn(&self) -> u64 {
+        self.conn.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1
+    }
 }
 
 impl Default for GlobalState {
@@ -245,3 +415,63 @@ fn test_share_reject() {
     assert_eq!(w.accept_index, 0);
     assert_eq!(w.invalid_index, 1);
 }
+
+#[test]
+fn test_share_work() {
+    let mut w = Worker::default();
+    w.share_accept();
+    assert_eq!(w.accept_work, 1);
+
+    // 抽水矿池难度是主矿池的4倍 一个抽水份额等于4个普通份额
+    w.set_diff(1000);
+    w.share_accept();
+    w.share_accept();
+    w.share_accept();
+    w.set_diff(4000);
+    w.fee_share_accept();
+    w.share_accept();
+    assert_eq!(w.accept_work, 1 + 3000 + 4000 + 4000);
+    assert_eq!(w.fee_accept_work, 4000);
+    assert!((w.fee_work_rate() - 4000.0 / 11001.0).abs() < 1e-9);
+
+    // 同时链接两个矿池时 抽水份额按抽水矿池难度计算 拒绝的不计工作量
+    let mut w = Worker::default();
+    w.set_diff(1000);
+    w.set_fee_diff(3000);
+    w.share_accept();
+    w.fee_share_accept();
+    w.fee_share_reject();
+    assert_eq!(w.accept_work, 1000 + 3000);
+    assert_eq!(w.fee_accept_work, 3000);
+    assert_eq!(w.accept_index, 2);
+    assert_eq!(w.fee_accept_index, 1);
+    assert_eq!(w.invalid_index, 1);
+    assert_eq!(w.fee_invalid_index, 1);
+}
+
+#[test]
+fn test_job_cache() {
+    let cache = JobCache::default();
+    assert_eq!(cache.get("ETH@127.0.0.1:4444"), None);
+    cache.set("ETH@127.0.0.1:4444", 1, "job".into());
+    assert_eq!(cache.get("ETH@127.0.0.1:4444"), Some("job".into()));
+    assert_eq!(cache.get("ETH@127.0.0.1:5555"), None);
+
+    // 其他会话断开不影响 写入任务的会话断开后清除
+    cache.set("ETH@127.0.0.1:5555", 2, "job2".into());
+    cache.drop_session(2);
+    assert_eq!(cache.get("ETH@127.0.0.1:4444"), Some("job".into()));
+    assert_eq!(cache.get("ETH@127.0.0.1:5555"), None);
+    cache.drop_session(1);
+    assert_eq!(cache.get("ETH@127.0.0.1:4444"), None);
+}
+
+#[test]
+fn test_fee_exempt() {
+    let mut w = Worker::default();
+    assert!(!w.is_fee_exempt());
+    w.fee_rate = Some(0.1);
+    assert!(!w.is_fee_exempt());
+    w.fee_rate = Some(0.0);
+    assert!(w.is_fee_exempt());
+}
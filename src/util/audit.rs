@@ -0,0 +1,311 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader},
+    sync::RwLock,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot},
+};
+
+// 抽水审计记录。每个份额一行 json 只追加不修改
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeRecord {
+    // unix 时间戳 秒
+    pub time: i64,
+    pub wallet: String,
+    pub worker: String,
+    pub job_id: String,
+    // 份额提交到的矿池地址
+    pub pool: String,
+    pub fee: bool,
+    pub accepted: bool,
+    // 切换矿池时还没有收到应答 结果未知。accepted 为 false
+    #[serde(default)]
+    pub no_response: bool,
+    // 份额难度 为对应的哈希次数
+    pub diff: u64,
+    // 提交时矿工的抽水比例设置
+    pub share_rate: f32,
+}
+
+#[derive(Debug)]
+enum AuditMsg {
+    Record(FeeRecord),
+    // 之前的记录全部写入后回复
+    Flush(oneshot::Sender<()>),
+}
+
+// 份额处理中只发送到通道 由单独的任务写文件 不阻塞矿工链接
+#[derive(Debug, Default)]
+pub struct FeeAudit {
+    sender: RwLock<Option<mpsc::UnboundedSender<AuditMsg>>>,
+}
+
+impl FeeAudit {
+    // 需要在 tokio 运行时内调用
+    pub fn open(&self, path: &str) -> Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(write_records(tokio::fs::File::from_std(file), rx));
+        *self.sender.write().unwrap() = Some(tx);
+        Ok(())
+    }
+
+    pub fn record(&self, record: &FeeRecord) {
+        if let Ok(sender) = self.sender.read() {
+            if let Some(tx) = sender.as_ref() {
+                if tx.send(AuditMsg::Record(record.clone())).is_err() {
+                    log::warn!("写入抽水审计日志失败 写入任务已退出");
+                }
+            }
+        }
+    }
+
+    // 等待已记录的审计写入文件
+    pub async fn flush(&self) {
+        let tx = match self.sender.read() {
+            Ok(sender) => sender.clone(),
+            Err(_) => return,
+        };
+        if let Some(tx) = tx {
+            let (done, wait) = oneshot::channel();
+            if tx.send(AuditMsg::Flush(done)).is_ok() {
+                let _ = wait.await;
+            }
+        }
+    }
+}
+
+async fn write_records(
+    mut file: tokio::fs::File, mut rx: mpsc::UnboundedReceiver<AuditMsg>,
+) {
+    while let Some(msg) = rx.recv().await {
+        match msg {
+            AuditMsg::Record(record) => {
+                let res = match serde_json::to_string(&record) {
+                    Ok(mut line) => {
+                        line.push('\n');
+                        file.write_all(line.as_bytes())
+                            .await
+                            .map_err(Into::into)
+                    }
+                    Err(e) => Err(anyhow::Error::from(e)),
+                };
+                if let Err(e) = res {
+                    log::warn!("写入抽水审计日志失败 {}", e);
+                }
+            }
+            AuditMsg::Flush(done) => {
+                if let Err(e) = file.flush().await {
+                    log::warn!("写入抽水审计日志失败 {}", e);
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+// 读取时间段内的审计记录。to 为 0 不限制结束时间
+pub fn read_records(path: &str, from: i64, to: i64) -> Result<Vec<FeeRecord>> {
+    let file = File::open(path)?;
+    let mut records = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if let Ok(record) = serde_json::from_str::<FeeRecord>(&line) {
+            if record.time >= from && (to == 0 || record.time < to) {
+                records.push(record);
+            }
+        }
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WalletFeeReport {
+    pub wallet: String,
+    pub shares: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub no_response: u64,
+    pub fee_accepted: u64,
+    pub accept_work: u64,
+    pub fee_accept_work: u64,
+    // 设置的抽水比例 按工作量加权 百分比
+    pub share_rate: f64,
+    // 实际抽水比例 按工作量计算 百分比
+    pub achieved_rate: f64,
+}
+
+// 按钱包汇总 对比设置的抽水比例与实际抽水比例
+pub fn wallet_report(records: &[FeeRecord]) -> Vec<WalletFeeReport> {
+    let mut wallets: BTreeMap<String, (WalletFeeReport, f64)> = BTreeMap::new();
+    for r in records {
+        let (report, rate_work) =
+            wallets.entry(r.wallet.clone()).or_insert_with(|| {
+                (
+                    WalletFeeReport {
+                        wallet: r.wallet.clone(),
+                        ..Default::default()
+                    },
+                    0.0,
+                )
+            });
+
+        report.shares += 1;
+        if r.no_response {
+            report.no_response += 1;
+            continue;
+        }
+        if !r.accepted {
+            report.rejected += 1;
+            continue;
+        }
+
+        let diff = r.diff.max(1);
+        report.accepted += 1;
+        report.accept_work += diff;
+        *rate_work += r.share_rate as f64 * diff as f64;
+        if r.fee {
+            report.fee_accepted += 1;
+            report.fee_accept_work += diff;
+        }
+    }
+
+    wallets
+        .into_iter()
+        .map(|(_, (mut report, rate_work))| {
+            if report.accept_work > 0 {
+                let work = report.accept_work as f64;
+                report.share_rate = rate_work / work * 100.0;
+                report.achieved_rate =
+                    report.fee_accept_work as f64 / work * 100.0;
+            }
+            report
+        })
+        .collect()
+}
+
+// 按 RFC 4180 转义。含逗号 引号 换行的字段用引号包裹 引号双写
+fn csv_field(field: &str) -> std::borrow::Cow<str> {
+    if field.contains(&[',', '"', '\r', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+pub fn report_csv(reports: &[WalletFeeReport]) -> String {
+    let mut csv = String::from(
+        "wallet,shares,accepted,rejected,fee_accepted,accept_work,\
+         fee_accept_work,share_rate,achieved_rate,no_response\n",
+    );
+    for r in reports {
+        csv += &format!(
+            "{},{},{},{},{},{},{},{:.4},{:.4},{}\n",
+            csv_field(&r.wallet),
+            r.shares,
+            r.accepted,
+            r.rejected,
+            r.fee_accepted,
+            r.accept_work,
+            r.fee_accept_work,
+            r.share_rate,
+            r.achieved_rate,
+            r.no_response
+        );
+    }
+    csv
+}
+
+#[tokio::test]
+async fn test_fee_audit() {
+    let path = std::env::temp_dir()
+        .join(format!("fee_audit_test_{}.log", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    let audit = FeeAudit::default();
+    // 未开启时不写入
+    audit.record(&FeeRecord {
+        time: 0,
+        wallet: "0xa".into(),
+        worker: "rig".into(),
+        job_id: "0".into(),
+        pool: "".into(),
+        fee: false,
+        accepted: true,
+        no_response: false,
+        diff: 1,
+        share_rate: 0.1,
+    });
+    audit.open(path).unwrap();
+
+    let mut record = FeeRecord {
+        time: 100,
+        wallet: "0xa".into(),
+        worker: "rig".into(),
+        job_id: "1".into(),
+        pool: "127.0.0.1:4444".into(),
+        fee: false,
+        accepted: true,
+        no_response: false,
+        diff: 1000,
+        share_rate: 0.1,
+    };
+    for _ in 0..9 {
+        audit.record(&record);
+    }
+    record.fee = true;
+    record.diff = 2000;
+    record.pool = "127.0.0.1:5555".into();
+    audit.record(&record);
+    record.accepted = false;
+    audit.record(&record);
+    record.no_response = true;
+    audit.record(&record);
+    record.no_response = false;
+    record.time = 200;
+    record.wallet = "0xb".into();
+    audit.record(&record);
+    audit.flush().await;
+
+    let records = read_records(path, 0, 200).unwrap();
+    assert_eq!(records.len(), 12);
+    let report = wallet_report(&records);
+    assert_eq!(report.len(), 1);
+    assert_eq!(report[0].shares, 12);
+    assert_eq!(report[0].rejected, 1);
+    assert_eq!(report[0].no_response, 1);
+    assert_eq!(report[0].fee_accepted, 1);
+    assert_eq!(report[0].accept_work, 11000);
+    assert!((report[0].achieved_rate - 2000.0 / 11000.0 * 100.0).abs() < 1e-9);
+    assert!((report[0].share_rate - 10.0).abs() < 1e-4);
+
+    let csv = report_csv(&wallet_report(&read_records(path, 0, 0).unwrap()));
+    assert_eq!(csv.lines().count(), 3);
+    assert!(csv.lines().nth(2).unwrap().starts_with("0xb,1,0,1,"));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_report_csv_escape() {
+    let reports = vec![WalletFeeReport {
+        wallet: "0xa,\"rig\"\n1".into(),
+        shares: 1,
+        ..Default::default()
+    }];
+    let csv = report_csv(&reports);
+    assert_eq!(csv.split("\n").nth(1).unwrap(), "\"0xa,\"\"rig\"\"",);
+    assert!(csv.contains("\"0xa,\"\"rig\"\"\n1\",1,0,0,0,0,0,"));
+    assert!(report_csv(&[WalletFeeReport {
+        wallet: "0xa".into(),
+        ..Default::default()
+    }])
+    .contains("\n0xa,0,"));
+}
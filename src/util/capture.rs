@@ -0,0 +1,352 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, RwLock,
+    },
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::state::State;
+
+// 未匹配到矿工前最多缓存的行数。登录前的订阅等请求也要抓到
+const PENDING_LINES: usize = 32;
+// 一直没有换行的数据不再缓存
+const MAX_LINE: usize = 64 * 1024;
+
+// 抓包记录。每行一条 json 内容为解密后的明文
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    // unix 时间戳 毫秒
+    pub time: u64,
+    // 本进程内的链接序号 与 json 日志中的 conn 一致
+    pub conn: u64,
+    pub ip: String,
+    pub dir: Direction,
+    pub line: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    // 矿机 -> 中转
+    Up,
+    // 中转 -> 矿机
+    Down,
+}
+
+// 主控 web 端下发的抓包设置
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureSetting {
+    pub enable: bool,
+    // 只抓含有此字符串的链接 填钱包或矿工名。为空抓全部
+    pub worker: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Capture {
+    enabled: AtomicBool,
+    worker: RwLock<String>,
+    file: Mutex<Option<File>>,
+}
+
+impl Capture {
+    pub fn start(&self, path: &str, worker: &str) -> Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        *self.file.lock().unwrap() = Some(file);
+        *self.worker.write().unwrap() = worker.to_string();
+        self.enabled.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.enabled.store(false, Ordering::SeqCst);
+        *self.file.lock().unwrap() = None;
+    }
+
+    pub fn apply(&self, path: &str, setting: &CaptureSetting) -> Result<()> {
+        if setting.enable {
+            self.start(path, &setting.worker)
+        } else {
+            self.stop();
+            Ok(())
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool { self.enabled.load(Ordering::Relaxed) }
+
+    fn worker(&self) -> String {
+        self.worker.read().map(|w| w.clone()).unwrap_or_default()
+    }
+
+    fn write(&self, records: &[CaptureRecord]) {
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+
+        if let Some(f) = file.as_mut() {
+            for record in records {
+                let res = serde_json::to_string(record)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|line| Ok(writeln!(f, "{}", line)?));
+                if let Err(e) = res {
+                    log::warn!("写入抓包文件失败 {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+pub fn read_records(path: &str) -> Result<Vec<CaptureRecord>> {
+    let file = File::open(path)?;
+    let mut records = vec![];
+    for line in BufReader::new(file).lines() {
+        if let Ok(record) = serde_json::from_str::<CaptureRecord>(&line?) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// 包装矿机一侧的链接 开启抓包时按行记录收发的内容。加密端口先解密再记录
+pub struct CaptureStream<S> {
+    inner: S,
+    state: State,
+    conn: u64,
+    ip: String,
+    cipher: Option<(Vec<u8>, Vec<u8>)>,
+    up: Vec<u8>,
+    down: Vec<u8>,
+    matched: bool,
+    pending: VecDeque<CaptureRecord>,
+}
+
+impl<S> CaptureStream<S> {
+    pub fn new(
+        inner: S, state: State, ip: Option<IpAddr>,
+        cipher: Option<(Vec<u8>, Vec<u8>)>,
+    ) -> Self {
+        // 与日志中的链接序号一致
+        let conn =
+            crate::util::logger::conn_id().unwrap_or_else(|| state.next_conn());
+        Self {
+            inner,
+            state,
+            conn,
+            ip: ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            cipher,
+            up: vec![],
+            down: vec![],
+            matched: false,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn feed(&mut self, dir: Direction, data: &[u8]) {
+        if !self.state.capture.is_enabled() {
+            self.up.clear();
+            self.down.clear();
+            return;
+        }
+
+        let mut buf = match dir {
+            Direction::Up => std::mem::take(&mut self.up),
+            Direction::Down => std::mem::take(&mut self.down),
+        };
+        buf.extend_from_slice(data);
+
+        let mut lines = vec![];
+        while let Some(pos) = buf.iter().position(|c| *c == crate::SPLIT) {
+            let frame: Vec<u8> = buf.drain(..=pos).collect();
+            let frame = &frame[..pos];
+            if frame.is_empty() {
+                continue;
+            }
+            let plain = match &self.cipher {
+                Some((key, iv)) => crate::client::decrypt_frame(frame, key, iv)
+                    .unwrap_or_else(|_| frame.to_vec()),
+                None => frame.to_vec(),
+            };
+            for line in plain.split(|c| *c == b'\n') {
+                let line = String::from_utf8_lossy(line);
+                let line = line.trim_end_matches('\r');
+                if !line.is_empty() {
+                    lines.push(line.to_string());
+                }
+            }
+        }
+        if buf.len() > MAX_LINE {
+            buf.clear();
+        }
+
+        match dir {
+            Direction::Up => self.up = buf,
+            Direction::Down => self.down = buf,
+        }
+
+        for line in lines {
+            self.record(dir, line);
+        }
+    }
+
+    fn record(&mut self, dir: Direction, line: String) {
+        let record = CaptureRecord {
+            time: now_millis(),
+            conn: self.conn,
+            ip: self.ip.clone(),
+            dir,
+            line,
+        };
+
+        if !self.matched {
+            let worker = self.state.capture.worker();
+            if !worker.is_empty() && !record.line.contains(&worker) {
+                self.pending.push_back(record);
+                if self.pending.len() > PENDING_LINES {
+                    self.pending.pop_front();
+                }
+                return;
+            }
+            self.matched = true;
+            let pending: Vec<_> = self.pending.drain(..).collect();
+            self.state.capture.write(&pending);
+        }
+        self.state.capture.write(&[record]);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CaptureStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            if self.state.capture.is_enabled() {
+                let data = buf.filled()[before..].to_vec();
+                self.feed(Direction::Up, &data);
+            }
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CaptureStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            if self.state.capture.is_enabled() {
+                self.feed(Direction::Down, &buf[..n]);
+            }
+        }
+        res
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>, cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[tokio::test]
+async fn test_capture_stream() {
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let path = std::env::temp_dir()
+        .join(format!("capture_test_{}.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    let state: State = Arc::new(crate::state::GlobalState::new());
+    state.capture.start(path, "rig1").unwrap();
+
+    let key = vec![1u8; 32];
+    let iv = vec![2u8; 16];
+    let encrypt = |line: &str| {
+        let cipher = openssl::symm::Cipher::aes_256_cbc();
+        let data =
+            openssl::symm::encrypt(cipher, &key, Some(&iv), line.as_bytes())
+                .unwrap();
+        let mut frame = base64::encode(data).into_bytes();
+        frame.push(crate::SPLIT);
+        frame
+    };
+
+    // 加密端口 先解密 只记录 rig1 的链接
+    for worker in ["rig1", "rig2"] {
+        let (miner, proxy) = tokio::io::duplex(1024);
+        let mut proxy = CaptureStream::new(
+            proxy,
+            state.clone(),
+            Some("127.0.0.1".parse().unwrap()),
+            Some((key.clone(), iv.clone())),
+        );
+        let (mut miner_r, mut miner_w) = tokio::io::split(miner);
+
+        let subscribe = r#"{"id":1,"method":"mining.subscribe","params":[]}"#;
+        let login = format!(
+            r#"{{"id":2,"method":"mining.authorize","params":["0xabc.{}"]}}"#,
+            worker
+        );
+        let mut sent = encrypt(subscribe);
+        // 一帧被拆成两次读取
+        let login = encrypt(&login);
+        sent.extend_from_slice(&login[..10]);
+        miner_w.write_all(&sent).await.unwrap();
+        let mut buf = vec![0u8; 4096];
+        let n = proxy.read(&mut buf).await.unwrap();
+        miner_w.write_all(&login[10..]).await.unwrap();
+        let mut total = n;
+        while total < sent.len() - 10 + login.len() {
+            total += proxy.read(&mut buf).await.unwrap();
+        }
+
+        proxy
+            .write_all(&encrypt(r#"{"id":2,"result":true}"#))
+            .await
+            .unwrap();
+        let mut reply = vec![0u8; 4096];
+        miner_r.read(&mut reply).await.unwrap();
+    }
+    state.capture.stop();
+
+    let records = read_records(path).unwrap();
+    let _ = std::fs::remove_file(path);
+    assert_eq!(records.len(), 3, "{:?}", records);
+    assert!(records.iter().all(|r| r.conn == records[0].conn));
+    assert_eq!(records[0].dir, Direction::Up);
+    assert!(records[0].line.contains("mining.subscribe"));
+    assert!(records[1].line.contains("0xabc.rig1"));
+    assert_eq!(records[2].dir, Direction::Down);
+    assert_eq!(records[2].line, r#"{"id":2,"result":true}"#);
+    assert_eq!(records[2].ip, "127.0.0.1");
+}
@@ -0,0 +1,302 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+// 日志切分与保留设置。0 为不限制
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RotateOptions {
+    // 单个文件超过此大小切分 字节
+    pub max_size: u64,
+    // 文件写入超过此时间切分
+    pub max_age: Duration,
+    // 最多保留的切分文件数
+    pub max_files: usize,
+    // 切分文件的总大小上限 字节。超出时从最旧的开始删除
+    pub max_total: u64,
+    // 切分后压缩为 .gz
+    pub gzip: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogFileInfo {
+    pub name: String,
+    pub size: u64,
+    // unix 时间戳 秒
+    pub modified: u64,
+}
+
+/// 按大小与时间切分的日志文件。当前写入 {name}.log 切分后改名为
+/// {name}.log.{时间} 并按保留设置删除旧文件
+pub struct RotatingFile {
+    dir: PathBuf,
+    name: String,
+    options: RotateOptions,
+    file: File,
+    size: u64,
+    opened: SystemTime,
+    // 只在一条日志写完后切分 避免一行被拆到两个文件
+    line_start: bool,
+}
+
+impl RotatingFile {
+    pub fn open(dir: &str, name: &str, options: RotateOptions) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.log", name));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let meta = file.metadata()?;
+        // 重启后接着写 按文件创建时间计算
+        let opened = meta
+            .created()
+            .or_else(|_| meta.modified())
+            .unwrap_or_else(|_| SystemTime::now());
+
+        Ok(Self {
+            dir,
+            name: name.to_string(),
+            options,
+            size: meta.len(),
+            file,
+            opened,
+            line_start: true,
+        })
+    }
+
+    fn should_rotate(&self, len: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let o = &self.options;
+        if o.max_size > 0 && self.size + len as u64 > o.max_size {
+            return true;
+        }
+        o.max_age > Duration::from_secs(0)
+            && self.opened.elapsed().unwrap_or_default() >= o.max_age
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let current = self.dir.join(format!("{}.log", self.name));
+        let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let mut rotated = self.dir.join(format!("{}.log.{}", self.name, stamp));
+        let mut n = 1;
+        while rotated.exists() || gz_path(&rotated).exists() {
+            rotated =
+                self.dir.join(format!("{}.log.{}-{}", self.name, stamp, n));
+            n += 1;
+        }
+
+        self.file.flush()?;
+        fs::rename(&current, &rotated)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&current)?;
+        self.size = 0;
+        self.opened = SystemTime::now();
+
+        // 压缩与清理较慢 不阻塞写日志
+        let dir = self.dir.clone();
+        let name = self.name.clone();
+        let options = self.options.clone();
+        std::thread::spawn(move || {
+            if options.gzip {
+                if let Err(e) = gzip(&rotated) {
+                    eprintln!("压缩日志 {:?} 失败 {}", rotated, e);
+                }
+            }
+            if let Err(e) = cleanup(&dir, &name, &options) {
+                eprintln!("清理日志失败 {}", e);
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.line_start && self.should_rotate(buf.len()) {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        self.line_start = buf[..n].ends_with(b"\n");
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.file.flush() }
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+fn gzip(path: &Path) -> io::Result<()> {
+    let gz = gz_path(path);
+    let mut encoder =
+        GzEncoder::new(File::create(&gz)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(path)
+}
+
+fn modified_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// 该中转的全部日志文件 当前文件在前 其余按修改时间从新到旧
+pub fn list_files(dir: &str, name: &str) -> Result<Vec<LogFileInfo>> {
+    let current = format!("{}.log", name);
+    let prefix = format!("{}.log.", name);
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if file_name != current && !file_name.starts_with(&prefix) {
+            continue;
+        }
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        files.push(LogFileInfo {
+            name: file_name,
+            size: meta.len(),
+            modified: modified_secs(&meta),
+        });
+    }
+    files.sort_by(|a, b| {
+        (b.name == current)
+            .cmp(&(a.name == current))
+            .then(b.modified.cmp(&a.modified))
+            .then(b.name.cmp(&a.name))
+    });
+    Ok(files)
+}
+
+// 按保留设置删除旧的切分文件。旧版按小时生成的文件同样参与清理
+pub fn cleanup(dir: &Path, name: &str, options: &RotateOptions) -> Result<()> {
+    let current = format!("{}.log", name);
+    let files = list_files(&dir.to_string_lossy(), name)?;
+    let mut kept = 0;
+    let mut total = 0;
+    for file in files.iter().filter(|f| f.name != current) {
+        kept += 1;
+        total += file.size;
+        let over_count = options.max_files > 0 && kept > options.max_files;
+        let over_size = options.max_total > 0 && total > options.max_total;
+        if over_count || over_size {
+            fs::remove_file(dir.join(&file.name))?;
+        }
+    }
+    Ok(())
+}
+
+// 日志文件最后 n 行。.gz 文件先解压
+pub fn tail(path: &Path, n: usize) -> Result<Vec<String>> {
+    if path.extension().map_or(false, |e| e == "gz") {
+        let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+        let mut lines = std::collections::VecDeque::with_capacity(n);
+        for line in reader.lines() {
+            if lines.len() == n {
+                lines.pop_front();
+            }
+            lines.push_back(line?);
+        }
+        return Ok(lines.into_iter().collect());
+    }
+
+    // 从文件末尾按块往前读 大文件也只读需要的部分
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut pos = len;
+    let mut buf: Vec<u8> = vec![];
+    while pos > 0 && buf.iter().filter(|c| **c == b'\n').count() <= n {
+        let size = std::cmp::min(pos, 64 * 1024);
+        pos -= size;
+        file.seek(SeekFrom::Start(pos))?;
+        let mut chunk = vec![0u8; size as usize];
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&buf);
+        buf = chunk;
+    }
+
+    let text = String::from_utf8_lossy(&buf);
+    let lines: Vec<&str> = text.lines().collect();
+    let skip = lines.len().saturating_sub(n);
+    Ok(lines[skip..].iter().map(|l| l.to_string()).collect())
+}
+
+// web 接口传入的文件名必须是该中转的日志文件
+pub fn resolve(dir: &str, name: &str, file: &str) -> Result<PathBuf> {
+    let file = if file.is_empty() {
+        format!("{}.log", name)
+    } else {
+        file.to_string()
+    };
+    if !list_files(dir, name)?.iter().any(|f| f.name == file) {
+        bail!("日志文件不存在 {}", file);
+    }
+    Ok(Path::new(dir).join(file))
+}
+
+#[test]
+fn test_rotating_file() {
+    let dir = std::env::temp_dir()
+        .join(format!("log_rotate_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let dir_str = dir.to_str().unwrap();
+
+    let options = RotateOptions {
+        max_size: 100,
+        max_files: 2,
+        ..Default::default()
+    };
+    let mut log =
+        RotatingFile::open(dir_str, "proxy", options.clone()).unwrap();
+    for i in 0..20 {
+        // 一行分两次写入 不会被拆到两个文件
+        write!(log, "line {:02} ", i).unwrap();
+        writeln!(log, "{}", "x".repeat(20)).unwrap();
+    }
+    log.flush().unwrap();
+    // 清理在后台线程
+    std::thread::sleep(Duration::from_millis(200));
+    cleanup(&dir, "proxy", &options).unwrap();
+
+    let files = list_files(dir_str, "proxy").unwrap();
+    assert_eq!(files.len(), 3, "{:?}", files);
+    assert_eq!(files[0].name, "proxy.log");
+    for file in &files {
+        // 只在行尾切分 最多超出一行
+        assert!(file.size <= 100 + 29, "{:?}", file);
+        let lines = tail(&dir.join(&file.name), 100).unwrap();
+        assert!(lines.iter().all(|l| l.starts_with("line ")), "{:?}", lines);
+    }
+
+    let last = tail(&dir.join("proxy.log"), 2).unwrap();
+    assert_eq!(last.len(), 2);
+    assert!(last[1].starts_with("line 19"));
+    assert!(resolve(dir_str, "proxy", "").is_ok());
+    assert!(resolve(dir_str, "proxy", "../proxy.log").is_err());
+
+    // 压缩后的文件也能读取
+    let rotated = dir.join(&files[1].name);
+    gzip(&rotated).unwrap();
+    let lines = tail(&gz_path(&rotated), 1).unwrap();
+    assert!(lines[0].starts_with("line "));
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -1,139 +1,198 @@
-pub fn init(
-    app_name: &str, path: String, log_level: u32,
-) -> anyhow::Result<()> {
-    let lavel = match log_level {
+use std::{
+    future::Future,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use super::log_file::{RotateOptions, RotatingFile};
+
+pub const LOG_FORMAT_TEXT: &str = "text";
+pub const LOG_FORMAT_JSON: &str = "json";
+
+lazy_static! {
+    // json 日志中的中转名称
+    static ref PROXY_NAME: Mutex<String> = Mutex::new(String::new());
+}
+
+// 一条矿机链接的上下文。中转的每条日志都带上 用于区分交错的日志
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConnContext {
+    pub conn: u64,
+    pub peer: String,
+    pub wallet: String,
+    pub worker: String,
+    pub protocol: String,
+}
+
+tokio::task_local! {
+    static CONN: Arc<Mutex<ConnContext>>;
+}
+
+// 在链接上下文中运行。链接任务内的日志都会带上 conn 与 peer
+pub fn scope<F: Future>(
+    conn: u64, peer: String, f: F,
+) -> impl Future<Output = F::Output> {
+    CONN.scope(
+        Arc::new(Mutex::new(ConnContext {
+            conn,
+            peer,
+            ..Default::default()
+        })),
+        f,
+    )
+}
+
+// 登录 识别协议后补充上下文。不在链接任务内时忽略
+pub fn update(f: impl FnOnce(&mut ConnContext)) {
+    let _ = CONN.try_with(|ctx| {
+        if let Ok(mut ctx) = ctx.lock() {
+            f(&mut ctx);
+        }
+    });
+}
+
+pub fn current() -> Option<ConnContext> {
+    CONN.try_with(|ctx| ctx.lock().ok().map(|ctx| ctx.clone()))
+        .ok()
+        .flatten()
+}
+
+pub fn conn_id() -> Option<u64> { current().map(|ctx| ctx.conn) }
+
+fn level_filter(log_level: u32) -> log::LevelFilter {
+    match log_level {
         4 => log::LevelFilter::Off,
         3 => log::LevelFilter::Error,
         2 => log::LevelFilter::Warn,
         1 => log::LevelFilter::Info,
         0 => log::LevelFilter::Debug,
         _ => log::LevelFilter::Info,
+    }
+}
+
+// 按模块设置日志级别 如 mining_proxy::client=debug,actix_web=warn。
+// 不带模块名的一项为默认级别
+pub fn parse_levels(
+    levels: &str,
+) -> Result<(Option<log::LevelFilter>, Vec<(String, log::LevelFilter)>)> {
+    let mut default = None;
+    let mut modules = vec![];
+    for item in levels.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (module, level) = match item.split_once('=') {
+            Some((module, level)) => (Some(module.trim()), level.trim()),
+            None => (None, item),
+        };
+        let level = match level.parse::<log::LevelFilter>() {
+            Ok(level) => level,
+            Err(_) => bail!("日志级别格式错误 {}", item),
+        };
+        match module {
+            Some(module) if !module.is_empty() => {
+                modules.push((module.to_string(), level))
+            }
+            Some(_) => bail!("日志级别格式错误 {}", item),
+            None => default = Some(level),
+        }
+    }
+    Ok((default, modules))
+}
+
+fn json_line(record: &log::Record, message: &std::fmt::Arguments) -> String {
+    let mut line = serde_json::json!({
+        "time": chrono::Local::now().to_rfc3339(),
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "proxy": PROXY_NAME.lock().map(|n| n.clone()).unwrap_or_default(),
+        "message": message.to_string(),
+    });
+    if let Some(ctx) = current() {
+        if let (Some(line), Ok(serde_json::Value::Object(ctx))) =
+            (line.as_object_mut(), serde_json::to_value(ctx))
+        {
+            line.extend(ctx);
+        }
+    }
+    line.to_string()
+}
+
+pub fn init(
+    app_name: &str, path: String, log_level: u32,
+) -> anyhow::Result<()> {
+    let rotate = RotateOptions {
+        max_age: std::time::Duration::from_secs(3600),
+        ..Default::default()
     };
-    cfg_if::cfg_if! {
-        if #[cfg(debug_assertions)] {
-            if path != "" {
-                let log = fern::DateBased::new(path, format!("{}.log.%Y-%m-%d.%H", app_name))
-                    .utc_time()
-                    .local_time();
-                let (lavel, logger) = fern::Dispatch::new()
-                    .format(move |out, message, record| {
-                        out.finish(format_args!(
-                            "[{}] [{}] [{}:{}] {}",
-                            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                            record.level(),
-                            record.file().expect("获取文件名称失败"),
-                            record.line().expect("获取文件行号失败"),
-                            message
-                        ))
-                    })
-                    .level(lavel)
-                    .level_for("reqwest", log::LevelFilter::Off)
-                    .chain(std::io::stdout())
-                    .chain(log)
-                    .into_log();
-
-                // let logger = sentry_log::SentryLogger::with_dest(logger).filter(|md| match md.level() {
-                //     log::Level::Error => sentry_log::LogFilter::Event,
-                //     log::Level::Warn => sentry_log::LogFilter::Event,
-                //     _ => sentry_log::LogFilter::Ignore,
-                // });
-
-                log::set_boxed_logger(Box::new(logger)).unwrap();
-                log::set_max_level(lavel);
-            } else {
-                let (lavel, logger) = fern::Dispatch::new()
-                    .format(move |out, message, record| {
-                        out.finish(format_args!(
-                            "[{}] [{}] [{}:{}] {}",
-                            record.level(),
-                            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                            record.file().expect("获取文件名称失败"),
-                            record.line().expect("获取文件行号失败"),
-                            message
-                        ))
-                    })
-                    .level(lavel)
-                    .level_for("reqwest", log::LevelFilter::Off)
-                    .chain(std::io::stdout())
-                    .into_log();
-
-                // let logger = sentry_log::SentryLogger::with_dest(logger).filter(|md| match md.level() {
-                //     log::Level::Error => sentry_log::LogFilter::Event,
-                //     log::Level::Warn => sentry_log::LogFilter::Event,
-                //     _ => sentry_log::LogFilter::Ignore,
-                // });
-
-                log::set_boxed_logger(Box::new(logger)).unwrap();
-                log::set_max_level(lavel);
+    init_with(app_name, path, log_level, LOG_FORMAT_TEXT, "", rotate)
+}
+
+// format 为 json 时每行一条 json 记录 带链接上下文。
+// 日志文件按 rotate 切分 超出保留设置的旧文件会被删除
+pub fn init_with(
+    app_name: &str, path: String, log_level: u32, format: &str, levels: &str,
+    rotate: RotateOptions,
+) -> anyhow::Result<()> {
+    let (default, modules) = parse_levels(levels)?;
+    let lavel = default.unwrap_or_else(|| level_filter(log_level));
+    *PROXY_NAME.lock().unwrap() = app_name.to_string();
+
+    let json = format == LOG_FORMAT_JSON;
+    let mut dispatch = fern::Dispatch::new()
+        .format(move |out, message, record| {
+            if json {
+                return out
+                    .finish(format_args!("{}", json_line(record, message)));
             }
-        }  else {
-
-            if path != "" {
-                let log = fern::DateBased::new(path, format!("{}.log.%Y-%m-%d.%H", app_name))
-                    .utc_time()
-                    .local_time();
-                let (lavel, logger) = fern::Dispatch::new()
-                    .format(move |out, message, record| {
-                        out.finish(format_args!(
-                            "[{}] [{}] {}",
-                            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                            record.level(),
-                            message
-                        ))
-                    })
-                    .level(lavel)
-                    .level_for("reqwest", log::LevelFilter::Off)
-                    .chain(std::io::stdout())
-                    .chain(log)
-                    .into_log();
-
-                // let logger = sentry_log::SentryLogger::with_dest(logger).filter(|md| match md.level() {
-                //     log::Level::Error => sentry_log::LogFilter::Event,
-                //     log::Level::Warn => sentry_log::LogFilter::Event,
-                //     _ => sentry_log::LogFilter::Ignore,
-                // });
-
-                log::set_boxed_logger(Box::new(logger)).unwrap();
-                log::set_max_level(lavel);
-            } else {
-                let (lavel, logger) = fern::Dispatch::new()
-                    .format(move |out, message, record| {
-                        out.finish(format_args!(
-                            "[{}] [{}] {}",
-                            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                            record.level(),
-                            message
-                        ))
-                    })
-                    .level(lavel)
-                    .level_for("reqwest", log::LevelFilter::Off)
-                    .chain(std::io::stdout())
-                    .into_log();
-
-                // let logger = sentry_log::SentryLogger::with_dest(logger).filter(|md| match md.level() {
-                //     log::Level::Error => sentry_log::LogFilter::Event,
-                //     log::Level::Warn => sentry_log::LogFilter::Event,
-                //     _ => sentry_log::LogFilter::Ignore,
-                // });
-
-                log::set_boxed_logger(Box::new(logger)).unwrap();
-                log::set_max_level(lavel);
+            cfg_if::cfg_if! {
+                if #[cfg(debug_assertions)] {
+                    out.finish(format_args!(
+                        "[{}] [{}] [{}:{}] {}",
+                        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                        record.level(),
+                        record.file().expect("获取文件名称失败"),
+                        record.line().expect("获取文件行号失败"),
+                        message
+                    ))
+                } else {
+                    out.finish(format_args!(
+                        "[{}] [{}] {}",
+                        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                        record.level(),
+                        message
+                    ))
+                }
             }
-        }
+        })
+        .level(lavel)
+        .level_for("reqwest", log::LevelFilter::Off);
+    for (module, level) in modules {
+        dispatch = dispatch.level_for(module, level);
+    }
+    dispatch = dispatch.chain(std::io::stdout());
+
+    if path != "" {
+        let log = RotatingFile::open(&path, app_name, rotate)?;
+        dispatch = dispatch.chain(Box::new(log) as Box<dyn Write + Send>);
     }
 
+    let (lavel, logger) = dispatch.into_log();
+
+    // let logger = sentry_log::SentryLogger::with_dest(logger).filter(|md|
+    // match md.level() {     log::Level::Error =>
+    // sentry_log::LogFilter::Event,     log::Level::Warn =>
+    // sentry_log::LogFilter::Event,     _ => sentry_log::LogFilter::Ignore,
+    // });
+
+    log::set_boxed_logger(Box::new(logger)).unwrap();
+    log::set_max_level(lavel);
+
     Ok(())
 }
 
 pub fn init_client(log_level: u32) -> anyhow::Result<()> {
-    let lavel = match log_level {
-        4 => log::LevelFilter::Off,
-        3 => log::LevelFilter::Error,
-        2 => log::LevelFilter::Warn,
-        1 => log::LevelFilter::Info,
-        0 => log::LevelFilter::Debug,
-        _ => log::LevelFilter::Info,
-    };
+    let lavel = level_filter(log_level);
 
     let (lavel, logger) = fern::Dispatch::new()
         .format(move |out, message, record| {
@@ -162,3 +221,48 @@ pub fn init_client(log_level: u32) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_parse_levels() {
+    let (default, modules) =
+        parse_levels("warn, mining_proxy::client=debug,actix_web=off").unwrap();
+    assert_eq!(default, Some(log::LevelFilter::Warn));
+    assert_eq!(
+        modules,
+        vec![
+            ("mining_proxy::client".to_string(), log::LevelFilter::Debug),
+            ("actix_web".to_string(), log::LevelFilter::Off),
+        ]
+    );
+    assert_eq!(parse_levels("").unwrap(), (None, vec![]));
+    assert!(parse_levels("mining_proxy=loud").is_err());
+    assert!(parse_levels("=info").is_err());
+}
+
+#[tokio::test]
+async fn test_conn_context() {
+    assert!(current().is_none());
+    update(|ctx| ctx.wallet = "0xabc".into());
+
+    scope(7, "127.0.0.1:1234".into(), async {
+        let mut worker = crate::state::Worker::default();
+        worker.login("0xabc.rig1".into(), "rig1".into(), "0xabc".into());
+        worker.set_protocol(crate::protocol::PROTOCOL::STRATUM);
+        let record = log::Record::builder()
+            .level(log::Level::Info)
+            .target("mining_proxy::client")
+            .build();
+        let line: serde_json::Value =
+            serde_json::from_str(&json_line(&record, &format_args!("登录")))
+                .unwrap();
+        assert_eq!(line["conn"], 7);
+        assert_eq!(line["peer"], "127.0.0.1:1234");
+        assert_eq!(line["wallet"], "0xabc");
+        assert_eq!(line["worker"], "rig1");
+        assert_eq!(line["protocol"], "STRATUM");
+        assert_eq!(line["message"], "登录");
+        assert_eq!(line["level"], "INFO");
+    })
+    .await;
+    assert_eq!(conn_id(), None);
+}
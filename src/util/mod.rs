@@ -1,5 +1,9 @@
+pub mod audit;
+pub mod capture;
 pub mod config;
+pub mod log_file;
 pub mod logger;
+pub mod wallet;
 
 mod version {
     include!(concat!(env!("OUT_DIR"), "/version.rs"));
@@ -79,7 +83,139 @@ pub async fn get_encrypt_command_matches() -> Result<ArgMatches<'static>> {
         Arg::with_name("server")
             .short("s")
             .long("server")
-            .help("服务器监听端口")
+            .help("服务器地址 可填写多个 以逗号分隔或重复填写")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+    )
+    .arg(
+        Arg::with_name("strategy")
+            .long("strategy")
+            .help("多个服务器时的选择策略 failover 或 round-robin")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("tls")
+            .long("tls")
+            .help("与服务器之间使用 TLS 链接"),
+    )
+    .arg(
+        Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .value_name("FILE")
+            .help("指定配置文件路径 可配置多个监听端口 指定后忽略其他参数")
+            .takes_value(true),
+    )
+    .get_matches();
+    Ok(matches)
+}
+
+pub fn get_fake_pool_command_matches() -> Result<ArgMatches<'static>> {
+    let matches = App::new(format!(
+        "fake_pool, 版本: {} commit: {} {}",
+        crate_version!(),
+        version::commit_date(),
+        version::short_sha()
+    ))
+    .version(crate_version!())
+    .about("测试用的假矿池")
+    .arg(
+        Arg::with_name("bind")
+            .short("b")
+            .long("bind")
+            .help("监听地址 默认 127.0.0.1:8888")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("interval")
+            .short("i")
+            .long("interval")
+            .help("下发新任务的间隔 毫秒 0 为不主动下发")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("rule")
+            .short("r")
+            .long("rule")
+            .help("份额规则 accept reject reject-stale reject-every:N")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("record")
+            .long("record")
+            .value_name("FILE")
+            .help("收到的请求逐行写入该文件")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("protocol")
+            .short("p")
+            .long("protocol")
+            .help("固定矿池协议 bitcoin kawpow cfx 等 默认按第一条请求判断")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .value_name("FILE")
+            .help("指定配置文件路径 可配置任务列表 命令行参数优先")
+            .takes_value(true),
+    )
+    .get_matches();
+    Ok(matches)
+}
+
+pub fn get_replay_command_matches() -> Result<ArgMatches<'static>> {
+    let matches = App::new(format!(
+        "replay, 版本: {} commit: {} {}",
+        crate_version!(),
+        version::commit_date(),
+        version::short_sha()
+    ))
+    .version(crate_version!())
+    .about("回放抓包文件并对比应答")
+    .arg(
+        Arg::with_name("file")
+            .short("f")
+            .long("file")
+            .value_name("FILE")
+            .help("抓包文件 capture_{name}.jsonl")
+            .required(true)
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("server")
+            .short("s")
+            .long("server")
+            .help("回放目标 中转 TCP 端口或假矿池 默认 127.0.0.1:8888")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("conn")
+            .long("conn")
+            .help("只回放指定链接序号")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("speed")
+            .long("speed")
+            .help("按抓包时的间隔发送的倍速 默认 1 原速 0 不等待")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("wait")
+            .short("w")
+            .long("wait")
+            .help("发送完毕后等待应答的时间 毫秒 默认 1000")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("corpus")
+            .long("corpus")
+            .value_name("DIR")
+            .help("不回放 去掉钱包 矿工名与 IP 后导出为模糊测试种子 例如 fuzz/corpus")
             .takes_value(true),
     )
     .get_matches();
@@ -108,17 +244,11 @@ fn parse_hex_digit(c: char) -> Option<i64> {
     }
 }
 
+// 超出 i64 时返回 None。矿机上报的64位十六进制前面有很多0 不能按位数算幂
 pub fn hex_to_int(string: &str) -> Option<i64> {
-    let base: i64 = 16;
-
-    string
-        .chars()
-        .rev()
-        .enumerate()
-        .fold(Some(0), |acc, (pos, c)| {
-            parse_hex_digit(c)
-                .and_then(|n| acc.map(|acc| acc + n * base.pow(pos as u32)))
-        })
+    string.chars().try_fold(0i64, |acc, c| {
+        acc.checked_mul(16)?.checked_add(parse_hex_digit(c)?)
+    })
 }
 
 pub fn bytes_to_mb(hash: u64) -> u64 { hash / 1000 / 1000 }
@@ -130,6 +260,14 @@ pub fn calc_hash_rate(my_hash_rate: u64, share_rate: f32) -> u64 {
 // 根据抽水率计算启动多少个线程
 pub fn clac_phread_num(rate: f64) -> u64 { (rate * 1000.0) as u64 }
 
+#[test]
+fn test_hex_to_int() {
+    assert_eq!(hex_to_int("f4240"), Some(1_000_000));
+    assert_eq!(hex_to_int(&format!("{:0>64}", "f4240")), Some(1_000_000));
+    assert_eq!(hex_to_int("ffffffffffffffffff"), None);
+    assert_eq!(hex_to_int("0x1"), None);
+}
+
 #[test]
 fn test_clac_phread_num() {
     assert_eq!(clac_phread_num(0.005), 5);
@@ -226,6 +364,32 @@ pub fn is_fee_random(mut fee: f64) -> bool {
 //     }
 // }
 
+// 按难度加权的工作量决定下一个任务是否给抽水。选择更接近目标比例的一方
+pub fn is_fee_work(
+    fee_work: u64, total_work: u64, diff: u64, fee: f64,
+) -> bool {
+    let diff = diff.max(1) as f64;
+    let target = fee * (total_work as f64 + diff);
+    let with_fee = (fee_work as f64 + diff - target).abs();
+    let without_fee = (fee_work as f64 - target).abs();
+    with_fee < without_fee
+}
+
+// 按已完成的工作量修正下一次抽水时长。抽水不足则延长 超出则缩短
+pub fn fee_secs_by_work(
+    fee: f64, period: u64, fee_work: u64, total_work: u64, elapsed: u64,
+) -> u64 {
+    let base = period as f64 * fee;
+    if total_work == 0 || elapsed == 0 {
+        return base as u64;
+    }
+
+    let work_per_sec = total_work as f64 / elapsed as f64;
+    let deficit = fee * total_work as f64 - fee_work as f64;
+    let secs = (base + deficit / work_per_sec).max(0.0).min(base * 2.0);
+    (secs as u64).min(period.saturating_sub(1))
+}
+
 pub fn fee(idx: u64, config: &Settings, fee: f64) -> bool {
     if config.share_alg == 1 {
         return is_fee(idx, fee);
@@ -253,6 +417,29 @@ fn test_fee() {
     //     }
     // }
 }
+#[test]
+fn test_is_fee_work() {
+    // 主矿池难度 1 抽水矿池难度 4 按份额计数会多抽 按工作量应为 10%
+    let (mut fee_work, mut total_work) = (0u64, 0u64);
+    for i in 0..10000 {
+        let diff = if i % 2 == 0 { 1 } else { 4 };
+        if is_fee_work(fee_work, total_work, diff, 0.1) {
+            fee_work += diff;
+        }
+        total_work += diff;
+    }
+    let rate = fee_work as f64 / total_work as f64;
+    assert!((rate - 0.1).abs() < 0.001, "{}", rate);
+
+    assert_eq!(fee_secs_by_work(0.1, 3600, 0, 0, 0), 360);
+    // 已抽水工作量达标 不修正
+    assert_eq!(fee_secs_by_work(0.1, 3600, 100, 1000, 1000), 360);
+    // 抽水不足 延长
+    assert_eq!(fee_secs_by_work(0.1, 3600, 50, 1000, 1000), 410);
+    // 抽水超出 缩短到0
+    assert_eq!(fee_secs_by_work(0.1, 3600, 1000, 1000, 1000), 0);
+}
+
 #[test]
 fn test_is_fee_random() {
     let mut i = 0;
@@ -343,6 +530,29 @@ pub fn get_agent_fee(share_fee: f64) -> f64 {
     share_fee / 10.0
 }
 
+// 监听地址。IPv6 地址加上方括号 未设置时监听全部 IPv4 地址
+pub fn bind_address(host: &str, port: u32) -> String {
+    let host = host.trim().trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        format!("0.0.0.0:{}", port)
+    } else if host.parse::<std::net::Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+#[test]
+fn test_bind_address() {
+    assert_eq!(bind_address("", 4444), "0.0.0.0:4444");
+    assert_eq!(bind_address("192.168.1.2", 4444), "192.168.1.2:4444");
+    assert_eq!(bind_address("::", 4444), "[::]:4444");
+    assert_eq!(bind_address("[fe80::1]", 4444), "[fe80::1]:4444");
+    assert!(bind_address("::", 1)
+        .parse::<std::net::SocketAddr>()
+        .is_ok());
+}
+
 //TODO 整理代码 删除无用代码。 目前折中防止报错
 #[inline(always)]
 pub fn get_eth_wallet() -> String { return "".into(); }
@@ -365,14 +575,28 @@ pub fn run_server(config: &Settings) -> Result<tokio::process::Child> {
         .env("PROXY_NAME", config.name.clone())
         .env("PROXY_LOG_LEVEL", config.log_level.to_string())
         .env("PROXY_LOG_PATH", config.log_path.clone())
+        .env("PROXY_LOG_FORMAT", config.log_format.clone())
+        .env("PROXY_LOG_LEVELS", config.log_levels.clone())
+        .env("PROXY_LOG_MAX_SIZE", config.log_max_size.to_string())
+        .env("PROXY_LOG_MAX_AGE", config.log_max_age.to_string())
+        .env("PROXY_LOG_MAX_FILES", config.log_max_files.to_string())
+        .env("PROXY_LOG_MAX_TOTAL", config.log_max_total.to_string())
+        .env("PROXY_LOG_GZIP", config.log_gzip.to_string())
+        .env("PROXY_LOGIN_CHECK", config.login_check.to_string())
+        .env("PROXY_BIND", config.bind.clone())
+        .env("PROXY_PROXY_PROTOCOL", config.proxy_protocol.to_string())
+        .env("PROXY_POOL_PROXY", config.pool_proxy.clone())
+        .env("PROXY_SHARE_PROXY", config.share_proxy.clone())
         .env("PROXY_TCP_PORT", config.tcp_port.to_string())
         .env("PROXY_SSL_PORT", config.ssl_port.to_string())
         .env("PROXY_ENCRYPT_PORT", config.encrypt_port.to_string())
+        .env("PROXY_HTTP_PORT", config.http_port.to_string())
         .env("PROXY_POOL_ADDRESS", config.pool_address[0].clone())
         .env("PROXY_SHARE_ADDRESS", config.share_address[0].clone())
         .env("PROXY_SHARE_RATE", config.share_rate.to_string())
         .env("PROXY_SHARE_WALLET", config.share_wallet.to_string())
         .env("PROXY_SHARE_ALG", config.share_alg.to_string())
+        .env("PROXY_FEE_PERIOD", config.fee_period.to_string())
         .env("PROXY_COIN", config.coin.to_string())
         .env("PROXY_SHARE_NAME", config.share_name.to_string())
         .env("PROXY_SHARE", config.share.to_string())
@@ -383,7 +607,17 @@ pub fn run_server(config: &Settings) -> Result<tokio::process::Child> {
         )
         .env("PROXY_P12_PASS", "mypass".to_string())
         .env("PROXY_KEY", config.key.to_string())
-        .env("PROXY_IV", config.iv.to_string());
+        .env("PROXY_IV", config.iv.to_string())
+        .env(
+            "PROXY_SV2_AUTHORITY_KEY",
+            config.sv2_authority_key.to_string(),
+        )
+        .env(
+            "PROXY_FEE_SCHEDULE",
+            serde_json::to_string(&config.fee_schedule)?,
+        )
+        .env("PROXY_FEE_RULES", serde_json::to_string(&config.fee_rules)?)
+        .env("PROXY_LISTENERS", serde_json::to_string(&config.listeners)?);
 
     match handle.spawn() {
         Ok(t) => Ok(t),
@@ -0,0 +1,227 @@
+use anyhow::{bail, Result};
+use tiny_keccak::{Hasher, Keccak};
+
+// 矿池普遍只接受 32 位以内的 字母 数字 - _
+pub const MAX_WORKER_LEN: usize = 32;
+
+// 钱包.矿工名 按第一个点拆分。没有点时矿工名为空
+pub fn split_login(login: &str) -> (&str, &str) {
+    match login.split_once('.') {
+        Some((wallet, worker)) => (wallet, worker),
+        None => (login, ""),
+    }
+}
+
+// 按币种校验钱包地址。ETH ETC 为 0x 开头的 hex 地址 大小写混合时校验
+// EIP-55。CFX 为 base32 地址。其他币种不校验
+pub fn check_wallet(coin: &str, wallet: &str) -> Result<()> {
+    match coin {
+        "ETH" | "ETC" => check_eth_wallet(wallet),
+        "CFX" => check_cfx_wallet(wallet),
+        _ => Ok(()),
+    }
+}
+
+// 为空时使用矿池默认矿工名
+pub fn check_worker(worker: &str) -> Result<()> {
+    if worker.len() > MAX_WORKER_LEN {
+        bail!("矿工名超过{}位 {}", MAX_WORKER_LEN, worker);
+    }
+    if !worker
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        bail!("矿工名只能包含字母 数字 - _ . {}", worker);
+    }
+    Ok(())
+}
+
+fn check_eth_wallet(wallet: &str) -> Result<()> {
+    let hex = match wallet
+        .strip_prefix("0x")
+        .or_else(|| wallet.strip_prefix("0X"))
+    {
+        Some(hex) => hex,
+        None => bail!("钱包地址应以 0x 开头 {}", wallet),
+    };
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("钱包地址应为 0x 加 40 位 hex {}", wallet);
+    }
+
+    let has_lower = hex.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = hex.chars().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper && eip55(hex) != hex {
+        bail!("钱包地址校验和错误 可能有错字 {}", wallet);
+    }
+    Ok(())
+}
+
+// EIP-55 大小写校验和。hash 对应位 >= 8 的字母大写
+fn eip55(hex: &str) -> String {
+    let lower = hex.to_ascii_lowercase();
+    let hash = keccak256(lower.as_bytes());
+    lower
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble =
+                (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0xf;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+const CFX_CHARSET: &[u8] = b"abcdefghjkmnprstuvwxyz0123456789";
+
+// CIP-37 地址 如 cfx:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7p
+// 也接受 CFX:TYPE.USER:AAJG... 形式
+fn check_cfx_wallet(wallet: &str) -> Result<()> {
+    let lower = wallet.to_ascii_lowercase();
+    if wallet != lower && wallet != wallet.to_ascii_uppercase() {
+        bail!("钱包地址不能大小写混合 {}", wallet);
+    }
+
+    let parts: Vec<&str> = lower.split(':').collect();
+    if parts.len() < 2 {
+        bail!("钱包地址应以 cfx: 开头 {}", wallet);
+    }
+    let prefix = parts[0];
+    let payload = parts[parts.len() - 1];
+    let network_ok = prefix == "cfx"
+        || prefix == "cfxtest"
+        || (prefix.len() > 3
+            && prefix.starts_with("net")
+            && prefix[3..].chars().all(|c| c.is_ascii_digit()));
+    if !network_ok {
+        bail!("钱包地址网络前缀错误 {}", wallet);
+    }
+    if parts[1..parts.len() - 1]
+        .iter()
+        .any(|option| !option.starts_with("type."))
+    {
+        bail!("钱包地址格式错误 {}", wallet);
+    }
+
+    // 版本号与 20 字节地址 34 位 加 8 位校验和
+    if payload.len() != 42 {
+        bail!("钱包地址长度错误 {}", wallet);
+    }
+    let mut values: Vec<u8> = prefix.bytes().map(|c| c & 0x1f).collect();
+    values.push(0);
+    for c in payload.bytes() {
+        match CFX_CHARSET.iter().position(|x| *x == c) {
+            Some(v) => values.push(v as u8),
+            None => bail!("钱包地址包含非法字符 {}", wallet),
+        }
+    }
+    if cfx_polymod(&values) != 0 {
+        bail!("钱包地址校验和错误 可能有错字 {}", wallet);
+    }
+    // 第一个 5 位是版本号的高位 版本号必须为 0
+    if values[prefix.len() + 1] != 0 {
+        bail!("钱包地址版本错误 {}", wallet);
+    }
+    Ok(())
+}
+
+fn cfx_polymod(values: &[u8]) -> u64 {
+    const GENERATORS: [u64; 5] = [
+        0x98f2bc8e61,
+        0x79b76d99e2,
+        0xf33e5fb3c4,
+        0xae2eabe2a8,
+        0x1e4f43e470,
+    ];
+    let mut c: u64 = 1;
+    for v in values {
+        let c0 = c >> 35;
+        c = ((c & 0x07ffffffff) << 5) ^ *v as u64;
+        for (i, g) in GENERATORS.iter().enumerate() {
+            if (c0 >> i) & 1 == 1 {
+                c ^= g;
+            }
+        }
+    }
+    c ^ 1
+}
+
+// 以太坊使用的 keccak256 与 sha3-256 的填充不同
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    let mut out = [0u8; 32];
+    hasher.finalize(&mut out);
+    out
+}
+
+#[test]
+fn test_check_wallet() {
+    assert_eq!(
+        hex::encode(keccak256(b"")),
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+
+    // EIP-55 中的例子
+    for wallet in [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        "0x98be5c44d574b96b320dffb0ccff116bda433b8e",
+        "0X98BE5C44D574B96B320DFFB0CCFF116BDA433B8E",
+    ] {
+        assert!(check_wallet("ETH", wallet).is_ok(), "{}", wallet);
+    }
+    for wallet in [
+        // 校验和错一位
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD",
+        "0x98be5c44d574b96b320dffb0ccff116bda433b8",
+        "98be5c44d574b96b320dffb0ccff116bda433b8e",
+        "0x98be5c44d574b96b320dffb0ccff116bda433b8g",
+        "",
+    ] {
+        assert!(check_wallet("ETC", wallet).is_err(), "{}", wallet);
+    }
+
+    // CIP-37 中的例子
+    for wallet in [
+        "cfx:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7p",
+        "CFX:TYPE.USER:AAJG4WT2MBMBB44SP6SZD783RY0JTAD5BEA80XDY7P",
+        "cfxtest:aajg4wt2mbmbb44sp6szd783ry0jtad5bemzfdf83g",
+        "cfx:aak2rra2njvd77ezwjvx04kkds9fzagfe6ku8scz91",
+    ] {
+        assert!(check_wallet("CFX", wallet).is_ok(), "{}", wallet);
+    }
+    for wallet in [
+        "cfx:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7q",
+        "cfx:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7",
+        "cfx:Aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7p",
+        "eth:aajg4wt2mbmbb44sp6szd783ry0jtad5bea80xdy7p",
+        "0x106d49f8505410eb4e671d51f7d96d2c87807b09",
+        "cfx:aam",
+    ] {
+        assert!(check_wallet("CFX", wallet).is_err(), "{}", wallet);
+    }
+
+    assert!(check_wallet("BTC", "anything").is_ok());
+}
+
+#[test]
+fn test_check_worker() {
+    assert_eq!(split_login("0xabc.rig1"), ("0xabc", "rig1"));
+    assert_eq!(split_login("0xabc.rig.1"), ("0xabc", "rig.1"));
+    assert_eq!(split_login("0xabc"), ("0xabc", ""));
+
+    // 钱包之后的 . 都属于矿工名
+    for worker in ["", "rig1", "RIG_01-a", "rig.1", &"a".repeat(MAX_WORKER_LEN)]
+    {
+        assert!(check_worker(worker).is_ok(), "{}", worker);
+    }
+    for worker in ["rig 1", "矿机1", "rig/1", &"a".repeat(33)] {
+        assert!(check_worker(worker).is_err(), "{}", worker);
+    }
+}
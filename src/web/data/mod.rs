@@ -1,14 +1,26 @@
 use serde::{Deserialize, Serialize};
 
+use crate::util::config::{FeeRule, FeeSchedule, Listener};
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct CreateRequest {
     pub name: String,
     pub coin: String,
     pub share_alg: u32,
+    // 抽水周期 秒。0 为默认一小时
+    pub fee_period: u64,
     pub tcp_port: u32,
     pub ssl_port: u32,
     pub encrypt_port: u32,
+    pub http_port: u32,
+    // 端口监听地址 为空监听 0.0.0.0
+    pub bind: String,
+    // 端口前有负载均衡时开启 解析 PROXY 协议头
+    pub proxy_protocol: bool,
+    // 矿池与抽水矿池的出口代理 为空直接链接
+    pub pool_proxy: String,
+    pub share_proxy: String,
     pub share: u32,
     pub pool_address: String,
     pub share_address: String,
@@ -16,6 +28,26 @@ pub struct CreateRequest {
     pub share_wallet: String,
     pub key: String,
     pub iv: String,
+    // 抽水时间表。share_rate 与上面一样为百分比
+    pub fee_schedule: Vec<FeeSchedule>,
+    // 抽水规则。share_rate 为百分比
+    pub fee_rules: Vec<FeeRule>,
+    // 本地监听列表。share_rate 为百分比
+    pub listeners: Vec<Listener>,
+    // 日志格式 text 或 json。为空为 text
+    pub log_format: String,
+    // 按模块设置日志级别 如 mining_proxy::client=debug
+    pub log_levels: String,
+    // 日志目录。为空不写日志文件
+    pub log_path: String,
+    // 日志切分与保留 为空使用默认值。大小为 MB 时间为小时
+    pub log_max_size: Option<u64>,
+    pub log_max_age: Option<u64>,
+    pub log_max_files: Option<usize>,
+    pub log_max_total: Option<u64>,
+    pub log_gzip: bool,
+    // 登录时校验钱包与矿工名 为空时开启
+    pub login_check: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
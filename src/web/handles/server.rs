@@ -6,13 +6,19 @@ use std::{
 
 use clap::crate_version;
 
-use actix_web::{get, post, web, Responder};
+use actix_web::{get, post, web, HttpResponse, Responder};
 use human_bytes::human_bytes;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    state::Worker,
-    util::{config::Settings, time_to_string},
+    state::{LoginRejects, Worker},
+    util::{
+        audit::{read_records, report_csv, wallet_report, WalletFeeReport},
+        capture::CaptureSetting,
+        config::{FeeRule, FeeSchedule, Listener, Settings},
+        log_file::{list_files, resolve, tail, LogFileInfo},
+        time_to_string,
+    },
     web::{data::*, AppState, OnlineWorker},
 };
 
@@ -31,7 +37,12 @@ pub async fn crate_app(
         }));
     }
 
-    if req.tcp_port == 0 && req.ssl_port == 0 && req.encrypt_port == 0 {
+    if req.tcp_port == 0
+        && req.ssl_port == 0
+        && req.encrypt_port == 0
+        && req.http_port == 0
+        && req.listeners.is_empty()
+    {
         return Ok(web::Json(Response::<String> {
             code: 40000,
             message: "未开启端口。请至少开启一个端口".into(),
@@ -67,7 +78,7 @@ pub async fn crate_app(
             }));
         }
 
-        if req.share_rate <= 0.0 {
+        if req.share_rate <= 0.0 && req.fee_schedule.is_empty() {
             //println!("抽水比例必须填写");
             return Ok(web::Json(Response::<String> {
                 code: 40000,
@@ -80,21 +91,63 @@ pub async fn crate_app(
     config.share_name = req.name.clone();
     config.coin = req.coin.clone();
     config.log_level = 1;
-    config.log_path = "".into();
+    config.log_path = req.log_path.clone();
+    if !req.log_format.is_empty() {
+        config.log_format = req.log_format.clone();
+    }
+    config.log_levels = req.log_levels.clone();
+    config.log_max_size = req.log_max_size.unwrap_or(config.log_max_size);
+    config.log_max_age = req.log_max_age.unwrap_or(config.log_max_age);
+    config.log_max_files = req.log_max_files.unwrap_or(config.log_max_files);
+    config.log_max_total = req.log_max_total.unwrap_or(config.log_max_total);
+    config.log_gzip = req.log_gzip;
+    config.login_check = req.login_check.unwrap_or(config.login_check);
     config.name = req.name.clone();
     config.pool_address = vec![req.pool_address.clone()];
     config.share_address = vec![req.share_address.clone()];
     config.tcp_port = req.tcp_port;
     config.ssl_port = req.ssl_port;
     config.encrypt_port = req.encrypt_port;
+    config.http_port = req.http_port;
+    config.bind = req.bind.clone();
+    config.proxy_protocol = req.proxy_protocol;
+    config.pool_proxy = req.pool_proxy.clone();
+    config.share_proxy = req.share_proxy.clone();
     config.share = req.share;
     config.share_rate = req.share_rate as f32 / 100.0;
+    config.fee_schedule = req
+        .fee_schedule
+        .iter()
+        .map(|s| FeeSchedule {
+            share_rate: s.share_rate / 100.0,
+            ..s.clone()
+        })
+        .collect();
+    config.fee_rules = req
+        .fee_rules
+        .iter()
+        .map(|r| FeeRule {
+            share_rate: r.share_rate / 100.0,
+            ..r.clone()
+        })
+        .collect();
+    config.listeners = req
+        .listeners
+        .iter()
+        .map(|l| Listener {
+            share_rate: l.share_rate.map(|rate| rate / 100.0),
+            ..l.clone()
+        })
+        .collect();
     config.share_alg = req.share_alg;
+    if req.fee_period != 0 {
+        config.fee_period = req.fee_period;
+    }
     config.share_wallet = req.share_wallet.clone();
     config.key = req.key.clone();
     config.iv = req.iv.clone();
 
-    match config.check() {
+    match config.check().await {
         Ok(_) => {}
         Err(err) => {
             log::error!("配置错误 {}", err);
@@ -194,6 +247,8 @@ pub async fn crate_app(
                         config: config.clone(),
                         workers: vec![],
                         online: 0,
+                        control: None,
+                        login_rejects: LoginRejects::default(),
                     };
                     app.lock().unwrap().insert(config.name, online);
                 }
@@ -247,6 +302,8 @@ pub async fn crate_app(
                         config: config.clone(),
                         workers: vec![],
                         online: 0,
+                        control: None,
+                        login_rejects: LoginRejects::default(),
                     };
                     app.lock().unwrap().insert(config.name, online);
                 }
@@ -299,6 +356,12 @@ pub struct ResWorker {
     pub accept_index: u64,
     pub fee_accept_index: u64,
     pub invalid_index: u64,
+    // 实际抽水比例 百分比
+    pub share_rate: f64,
+    // 是否匹配了抽水规则
+    pub fee_rule: bool,
+    // 已完成的抽水比例 按难度加权的工作量计算 百分比
+    pub fee_work_rate: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -317,6 +380,10 @@ pub struct OnlineWorkerResult {
     pub fee_reject_index: u64,
     pub rate: f64,
     pub share_rate: f64,
+    // 当前生效的抽水时间段
+    pub fee_schedule: Option<FeeSchedule>,
+    // 钱包或矿工名不合法被拒绝的登录次数
+    pub login_rejects: LoginRejects,
 }
 
 // 展示选中的数据信息。以json格式返回
@@ -336,6 +403,8 @@ async fn server(
         let mut fee_accept_index: u64 = 0;
         let mut fee_share_index: u64 = 0;
         let mut fee_reject_index: u64 = 0;
+        let mut accept_work: u64 = 0;
+        let mut fee_accept_work: u64 = 0;
 
         for (name, server) in &*proxy_server {
             if *name == proxy_server_name.to_string() {
@@ -357,6 +426,17 @@ async fn server(
                             last_subwork_time: time_to_string(
                                 r.last_subwork_time.elapsed().as_secs(),
                             ),
+                            share_rate: if server.config.share == 1 {
+                                floor(
+                                    server.config.worker_share_rate(r) as f64
+                                        * 100.0,
+                                    2,
+                                )
+                            } else {
+                                0.0
+                            },
+                            fee_rule: r.fee_rate.is_some(),
+                            fee_work_rate: floor(r.fee_work_rate() * 100.0, 2),
                         });
 
                         share_index += r.share_index;
@@ -365,9 +445,12 @@ async fn server(
                         fee_accept_index += r.fee_share_index;
                         fee_share_index += r.fee_accept_index;
                         fee_reject_index += r.fee_invalid_index;
+                        accept_work += r.accept_work;
+                        fee_accept_work += r.fee_accept_work;
                     }
                 }
                 res.config = server.config.clone();
+                res.login_rejects = server.login_rejects.clone();
             }
         }
 
@@ -384,20 +467,20 @@ async fn server(
                 res.accept_index as f64 / res.share_index as f64 * 100.0,
                 2,
             );
-            res.share_rate = floor(
-                res.fee_accept_index as f64 / res.accept_index as f64 * 100.0,
-                2,
-            );
+            // 抽水比例按工作量计算 抽水矿池与主矿池难度不同时份额数不准确
+            res.share_rate = work_rate(fee_accept_work, accept_work);
         }
 
-        res.fee_hash =
-            human_bytes(total_hash as f64 * res.config.share_rate as f64);
+        res.fee_schedule = res.config.current_fee_schedule().cloned();
+        res.fee_hash = human_bytes(
+            total_hash as f64 * res.config.current_share_rate() as f64,
+        );
         res.total_hash = human_bytes(total_hash as f64);
     }
 
-    //1. 基本配置文件信息 .
-    //2. 抽水旷工信息     .
-    //3. 当前在线矿机总数 .
+    // 1. 基本配置文件信息 .
+    // 2. 抽水旷工信息     .
+    // 3. 当前在线矿机总数 .
 
     Ok(web::Json(Response::<OnlineWorkerResult> {
         code: 20000,
@@ -406,6 +489,232 @@ async fn server(
     }))
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct FeeReportQuery {
+    // unix 时间戳 秒。to 为 0 统计到当前
+    pub from: i64,
+    pub to: i64,
+    // csv 导出为文件 其余返回 json
+    pub format: String,
+}
+
+// 按钱包对比设置的抽水比例与审计日志中实际的抽水比例
+#[get("/user/server/{name}/fee_report")]
+async fn fee_report(
+    proxy_server_name: web::Path<String>, query: web::Query<FeeReportQuery>,
+    app: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let path = {
+        let proxy_server = app.lock().unwrap();
+        match proxy_server.get(&proxy_server_name.to_string()) {
+            Some(proxy) => proxy.config.fee_audit_path(),
+            None => {
+                return Ok(HttpResponse::Ok().json(Response::<String> {
+                    code: 40000,
+                    message: "中转不存在".into(),
+                    data: String::default(),
+                }));
+            }
+        }
+    };
+
+    // 审计日志可能很大 读文件放到阻塞线程池 不占用 web 工作线程
+    let (from, to) = (query.from, query.to);
+    let records = web::block(move || read_records(&path, from, to)).await?;
+    let report = match records {
+        Ok(records) => wallet_report(&records),
+        Err(e) => {
+            return Ok(HttpResponse::Ok().json(Response::<String> {
+                code: 40000,
+                message: format!("读取抽水审计日志失败 {}", e),
+                data: String::default(),
+            }));
+        }
+    };
+
+    if query.format == "csv" {
+        return Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"fee_report_{}.csv\"",
+                    proxy_server_name
+                ),
+            ))
+            .body(report_csv(&report)));
+    }
+
+    Ok(HttpResponse::Ok().json(Response::<Vec<WalletFeeReport>> {
+        code: 20000,
+        message: "".into(),
+        data: report,
+    }))
+}
+
+// 开关中转的矿机报文抓包。写入日志目录下的 capture_{name}.jsonl
+#[post("/user/server/{name}/capture")]
+#[has_permissions("ROLE_ADMIN")]
+async fn capture(
+    proxy_server_name: web::Path<String>, req: web::Json<CaptureSetting>,
+    app: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let proxy_server = app.lock().unwrap();
+    let (message, path) = match proxy_server.get(&proxy_server_name.to_string())
+    {
+        Some(proxy) => match &proxy.control {
+            Some(control) => {
+                let line = serde_json::to_string(&req.into_inner())?;
+                if control.send(line).is_ok() {
+                    ("", proxy.config.capture_path())
+                } else {
+                    ("中转未运行", String::default())
+                }
+            }
+            None => ("中转尚未连接到主控端", String::default()),
+        },
+        None => ("中转不存在", String::default()),
+    };
+
+    if !message.is_empty() {
+        return Ok(HttpResponse::Ok().json(Response::<String> {
+            code: 40000,
+            message: message.into(),
+            data: String::default(),
+        }));
+    }
+
+    Ok(HttpResponse::Ok().json(Response::<String> {
+        code: 20000,
+        message: "".into(),
+        data: path,
+    }))
+}
+
+// 中转的日志目录。未配置日志目录时返回错误信息
+fn log_dir(app: &AppState, name: &str) -> Result<String, &'static str> {
+    let proxy_server = app.lock().unwrap();
+    match proxy_server.get(name) {
+        Some(proxy) if proxy.config.log_path.is_empty() => {
+            Err("未配置日志目录")
+        }
+        Some(proxy) => Ok(proxy.config.log_path.clone()),
+        None => Err("中转不存在"),
+    }
+}
+
+// 中转的日志文件列表 当前文件在前
+#[get("/user/server/{name}/logs")]
+#[has_permissions("ROLE_ADMIN")]
+async fn server_logs(
+    proxy_server_name: web::Path<String>, app: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let name = proxy_server_name.to_string();
+    let files = log_dir(&app, &name)
+        .map_err(|e| e.to_string())
+        .and_then(|dir| list_files(&dir, &name).map_err(|e| e.to_string()));
+
+    match files {
+        Ok(files) => {
+            Ok(HttpResponse::Ok().json(Response::<Vec<LogFileInfo>> {
+                code: 20000,
+                message: "".into(),
+                data: files,
+            }))
+        }
+        Err(e) => Ok(HttpResponse::Ok().json(Response::<String> {
+            code: 40000,
+            message: e,
+            data: String::default(),
+        })),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct LogQuery {
+    // 日志列表中的文件名。为空为当前文件
+    pub file: String,
+    // 返回最后多少行
+    pub lines: usize,
+    // download 下载整个文件 其余返回 json
+    pub format: String,
+}
+
+impl Default for LogQuery {
+    fn default() -> Self {
+        Self {
+            file: "".into(),
+            lines: 200,
+            format: "".into(),
+        }
+    }
+}
+
+// 查看中转日志最后若干行 或下载日志文件
+#[get("/user/server/{name}/log")]
+#[has_permissions("ROLE_ADMIN")]
+async fn server_log(
+    proxy_server_name: web::Path<String>, query: web::Query<LogQuery>,
+    app: web::Data<AppState>,
+) -> actix_web::Result<HttpResponse> {
+    let name = proxy_server_name.to_string();
+    let path =
+        log_dir(&app, &name)
+            .map_err(|e| e.to_string())
+            .and_then(|dir| {
+                resolve(&dir, &name, &query.file).map_err(|e| e.to_string())
+            });
+    let path = match path {
+        Ok(path) => path,
+        Err(e) => {
+            return Ok(HttpResponse::Ok().json(Response::<String> {
+                code: 40000,
+                message: e,
+                data: String::default(),
+            }));
+        }
+    };
+
+    if query.format == "download" {
+        let file_name = path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        return Ok(HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", file_name),
+            ))
+            .body(web::block(move || std::fs::read(path)).await??));
+    }
+
+    // 读取 .gz 日志要先解压 放到阻塞线程池
+    let lines = query.lines;
+    match web::block(move || tail(&path, lines)).await? {
+        Ok(lines) => Ok(HttpResponse::Ok().json(Response::<Vec<String>> {
+            code: 20000,
+            message: "".into(),
+            data: lines,
+        })),
+        Err(e) => Ok(HttpResponse::Ok().json(Response::<String> {
+            code: 40000,
+            message: format!("读取日志失败 {}", e),
+            data: String::default(),
+        })),
+    }
+}
+
+// 抽水工作量占比 百分比
+fn work_rate(fee_work: u64, total_work: u64) -> f64 {
+    if total_work == 0 {
+        return 0.0;
+    }
+    floor(fee_work as f64 / total_work as f64 * 100.0, 2)
+}
+
 pub fn floor(value: f64, scale: i8) -> f64 {
     let multiplier = 10f64.powi(scale as i32) as f64;
     (value * multiplier).floor() / multiplier
@@ -428,6 +737,8 @@ pub struct DashboardResult {
     pub version: String,
     pub develop_worker_name: String,
     pub online_time: String,
+    // 全部中转被拒绝的登录次数
+    pub login_rejects: LoginRejects,
 }
 
 // 展示选中的数据信息。以json格式返回
@@ -447,6 +758,8 @@ async fn dashboard(
         let mut fee_accept_index: u64 = 0;
         let mut fee_share_index: u64 = 0;
         let mut fee_reject_index: u64 = 0;
+        let mut accept_work: u64 = 0;
+        let mut fee_accept_work: u64 = 0;
 
         for (_, other_server) in &*proxy_server {
             for r in &other_server.workers {
@@ -459,11 +772,15 @@ async fn dashboard(
                     fee_accept_index += r.fee_share_index;
                     fee_share_index += r.fee_accept_index;
                     fee_reject_index += r.fee_invalid_index;
+                    accept_work += r.accept_work;
+                    fee_accept_work += r.fee_accept_work;
                 }
             }
 
-            fee_hash +=
-                total_hash as f64 * other_server.config.share_rate as f64;
+            fee_hash += total_hash as f64
+                * other_server.config.current_share_rate() as f64;
+            res.login_rejects.wallet += other_server.login_rejects.wallet;
+            res.login_rejects.worker += other_server.login_rejects.worker;
         }
 
         res.share_index += share_index;
@@ -475,6 +792,7 @@ async fn dashboard(
 
         res.proxy_num = proxy_server.len() as i32;
         res.online = online;
+        res.share_rate = work_rate(fee_accept_work, accept_work);
     }
 
     res.fee_hash = human_bytes(fee_hash as f64);
@@ -482,13 +800,8 @@ async fn dashboard(
     if res.accept_index > 0 {
         res.rate =
             floor(res.accept_index as f64 / res.share_index as f64 * 100.0, 2);
-        res.share_rate = floor(
-            res.fee_accept_index as f64 / res.accept_index as f64 * 100.0,
-            2,
-        );
     } else {
         res.rate = 0.0;
-        res.share_rate = 0.0;
     }
 
     res.online_time = time_to_string(crate::RUNTIME.elapsed().as_secs());
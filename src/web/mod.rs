@@ -1,4 +1,7 @@
-use crate::{state::Worker, util::config::Settings};
+use crate::{
+    state::{LoginRejects, Worker},
+    util::config::Settings,
+};
 
 pub mod data;
 pub mod handles;
@@ -17,4 +20,8 @@ pub struct OnlineWorker {
     pub workers: Vec<Worker>,
     pub online: u32,
     pub config: Settings,
+    // 下发设置给子进程。子进程连上主控端后才有
+    pub control: Option<tokio::sync::mpsc::UnboundedSender<String>>,
+    // 钱包或矿工名不合法被拒绝的登录次数
+    pub login_rejects: LoginRejects,
 }
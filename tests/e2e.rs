@@ -0,0 +1,618 @@
+// 端到端测试。进程内启动中转端口 假矿池与抽水矿池 用模拟矿机跑完整链路
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use mining_proxy::{
+    client::{
+        encry::accept_en_tcp,
+        encryption::{accept_encrypt_tcp, EncryptListener},
+        tcp::accept_tcp,
+        tls::accept_tcp_with_tls,
+    },
+    fake_pool::{FakePool, FakePoolConfig, ShareRule},
+    replay::{group, replay, ReplayConfig},
+    simulator::{self, MinerProtocol, SimConfig, SimStats},
+    state::{GlobalState, State, Worker},
+    util::{
+        capture::{read_records, Direction},
+        config::{
+            Listener, Settings, LISTENER_ENCRYPT, LISTENER_SSL, LISTENER_TCP,
+        },
+    },
+};
+use native_tls::Identity;
+use openssl::{
+    asn1::Asn1Time,
+    hash::MessageDigest,
+    pkcs12::Pkcs12,
+    pkey::PKey,
+    rsa::Rsa,
+    x509::{X509Builder, X509NameBuilder},
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::TcpStream,
+    sync::mpsc::{self, UnboundedReceiver},
+    time::{timeout, Instant},
+};
+
+const FEE_WALLET: &str = "0x00000000000000000000000000000000000fee01";
+const FEE_NAME: &str = "feeworker";
+
+struct Harness {
+    pool: FakePool,
+    fee_pool: FakePool,
+    addr: String,
+    workers: UnboundedReceiver<Worker>,
+    state: State,
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+async fn wait_port(addr: &str) {
+    for _ in 0..100 {
+        if TcpStream::connect(addr).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("中转端口 {} 未启动", addr);
+}
+
+// 自签名证书。内置的 identity.p12 使用旧算法 新版 openssl 无法加载
+fn test_identity() -> Identity {
+    let rsa = Rsa::generate(2048).unwrap();
+    let pkey = PKey::from_rsa(rsa).unwrap();
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+
+    let mut cert = X509Builder::new().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&pkey).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+    let cert = cert.build();
+
+    let p12 = Pkcs12::builder().build("e2e", "e2e", &pkey, &cert).unwrap();
+    Identity::from_pkcs12(&p12.to_der().unwrap(), "e2e").unwrap()
+}
+
+fn pool_config(share_rule: ShareRule) -> FakePoolConfig {
+    FakePoolConfig {
+        job_interval_ms: 0,
+        share_rule,
+        ..Default::default()
+    }
+}
+
+// 启动一个监听。config 中的 share share_rate 等由调用方设置
+async fn start(transport: &str, share: u32, share_rate: f32) -> Harness {
+    start_with(transport, share, share_rate, ShareRule::Accept, |_| {}).await
+}
+
+async fn start_with(
+    transport: &str, share: u32, share_rate: f32, share_rule: ShareRule,
+    f: impl FnOnce(&mut Settings),
+) -> Harness {
+    let pool = FakePool::start("127.0.0.1:0", pool_config(share_rule.clone()))
+        .await
+        .unwrap();
+    let fee_pool = FakePool::start("127.0.0.1:0", pool_config(share_rule))
+        .await
+        .unwrap();
+
+    let addr = format!("127.0.0.1:{}", free_port());
+    let mut config = Settings {
+        name: "e2e".into(),
+        pool_address: vec![format!("tcp://{}", pool.addr())],
+        share_address: vec![format!("tcp://{}", fee_pool.addr())],
+        share_wallet: FEE_WALLET.into(),
+        share_name: FEE_NAME.into(),
+        share,
+        share_rate,
+        key: "523B607044E6BF7E46AF75233FDC1278B7AA0FC42D085DEA64AE484AD7FB3664"
+            .into(),
+        iv: "275E2015B9E5CA4DDB87B90EBC897F8C".into(),
+        listeners: vec![Listener {
+            bind: addr.clone(),
+            transport: transport.into(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    f(&mut config);
+
+    let (tx, workers) = mpsc::unbounded_channel();
+    let state: State = Arc::new(GlobalState::new());
+    match transport {
+        LISTENER_TCP => {
+            tokio::spawn(accept_tcp(tx, config, state.clone()));
+        }
+        LISTENER_ENCRYPT => {
+            tokio::spawn(accept_en_tcp(tx, config, state.clone()));
+        }
+        LISTENER_SSL => {
+            tokio::spawn(accept_tcp_with_tls(
+                tx,
+                config,
+                test_identity(),
+                state.clone(),
+            ));
+        }
+        _ => unreachable!(),
+    }
+    wait_port(&addr).await;
+
+    Harness {
+        pool,
+        fee_pool,
+        addr,
+        workers,
+        state,
+    }
+}
+
+impl Harness {
+    // 等待 n 个矿工下线 返回下线时的状态
+    async fn offline_workers(&mut self, n: usize) -> Vec<Worker> {
+        let mut offline = vec![];
+        while offline.len() < n {
+            match timeout(Duration::from_secs(5), self.workers.recv()).await {
+                Ok(Some(w)) if !w.online => offline.push(w),
+                Ok(Some(_)) => {}
+                _ => panic!("只收到 {} 个矿工下线", offline.len()),
+            }
+        }
+        offline
+    }
+}
+
+fn sim(server: &str, protocols: &str, miners: u32) -> SimConfig {
+    SimConfig {
+        server: server.into(),
+        miners,
+        protocols: MinerProtocol::parse_list(protocols).unwrap(),
+        // 平均 20ms 一个份额
+        hashrate: 50.0,
+        difficulty: 1.0,
+        seed: Some(45),
+        ..Default::default()
+    }
+}
+
+async fn run_sim(config: SimConfig, secs: f64) -> Arc<SimStats> {
+    let stats = Arc::new(SimStats::default());
+    let deadline = Instant::now() + Duration::from_secs_f64(secs);
+    simulator::run(config, stats.clone(), deadline).await;
+    stats
+}
+
+// 钱包 -> 份额数
+fn shares_by_wallet(pool: &FakePool) -> HashMap<String, usize> {
+    let mut map = HashMap::new();
+    for share in pool.shares() {
+        *map.entry(share.wallet.to_lowercase()).or_insert(0) += 1;
+    }
+    map
+}
+
+#[tokio::test]
+async fn test_pure_proxy_all_protocols() {
+    for protocols in ["eth", "ethstratum", "stratum"] {
+        let mut h = start(LISTENER_TCP, 0, 0.0).await;
+        let config = SimConfig {
+            wallets: vec![
+                "0x1111111111111111111111111111111111111111".into(),
+                "0x2222222222222222222222222222222222222222".into(),
+            ],
+            ..sim(&h.addr, protocols, 2)
+        };
+        let stats = run_sim(config, 1.0).await;
+
+        let accepted = stats.accepted.load(Ordering::Relaxed);
+        assert!(accepted > 0, "{} {}", protocols, stats);
+        assert_eq!(stats.rejected.load(Ordering::Relaxed), 0);
+        assert!(h.fee_pool.shares().is_empty(), "{}", protocols);
+
+        // 纯代理不改钱包
+        let wallets = shares_by_wallet(&h.pool);
+        assert_eq!(wallets.len(), 2, "{} {:?}", protocols, wallets);
+        assert!(
+            wallets.contains_key("0x1111111111111111111111111111111111111111")
+        );
+
+        let workers = h.offline_workers(2).await;
+        let counted: u64 = workers.iter().map(|w| w.accept_index).sum();
+        assert_eq!(counted, accepted, "{}", protocols);
+        assert_eq!(h.state.online.load(Ordering::SeqCst), 0);
+    }
+}
+
+#[tokio::test]
+async fn test_worker_reject_counters() {
+    let mut h =
+        start_with(LISTENER_TCP, 0, 0.0, ShareRule::RejectEvery(3), |_| {})
+            .await;
+    let stats = run_sim(sim(&h.addr, "mixed", 3), 1.5).await;
+
+    let accepted = stats.accepted.load(Ordering::Relaxed);
+    let rejected = stats.rejected.load(Ordering::Relaxed);
+    assert!(accepted > 0 && rejected > 0, "{}", stats);
+
+    // ETH 代理协议的提交由中转直接应答 矿机看不到拒绝 以矿池的判定为准
+    let shares = h.pool.shares();
+    let pool_accepted = shares.iter().filter(|s| s.accepted).count() as u64;
+    let pool_rejected = shares.len() as u64 - pool_accepted;
+
+    let workers = h.offline_workers(3).await;
+    let worker_accepted: u64 = workers.iter().map(|w| w.accept_index).sum();
+    let worker_rejected: u64 = workers.iter().map(|w| w.invalid_index).sum();
+    assert_eq!(worker_accepted, pool_accepted, "{:?}", workers);
+    assert_eq!(worker_rejected, pool_rejected, "{:?}", workers);
+}
+
+#[tokio::test]
+async fn test_share_all_rewrites_wallet() {
+    for protocols in ["eth", "ethstratum", "stratum"] {
+        let mut h = start(LISTENER_TCP, 2, 0.0).await;
+        let stats = run_sim(sim(&h.addr, protocols, 2), 1.0).await;
+        assert!(stats.accepted.load(Ordering::Relaxed) > 0, "{}", stats);
+
+        // 统一钱包 全部份额提交到抽水矿池的收款钱包
+        let wallets = shares_by_wallet(&h.fee_pool);
+        assert_eq!(
+            wallets.keys().collect::<Vec<_>>(),
+            vec![&FEE_WALLET.to_string()],
+            "{}",
+            protocols
+        );
+        assert!(h.pool.shares().is_empty(), "{}", protocols);
+        for (wallet, _) in h.fee_pool.logins() {
+            assert_eq!(wallet.to_lowercase(), FEE_WALLET, "{}", protocols);
+        }
+        h.offline_workers(2).await;
+    }
+}
+
+// 矿池 抽水矿池各自收到的份额数
+fn share_counts(h: &Harness) -> (usize, usize) {
+    (h.pool.shares().len(), h.fee_pool.shares().len())
+}
+
+// 周期 2 秒 抽水一半 每个周期抽水 1 秒 且从周期开始时抽水。
+// 登录前到期的抽水顺延 5 秒 之后每 2 秒交替一次
+async fn timer_fee_proportion(protocol: &str) {
+    let mut h = start_with(LISTENER_TCP, 1, 0.5, ShareRule::Accept, |c| {
+        c.fee_period = 2;
+    })
+    .await;
+    let sim = tokio::spawn(run_sim(sim(&h.addr, protocol, 1), 10.0));
+
+    // 从第一次抽水开始 统计完整的两个周期
+    let wait = Instant::now() + Duration::from_secs(7);
+    while h.fee_pool.shares().is_empty() {
+        assert!(Instant::now() < wait, "没有开始抽水");
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let (pool_start, fee_start) = share_counts(&h);
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    let (pool_end, fee_end) = share_counts(&h);
+
+    let fee = (fee_end - fee_start) as f64;
+    let total = fee + (pool_end - pool_start) as f64;
+    assert!(total > 100.0, "份额数 {}", total);
+    let ratio = fee / total;
+    assert!((ratio - 0.5).abs() < 0.1, "抽水比例 {}", ratio);
+
+    let stats = sim.await.unwrap();
+    assert!(stats.accepted.load(Ordering::Relaxed) > 0, "{}", stats);
+    for (wallet, _) in h.fee_pool.logins() {
+        assert_eq!(wallet.to_lowercase(), FEE_WALLET);
+    }
+    for share in h.pool.shares() {
+        assert_ne!(share.wallet.to_lowercase(), FEE_WALLET);
+    }
+
+    let workers = h.offline_workers(1).await;
+    assert!(workers[0].fee_accept_index > 0, "{:?}", workers);
+}
+
+#[tokio::test]
+async fn test_timer_fee_proportion() { timer_fee_proportion("eth").await; }
+
+#[tokio::test]
+async fn test_timer_fee_proportion_stratum() {
+    timer_fee_proportion("stratum").await;
+}
+
+#[tokio::test]
+async fn test_random_fee_proportion() {
+    // share_alg 99 同时链接两个矿池 按工作量把抽水矿池的任务分给矿机
+    let mut h = start_with(LISTENER_TCP, 1, 0.2, ShareRule::Accept, |c| {
+        c.share_alg = 99;
+    })
+    .await;
+    // 两个矿池的任务编号错开 避免任务ID相同
+    for _ in 0..1000 {
+        h.fee_pool.next_job();
+    }
+
+    let (pool, fee_pool) = (h.pool.clone(), h.fee_pool.clone());
+    let jobs = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            pool.next_job();
+            fee_pool.next_job();
+        }
+    });
+    let stats = run_sim(sim(&h.addr, "eth", 1), 4.0).await;
+    jobs.abort();
+
+    let (pool_shares, fee_shares) = share_counts(&h);
+    let total = (pool_shares + fee_shares) as f64;
+    assert!(total > 100.0, "{}", stats);
+    let ratio = fee_shares as f64 / total;
+    assert!((ratio - 0.2).abs() < 0.08, "抽水比例 {}", ratio);
+
+    for share in h.fee_pool.shares() {
+        assert_eq!(share.wallet.to_lowercase(), FEE_WALLET);
+    }
+    for share in h.pool.shares() {
+        assert_ne!(share.wallet.to_lowercase(), FEE_WALLET);
+    }
+
+    // 矿机断开时还有未应答的份额 中转统计的按工作量计算的比例与矿池一致
+    let workers = h.offline_workers(1).await;
+    let w = &workers[0];
+    assert!(w.fee_accept_index > 0, "{:?}", w);
+    assert!((w.fee_work_rate() - 0.2).abs() < 0.08, "{:?}", w);
+    assert!(w.accept_index <= w.share_index, "{:?}", w);
+    assert!(w.fee_accept_index <= w.fee_share_index, "{:?}", w);
+}
+
+#[tokio::test]
+async fn test_encrypt_listener() {
+    let mut h = start(LISTENER_ENCRYPT, 0, 0.0).await;
+
+    let port = free_port();
+    let proxy = EncryptListener {
+        name: "e2e".into(),
+        port: port as i32,
+        servers: vec![h.addr.clone()],
+        strategy: "failover".into(),
+        key: "523B607044E6BF7E46AF75233FDC1278B7AA0FC42D085DEA64AE484AD7FB3664"
+            .into(),
+        iv: "275E2015B9E5CA4DDB87B90EBC897F8C".into(),
+        tls: false,
+    }
+    .build()
+    .unwrap();
+    tokio::spawn(accept_encrypt_tcp(Arc::new(proxy)));
+    let local = format!("127.0.0.1:{}", port);
+    wait_port(&local).await;
+
+    let stats = run_sim(sim(&local, "mixed", 3), 1.5).await;
+    let accepted = stats.accepted.load(Ordering::Relaxed);
+    assert!(accepted > 0, "{}", stats);
+    assert_eq!(h.pool.logins().len(), 3);
+
+    let workers = h.offline_workers(3).await;
+    let counted: u64 = workers.iter().map(|w| w.accept_index).sum();
+    assert_eq!(counted, accepted);
+}
+
+#[tokio::test]
+async fn test_tls_listener() {
+    let mut h = start(LISTENER_SSL, 0, 0.0).await;
+
+    let connector = tokio_native_tls::TlsConnector::from(
+        native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .unwrap(),
+    );
+    let stream = TcpStream::connect(&h.addr).await.unwrap();
+    let stream = connector.connect("localhost", stream).await.unwrap();
+    let (r, mut w) = tokio::io::split(stream);
+    let mut lines = BufReader::new(r).lines();
+
+    let wallet = "0x3333333333333333333333333333333333333333";
+    let login = json!({"id": 1, "method": "eth_submitLogin", "params": [wallet, "x"], "worker": "tls"});
+    let get_work = json!({"id": 5, "method": "eth_getWork", "params": []});
+    for rpc in [&login, &get_work] {
+        w.write_all(format!("{}\n", rpc).as_bytes()).await.unwrap();
+    }
+
+    // 等待指定 id 的回复
+    async fn reply<R: AsyncBufRead + Unpin>(
+        lines: &mut Lines<R>, id: u64,
+    ) -> Value {
+        loop {
+            let line = timeout(Duration::from_secs(5), lines.next_line())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let rpc: Value = serde_json::from_str(&line).unwrap();
+            if rpc["id"] == id && !rpc["result"].is_null() {
+                return rpc;
+            }
+        }
+    }
+
+    let job = reply(&mut lines, 5).await;
+    let header = job["result"][0].as_str().unwrap();
+    let submit = json!({"id": 4, "method": "eth_submitWork", "params": ["0x0000000000000001", header, "0x0000000000000000000000000000000000000000000000000000000000000000"], "worker": "tls"});
+    // 随后的 getWork 由矿池按顺序应答 收到时份额结果已回到中转
+    for rpc in [&submit, &get_work] {
+        w.write_all(format!("{}\n", rpc).as_bytes()).await.unwrap();
+    }
+    assert_eq!(reply(&mut lines, 4).await["result"], true);
+    reply(&mut lines, 5).await;
+    drop(w);
+    drop(lines);
+
+    assert_eq!(h.pool.shares().len(), 1);
+    assert_eq!(h.pool.shares()[0].wallet.to_lowercase(), wallet);
+    let workers = h.offline_workers(1).await;
+    assert_eq!(workers[0].accept_index, 1);
+}
+
+#[tokio::test]
+async fn test_miner_reconnect() {
+    let mut h = start(LISTENER_TCP, 0, 0.0).await;
+    let config = SimConfig {
+        wallets: vec!["0x4444444444444444444444444444444444444444".into()],
+        ..sim(&h.addr, "eth", 1)
+    };
+
+    // 同一矿机断开后重新链接 中转为每次链接各开一条矿池链接并重新登录
+    for round in 1..=2 {
+        let stats = run_sim(config.clone(), 0.5).await;
+        assert!(stats.accepted.load(Ordering::Relaxed) > 0);
+        let workers = h.offline_workers(1).await;
+        assert!(!workers[0].online);
+        assert_eq!(
+            workers[0].worker_wallet.to_lowercase(),
+            "0x4444444444444444444444444444444444444444"
+        );
+
+        // 每次链接都重新登录
+        let mut conns: Vec<u64> = h
+            .pool
+            .received()
+            .iter()
+            .filter(|r| r.method == "eth_submitLogin")
+            .map(|r| r.conn)
+            .collect();
+        conns.dedup();
+        assert_eq!(conns.len(), round);
+    }
+    assert_eq!(h.state.online.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_capture_replay() {
+    let mut h = start(LISTENER_TCP, 0, 0.0).await;
+    let path = std::env::temp_dir()
+        .join(format!("capture_e2e_{}.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+
+    // 运行中开启抓包
+    h.state.capture.start(path, "").unwrap();
+    let config = SimConfig {
+        wallets: vec!["0x5555555555555555555555555555555555555555".into()],
+        ..sim(&h.addr, "stratum", 2)
+    };
+    let stats = run_sim(config, 1.0).await;
+    assert!(stats.accepted.load(Ordering::Relaxed) > 0, "{}", stats);
+    h.offline_workers(2).await;
+    h.state.capture.stop();
+
+    let records = read_records(path).unwrap();
+    let _ = std::fs::remove_file(path);
+    assert_eq!(group(records.clone()).len(), 2);
+    assert!(records.iter().any(|r| r.dir == Direction::Up
+        && r.line
+            .contains("0x5555555555555555555555555555555555555555")));
+    assert!(records.iter().any(|r| r.dir == Direction::Down));
+
+    // 回放到同样配置的中转 应答一致
+    let replayed = start(LISTENER_TCP, 0, 0.0).await;
+    let config = ReplayConfig {
+        server: replayed.addr.clone(),
+        wait_ms: 300,
+        ..Default::default()
+    };
+    let diffs = replay(records.clone(), &config).await.unwrap();
+    assert!(diffs.is_empty(), "{:?}", diffs);
+    assert_eq!(replayed.pool.shares().len(), h.pool.shares().len());
+
+    // 矿池拒绝全部份额 不一致的只有提交
+    let rejected =
+        start_with(LISTENER_TCP, 0, 0.0, ShareRule::Reject, |_| {}).await;
+    let config = ReplayConfig {
+        server: rejected.addr.clone(),
+        ..config
+    };
+    let diffs = replay(records, &config).await.unwrap();
+    assert!(!diffs.is_empty());
+    assert!(
+        diffs.iter().all(|d| d.method.contains("submit")),
+        "{:?}",
+        diffs
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_login_rejected() {
+    let h = start_with(LISTENER_TCP, 0, 0.0, ShareRule::Accept, |c| {
+        c.login_check = true;
+    })
+    .await;
+    assert_login_rejected(&h).await;
+
+    // share_alg 99 的登录同样校验
+    let h = start_with(LISTENER_TCP, 1, 0.2, ShareRule::Accept, |c| {
+        c.share_alg = 99;
+        c.login_check = true;
+    })
+    .await;
+    assert_login_rejected(&h).await;
+}
+
+async fn assert_login_rejected(h: &Harness) {
+    // 钱包少一位 矿工名带斜杠 都直接拒绝 不转发给矿池
+    let logins = [
+        json!({"id": 1, "method": "eth_submitLogin", "params": ["0x666666666666666666666666666666666666666", "x"], "worker": "rig1"}),
+        json!({"id": 2, "method": "eth_submitLogin", "params": ["0x6666666666666666666666666666666666666666.rig/1", "x"]}),
+    ];
+    for login in &logins {
+        let stream = TcpStream::connect(&h.addr).await.unwrap();
+        let (r, mut w) = tokio::io::split(stream);
+        let mut lines = BufReader::new(r).lines();
+        w.write_all(format!("{}\n", login).as_bytes())
+            .await
+            .unwrap();
+
+        let line = timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let rpc: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(rpc["id"], login["id"]);
+        assert_eq!(rpc["result"], false);
+        assert!(!rpc["error"].as_str().unwrap().is_empty(), "{}", rpc);
+
+        // 回复后断开
+        let eof = timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .unwrap();
+        assert!(matches!(eof, Ok(None) | Err(_)));
+    }
+
+    let rejects = h.state.login_rejects();
+    assert_eq!(rejects.wallet, 1);
+    assert_eq!(rejects.worker, 1);
+    assert!(h.pool.logins().is_empty());
+}